@@ -0,0 +1,96 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::time::Duration;
+
+// a step in the step mode, a frame at 60 FPS
+pub const FIXED_TIMESTEP: Duration = Duration::from_micros(16_667);
+
+// The time of the animations in the scene. In the step mode, the time only advances by a fixed
+// timestep when stepped, and the scene is only rendered in the frames stepped.
+#[derive(Debug, Clone, Default)]
+pub struct SceneClock {
+    time: Duration,
+    // the frames the time advanced in
+    frame: u64,
+    step_mode: bool,
+    // whether the time is stepped in this frame
+    stepped: bool,
+}
+
+impl SceneClock {
+    // called at the start of every frame, before advancing or stepping
+    pub fn begin_frame(&mut self) {
+        self.stepped = false;
+    }
+
+    // by the frame time, ignored in the step mode
+    pub fn advance(&mut self, delta: Duration) {
+        if self.step_mode {
+            return;
+        }
+        self.time += delta;
+        self.frame += 1;
+    }
+
+    // ignored unless in the step mode
+    pub fn step(&mut self) {
+        if !self.step_mode {
+            return;
+        }
+        self.time += FIXED_TIMESTEP;
+        self.frame += 1;
+        self.stepped = true;
+    }
+
+    pub fn get_time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_step_mode(&self) -> bool {
+        self.step_mode
+    }
+
+    pub fn set_step_mode(&mut self, step_mode: bool) {
+        self.step_mode = step_mode;
+    }
+
+    pub fn is_stepped(&self) -> bool {
+        self.stepped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_mode_advances_by_the_fixed_timestep() {
+        let mut clock = SceneClock::default();
+        clock.begin_frame();
+        clock.advance(Duration::from_millis(30));
+        // stepping is only for the step mode
+        clock.step();
+        assert_eq!(clock.get_time(), Duration::from_millis(30));
+        assert_eq!(clock.get_frame(), 1);
+        assert!(!clock.is_stepped());
+
+        clock.set_step_mode(true);
+        clock.begin_frame();
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(clock.get_time(), Duration::from_millis(30));
+        clock.step();
+        assert!(clock.is_stepped());
+        assert_eq!(clock.get_time(), Duration::from_millis(30) + FIXED_TIMESTEP);
+        assert_eq!(clock.get_frame(), 2);
+        clock.begin_frame();
+        assert!(!clock.is_stepped());
+        assert_eq!(clock.get_frame(), 2);
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::sync::Arc;
+
+use imgui::DrawData;
+use imgui_vulkano_renderer::Renderer as UiRenderer;
+use vulkano::{
+    command_buffer::AutoCommandBufferBuilder,
+    device::{Device, Queue},
+    format::Format,
+    image::{traits::ImageViewAccess, AttachmentImage, ImageAccess, ImageUsage},
+};
+
+use crate::errors::*;
+
+// The scene shaders encode their output with the gamma themselves, and imgui blends its sRGB
+// vertex colors as they are, so both have to draw to UNORM images. On an sRGB swapchain the
+// hardware would encode the colors again, so the UI is drawn to a UNORM image instead, whose bytes
+// are copied to the swapchain image as they are.
+pub struct Compositor {
+    format: Format,
+    // None when the swapchain format is already UNORM
+    ui_target: Option<Arc<AttachmentImage>>,
+}
+
+// the UNORM format with the same layout, the format itself if there is none
+pub fn unorm_format(format: Format) -> Format {
+    match format {
+        Format::B8G8R8A8Srgb => Format::B8G8R8A8Unorm,
+        Format::R8G8B8A8Srgb => Format::R8G8B8A8Unorm,
+        Format::A8B8G8R8SrgbPack32 => Format::A8B8G8R8UnormPack32,
+        Format::B8G8R8Srgb => Format::B8G8R8Unorm,
+        Format::R8G8B8Srgb => Format::R8G8B8Unorm,
+        format => format,
+    }
+}
+
+fn create_ui_target(
+    device: Arc<Device>,
+    dimensions: [u32; 2],
+    format: Format,
+) -> Result<Arc<AttachmentImage>> {
+    AttachmentImage::with_usage(
+        device,
+        dimensions,
+        format,
+        ImageUsage {
+            transfer_source: true,
+            transfer_destination: true,
+            ..ImageUsage::color_attachment()
+        },
+    )
+    .chain_err(|| ErrorKind::GpuResource("fail to create the image to draw the UI on".to_owned()))
+}
+
+impl Compositor {
+    pub fn new(
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        swapchain_format: Format,
+    ) -> Result<Self> {
+        let format = unorm_format(swapchain_format);
+        let ui_target = if format == swapchain_format {
+            None
+        } else {
+            Some(create_ui_target(device, dimensions, format)?)
+        };
+        Ok(Self { format, ui_target })
+    }
+
+    // the format to draw the scene and the UI in
+    pub fn get_format(&self) -> Format {
+        self.format
+    }
+
+    pub fn resize(&mut self, device: Arc<Device>, dimensions: [u32; 2]) -> Result<()> {
+        if let Some(ui_target) = self.ui_target.as_mut() {
+            *ui_target = create_ui_target(device, dimensions, self.format)?;
+        }
+        Ok(())
+    }
+
+    // Draws the UI over the background, the scene image of the same size as the target or a blank
+    // one if None.
+    pub fn record<I, P>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<P>,
+        ui_renderer: &mut UiRenderer,
+        queue: Arc<Queue>,
+        background: Option<Arc<AttachmentImage>>,
+        target: I,
+        draw_data: &DrawData,
+    ) -> Result<()>
+    where
+        I: ImageAccess + ImageViewAccess + Clone + Send + Sync + 'static,
+    {
+        match &self.ui_target {
+            Some(ui_target) => {
+                Self::record_layers(
+                    builder,
+                    ui_renderer,
+                    queue,
+                    background,
+                    ui_target.clone(),
+                    draw_data,
+                )?;
+                let [width, height] = AttachmentImage::dimensions(ui_target);
+                builder
+                    .copy_image(
+                        ui_target.clone(),
+                        [0; 3],
+                        0,
+                        0,
+                        target,
+                        [0; 3],
+                        0,
+                        0,
+                        [width, height, 1],
+                        1,
+                    )
+                    .chain_err(|| "fail to copy the UI image to the target")?;
+                Ok(())
+            }
+            None => Self::record_layers(builder, ui_renderer, queue, background, target, draw_data),
+        }
+    }
+
+    fn record_layers<I, P>(
+        builder: &mut AutoCommandBufferBuilder<P>,
+        ui_renderer: &mut UiRenderer,
+        queue: Arc<Queue>,
+        background: Option<Arc<AttachmentImage>>,
+        target: I,
+        draw_data: &DrawData,
+    ) -> Result<()>
+    where
+        I: ImageAccess + ImageViewAccess + Clone + Send + Sync + 'static,
+    {
+        match background {
+            Some(background) => {
+                let [width, height] = AttachmentImage::dimensions(&background);
+                builder
+                    .copy_image(
+                        background,
+                        [0; 3],
+                        0,
+                        0,
+                        target.clone(),
+                        [0; 3],
+                        0,
+                        0,
+                        [width, height, 1],
+                        1,
+                    )
+                    .chain_err(|| "fail to copy the scene image under the UI")?;
+            }
+            None => {
+                builder
+                    .clear_color_image(target.clone(), [0.0; 4].into())
+                    .chain_err(|| "fail to clear the background of the UI")?;
+            }
+        }
+        ui_renderer
+            .draw_commands(builder, queue, target, draw_data)
+            .chain_err(|| "fail to draw the UI")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use imgui::Context;
+    use vulkano::{
+        buffer::{BufferUsage, CpuAccessibleBuffer},
+        command_buffer::CommandBuffer,
+        sync::GpuFuture,
+    };
+
+    use crate::gpu_test::create_queue;
+
+    #[test]
+    fn test_unorm_format() {
+        assert_eq!(unorm_format(Format::B8G8R8A8Srgb), Format::B8G8R8A8Unorm);
+        assert_eq!(unorm_format(Format::B8G8R8A8Unorm), Format::B8G8R8A8Unorm);
+        assert_eq!(
+            unorm_format(Format::R16G16B16A16Sfloat),
+            Format::R16G16B16A16Sfloat
+        );
+    }
+
+    // a half transparent 50% gray rect over a 50% gray scene stays 50% gray on both swapchains
+    #[test]
+    fn test_gray_ui_over_gray_scene() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        const SIZE: u32 = 8;
+        let mut imgui = Context::create();
+        imgui.set_ini_filename(None);
+        imgui.io_mut().display_size = [SIZE as f32, SIZE as f32];
+        for swapchain_format in [Format::B8G8R8A8Unorm, Format::B8G8R8A8Srgb]
+            .iter()
+            .cloned()
+        {
+            let compositor = Compositor::new(device.clone(), [SIZE; 2], swapchain_format).unwrap();
+            let mut ui_renderer = UiRenderer::init(
+                &mut imgui,
+                device.clone(),
+                queue.clone(),
+                compositor.get_format(),
+            )
+            .unwrap();
+            let scene = AttachmentImage::with_usage(
+                device.clone(),
+                [SIZE; 2],
+                compositor.get_format(),
+                ImageUsage {
+                    transfer_source: true,
+                    transfer_destination: true,
+                    ..ImageUsage::color_attachment()
+                },
+            )
+            .unwrap();
+            // stands in for the swapchain image
+            let target = AttachmentImage::with_usage(
+                device.clone(),
+                [SIZE; 2],
+                swapchain_format,
+                ImageUsage {
+                    transfer_source: true,
+                    transfer_destination: true,
+                    ..ImageUsage::color_attachment()
+                },
+            )
+            .unwrap();
+            let readback = CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::transfer_destination(),
+                false,
+                (0..SIZE * SIZE * 4).map(|_| 0u8),
+            )
+            .unwrap();
+
+            let ui = imgui.frame();
+            ui.get_background_draw_list()
+                .add_rect([0.0, 0.0], [SIZE as f32; 2], [0.5, 0.5, 0.5, 0.5])
+                .filled(true)
+                .build();
+            let draw_data = ui.render();
+            let mut builder =
+                AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+                    .unwrap();
+            builder
+                .clear_color_image(scene.clone(), [0.5, 0.5, 0.5, 1.0].into())
+                .unwrap();
+            compositor
+                .record(
+                    &mut builder,
+                    &mut ui_renderer,
+                    queue.clone(),
+                    Some(scene),
+                    target.clone(),
+                    draw_data,
+                )
+                .unwrap();
+            builder
+                .copy_image_to_buffer(target, readback.clone())
+                .unwrap();
+            builder
+                .build()
+                .unwrap()
+                .execute(queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+
+            for pixel in readback.read().unwrap().chunks(4) {
+                for c in &pixel[..3] {
+                    assert!(
+                        (127..=129).contains(c),
+                        "{:?} on the {:?} swapchain",
+                        pixel,
+                        swapchain_format
+                    );
+                }
+            }
+        }
+    }
+}
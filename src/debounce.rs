@@ -0,0 +1,86 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::time::{Duration, Instant};
+
+// Settles on a value once it stops changing for the delay, e.g. the size of a window being
+// dragged, so that the work depending on it isn't redone every frame. The first value settles at
+// once.
+pub struct Debouncer<T> {
+    delay: Duration,
+    settled: Option<T>,
+    // the latest value different from the settled one, and since when it's seen
+    pending: Option<(T, Instant)>,
+}
+
+impl<T: Copy + PartialEq> Debouncer<T> {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            settled: None,
+            pending: None,
+        }
+    }
+
+    pub fn update(&mut self, value: T, now: Instant) -> Option<T> {
+        match self.settled {
+            Some(settled) if settled == value => self.pending = None,
+            Some(_) => match self.pending {
+                Some((pending, since)) if pending == value => {
+                    if now.duration_since(since) >= self.delay {
+                        self.settled = Some(value);
+                        self.pending = None;
+                    }
+                }
+                _ => self.pending = Some((value, now)),
+            },
+            None => self.settled = Some(value),
+        }
+        self.settled
+    }
+
+    pub fn get(&self) -> Option<T> {
+        self.settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn test_the_first_value_settles_at_once() {
+        let mut debouncer = Debouncer::new(DELAY);
+        assert_eq!(debouncer.get(), None);
+        assert_eq!(debouncer.update(1, Instant::now()), Some(1));
+    }
+
+    #[test]
+    fn test_settle_after_the_delay() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(DELAY);
+        debouncer.update(1, start);
+        assert_eq!(debouncer.update(2, start), Some(1));
+        assert_eq!(debouncer.update(2, start + DELAY / 2), Some(1));
+        assert_eq!(debouncer.update(2, start + DELAY), Some(2));
+    }
+
+    #[test]
+    fn test_changes_restart_the_delay() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(DELAY);
+        debouncer.update(1, start);
+        debouncer.update(2, start);
+        debouncer.update(3, start + DELAY / 2);
+        assert_eq!(debouncer.update(3, start + DELAY), Some(1));
+        assert_eq!(debouncer.update(3, start + DELAY * 3 / 2), Some(3));
+        // going back to the settled value drops the pending one
+        debouncer.update(4, start + DELAY * 2);
+        debouncer.update(3, start + DELAY * 2);
+        assert_eq!(debouncer.update(4, start + DELAY * 4), Some(3));
+    }
+}
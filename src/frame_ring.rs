@@ -0,0 +1,95 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+// A slot per frame in flight, holding e.g. the fence of the frame last submitted from the slot.
+// Before a slot is reused, the frame in it has to be finished, so at most as many frames as the
+// slots are in flight.
+pub struct FrameRing<T> {
+    frames: Vec<Option<T>>,
+    current: usize,
+    // the slot of the last submitted frame
+    previous: Option<usize>,
+}
+
+impl<T> FrameRing<T> {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "there should be at least 1 frame in flight");
+        Self {
+            frames: (0..size).map(|_| None).collect(),
+            current: 0,
+            previous: None,
+        }
+    }
+
+    // the frame submitted the last time the current slot was used
+    pub fn take_current(&mut self) -> Option<T> {
+        self.frames[self.current].take()
+    }
+
+    pub fn previous(&self) -> Option<&T> {
+        self.previous.and_then(|i| self.frames[i].as_ref())
+    }
+
+    // None if the frame failed to be submitted
+    pub fn submit(&mut self, frame: Option<T>) {
+        self.frames[self.current] = frame;
+        self.previous = Some(self.current);
+        self.current = (self.current + 1) % self.frames.len();
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.frames.iter_mut().flatten()
+    }
+
+    // the frames in the order they are submitted
+    pub fn drain(&mut self) -> Vec<T> {
+        let len = self.frames.len();
+        let current = self.current;
+        self.previous = None;
+        (0..len)
+            .filter_map(|i| self.frames[(current + i) % len].take())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_reused_in_order() {
+        let mut ring = FrameRing::new(2);
+        assert_eq!(ring.take_current(), None);
+        assert_eq!(ring.previous(), None);
+        ring.submit(Some(0));
+        assert_eq!(ring.previous(), Some(&0));
+        assert_eq!(ring.take_current(), None);
+        ring.submit(Some(1));
+        assert_eq!(ring.take_current(), Some(0));
+        assert_eq!(ring.previous(), Some(&1));
+        ring.submit(Some(2));
+        assert_eq!(ring.drain(), vec![1, 2]);
+        assert_eq!(ring.previous(), None);
+    }
+
+    #[test]
+    fn test_single_frame_in_flight() {
+        let mut ring = FrameRing::new(1);
+        ring.submit(Some(0));
+        assert_eq!(ring.previous(), Some(&0));
+        // the previous frame is waited for before the next one starts
+        assert_eq!(ring.take_current(), Some(0));
+        assert_eq!(ring.previous(), None);
+    }
+
+    #[test]
+    fn test_failed_submission() {
+        let mut ring = FrameRing::new(2);
+        ring.submit(Some(0));
+        ring.submit(None);
+        assert_eq!(ring.previous(), None);
+        assert_eq!(ring.take_current(), Some(0));
+    }
+}
@@ -0,0 +1,30 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+// the fixtures of the tests that need a Vulkan device
+
+use std::sync::Arc;
+
+use vulkano::{
+    device::{Device, DeviceExtensions, Features, Queue},
+    instance::{Instance, InstanceExtensions, PhysicalDevice},
+};
+
+// None if there is no Vulkan implementation available, e.g. on CI
+pub fn create_queue() -> Option<Arc<Queue>> {
+    let instance = Instance::new(None, &InstanceExtensions::none(), None).ok()?;
+    let physical = PhysicalDevice::enumerate(&instance).next()?;
+    let queue_family = physical
+        .queue_families()
+        .find(|queue_family| queue_family.supports_graphics())?;
+    let (_, mut queues) = Device::new(
+        physical,
+        &Features::none(),
+        &DeviceExtensions::none(),
+        [(queue_family, 0.5)].iter().cloned(),
+    )
+    .ok()?;
+    queues.next()
+}
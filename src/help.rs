@@ -0,0 +1,130 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use winit::event::VirtualKeyCode;
+
+use crate::settings::{get_key_name, KeyBindings};
+
+pub const HELP_KEY: VirtualKeyCode = VirtualKeyCode::F1;
+pub const SCREENSHOT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+pub const STEP_KEY: VirtualKeyCode = VirtualKeyCode::F10;
+// unless bound to move the camera or quit
+pub const ROLL_LEFT_KEY: VirtualKeyCode = VirtualKeyCode::Q;
+pub const ROLL_RIGHT_KEY: VirtualKeyCode = VirtualKeyCode::E;
+
+// a group of the controls in the help overlay, each entry is the input and what it does
+#[derive(Debug, Clone, PartialEq)]
+pub struct HelpSection {
+    pub category: &'static str,
+    pub entries: Vec<(String, String)>,
+}
+
+// The controls with the keys currently bound, so that the help follows the rebinding.
+pub fn get_help_sections(key_bindings: &KeyBindings) -> Vec<HelpSection> {
+    let mut camera: Vec<_> = KeyBindings::DIRECTIONS
+        .iter()
+        .enumerate()
+        .map(|(index, (_, name))| {
+            (
+                get_key_name(key_bindings.get_key(index)),
+                format!("move {}", name),
+            )
+        })
+        .collect();
+    for (key, action) in [
+        (ROLL_LEFT_KEY, "roll to the left"),
+        (ROLL_RIGHT_KEY, "roll to the right"),
+    ]
+    .iter()
+    {
+        if !key_bindings.is_bound(*key) {
+            camera.push((get_key_name(*key), action.to_string()));
+        }
+    }
+    camera.push((
+        "left mouse drag".to_owned(),
+        "orbit around the model".to_owned(),
+    ));
+    camera.push(("middle mouse drag".to_owned(), "look around".to_owned()));
+    camera.push((
+        "mouse wheel".to_owned(),
+        "dolly towards the model".to_owned(),
+    ));
+    camera.push((
+        "ctrl + mouse wheel".to_owned(),
+        "zoom, the perspective camera only".to_owned(),
+    ));
+
+    let mut shortcuts = vec![
+        (get_key_name(HELP_KEY), "show or hide this help".to_owned()),
+        (
+            get_key_name(VirtualKeyCode::Escape),
+            "close this help, or cancel the rebinding".to_owned(),
+        ),
+    ];
+    if let Some(quit) = key_bindings.get_quit_key() {
+        shortcuts.push((get_key_name(quit), "quit".to_owned()));
+    }
+
+    let debug = vec![
+        (
+            get_key_name(SCREENSHOT_KEY),
+            "take a screenshot into the working directory".to_owned(),
+        ),
+        (
+            get_key_name(STEP_KEY),
+            "advance the animations by a frame in the step mode".to_owned(),
+        ),
+    ];
+
+    vec![
+        HelpSection {
+            category: "camera",
+            entries: camera,
+        },
+        HelpSection {
+            category: "shortcuts",
+            entries: shortcuts,
+        },
+        HelpSection {
+            category: "debug",
+            entries: debug,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find_input<'a>(sections: &'a [HelpSection], action: &str) -> Option<&'a str> {
+        sections
+            .iter()
+            .flat_map(|section| section.entries.iter())
+            .find(|(_, entry_action)| entry_action == action)
+            .map(|(input, _)| input.as_str())
+    }
+
+    #[test]
+    fn test_help_follows_the_key_bindings() {
+        let mut key_bindings = KeyBindings::default();
+        let sections = get_help_sections(&key_bindings);
+        let categories: Vec<_> = sections.iter().map(|section| section.category).collect();
+        assert_eq!(categories, vec!["camera", "shortcuts", "debug"]);
+        assert_eq!(find_input(&sections, "move forward"), Some("W"));
+        assert_eq!(find_input(&sections, "quit"), None);
+        assert_eq!(find_input(&sections, "roll to the left"), Some("Q"));
+        assert_eq!(find_input(&sections, "roll to the right"), Some("E"));
+
+        key_bindings.bind(0, VirtualKeyCode::E);
+        key_bindings.bind_quit(Some(VirtualKeyCode::Q)).unwrap();
+        let sections = get_help_sections(&key_bindings);
+        assert_eq!(find_input(&sections, "move forward"), Some("E"));
+        assert_eq!(find_input(&sections, "quit"), Some("Q"));
+        // the keys bound to something else don't roll
+        assert_eq!(find_input(&sections, "roll to the left"), None);
+        assert_eq!(find_input(&sections, "roll to the right"), None);
+    }
+}
@@ -1,21 +1,45 @@
 #![recursion_limit = "1024"]
 
+mod clock;
+mod composite;
+mod debounce;
+mod frame_ring;
+#[cfg(test)]
+mod gpu_test;
+mod help;
+#[macro_use]
+mod profile;
+mod readback;
 mod scene;
+mod settings;
 mod support;
 
+use clock::SceneClock;
+use debounce::Debouncer;
+use help::{get_help_sections, HELP_KEY, ROLL_LEFT_KEY, ROLL_RIGHT_KEY, SCREENSHOT_KEY, STEP_KEY};
+use profile::{MergedScope, ProfileReason};
+use settings::{
+    find_held_bindable_key, find_held_quit_key, get_key_name, KeyBindings, Settings,
+    MOUSE_SENSITIVITY_RANGE, SETTINGS_PATH,
+};
 use std::{
+    borrow::Cow,
     cell::RefCell,
-    path::PathBuf,
+    path::{Path, PathBuf},
     rc::Rc,
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use support::{UiAppearance, UiTheme, UI_SCALE_RANGE};
 
-use euclid::{approxeq::ApproxEq, point3, vec2, vec3, Angle, Transform3D, Vector2D};
+use euclid::{
+    approxeq::ApproxEq, point2, point3, size2, vec3, Angle, Box2D, Point2D, Point3D, Transform3D,
+    Vector3D,
+};
 use imgui::*;
 use vulkano::swapchain::Surface;
 use winit::{
-    dpi::LogicalPosition,
+    dpi::{LogicalPosition, PhysicalPosition},
     event::{ElementState, MouseButton as WinitMouseButton, VirtualKeyCode},
     window::Window as WinitWindow,
 };
@@ -24,12 +48,64 @@ use winit::{
 extern crate error_chain;
 
 use scene::{
-    Camera, CameraControl, CameraDirection, ModelAndTexture, Renderer as SceneRenderer,
-    State as SceneState, ViewSpace,
+    dump_shader_reflection, find_skybox_faces, linear_to_srgb, smooth_near_far, srgb_to_linear,
+    Camera, CameraConfig, CameraControl, CameraMatrices, CullMode, DenoiseParams, DirectionalLight,
+    FailedResource, FlareTexture, FrontFace, HemisphereAmbient, ImportOptions, LengthUnit,
+    LogicalPixelSpace, LuminanceStats, ModelAndTexture, NDCSpace, PointLightState, ProbedPixel,
+    RasterState, RenderMode, Renderer as SceneRenderer, RollDirection, SceneUnits, ShadowConfig,
+    ShadowFilter, SpecularModel, State as SceneState, TriangleSpace, UpAxis, ViewportMapper,
+    WorldSpace, DEFAULT_LIGHT_INTENSITY, DEFAULT_PATH_TRACE_SAMPLES, DEFAULT_SHININESS,
+    MAX_AMBIENT_STRENGTH, MAX_PCF_KERNEL_RADIUS, MAX_POINT_LIGHTS, MAX_SHININESS,
+    MAX_SPECULAR_AA_STRENGTH, MIN_AUTO_NEAR, MIN_SHININESS,
 };
 
 mod errors {
-    error_chain! {}
+    error_chain! {
+        errors {
+            AssetNotFound(path: String) {
+                display("fail to find the asset {}", path)
+            }
+            AssetParse(path: String) {
+                display("fail to parse the asset {}", path)
+            }
+            UnsupportedFeature(message: String) {
+                display("{}", message)
+            }
+            GpuResource(message: String) {
+                display("{}", message)
+            }
+            Validation(message: String) {
+                display("{}", message)
+            }
+        }
+    }
+
+    // the first kind in the chain other than a plain message
+    pub fn find_error_kind(e: &Error) -> Option<&ErrorKind> {
+        let mut current = Some(e);
+        while let Some(e) = current {
+            match e.kind() {
+                ErrorKind::Msg(_) => (),
+                kind => return Some(kind),
+            }
+            current =
+                e.1.next_error
+                    .as_ref()
+                    .and_then(|next| next.downcast_ref::<Error>());
+        }
+        None
+    }
+
+    pub fn exit_code(e: &Error) -> i32 {
+        match find_error_kind(e) {
+            Some(ErrorKind::AssetNotFound(_)) => 2,
+            Some(ErrorKind::AssetParse(_)) => 3,
+            Some(ErrorKind::UnsupportedFeature(_)) => 4,
+            Some(ErrorKind::GpuResource(_)) => 5,
+            Some(ErrorKind::Validation(_)) => 6,
+            _ => 1,
+        }
+    }
 
     pub fn eprint_chained_err(e: &Error) {
         eprintln!("error: {}", e);
@@ -42,19 +118,131 @@ mod errors {
             eprintln!("backtrace: {:?}", backtrace);
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_find_error_kind_through_the_chain() {
+            let e: Result<()> = Err(ErrorKind::AssetNotFound("model.obj".to_owned()).into());
+            let e = e
+                .chain_err(|| "fail to load the model")
+                .chain_err(|| "fail to load the scene")
+                .unwrap_err();
+            assert!(matches!(
+                find_error_kind(&e),
+                Some(ErrorKind::AssetNotFound(path)) if path == "model.obj"
+            ));
+            assert_eq!(exit_code(&e), 2);
+
+            let e: Error = "fail to do something".into();
+            assert!(find_error_kind(&e).is_none());
+            assert_eq!(exit_code(&e), 1);
+        }
+    }
 }
 
 use errors::*;
 
-fn select_model_and_texture_files() -> Result<Option<ModelAndTexture>> {
-    let model_path =
-        tinyfiledialogs::open_file_dialog("select model file", "", Some((&["*.obj"], "")));
-    let model_path = if let Some(model_path) = model_path {
-        PathBuf::from(model_path)
-    } else {
-        return Ok(None);
-    };
-    ModelAndTexture::load(&model_path).map(Some)
+// None if the user cancels the dialog
+fn select_model_file() -> Option<PathBuf> {
+    tinyfiledialogs::open_file_dialog("select model file", "", Some((&["*.obj"], "")))
+        .map(PathBuf::from)
+}
+
+// the folder with the six faces, see find_skybox_faces, None if the user cancels the dialog
+fn select_skybox_folder() -> Option<PathBuf> {
+    tinyfiledialogs::select_folder_dialog("select the skybox folder", "").map(PathBuf::from)
+}
+
+// the range the mouse wheel zooms the perspective camera in, well inside the 0 to pi Camera::new
+// accepts
+const MIN_FOV: Angle<f32> = Angle {
+    radians: std::f32::consts::PI / 18.0,
+};
+const MAX_FOV: Angle<f32> = Angle {
+    radians: std::f32::consts::FRAC_PI_2,
+};
+
+// in meters, how close the mouse wheel dollies the camera to the look target
+const MIN_DOLLY_DISTANCE: f32 = 0.05;
+
+const MAX_CAMERA_PITCH: Angle<f32> = Angle {
+    radians: 89.0 * std::f32::consts::PI / 180.0,
+};
+
+const ERROR_TOAST_DURATION: Duration = Duration::from_secs(5);
+const HELP_HINT_DURATION: Duration = Duration::from_secs(10);
+
+// in the working directory, next to the screenshots
+const CAMERA_CONFIG_PATH: &str = "camera.json";
+// the view and projection matrices exported from other tools
+const CAMERA_MATRICES_PATH: &str = "camera_matrices.json";
+
+// a point light as edited in the UI
+struct PointLightEdit {
+    position: [f32; 3],
+    // sRGB, picked in the UI
+    color: [f32; 3],
+    intensity: f32,
+    // circle around the position over time
+    orbit: bool,
+}
+
+impl Default for PointLightEdit {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            color: [1.0; 3],
+            intensity: DEFAULT_LIGHT_INTENSITY,
+            orbit: false,
+        }
+    }
+}
+
+struct ErrorToast {
+    message: String,
+    color: [f32; 4],
+    created: Instant,
+}
+
+// a tree of the scopes, with the loops merged and their share of the frame
+fn build_profile_scopes(ui: &Ui, scopes: &[MergedScope], total_us: u64) {
+    for scope in scopes {
+        let count = if scope.count > 1 {
+            format!(" x{}", scope.count)
+        } else {
+            String::new()
+        };
+        // the id stays the same while the times change, so that the node stays open
+        let id = ImString::new(scope.name);
+        let label = im_str!(
+            "{}{}: {:.3} ms ({:.0}%)",
+            scope.name,
+            count,
+            scope.duration_us as f64 / 1000.0,
+            scope.duration_us as f64 * 100.0 / total_us.max(1) as f64
+        );
+        TreeNode::new(&id)
+            .label(&label)
+            .default_open(true)
+            .leaf(scope.children.is_empty())
+            .build(ui, || build_profile_scopes(ui, &scope.children, total_us));
+    }
+}
+
+// a different color for every kind of error, so that e.g. a missing file is easily told apart
+// from an unsupported model
+fn error_color(kind: Option<&ErrorKind>) -> [f32; 4] {
+    match kind {
+        Some(ErrorKind::AssetNotFound(_)) => [1.0, 0.8, 0.2, 1.0],
+        Some(ErrorKind::AssetParse(_)) => [1.0, 0.5, 0.1, 1.0],
+        Some(ErrorKind::UnsupportedFeature(_)) => [0.7, 0.5, 1.0, 1.0],
+        Some(ErrorKind::GpuResource(_)) => [1.0, 0.2, 0.2, 1.0],
+        Some(ErrorKind::Validation(_)) => [0.4, 0.8, 1.0, 1.0],
+        _ => [1.0, 1.0, 1.0, 1.0],
+    }
 }
 
 struct Application {
@@ -62,80 +250,900 @@ struct Application {
     scene_renderer: Rc<RefCell<SceneRenderer>>,
 
     mouse_middle_button_held: bool,
-    cursor_lock_position: Option<LogicalPosition<f64>>,
+    // the left button orbits the camera around the model
+    mouse_left_button_held: bool,
+    // whether the UI took the mouse in the last frame, so that clicking it doesn't orbit the camera
+    ui_wants_mouse: bool,
+    // the mouse wheel zooms the fov instead of dollying the camera while ctrl is held
+    ctrl_held: bool,
+    // In physical pixels. The UI divides the physical pixels by its rounded scale factor while
+    // winit's logical pixels use the exact one, so positions from the UI can't be handed to winit
+    // as LogicalPosition on fractional scale factors.
+    cursor_lock_position: Option<PhysicalPosition<f64>>,
+    // in the logical pixels of the UI, see LogicalPixelSpace
     cursor_position: LogicalPosition<f64>,
+    viewport_mapper: ViewportMapper,
 
     color_picker_visible: bool,
+    // the colors edited in the UI are sRGB, and converted to linear for the scene
     color: [f32; 3],
     recent_frame_times: Vec<Instant>,
     camera: Option<Camera>,
+    orthographic_camera: bool,
+    // of the perspective camera, zoomed with the mouse wheel while ctrl is held
+    fov: Angle<f32>,
+    // keep the camera from looking straight up or down, where the view flips
+    clamp_camera_pitch: bool,
+    // the middle button orbits the camera around the target instead of turning it in place
+    orbit_camera: bool,
+    orbit_target: [f32; 3],
+    // fit the near and far to the scene every frame, otherwise use the ones below
+    auto_near_far: bool,
+    near: f32,
+    far: f32,
+    last_near_far_update: Option<Instant>,
+    // in the view frustum of the last scene state
+    objects_in_view: usize,
+    // in meters per second, see scene_units
     camera_speed: f32,
-    model_path: Option<String>,
-    start_time: Instant,
+    // move slower when zoomed in, see fov_speed_scale
+    scale_speed_by_fov: bool,
+    // ease the movement with the keys in and out
+    smooth_camera_movement: bool,
+    // in seconds, the time for the velocity to get about 2/3 of the way while the keys are held
+    camera_acceleration_time: f32,
+    // in seconds, the same after the keys are released
+    camera_damping_time: f32,
+    camera_velocity: Vector3D<f32, WorldSpace>,
+    model_path: Option<PathBuf>,
+    // of the loaded model, remembered for its file
+    scene_units: SceneUnits,
+    // advanced by the frame time unless the scene is frozen, or stepped in the step mode
+    scene_clock: SceneClock,
+    // whether F10 is held in the last frame, the scene clock steps when it's pressed
+    step_key_held: bool,
+    denoise_path_traced_image: bool,
+    scene_frozen: bool,
+    batch_small_groups: bool,
+    batch_threshold: i32,
+    flip_normals: bool,
+    error_toast: Option<ErrorToast>,
+    // whether F12 is held in the last frame, a screenshot is taken when it's pressed
+    screenshot_key_held: bool,
+    screenshot_requested: bool,
+    screenshot_count: u32,
+    in_flight_readbacks: usize,
+    luminance_histogram_visible: bool,
+    luminance_stats: Option<LuminanceStats>,
+    pixel_probe_visible: bool,
+    probed_pixel: Option<ProbedPixel>,
+    // sRGB colors like the color above
+    ambient: HemisphereAmbient,
+    point_lights: Vec<PointLightEdit>,
+    // the directional light, from the azimuth and the elevation in degrees
+    sun_enabled: bool,
+    sun_azimuth: f32,
+    sun_elevation: f32,
+    // sRGB, picked in the UI
+    sun_color: [f32; 3],
+    sun_intensity: f32,
+    // sRGB, where the scene has nothing drawn
+    background_color: [f32; 4],
+    render_mode: RenderMode,
+    // show the scene in a UI window instead of behind the UI
+    scene_in_window: bool,
+    scene_texture: Option<(TextureId, [u32; 2])>,
+    // in the last frame, whether the scene image is hovered and its window is focused
+    scene_view_hovered: bool,
+    scene_view_focused: bool,
+    // the size of the scene image in physical pixels, so that dragging the window doesn't
+    // recreate the render target every frame
+    scene_view_size: Debouncer<[u32; 2]>,
+    settings: Settings,
+    // the UI scale is only applied when the slider is released, since it rebuilds the font atlas
+    ui_scale_edit: f32,
+    // the index in KeyBindings::DIRECTIONS waiting for the next key pressed
+    rebinding_direction: Option<usize>,
+    // the model file selected, waiting for the import options to be confirmed
+    pending_import: Option<(PathBuf, ImportOptions)>,
+    // waiting for the next key pressed to quit with
+    rebinding_quit_key: bool,
+    // whether the quit key is held in the last frame, the application quits when it's pressed
+    quit_key_held: bool,
+    // nothing of the scene is saved, so the loaded models are lost on quitting
+    scene_edited: bool,
+    // ask before quitting with the scene edited
+    confirm_quit: bool,
+    quit_confirmation_requested: bool,
+    quit_requested: bool,
+    help_visible: bool,
+    // the CPU time of the phases of the frames, only recorded while it's open
+    profiler_visible: bool,
+    // whether F1 and escape are held in the last frame, the help toggles when F1 is pressed
+    help_key_held: bool,
+    escape_held: bool,
+    // when the hint pointing at the help is shown on the first launch
+    help_hint_created: Option<Instant>,
 }
 
 impl support::ApplicationT for Application {
     fn new(surface: Arc<Surface<WinitWindow>>, scene_renderer: Rc<RefCell<SceneRenderer>>) -> Self {
-        Application {
+        let settings = Settings::load(Path::new(SETTINGS_PATH))
+            .chain_err(|| "fail to load the settings, use the default ones");
+        let mut application = Application {
             surface,
             scene_renderer,
 
             cursor_position: LogicalPosition::new(0.0, 0.0),
+            viewport_mapper: ViewportMapper::new(1.0, size2(1, 1), size2(1, 1)),
             mouse_middle_button_held: false,
+            mouse_left_button_held: false,
+            ui_wants_mouse: false,
+            ctrl_held: false,
             cursor_lock_position: None,
 
             color_picker_visible: false,
             color: [1.0, 0.0, 0.0],
             recent_frame_times: vec![],
             camera: None,
+            orthographic_camera: false,
+            fov: Angle::pi() / 4.0,
+            clamp_camera_pitch: true,
+            orbit_camera: false,
+            orbit_target: [0.0, 0.0, 0.0],
+            auto_near_far: true,
+            near: 1.0,
+            far: 100.0,
+            last_near_far_update: None,
+            objects_in_view: 0,
             camera_speed: 1.0,
+            scale_speed_by_fov: true,
+            smooth_camera_movement: false,
+            camera_acceleration_time: 0.2,
+            camera_damping_time: 0.2,
+            camera_velocity: Vector3D::zero(),
             model_path: None,
-            start_time: Instant::now(),
+            scene_units: SceneUnits::default(),
+            scene_clock: SceneClock::default(),
+            step_key_held: false,
+            denoise_path_traced_image: true,
+            scene_frozen: false,
+            batch_small_groups: false,
+            batch_threshold: 64,
+            flip_normals: false,
+            error_toast: None,
+            screenshot_key_held: false,
+            screenshot_requested: false,
+            screenshot_count: 0,
+            in_flight_readbacks: 0,
+            luminance_histogram_visible: false,
+            luminance_stats: None,
+            pixel_probe_visible: false,
+            probed_pixel: None,
+            ambient: {
+                let ambient = HemisphereAmbient::default();
+                HemisphereAmbient {
+                    sky_color: linear_to_srgb(ambient.sky_color),
+                    ground_color: linear_to_srgb(ambient.ground_color),
+                    ..ambient
+                }
+            },
+            point_lights: vec![PointLightEdit {
+                orbit: true,
+                ..Default::default()
+            }],
+            sun_enabled: false,
+            sun_azimuth: 30.0,
+            sun_elevation: 45.0,
+            sun_color: [1.0, 1.0, 1.0],
+            background_color: [0.0; 4],
+            render_mode: RenderMode::default(),
+            sun_intensity: 1.0,
+            scene_in_window: false,
+            scene_texture: None,
+            scene_view_hovered: false,
+            scene_view_focused: false,
+            scene_view_size: Debouncer::new(Duration::from_millis(200)),
+            settings: Settings::default(),
+            ui_scale_edit: Settings::default().ui_appearance.scale,
+            rebinding_direction: None,
+            pending_import: None,
+            rebinding_quit_key: false,
+            quit_key_held: false,
+            scene_edited: false,
+            confirm_quit: true,
+            quit_confirmation_requested: false,
+            quit_requested: false,
+            help_visible: false,
+            profiler_visible: false,
+            help_key_held: false,
+            escape_held: false,
+            help_hint_created: None,
+        };
+        match settings {
+            Ok(settings) => {
+                application.ui_scale_edit = settings.ui_appearance.scale;
+                application.settings = settings;
+                // not on the failed loading, which would overwrite the settings with the default
+                if !application.settings.help_hint_shown {
+                    application.help_hint_created = Some(Instant::now());
+                    application.settings.help_hint_shown = true;
+                    if let Err(e) = application
+                        .settings
+                        .save(Path::new(SETTINGS_PATH))
+                        .chain_err(|| "fail to save the settings")
+                    {
+                        application.report_error(e);
+                    }
+                }
+            }
+            Err(e) => application.report_error(e),
         }
+        application
     }
 
     fn get_scene_state(&mut self) -> Result<SceneState> {
-        let time_elapsed = self.start_time.elapsed();
-        let point_light_transform = Transform3D::identity()
-            .then_scale(0.1, 0.1, 0.1)
-            .then_translate(vec3(
-                2.0 * (time_elapsed.as_secs_f32() * 6.0).sin(),
-                3.0 * (time_elapsed.as_secs_f32() * 4.0).cos(),
-                2.0 * (time_elapsed.as_secs_f32() * 2.0).cos(),
-            ));
-        let speed = Angle::pi() / 10.0;
-        let model_transform = Transform3D::identity()
-            .then_translate(vec3(0.0, -2.0, 0.0))
-            .then_rotate(0.0, 1.0, 0.0, speed * time_elapsed.as_secs_f32());
+        let time_elapsed = self.scene_clock.get_time();
+        let point_lights: Vec<_> = self
+            .point_lights
+            .iter()
+            .map(|light| {
+                let [x, y, z] = light.position;
+                let mut position = vec3(x, y, z);
+                if light.orbit {
+                    position += vec3(
+                        2.0 * (time_elapsed.as_secs_f32() * 6.0).sin(),
+                        3.0 * (time_elapsed.as_secs_f32() * 4.0).cos(),
+                        2.0 * (time_elapsed.as_secs_f32() * 2.0).cos(),
+                    );
+                }
+                PointLightState {
+                    transform: Transform3D::identity()
+                        .then_scale(0.1, 0.1, 0.1)
+                        .then_translate(position),
+                    color: srgb_to_linear(light.color),
+                    intensity: light.intensity,
+                }
+            })
+            .collect();
+        let point_light_transforms: Vec<_> =
+            point_lights.iter().map(|light| light.transform).collect();
+        let model_transform = self.get_model_transform();
+        let now = Instant::now();
+        let near_far_time_elapsed = self
+            .last_near_far_update
+            .replace(now)
+            .map(|last_update| now.duration_since(last_update));
+        let bounds = self
+            .scene_renderer
+            .borrow()
+            .get_scene_bounds(&model_transform, &point_light_transforms);
+        let (auto_near_far, manual_near_far) = (self.auto_near_far, (self.near, self.far));
+        let min_near = self.scene_units.meters_to_units(MIN_AUTO_NEAR);
+        let camera = self.get_camera_mut().chain_err(|| "fail to get camera")?;
+        let (near, far) = if auto_near_far {
+            match (
+                camera.fit_near_far(&bounds, min_near),
+                near_far_time_elapsed,
+            ) {
+                (Some(target), Some(time_elapsed)) => {
+                    smooth_near_far(camera.get_near_far(), target, time_elapsed)
+                }
+                (Some(target), None) => target,
+                // nothing to see, keep the last ones
+                (None, _) => camera.get_near_far(),
+            }
+        } else {
+            manual_near_far
+        };
+        camera
+            .set_near_far(near, far)
+            .chain_err(|| "fail to set the near and far of the camera")?;
+        let camera = camera.clone();
+        self.objects_in_view = self
+            .scene_renderer
+            .borrow()
+            .count_objects_in_view(&camera, &model_transform);
         Ok(SceneState {
-            point_light_transform,
-            color: self.color,
-            camera: self
-                .get_camera_mut()
-                .chain_err(|| "fail to get camera")?
-                .clone(),
+            point_lights,
+            color: srgb_to_linear(self.color),
+            camera,
             model_transform,
+            ambient: HemisphereAmbient {
+                sky_color: srgb_to_linear(self.ambient.sky_color),
+                ground_color: srgb_to_linear(self.ambient.ground_color),
+                ..self.ambient
+            },
+            sun: DirectionalLight::from_sky(
+                Angle::degrees(self.sun_azimuth),
+                Angle::degrees(self.sun_elevation),
+                srgb_to_linear(self.sun_color),
+                if self.sun_enabled {
+                    self.sun_intensity
+                } else {
+                    0.0
+                },
+            ),
+            time: self.scene_clock.get_time(),
+            background_color: {
+                let [r, g, b, a] = self.background_color;
+                let [r, g, b] = srgb_to_linear([r, g, b]);
+                [r, g, b, a]
+            },
+            render_mode: self.render_mode,
         })
     }
 
     fn update_ui(&mut self, ui: &mut Ui) -> Result<()> {
         let now = Instant::now();
+        self.scene_clock.begin_frame();
+        if !self.scene_frozen {
+            self.scene_clock
+                .advance(Duration::from_secs_f32(ui.io().delta_time));
+        }
+        let step_key_held = ui.io().keys_down[STEP_KEY as usize];
+        if step_key_held && !self.step_key_held && !ui.io().want_text_input {
+            self.scene_clock.step();
+        }
+        self.step_key_held = step_key_held;
+        self.ui_wants_mouse = ui.io().want_capture_mouse;
+        self.ctrl_held = ui.io().key_ctrl;
         self.recent_frame_times.push(now);
         self.recent_frame_times
             .retain(|frame_time| now.duration_since(*frame_time) < Duration::from_secs(1));
 
-        self.update_camera_from_key_state(
-            &ui.io().keys_down,
-            Duration::from_secs_f32(ui.io().delta_time),
-        )
-        .chain_err(|| "fail to update the camera from key state")?;
+        // the key pressed to rebind doesn't move the camera
+        if self.rebinding_direction.is_none()
+            && (self.is_scene_view_focused() || self.is_scene_view_hovered())
+        {
+            self.update_camera_from_key_state(
+                &ui.io().keys_down,
+                Duration::from_secs_f32(ui.io().delta_time),
+            )
+            .chain_err(|| "fail to update the camera from key state")?;
+        }
+        let screenshot_key_held = ui.io().keys_down[SCREENSHOT_KEY as usize];
+        if screenshot_key_held && !self.screenshot_key_held {
+            self.screenshot_requested = true;
+        }
+        self.screenshot_key_held = screenshot_key_held;
+        let help_key_held = ui.io().keys_down[HELP_KEY as usize];
+        if help_key_held && !self.help_key_held {
+            self.help_visible = !self.help_visible;
+        }
+        self.help_key_held = help_key_held;
+        // escape cancels the rebinding first, and closes the help instead of quitting
+        let escape_held = ui.io().keys_down[VirtualKeyCode::Escape as usize];
+        let help_closed = escape_held
+            && !self.escape_held
+            && self.help_visible
+            && self.rebinding_direction.is_none()
+            && !self.rebinding_quit_key;
+        if help_closed {
+            self.help_visible = false;
+        }
+        self.escape_held = escape_held;
+        let quit_key_held = self
+            .settings
+            .key_bindings
+            .get_quit_key()
+            .is_some_and(|key| ui.io().keys_down[key as usize]);
+        // escape also cancels the rebinding, and releases nothing while the cursor is locked for
+        // the mouse look, and the keys typed into the UI don't quit
+        if quit_key_held
+            && !self.quit_key_held
+            && self.cursor_lock_position.is_none()
+            && self.rebinding_direction.is_none()
+            && !self.rebinding_quit_key
+            && !ui.io().want_text_input
+            && !help_closed
+        {
+            self.request_quit();
+        }
+        self.quit_key_held = quit_key_held;
         let [cursor_x, cursor_y] = ui.io().mouse_pos;
         self.cursor_position = LogicalPosition::new(cursor_x.into(), cursor_y.into());
+        // the UI may round the scale factor of the window, so use the one the UI reports
+        let scale_factor = f64::from(ui.io().display_framebuffer_scale[0]);
+        if !self.scene_in_window {
+            // inner_size is in physical pixels, the scene image may lag behind it on resizing
+            let inner_size = self.surface.window().inner_size();
+            let [width, height] = match self.scene_texture {
+                Some((_, dimensions)) => dimensions,
+                None => [inner_size.width, inner_size.height],
+            };
+            self.viewport_mapper = ViewportMapper::new(
+                scale_factor,
+                size2(inner_size.width, inner_size.height),
+                size2(width, height),
+            );
+        }
 
+        let mut errors = vec![];
+        // the model waiting for the warm-up is added once it's ready
+        if let Err(e) = self
+            .scene_renderer
+            .borrow_mut()
+            .poll_pending_load()
+            .chain_err(|| "fail to load the model prepared")
+        {
+            errors.push(e);
+        }
+        let uploading_objects = self.scene_renderer.borrow_mut().poll_uploads();
         Window::new(im_str!("Hello world"))
             .size([300.0, 110.0], Condition::FirstUseEver)
             .build(ui, || {
                 ui.text(format!("FPS {}", self.recent_frame_times.len()));
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("?")) {
+                    self.help_visible = true;
+                }
+                ui.text(format!(
+                    "readbacks in flight: {} (F12 to take a screenshot)",
+                    self.in_flight_readbacks
+                ));
+                {
+                    let scene_renderer = self.scene_renderer.borrow();
+                    ui.text(format!(
+                        "draw recording: {} us ({})",
+                        scene_renderer.get_draw_recording_time().as_micros(),
+                        if scene_renderer.is_command_buffer_reuse_enabled() {
+                            "reused"
+                        } else {
+                            "recorded every frame"
+                        }
+                    ));
+                }
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("CPU profiler"), &mut self.profiler_visible);
+                match self.get_cursor_ndc() {
+                    Some(ndc) => ui.text(format!("cursor: ({:.3}, {:.3}) in NDC", ndc.x, ndc.y)),
+                    None => ui.text("cursor: outside the scene"),
+                }
+                let draw_call_groups = self.scene_renderer.borrow().get_draw_call_groups();
+                ui.text(format!(
+                    "draw calls: {} for {} groups",
+                    draw_call_groups.len(),
+                    draw_call_groups.iter().map(Vec::len).sum::<usize>()
+                ));
+                ui.text(format!("objects in view: {}", self.objects_in_view));
+                ui.text(format!(
+                    "shader permutations: {}",
+                    self.scene_renderer
+                        .borrow()
+                        .get_live_shader_permutations()
+                        .join(", ")
+                ));
+                if CollapsingHeader::new(im_str!("draw calls")).build(ui) {
+                    let index_types = self.scene_renderer.borrow().get_draw_call_index_types();
+                    for (i, (groups, index_type)) in
+                        draw_call_groups.iter().zip(index_types).enumerate()
+                    {
+                        let names: Vec<_> =
+                            groups.iter().map(|group| group.name.as_str()).collect();
+                        let triangle_count: u32 = groups
+                            .iter()
+                            .map(|group| group.indices.len() as u32 / 3)
+                            .sum();
+                        ui.text(format!(
+                            "{}: {} triangles in {}, {} indices",
+                            i,
+                            triangle_count,
+                            names.join(", "),
+                            index_type
+                        ));
+                    }
+                }
+                if CollapsingHeader::new(im_str!("materials")).build(ui) {
+                    let materials = self.scene_renderer.borrow().materials();
+                    for material in materials {
+                        let diffuse = match (&material.texture_path, material.kd) {
+                            (Some(path), _) => format!("texture {}", path.display()),
+                            (None, Some(kd)) => format!("kd {:?}", kd),
+                            (None, None) => "no diffuse".to_owned(),
+                        };
+                        ui.text(format!(
+                            "{}{}: {}, ks {:?}",
+                            material.name,
+                            if material.is_textured() {
+                                " (textured)"
+                            } else {
+                                ""
+                            },
+                            diffuse,
+                            material.ks
+                        ));
+                        if material.is_textured() {
+                            self.build_uv_animation_editor(ui, &material.name, &mut errors);
+                        }
+                        self.build_double_sided_override(ui, &material.name, &mut errors);
+                    }
+                }
+                ui.checkbox(im_str!("freeze scene"), &mut self.scene_frozen);
+                let mut step_mode = self.scene_clock.is_step_mode();
+                if ui.checkbox(
+                    &im_str!("step mode ({} to step)", get_key_name(STEP_KEY)),
+                    &mut step_mode,
+                ) {
+                    self.scene_clock.set_step_mode(step_mode);
+                }
+                if step_mode {
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("step")) {
+                        self.scene_clock.step();
+                    }
+                }
+                ui.text(format!(
+                    "frame {}, scene time {:.3} s",
+                    self.scene_clock.get_frame(),
+                    self.scene_clock.get_time().as_secs_f32()
+                ));
+                ui.checkbox(
+                    im_str!("orthographic camera"),
+                    &mut self.orthographic_camera,
+                );
+                if !self.orthographic_camera {
+                    let mut fov = self.fov.to_degrees();
+                    if Slider::new(im_str!("fov"))
+                        .range(MIN_FOV.to_degrees()..=MAX_FOV.to_degrees())
+                        .display_format(im_str!("%.1f deg"))
+                        .build(ui, &mut fov)
+                    {
+                        if let Err(e) = self.set_fov(Angle::degrees(fov)) {
+                            errors.push(e);
+                        }
+                    }
+                }
+                ui.checkbox(im_str!("clamp camera pitch"), &mut self.clamp_camera_pitch);
+                ui.same_line(0.0);
+                // undoes the roll
+                if ui.small_button(im_str!("level")) {
+                    if let Err(e) = self
+                        .get_camera_mut()
+                        .and_then(|camera| camera.set_up(&vec3(0.0, 1.0, 0.0)))
+                        .chain_err(|| "fail to level the camera")
+                    {
+                        errors.push(e);
+                    }
+                }
+                if ui.small_button(im_str!("save camera")) {
+                    if let Err(e) = self.save_camera() {
+                        errors.push(e);
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("load camera")) {
+                    if let Err(e) = self.load_camera() {
+                        errors.push(e);
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("import camera matrices")) {
+                    if let Err(e) = self.import_camera_matrices() {
+                        errors.push(e);
+                    }
+                }
+                if let Err(e) = self.build_scene_units_combo(ui) {
+                    errors.push(e);
+                }
+                let unit = self.scene_units.get_symbol();
+                Slider::new(im_str!("camera speed"))
+                    .range(0.1..=20.0)
+                    .flags(SliderFlags::LOGARITHMIC)
+                    .display_format(im_str!("%.2f m/s"))
+                    .build(ui, &mut self.camera_speed);
+                ui.text(format!("camera speed {:.3} {}/s", self.get_speed(), unit));
+                ui.checkbox(im_str!("orbit camera"), &mut self.orbit_camera);
+                if self.orbit_camera {
+                    Drag::new(im_str!("orbit target"))
+                        .speed(self.scene_units.meters_to_units(0.05))
+                        .display_format(&ImString::new(format!("%.2f {}", unit)))
+                        .build_array(ui, &mut self.orbit_target);
+                }
+                ui.checkbox(
+                    im_str!("scale camera speed by fov"),
+                    &mut self.scale_speed_by_fov,
+                );
+                ui.checkbox(
+                    im_str!("smooth camera movement"),
+                    &mut self.smooth_camera_movement,
+                );
+                if self.smooth_camera_movement {
+                    Slider::new(im_str!("acceleration time"))
+                        .range(0.02..=1.0)
+                        .display_format(im_str!("%.2f s"))
+                        .build(ui, &mut self.camera_acceleration_time);
+                    Slider::new(im_str!("damping time"))
+                        .range(0.02..=1.0)
+                        .display_format(im_str!("%.2f s"))
+                        .build(ui, &mut self.camera_damping_time);
+                }
+                ui.checkbox(im_str!("auto near and far"), &mut self.auto_near_far);
+                if self.auto_near_far {
+                    if let Some(camera) = self.camera.as_ref() {
+                        let (near, far) = camera.get_near_far();
+                        ui.text(format!(
+                            "near {:.3} {}, far {:.1} {}",
+                            near, unit, far, unit
+                        ));
+                    }
+                } else {
+                    let (mut near, mut far) = (self.near, self.far);
+                    if DragRange::new(im_str!("near and far"))
+                        .range(
+                            self.scene_units.meters_to_units(MIN_AUTO_NEAR)
+                                ..=self.scene_units.meters_to_units(10000.0),
+                        )
+                        .speed(self.scene_units.meters_to_units(0.1))
+                        .display_format(&ImString::new(format!("%.3f {}", unit)))
+                        .flags(SliderFlags::LOGARITHMIC)
+                        .build(ui, &mut near, &mut far)
+                    {
+                        // the drag allows them to be equal
+                        if let Err(e) = self.set_manual_near_far(near, far.max(near * 1.01)) {
+                            errors.push(e);
+                        }
+                    }
+                }
+                ui.checkbox(im_str!("scene in a window"), &mut self.scene_in_window);
+                for (i, mode) in RenderMode::ALL.iter().enumerate() {
+                    if i > 0 {
+                        ui.same_line(0.0);
+                    }
+                    ui.radio_button(
+                        &ImString::new(mode.get_name()),
+                        &mut self.render_mode,
+                        *mode,
+                    );
+                }
+                ui.checkbox(
+                    im_str!("luminance histogram"),
+                    &mut self.luminance_histogram_visible,
+                );
+                ui.checkbox(im_str!("pixel probe"), &mut self.pixel_probe_visible);
+                if CollapsingHeader::new(im_str!("point lights")).build(ui) {
+                    if let Err(e) = self.build_point_lights_section(ui) {
+                        errors.push(e);
+                    }
+                }
+                let mut light_marker_unlit = self.scene_renderer.borrow().is_light_marker_unlit();
+                if ui.checkbox(im_str!("unlit light marker"), &mut light_marker_unlit) {
+                    self.scene_renderer
+                        .borrow_mut()
+                        .set_light_marker_unlit(light_marker_unlit);
+                }
+                // unchecked, the linear colors are shown as they are, to compare the two
+                let mut gamma_correction = self.scene_renderer.borrow().is_gamma_correction();
+                if ui.checkbox(im_str!("gamma correction"), &mut gamma_correction) {
+                    self.scene_renderer
+                        .borrow_mut()
+                        .set_gamma_correction(gamma_correction);
+                }
+                let mut ground_plane = self.scene_renderer.borrow().is_ground_plane();
+                if ui.checkbox(im_str!("ground plane"), &mut ground_plane) {
+                    if let Err(e) = self
+                        .scene_renderer
+                        .borrow_mut()
+                        .set_ground_plane(ground_plane)
+                        .chain_err(|| "fail to set the ground plane")
+                    {
+                        errors.push(e);
+                    }
+                }
+                // the first point light casts the shadows, unless the draws are reused
+                let shadow_config = self.scene_renderer.borrow().get_shadow_config();
+                let mut bias = shadow_config.bias;
+                let mut shadow_config_changed = Slider::new(im_str!("shadow bias"))
+                    .range(0.0..=0.05)
+                    .display_format(im_str!("%.4f"))
+                    .build(ui, &mut bias);
+                let (mut filter, mut kernel_radius) = match shadow_config.filter {
+                    ShadowFilter::Hard => (0, 1),
+                    ShadowFilter::Pcf { kernel_radius } => (1, kernel_radius),
+                    ShadowFilter::PoissonPcf { kernel_radius } => (2, kernel_radius),
+                };
+                shadow_config_changed |= ComboBox::new(im_str!("shadow filter")).build_simple(
+                    ui,
+                    &mut filter,
+                    &["hard", "PCF", "Poisson PCF"],
+                    &|name| Cow::Owned(ImString::new(*name)),
+                );
+                if filter != 0 {
+                    shadow_config_changed |= Slider::new(im_str!("PCF kernel radius"))
+                        .range(1..=MAX_PCF_KERNEL_RADIUS)
+                        .build(ui, &mut kernel_radius);
+                }
+                let filter = match filter {
+                    1 => ShadowFilter::Pcf { kernel_radius },
+                    2 => ShadowFilter::PoissonPcf { kernel_radius },
+                    _ => ShadowFilter::Hard,
+                };
+                match filter {
+                    ShadowFilter::Hard => {}
+                    ShadowFilter::Pcf { .. } => {
+                        ui.text(format!("{0}x{0} samples", filter.get_kernel_size()));
+                    }
+                    ShadowFilter::PoissonPcf { .. } => {
+                        let poisson_disk_size = self
+                            .scene_renderer
+                            .borrow()
+                            .get_noise()
+                            .get_poisson_disk_size();
+                        ui.text(format!(
+                            "{} samples",
+                            filter.get_tap_count(poisson_disk_size)
+                        ));
+                    }
+                }
+                if shadow_config_changed {
+                    if let Err(e) = self
+                        .scene_renderer
+                        .borrow_mut()
+                        .set_shadow_config(ShadowConfig { bias, filter })
+                        .chain_err(|| "fail to set the shadows")
+                    {
+                        errors.push(e);
+                    }
+                }
+                // widens the highlights where the normal varies within a pixel, 0 disables it
+                let mut specular_aa_strength =
+                    self.scene_renderer.borrow().get_specular_aa_strength();
+                if Slider::new(im_str!("specular anti-aliasing"))
+                    .range(0.0..=MAX_SPECULAR_AA_STRENGTH)
+                    .build(ui, &mut specular_aa_strength)
+                {
+                    if let Err(e) = self
+                        .scene_renderer
+                        .borrow_mut()
+                        .set_specular_aa_strength(specular_aa_strength)
+                        .chain_err(|| "fail to set the specular anti-aliasing")
+                    {
+                        errors.push(e);
+                    }
+                }
+                let specular_model = self.scene_renderer.borrow().get_specular_model();
+                let mut specular_model_index = SpecularModel::ALL
+                    .iter()
+                    .position(|model| *model == specular_model)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("specular model")).build_simple(
+                    ui,
+                    &mut specular_model_index,
+                    &SpecularModel::ALL,
+                    &|model| Cow::Owned(ImString::new(model.get_name())),
+                ) {
+                    self.scene_renderer
+                        .borrow_mut()
+                        .set_specular_model(SpecularModel::ALL[specular_model_index]);
+                }
+                // the Ns of every material is replaced while it's checked
+                let shininess_override = self.scene_renderer.borrow().get_shininess_override();
+                let mut override_shininess = shininess_override.is_some();
+                let mut shininess = shininess_override.unwrap_or(DEFAULT_SHININESS);
+                let mut shininess_changed =
+                    ui.checkbox(im_str!("override shininess"), &mut override_shininess);
+                if override_shininess {
+                    shininess_changed |= Slider::new(im_str!("shininess"))
+                        .range(MIN_SHININESS..=MAX_SHININESS)
+                        .flags(SliderFlags::LOGARITHMIC)
+                        .build(ui, &mut shininess);
+                }
+                if shininess_changed {
+                    if let Err(e) = self
+                        .scene_renderer
+                        .borrow_mut()
+                        .set_shininess_override(Some(shininess).filter(|_| override_shininess))
+                        .chain_err(|| "fail to set the shininess")
+                    {
+                        errors.push(e);
+                    }
+                }
+                let mut flare = self.scene_renderer.borrow().get_flare_settings();
+                let mut flare_changed = ui.checkbox(im_str!("light flare"), &mut flare.enabled);
+                if flare.enabled {
+                    let mut texture = FlareTexture::ALL
+                        .iter()
+                        .position(|texture| *texture == flare.texture)
+                        .unwrap_or(0);
+                    if ComboBox::new(im_str!("flare texture")).build_simple(
+                        ui,
+                        &mut texture,
+                        &FlareTexture::ALL,
+                        &|texture| Cow::Owned(ImString::new(texture.get_name())),
+                    ) {
+                        flare.texture = FlareTexture::ALL[texture];
+                        flare_changed = true;
+                    }
+                    flare_changed |= Slider::new(im_str!("flare size"))
+                        .range(0.05..=2.0)
+                        .build(ui, &mut flare.size);
+                }
+                if flare_changed {
+                    if let Err(e) = self.scene_renderer.borrow_mut().set_flare_settings(flare) {
+                        errors.push(e.chain_err(|| "fail to change the light flare"));
+                    }
+                }
+                if CollapsingHeader::new(im_str!("mouse look")).build(ui) {
+                    if let Err(e) = self.build_mouse_look_section(ui) {
+                        errors.push(e);
+                    }
+                }
+                if CollapsingHeader::new(im_str!("key bindings")).build(ui) {
+                    if let Err(e) = self.build_key_bindings_section(ui) {
+                        errors.push(e);
+                    }
+                } else {
+                    self.rebinding_direction = None;
+                    self.rebinding_quit_key = false;
+                }
+                if CollapsingHeader::new(im_str!("UI appearance")).build(ui) {
+                    if let Err(e) = self.build_ui_appearance_section(ui) {
+                        errors.push(e);
+                    }
+                }
+                ui.checkbox(im_str!("hemisphere ambient"), &mut self.ambient.enabled);
+                if self.ambient.enabled {
+                    ColorEdit::new(im_str!("sky"), &mut self.ambient.sky_color).build(ui);
+                    ColorEdit::new(im_str!("ground"), &mut self.ambient.ground_color).build(ui);
+                    Slider::new(im_str!("ambient intensity"))
+                        .range(0.0..=2.0)
+                        .build(ui, &mut self.ambient.intensity);
+                }
+                ui.checkbox(im_str!("sun"), &mut self.sun_enabled);
+                if self.sun_enabled {
+                    Slider::new(im_str!("sun azimuth"))
+                        .range(-180.0..=180.0)
+                        .display_format(im_str!("%.0f deg"))
+                        .build(ui, &mut self.sun_azimuth);
+                    Slider::new(im_str!("sun elevation"))
+                        .range(-90.0..=90.0)
+                        .display_format(im_str!("%.0f deg"))
+                        .build(ui, &mut self.sun_elevation);
+                    ColorEdit::new(im_str!("sun color"), &mut self.sun_color).build(ui);
+                    Slider::new(im_str!("sun intensity"))
+                        .range(0.0..=5.0)
+                        .build(ui, &mut self.sun_intensity);
+                }
+                ColorEdit::new(im_str!("background"), &mut self.background_color).build(ui);
+                if ui.small_button(im_str!("load skybox")) {
+                    if let Some(dir) = select_skybox_folder() {
+                        if let Err(e) = find_skybox_faces(&dir)
+                            .and_then(|faces| self.scene_renderer.borrow_mut().load_skybox(faces))
+                            .chain_err(|| format!("fail to load the skybox in {}", dir.display()))
+                        {
+                            errors.push(e);
+                        }
+                    }
+                }
+                if let Some(faces) = self.scene_renderer.borrow().get_skybox_paths() {
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("clear skybox")) {
+                        self.scene_renderer.borrow_mut().clear_skybox();
+                    }
+                    if let Some(dir) = faces[0].parent() {
+                        ui.text(format!("skybox: {}", dir.display()));
+                    }
+                    let mut diffuse_ibl = self.scene_renderer.borrow().is_diffuse_ibl();
+                    if ui.checkbox(im_str!("diffuse IBL"), &mut diffuse_ibl) {
+                        self.scene_renderer
+                            .borrow_mut()
+                            .set_diffuse_ibl(diffuse_ibl);
+                    }
+                    let mut ambient_strength = self.scene_renderer.borrow().get_ambient_strength();
+                    if Slider::new(im_str!("ambient strength"))
+                        .range(0.0..=MAX_AMBIENT_STRENGTH)
+                        .build(ui, &mut ambient_strength)
+                    {
+                        if let Err(e) = self
+                            .scene_renderer
+                            .borrow_mut()
+                            .set_ambient_strength(ambient_strength)
+                            .chain_err(|| "fail to set the ambient strength")
+                        {
+                            errors.push(e);
+                        }
+                    }
+                }
                 if ui.small_button(im_str!("togle color picker")) {
                     self.color_picker_visible = !self.color_picker_visible;
                 }
@@ -144,27 +1152,114 @@ impl support::ApplicationT for Application {
                     self.color[0], self.color[1], self.color[2]
                 ));
 
-                if ui.small_button(im_str!("select model files")) {
-                    let res = select_model_and_texture_files()
-                        .chain_err(|| "fail to load the model file or the texture file");
-                    match res {
-                        Ok(Some(model_and_texture)) => {
-                            if let Err(ref e) = self
-                                .scene_renderer
-                                .borrow_mut()
-                                .load_model_and_texture(model_and_texture)
-                            {
-                                eprint_chained_err(e);
-                            }
-                        }
-                        Ok(None) => (), /* do nothing, the user cancel the operation */
-                        Err(ref e) => eprint_chained_err(e),
+                ui.checkbox(im_str!("batch small groups"), &mut self.batch_small_groups);
+                if self.batch_small_groups {
+                    ui.input_int(im_str!("triangle threshold"), &mut self.batch_threshold)
+                        .build();
+                    self.batch_threshold = self.batch_threshold.max(1);
+                }
+                ui.checkbox(im_str!("flip normals"), &mut self.flip_normals);
+                if self.scene_renderer.borrow().is_preparing() {
+                    ui.text("preparing\u{2026}");
+                } else if uploading_objects > 0 {
+                    let (in_flight, max_in_flight) =
+                        self.scene_renderer.borrow_mut().get_uploads_in_flight();
+                    ui.text(format!(
+                        "uploading {} objects\u{2026} ({}/{} uploads in flight)",
+                        uploading_objects, in_flight, max_in_flight
+                    ));
+                } else if ui.small_button(im_str!("select model files")) {
+                    // nothing to do if the user cancels the operation
+                    if let Some(path) = select_model_file() {
+                        let options = self
+                            .settings
+                            .import_options
+                            .get(&path)
+                            .cloned()
+                            .unwrap_or_default();
+                        self.pending_import = Some((path, options));
+                        ui.open_popup(im_str!("import options"));
+                    }
+                }
+                if let Err(e) = self.build_import_options_popup(ui) {
+                    errors.push(e);
+                }
+                if let Err(e) = self.build_resource_failures_section(ui) {
+                    errors.push(e);
+                }
+                self.build_material_warnings_section(ui);
+                {
+                    let scene_renderer = self.scene_renderer.borrow();
+                    let noise = scene_renderer.get_noise();
+                    ui.text(format!(
+                        "noise seed: {} (hash {:016x})",
+                        noise.get_seed(),
+                        noise.content_hash()
+                    ));
+                    let [jitter_x, jitter_y] = noise.jitter(self.scene_clock.get_frame());
+                    ui.text(format!("frame jitter: ({:.3}, {:.3})", jitter_x, jitter_y));
+                }
+                ui.checkbox(im_str!("denoise"), &mut self.denoise_path_traced_image);
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("path trace")) {
+                    if let Err(e) = self
+                        .save_path_traced_image()
+                        .chain_err(|| "fail to save the path traced image")
+                    {
+                        errors.push(e);
+                    }
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("render")) {
+                    if let Err(e) = self
+                        .save_rendered_image()
+                        .chain_err(|| "fail to save the rendered image")
+                    {
+                        errors.push(e);
                     }
                 }
                 if let Some(ref model_path) = self.model_path {
-                    ui.text(format!("model path: {}", model_path));
+                    ui.text(format!("model path: {}", model_path.display()));
                 }
             });
+        self.build_objects_window(ui, &mut errors);
+        self.build_scene_window(ui);
+        self.build_luminance_histogram_window(ui);
+        self.build_pixel_probe_window(ui);
+        self.build_quit_confirmation_popup(ui);
+        self.build_help_window(ui);
+        self.build_profiler_window(ui);
+        for e in errors {
+            self.report_error(e);
+        }
+        if let Some(ref toast) = self.error_toast {
+            if toast.created.elapsed() < ERROR_TOAST_DURATION {
+                Window::new(im_str!("error"))
+                    .position([10.0, 130.0], Condition::FirstUseEver)
+                    .always_auto_resize(true)
+                    .build(ui, || ui.text_colored(toast.color, &toast.message));
+            } else {
+                self.error_toast = None;
+            }
+        }
+        if let Some(created) = self.help_hint_created {
+            if created.elapsed() < HELP_HINT_DURATION && !self.help_visible {
+                let [_, display_height] = ui.io().display_size;
+                Window::new(im_str!("hint"))
+                    .position([10.0, display_height - 10.0], Condition::Always)
+                    .position_pivot([0.0, 1.0])
+                    .always_auto_resize(true)
+                    .title_bar(false)
+                    .build(ui, || {
+                        ui.text(format!(
+                            "press {} or the ? button for the controls",
+                            get_key_name(HELP_KEY)
+                        ))
+                    });
+            } else {
+                self.help_hint_created = None;
+            }
+        }
         if self.color_picker_visible {
             let editable_color: EditableColor = (&mut self.color).into();
             let cp = ColorPicker::new(im_str!("color_picker"), editable_color);
@@ -174,6 +1269,79 @@ impl support::ApplicationT for Application {
         Ok(())
     }
 
+    // in the step mode, the scene is only rendered in the frames stepped
+    fn is_scene_frozen(&self) -> bool {
+        (self.scene_frozen || self.scene_clock.is_step_mode()) && !self.scene_clock.is_stepped()
+    }
+
+    fn take_screenshot_request(&mut self) -> Option<PathBuf> {
+        if !std::mem::take(&mut self.screenshot_requested) {
+            return None;
+        }
+        Some(self.next_screenshot_path("screenshot"))
+    }
+
+    fn set_in_flight_readbacks(&mut self, count: usize) {
+        self.in_flight_readbacks = count;
+    }
+
+    fn on_background_error(&mut self, e: Error) {
+        self.report_error(e);
+    }
+
+    fn on_close_requested(&mut self) -> bool {
+        self.request_quit();
+        self.quit_requested
+    }
+
+    fn is_quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    fn set_scene_texture(&mut self, texture_id: TextureId, dimensions: [u32; 2]) {
+        self.scene_texture = Some((texture_id, dimensions));
+    }
+
+    fn is_scene_in_window(&self) -> bool {
+        self.scene_in_window
+    }
+
+    fn get_scene_view_dimensions(&self) -> Option<[u32; 2]> {
+        self.scene_view_size.get()
+    }
+
+    fn get_ui_appearance(&self) -> UiAppearance {
+        self.settings.ui_appearance
+    }
+
+    fn is_luminance_histogram_enabled(&self) -> bool {
+        self.luminance_histogram_visible
+    }
+
+    fn set_luminance_stats(&mut self, stats: LuminanceStats) {
+        self.luminance_stats = Some(stats);
+    }
+
+    // the pixel of the scene image under the cursor, unless the UI is over it
+    fn get_probed_pixel(&self) -> Option<[u32; 2]> {
+        if !self.pixel_probe_visible
+            || !self.is_scene_view_hovered()
+            || (self.ui_wants_mouse && !self.scene_in_window)
+        {
+            return None;
+        }
+        let physical = self
+            .viewport_mapper
+            .logical_to_physical(point2(self.cursor_position.x, self.cursor_position.y));
+        let pixel = self.viewport_mapper.physical_to_render_target(physical)?;
+        Some([pixel.x as u32, pixel.y as u32])
+    }
+
+    fn set_probed_pixel(&mut self, probed: ProbedPixel) {
+        self.probed_pixel = Some(probed);
+    }
+
+    // the delta is the raw mouse motion, which isn't affected by the scale factor
     fn on_mouse_move(&mut self, (delta_x, delta_y): (f64, f64)) -> Result<()> {
         if let Some(location) = self.cursor_lock_position {
             self.surface
@@ -182,34 +1350,98 @@ impl support::ApplicationT for Application {
                 .chain_err(|| "fail to set cursor position when trying to lock the cursor")?;
         }
 
+        // in radians per physical pixel, dragging right turns the model to the right
+        const ORBIT_SPEED: f32 = 0.005;
+        let (yaw, pitch) = (
+            Angle::radians(-delta_x as f32 * ORBIT_SPEED),
+            Angle::radians(delta_y as f32 * ORBIT_SPEED),
+        );
+        let pivot = if self.mouse_left_button_held {
+            Some(self.get_look_target())
+        } else if self.mouse_middle_button_held && self.orbit_camera {
+            let [x, y, z] = self.orbit_target;
+            Some(point3(x, y, z))
+        } else {
+            None
+        };
+        if let Some(pivot) = pivot {
+            // there is no sphere to orbit on at the pivot, wait for the camera to be moved away
+            // instead of failing in the middle of the drag
+            let camera = self.get_camera_mut().chain_err(|| "fail to get camera")?;
+            if (camera.get_position() - pivot).length() < f32::approx_epsilon() {
+                return Ok(());
+            }
+            return self
+                .orbit_camera(pivot, yaw, pitch)
+                .chain_err(|| format!("fail to orbit the camera around {:?}", pivot));
+        }
         if !self.mouse_middle_button_held {
             return Ok(());
         }
-        const ROTATION_SPEED: f32 = 0.001;
-        let mut delta: Vector2D<f32, ViewSpace> =
-            vec2(delta_x as f32, -delta_y as f32) * ROTATION_SPEED;
-        if delta.length() > 1.0 {
-            delta = delta.normalize();
+        let (yaw, pitch) = self
+            .settings
+            .mouse_look
+            .get_yaw_and_pitch((delta_x, delta_y));
+        self.turn_camera(yaw, pitch)
+            .chain_err(|| "fail to turn the camera with the middle button held")
+    }
+
+    fn on_mouse_wheel(&mut self, delta: f32) -> Result<()> {
+        // scrolling the UI over the scene shouldn't move the camera, the scene in a UI window
+        // always takes the mouse of the UI
+        if !self.is_scene_view_hovered() || (self.ui_wants_mouse && !self.scene_in_window) {
+            return Ok(());
         }
-        self.rotate_camera_to(delta.to_point())
-            .chain_err(|| "fail to rotate camera with the middle button held")?;
-        Ok(())
+        const ZOOM_PER_LINE: f32 = 0.9;
+        if !self.ctrl_held {
+            let target = self.get_look_target();
+            let min_distance = self.scene_units.meters_to_units(MIN_DOLLY_DISTANCE);
+            return self
+                .dolly_camera(target, delta, ZOOM_PER_LINE, min_distance)
+                .chain_err(|| format!("fail to dolly the camera towards {:?}", target));
+        }
+        // the orthographic camera has no fov to zoom
+        if self.orthographic_camera {
+            return Ok(());
+        }
+        let fov = Angle::radians(
+            (self.fov.radians * ZOOM_PER_LINE.powf(delta)).clamp(MIN_FOV.radians, MAX_FOV.radians),
+        );
+        self.set_fov(fov).chain_err(|| "fail to zoom the camera")
     }
 
     fn on_mouse_button(&mut self, button: WinitMouseButton, state: ElementState) -> Result<()> {
-        let window = self.surface.window();
         match button {
             WinitMouseButton::Middle => match state {
+                ElementState::Pressed if !self.is_scene_view_hovered() => Ok(()),
                 ElementState::Pressed => {
                     self.mouse_middle_button_held = true;
-                    self.cursor_lock_position.replace(self.cursor_position);
-                    window.set_cursor_visible(false);
+                    self.lock_cursor();
                     Ok(())
                 }
                 ElementState::Released => {
                     self.mouse_middle_button_held = false;
-                    self.cursor_lock_position = None;
-                    window.set_cursor_visible(true);
+                    self.unlock_cursor();
+                    Ok(())
+                }
+            },
+            WinitMouseButton::Left => match state {
+                // clicking the UI over the scene shouldn't orbit it, the scene in a UI window
+                // always takes the mouse of the UI
+                ElementState::Pressed
+                    if !self.is_scene_view_hovered()
+                        || (self.ui_wants_mouse && !self.scene_in_window) =>
+                {
+                    Ok(())
+                }
+                ElementState::Pressed => {
+                    self.mouse_left_button_held = true;
+                    self.lock_cursor();
+                    Ok(())
+                }
+                ElementState::Released => {
+                    self.mouse_left_button_held = false;
+                    self.unlock_cursor();
                     Ok(())
                 }
             },
@@ -220,86 +1452,1144 @@ impl support::ApplicationT for Application {
 
 impl CameraControl for Application {
     fn get_camera_mut(&mut self) -> Result<&mut Camera> {
-        let inner_size = self.surface.window().inner_size();
-        let aspect_ratio = (inner_size.width as f32) / (inner_size.height as f32);
-        let fov = Angle::pi() / 4.0;
-        let near = 1.0;
-        let far = 100.0;
-        let up = vec3(0.0, 1.0, 0.0);
-
-        let camera = match self.camera.take() {
-            Some(camera) if !camera.get_aspect_ratio().approx_eq(&aspect_ratio) => {
-                let position = camera.get_position();
-                Camera::new(
-                    fov,
-                    aspect_ratio,
+        // the scene image may not have the size of the window when it's shown in a UI window
+        let [width, height] = match self.scene_texture {
+            Some((_, dimensions)) => dimensions,
+            None => {
+                let inner_size = self.surface.window().inner_size();
+                [inner_size.width, inner_size.height]
+            }
+        };
+        let aspect_ratio = (width as f32) / (height as f32);
+        let orthographic = self.orthographic_camera;
+        let fov = self.fov;
+        let (near, far) = match self.camera.as_ref() {
+            Some(camera) => camera.get_near_far(),
+            None => (self.near, self.far),
+        };
+        let create_camera = |position: &Point3D<f32, WorldSpace>,
+                             look_at: &Point3D<f32, WorldSpace>,
+                             up: &Vector3D<f32, WorldSpace>| {
+            if orthographic {
+                // 10 units high, about what the perspective camera sees at the model
+                let half_height = 5.0;
+                let half_width = half_height * aspect_ratio;
+                Camera::new_orthographic(
+                    &Box2D::new(
+                        point2(-half_width, -half_height),
+                        point2(half_width, half_height),
+                    ),
                     near,
                     far,
+                    position,
+                    look_at,
+                    up,
+                )
+            } else {
+                Camera::new(fov, aspect_ratio, near, far, position, look_at, up)
+            }
+        };
+
+        let mut camera = match self.camera.take() {
+            Some(camera) if camera.is_orthographic() != orthographic => {
+                // keeps the roll
+                let position = camera.get_position();
+                create_camera(
                     &position,
                     &(position + camera.get_direction()),
-                    &up,
+                    &camera.get_up(),
                 )
-                .chain_err(|| "fail to re-create camera for app state when aspect ratio changes")?
+                .chain_err(|| "fail to re-create camera for app state when projection changes")?
+            }
+            Some(mut camera) => {
+                if !camera.get_aspect_ratio().approx_eq(&aspect_ratio) {
+                    camera
+                        .set_aspect_ratio(aspect_ratio)
+                        .chain_err(|| "fail to set the aspect ratio of the camera")?;
+                }
+                if matches!(camera.get_fov(), Some(camera_fov) if camera_fov != fov) {
+                    camera
+                        .set_fov(fov)
+                        .chain_err(|| "fail to set the fov of the camera")?;
+                }
+                camera
             }
-            Some(camera) => camera,
-            None => Camera::new(
-                fov,
-                aspect_ratio,
-                near,
-                far,
+            None => create_camera(
                 &point3(0.0, 0.0, 5.0),
                 &point3(0.0, 0.0, 0.0),
                 &vec3(0.0, 1.0, 0.0),
             )
             .chain_err(|| "fail to initialize camera for app state")?,
         };
+        let max_pitch = if self.clamp_camera_pitch {
+            Some(MAX_CAMERA_PITCH)
+        } else {
+            None
+        };
+        if camera.get_max_pitch() != max_pitch {
+            camera
+                .set_max_pitch(max_pitch)
+                .chain_err(|| "fail to set the max pitch of the camera")?;
+        }
         self.camera.replace(camera);
         Ok(self.camera.as_mut().unwrap())
     }
 
     fn get_speed(&self) -> f32 {
-        self.camera_speed
+        self.scene_units.meters_to_units(self.camera_speed)
+    }
+
+    fn is_speed_scaled_by_fov(&self) -> bool {
+        self.scale_speed_by_fov
+    }
+
+    fn get_smoothing(&self) -> Option<(Duration, Duration)> {
+        if self.smooth_camera_movement {
+            Some((
+                Duration::from_secs_f32(self.camera_acceleration_time),
+                Duration::from_secs_f32(self.camera_damping_time),
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn get_velocity(&self) -> Vector3D<f32, WorldSpace> {
+        self.camera_velocity
+    }
+
+    fn set_velocity(&mut self, velocity: Vector3D<f32, WorldSpace>) {
+        self.camera_velocity = velocity;
     }
 }
 
 impl Application {
+    fn report_error(&mut self, e: Error) {
+        eprint_chained_err(&e);
+        self.error_toast = Some(ErrorToast {
+            message: e
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\ncaused by: "),
+            color: error_color(find_error_kind(&e)),
+            created: Instant::now(),
+        });
+    }
+
+    fn get_cursor_ndc(&self) -> Option<Point2D<f32, NDCSpace>> {
+        let cursor: Point2D<f64, LogicalPixelSpace> =
+            point2(self.cursor_position.x, self.cursor_position.y);
+        self.viewport_mapper.logical_to_ndc(cursor)
+    }
+
+    // the model turns around the y axis over the scene time
+    fn get_model_transform(&self) -> Transform3D<f32, TriangleSpace, WorldSpace> {
+        let speed = Angle::pi() / 10.0;
+        Transform3D::identity()
+            .then_translate(vec3(0.0, -2.0, 0.0))
+            .then_rotate(
+                0.0,
+                1.0,
+                0.0,
+                speed * self.scene_clock.get_time().as_secs_f32(),
+            )
+    }
+
+    // hides the cursor and keeps it in place while dragging the camera
+    fn lock_cursor(&mut self) {
+        let position = self
+            .viewport_mapper
+            .logical_to_physical(point2(self.cursor_position.x, self.cursor_position.y));
+        self.cursor_lock_position
+            .replace(PhysicalPosition::new(position.x, position.y));
+        self.surface.window().set_cursor_visible(false);
+    }
+
+    // until both buttons dragging the camera are released
+    fn unlock_cursor(&mut self) {
+        if self.mouse_left_button_held || self.mouse_middle_button_held {
+            return;
+        }
+        self.cursor_lock_position = None;
+        self.surface.window().set_cursor_visible(true);
+    }
+
+    // Applied to the camera right away, so that an invalid fov is reported and the last valid one
+    // kept, instead of failing the next frame. Only the perspective cameras have a fov.
+    fn set_fov(&mut self, fov: Angle<f32>) -> Result<()> {
+        let camera = self.get_camera_mut().chain_err(|| "fail to get camera")?;
+        if !camera.is_orthographic() {
+            camera
+                .set_fov(fov)
+                .chain_err(|| format!("fail to set the fov to {} degrees", fov.to_degrees()))?;
+        }
+        self.fov = fov;
+        Ok(())
+    }
+
+    // see set_fov, used unless the near and far are fit to the scene
+    fn set_manual_near_far(&mut self, near: f32, far: f32) -> Result<()> {
+        self.get_camera_mut()
+            .chain_err(|| "fail to get camera")?
+            .set_near_far(near, far)
+            .chain_err(|| format!("fail to set the near {} and the far {}", near, far))?;
+        self.near = near;
+        self.far = far;
+        Ok(())
+    }
+
+    // what the left button orbits around and the mouse wheel dollies towards, the center of the
+    // model, or the orbit target without any model
+    fn get_look_target(&self) -> Point3D<f32, WorldSpace> {
+        let [x, y, z] = self.orbit_target;
+        self.scene_renderer
+            .borrow()
+            .get_model_center(&self.get_model_transform())
+            .unwrap_or_else(|| point3(x, y, z))
+    }
+
+    // always true when the scene is behind the UI
+    fn is_scene_view_hovered(&self) -> bool {
+        !self.scene_in_window || self.scene_view_hovered
+    }
+
+    fn is_scene_view_focused(&self) -> bool {
+        !self.scene_in_window || self.scene_view_focused
+    }
+
+    // the scene image fills the window, and is rendered at the size of the window once it stops
+    // changing, stretched in the meantime
+    fn build_scene_window(&mut self, ui: &Ui) {
+        self.scene_view_hovered = false;
+        self.scene_view_focused = false;
+        let (texture_id, [width, height]) = match self.scene_texture {
+            Some(scene_texture) if self.scene_in_window => scene_texture,
+            _ => return,
+        };
+        let [scale_x, scale_y] = ui.io().display_framebuffer_scale;
+        let mut scene_view = None;
+        Window::new(im_str!("Scene"))
+            .size([640.0, 360.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let size = ui.content_region_avail();
+                let position = ui.cursor_pos();
+                Image::new(texture_id, size).build(ui);
+                // takes the left drag that orbits the camera, which moves the window otherwise
+                ui.set_cursor_pos(position);
+                ui.invisible_button(im_str!("scene view"), size);
+                scene_view = Some((
+                    size,
+                    ui.item_rect_min(),
+                    ui.item_rect_max(),
+                    ui.is_item_hovered(),
+                    ui.is_window_focused(),
+                ));
+            });
+        let ([view_width, view_height], [min_x, min_y], [max_x, max_y], hovered, focused) =
+            match scene_view {
+                Some(scene_view) => scene_view,
+                None => return,
+            };
+        self.scene_view_hovered = hovered;
+        self.scene_view_focused = focused;
+        self.scene_view_size.update(
+            [
+                ((view_width * scale_x).round() as u32).max(1),
+                ((view_height * scale_y).round() as u32).max(1),
+            ],
+            Instant::now(),
+        );
+        // picking goes through the image rect on the screen
+        let scale_factor = f64::from(scale_x);
+        self.viewport_mapper = ViewportMapper::with_viewport(
+            scale_factor,
+            Box2D::new(
+                point2(f64::from(min_x), f64::from(min_y)) * scale_factor,
+                point2(f64::from(max_x), f64::from(max_y)) * scale_factor,
+            ),
+            size2(width, height),
+        );
+    }
+
+    // the luminance of the scene image, to tune the light intensities against
+    fn build_luminance_histogram_window(&mut self, ui: &Ui) {
+        if !self.luminance_histogram_visible {
+            return;
+        }
+        let stats = &self.luminance_stats;
+        Window::new(im_str!("Luminance"))
+            .size([300.0, 180.0], Condition::FirstUseEver)
+            .opened(&mut self.luminance_histogram_visible)
+            .build(ui, || match stats {
+                Some(stats) => {
+                    ui.plot_histogram(im_str!("##luminance histogram"), &stats.bins)
+                        .scale_min(0.0)
+                        .graph_size([-1.0, 80.0])
+                        .build();
+                    ui.text(format!(
+                        "min {:.3}, avg {:.3}, max {:.3}",
+                        stats.min, stats.average, stats.max
+                    ));
+                    ui.text(format!("clipped: {:.2}%", stats.clipped * 100.0));
+                }
+                None => ui.text("waiting for the first histogram"),
+            });
+    }
+
+    // The shaded color of the pixel under the cursor, read back a frame or more late, and the
+    // position and the normal of the surface there, cast on the CPU since there is no G-buffer.
+    fn build_pixel_probe_window(&mut self, ui: &Ui) {
+        if !self.pixel_probe_visible {
+            return;
+        }
+        let surface = match (self.camera.as_ref(), self.get_cursor_ndc()) {
+            (Some(camera), Some(ndc)) => {
+                self.scene_renderer
+                    .borrow()
+                    .probe_surface(camera, self.get_model_transform(), ndc)
+            }
+            _ => Ok(None),
+        };
+        let probed_pixel = &self.probed_pixel;
+        Window::new(im_str!("Pixel probe"))
+            .size([300.0, 120.0], Condition::FirstUseEver)
+            .opened(&mut self.pixel_probe_visible)
+            .build(ui, || {
+                match probed_pixel {
+                    Some(ProbedPixel { pixel, color }) => {
+                        ui.text(format!("pixel: ({}, {})", pixel[0], pixel[1]));
+                        ui.text(format!(
+                            "linear color: {:.3}, {:.3}, {:.3}",
+                            color[0], color[1], color[2]
+                        ));
+                    }
+                    None => ui.text("waiting for the first pixel"),
+                }
+                match &surface {
+                    Ok(Some((position, normal))) => {
+                        ui.text(format!(
+                            "position: {:.3}, {:.3}, {:.3}",
+                            position.x, position.y, position.z
+                        ));
+                        ui.text(format!(
+                            "normal: {:.3}, {:.3}, {:.3}",
+                            normal.x, normal.y, normal.z
+                        ));
+                    }
+                    Ok(None) => ui.text("no surface under the cursor"),
+                    Err(e) => ui.text(format!("fail to probe the surface: {}", e)),
+                }
+            });
+    }
+
+    // the changes are saved at once
+    fn build_ui_appearance_section(&mut self, ui: &Ui) -> Result<()> {
+        let mut appearance = self.settings.ui_appearance;
+        let mut theme = UiTheme::ALL
+            .iter()
+            .position(|theme| *theme == appearance.theme)
+            .unwrap_or(0);
+        if ComboBox::new(im_str!("theme")).build_simple(ui, &mut theme, &UiTheme::ALL, &|theme| {
+            Cow::Owned(ImString::new(theme.get_name()))
+        }) {
+            appearance.theme = UiTheme::ALL[theme];
+        }
+        Slider::new(im_str!("UI scale"))
+            .range(UI_SCALE_RANGE)
+            .build(ui, &mut self.ui_scale_edit);
+        if ui.is_item_deactivated_after_edit() {
+            appearance.scale = self.ui_scale_edit;
+        }
+        if appearance == self.settings.ui_appearance {
+            return Ok(());
+        }
+        self.settings.ui_appearance = appearance;
+        self.settings
+            .save(Path::new(SETTINGS_PATH))
+            .chain_err(|| "fail to save the settings")
+    }
+
+    // the lights take effect in the next scene state
+    fn build_point_lights_section(&mut self, ui: &Ui) -> Result<()> {
+        let mut removed = None;
+        for (i, light) in self.point_lights.iter_mut().enumerate() {
+            let _id = ui.push_id(i as i32);
+            ui.text(format!("light {}", i));
+            Drag::new(im_str!("position"))
+                .speed(0.05)
+                .build_array(ui, &mut light.position);
+            ColorEdit::new(im_str!("color"), &mut light.color).build(ui);
+            Slider::new(im_str!("intensity"))
+                .range(0.0..=5.0)
+                .build(ui, &mut light.intensity);
+            ui.checkbox(im_str!("orbit"), &mut light.orbit);
+            if ui.small_button(im_str!("remove")) {
+                removed = Some(i);
+            }
+        }
+        if let Some(i) = removed {
+            self.point_lights.remove(i);
+        }
+        if ui.small_button(im_str!("add point light")) {
+            if self.point_lights.len() >= MAX_POINT_LIGHTS {
+                bail!(ErrorKind::Validation(format!(
+                    "at most {} point lights are supported",
+                    MAX_POINT_LIGHTS
+                )));
+            }
+            self.point_lights.push(Default::default());
+        }
+        Ok(())
+    }
+
+    // the changes take effect at once, and are saved when done editing
+    fn build_mouse_look_section(&mut self, ui: &Ui) -> Result<()> {
+        let mouse_look = &mut self.settings.mouse_look;
+        Slider::new(im_str!("sensitivity"))
+            .range(MOUSE_SENSITIVITY_RANGE)
+            .display_format(im_str!("%.3f deg per count"))
+            .flags(SliderFlags::LOGARITHMIC)
+            .build(ui, &mut mouse_look.sensitivity);
+        let mut changed = ui.is_item_deactivated_after_edit();
+        changed |= ui.checkbox(im_str!("invert y"), &mut mouse_look.invert_y);
+        if !changed {
+            return Ok(());
+        }
+        self.settings
+            .save(Path::new(SETTINGS_PATH))
+            .chain_err(|| "fail to save the settings")
+    }
+
+    fn request_quit(&mut self) {
+        if self.confirm_quit && self.scene_edited {
+            self.quit_confirmation_requested = true;
+        } else {
+            self.quit_requested = true;
+        }
+    }
+
+    // centered over everything, and lists the keys currently bound
+    fn build_help_window(&mut self, ui: &Ui) {
+        if !self.help_visible {
+            return;
+        }
+        let [display_width, display_height] = ui.io().display_size;
+        let sections = get_help_sections(&self.settings.key_bindings);
+        Window::new(im_str!("help"))
+            .position(
+                [display_width / 2.0, display_height / 2.0],
+                Condition::Always,
+            )
+            .position_pivot([0.5, 0.5])
+            .bg_alpha(0.8)
+            .always_auto_resize(true)
+            .collapsible(false)
+            .opened(&mut self.help_visible)
+            .build(ui, || {
+                for section in sections.iter() {
+                    ui.text_colored([1.0, 0.8, 0.3, 1.0], section.category);
+                    for (input, action) in section.entries.iter() {
+                        ui.bullet_text(&im_str!("{}: {}", input, action));
+                    }
+                }
+            });
+    }
+
+    // the textures and the meshes the GPU had no memory for, nothing unless some have failed
+    fn build_resource_failures_section(&mut self, ui: &Ui) -> Result<()> {
+        let failures = self.scene_renderer.borrow().get_resource_failures();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let header = im_str!(
+            "{} resources out of GPU memory###failed resources",
+            failures.len()
+        );
+        if !CollapsingHeader::new(&header).build(ui) {
+            return Ok(());
+        }
+        for failure in failures.iter() {
+            let resource = match &failure.resource {
+                FailedResource::Texture { material } => {
+                    format!("texture of {}, drawn with a checkerboard", material)
+                }
+                FailedResource::Mesh { groups } => {
+                    format!("mesh of {}, not drawn", groups.join(", "))
+                }
+            };
+            ui.bullet_text(&im_str!("{}: {}", resource, failure.error));
+        }
+        // once other applications free some memory
+        if ui.small_button(im_str!("retry failed resources")) {
+            self.scene_renderer
+                .borrow_mut()
+                .retry_failed_resources()
+                .chain_err(|| "fail to retry the resources out of GPU memory")?;
+        }
+        Ok(())
+    }
+
+    // the mtl entries drawn differently from what they say, nothing unless there are some
+    fn build_material_warnings_section(&self, ui: &Ui) {
+        let scene_renderer = self.scene_renderer.borrow();
+        let warnings = scene_renderer.get_material_warnings();
+        if warnings.is_empty() {
+            return;
+        }
+        let header = im_str!("{} material warnings###material warnings", warnings.len());
+        if !CollapsingHeader::new(&header).build(ui) {
+            return;
+        }
+        for warning in warnings.iter() {
+            ui.bullet_text(&im_str!("{}", warning));
+        }
+    }
+
+    // The scopes of a model loaded are in the frame it's loaded in, so that e.g. the slowest frame
+    // breaks the load time down.
+    fn build_profiler_window(&mut self, ui: &Ui) {
+        if self.profiler_visible {
+            Window::new(im_str!("CPU profiler"))
+                .size([360.0, 400.0], Condition::FirstUseEver)
+                .opened(&mut self.profiler_visible)
+                .build(ui, || {
+                    let frames = [
+                        (im_str!("last frame"), profile::get_last_frame()),
+                        (
+                            im_str!("slowest of the recent frames"),
+                            profile::get_slowest_frame(),
+                        ),
+                    ];
+                    for (header, frame) in frames.iter() {
+                        if !CollapsingHeader::new(header).default_open(true).build(ui) {
+                            continue;
+                        }
+                        match frame {
+                            Some(frame) => {
+                                let total_us = frame.get_total_us();
+                                ui.text(format!(
+                                    "frame {}: {:.3} ms",
+                                    frame.frame,
+                                    total_us as f64 / 1000.0
+                                ));
+                                build_profile_scopes(ui, &frame.merge_scopes(), total_us);
+                            }
+                            None => ui.text("no frame profiled yet"),
+                        }
+                    }
+                });
+        }
+        profile::set_enabled(ProfileReason::Panel, self.profiler_visible);
+    }
+
+    fn build_quit_confirmation_popup(&mut self, ui: &Ui) {
+        if std::mem::take(&mut self.quit_confirmation_requested) {
+            ui.open_popup(im_str!("quit"));
+        }
+        PopupModal::new(ui, im_str!("quit"))
+            .always_auto_resize(true)
+            .build(|| {
+                ui.text("The loaded models aren't saved. Quit anyway?");
+                if ui.small_button(im_str!("quit")) {
+                    self.quit_requested = true;
+                    ui.close_current_popup();
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("cancel")) {
+                    ui.close_current_popup();
+                }
+            });
+    }
+
+    // The options of the selected model file, remembered for the file once it's imported.
+    fn build_import_options_popup(&mut self, ui: &Ui) -> Result<()> {
+        let mut import = None;
+        PopupModal::new(ui, im_str!("import options"))
+            .always_auto_resize(true)
+            .build(|| {
+                let (path, options) = match self.pending_import.as_mut() {
+                    Some(pending_import) => pending_import,
+                    None => {
+                        ui.close_current_popup();
+                        return;
+                    }
+                };
+                ui.text(format!("{}", path.display()));
+                let mut up_axis = UpAxis::ALL
+                    .iter()
+                    .position(|axis| *axis == options.up_axis)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("up axis")).build_simple(
+                    ui,
+                    &mut up_axis,
+                    &UpAxis::ALL,
+                    &|axis| Cow::Owned(ImString::new(axis.get_name())),
+                ) {
+                    options.up_axis = UpAxis::ALL[up_axis];
+                }
+                let mut unit = LengthUnit::ALL
+                    .iter()
+                    .position(|unit| *unit == options.unit)
+                    .unwrap_or(0);
+                if ComboBox::new(im_str!("unit")).build_simple(
+                    ui,
+                    &mut unit,
+                    &LengthUnit::ALL,
+                    &|unit| Cow::Owned(ImString::new(unit.get_name())),
+                ) {
+                    options.unit = LengthUnit::ALL[unit];
+                }
+                if ui.small_button(im_str!("import")) {
+                    import = self.pending_import.take();
+                    ui.close_current_popup();
+                }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("cancel")) {
+                    self.pending_import = None;
+                    ui.close_current_popup();
+                }
+            });
+        let (path, options) = match import {
+            Some(import) => import,
+            None => return Ok(()),
+        };
+        let model_and_texture = ModelAndTexture::load(&path)
+            .chain_err(|| "fail to load the model file or the texture file")?;
+        self.scene_renderer
+            .borrow_mut()
+            .start_loading_model(
+                model_and_texture,
+                if self.batch_small_groups {
+                    Some(self.batch_threshold as usize)
+                } else {
+                    None
+                },
+                self.flip_normals,
+                options,
+            )
+            .chain_err(|| format!("fail to import {}", path.display()))?;
+        self.scene_edited = true;
+        self.scene_units = self
+            .settings
+            .scene_units
+            .get(&path)
+            .cloned()
+            .unwrap_or_default();
+        self.model_path = Some(path.clone());
+        if self.settings.import_options.get(&path) == Some(&options) {
+            return Ok(());
+        }
+        self.settings.import_options.insert(path, options);
+        self.settings
+            .save(Path::new(SETTINGS_PATH))
+            .chain_err(|| "fail to save the settings")
+    }
+
+    // The presets and a custom scale, saved for the loaded model file once changed.
+    fn build_scene_units_combo(&mut self, ui: &Ui) -> Result<()> {
+        let mut choices = SceneUnits::PRESETS.to_vec();
+        let custom = match self.scene_units {
+            SceneUnits::Custom(_) => self.scene_units,
+            _ => SceneUnits::Custom(self.scene_units.get_meters_per_unit()),
+        };
+        choices.push(custom);
+        let mut index = choices
+            .iter()
+            .position(|units| units.get_name() == self.scene_units.get_name())
+            .unwrap_or(0);
+        let mut units = self.scene_units;
+        if ComboBox::new(im_str!("scene units")).build_simple(ui, &mut index, &choices, &|units| {
+            Cow::Owned(ImString::new(units.get_name()))
+        }) {
+            units = choices[index];
+        }
+        if let SceneUnits::Custom(meters_per_unit) = units {
+            let mut meters_per_unit = meters_per_unit;
+            // applied on enter instead of saving the settings on every keystroke
+            if ui
+                .input_float(im_str!("meters per unit"), &mut meters_per_unit)
+                .enter_returns_true(true)
+                .build()
+            {
+                units = SceneUnits::custom(meters_per_unit)?;
+            }
+        }
+        if units == self.scene_units {
+            return Ok(());
+        }
+        self.scene_units = units;
+        let path = match self.model_path {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+        self.settings.scene_units.insert(path, units);
+        self.settings
+            .save(Path::new(SETTINGS_PATH))
+            .chain_err(|| "fail to save the settings")
+    }
+
+    // Click a direction and press the key to bind, or escape to cancel. Saved once bound.
+    fn build_key_bindings_section(&mut self, ui: &Ui) -> Result<()> {
+        if self.rebinding_quit_key {
+            if let Some(key) = find_held_quit_key(&ui.io().keys_down) {
+                self.rebinding_quit_key = false;
+                self.settings.key_bindings.bind_quit(Some(key))?;
+                self.settings
+                    .save(Path::new(SETTINGS_PATH))
+                    .chain_err(|| "fail to save the settings")?;
+            }
+        }
+        if let Some(index) = self.rebinding_direction {
+            let keys_down = &ui.io().keys_down;
+            if keys_down[VirtualKeyCode::Escape as usize] {
+                self.rebinding_direction = None;
+            } else if let Some(key) = find_held_bindable_key(keys_down) {
+                self.rebinding_direction = None;
+                self.settings.key_bindings.bind(index, key);
+                self.settings
+                    .save(Path::new(SETTINGS_PATH))
+                    .chain_err(|| "fail to save the settings")?;
+            }
+        }
+        for (index, (_, name)) in KeyBindings::DIRECTIONS.iter().enumerate() {
+            let label = if self.rebinding_direction == Some(index) {
+                "press a key".to_owned()
+            } else {
+                get_key_name(self.settings.key_bindings.get_key(index))
+            };
+            if ui.small_button(&im_str!("{}##{}", label, name)) {
+                self.rebinding_direction = Some(index);
+                self.rebinding_quit_key = false;
+            }
+            ui.same_line(0.0);
+            ui.text(name);
+        }
+        // escape can only be bound here, so clicking again cancels
+        let quit_label = match self.settings.key_bindings.get_quit_key() {
+            _ if self.rebinding_quit_key => "press a key".to_owned(),
+            Some(key) => get_key_name(key),
+            None => "none".to_owned(),
+        };
+        if ui.small_button(&im_str!("{}##quit", quit_label)) {
+            self.rebinding_quit_key = !self.rebinding_quit_key;
+            self.rebinding_direction = None;
+        }
+        ui.same_line(0.0);
+        ui.text("quit");
+        if self.settings.key_bindings.get_quit_key().is_some() {
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("unbind##quit")) {
+                self.rebinding_quit_key = false;
+                self.settings.key_bindings.bind_quit(None)?;
+                self.settings
+                    .save(Path::new(SETTINGS_PATH))
+                    .chain_err(|| "fail to save the settings")?;
+            }
+        }
+        ui.checkbox(
+            im_str!("confirm quitting with models loaded"),
+            &mut self.confirm_quit,
+        );
+        if ui.small_button(im_str!("reset to WASD/ZX")) {
+            self.rebinding_direction = None;
+            self.rebinding_quit_key = false;
+            self.settings.key_bindings = KeyBindings::default();
+            self.settings
+                .save(Path::new(SETTINGS_PATH))
+                .chain_err(|| "fail to save the settings")?;
+        }
+        Ok(())
+    }
+
+    // the open surfaces are drawn double-sided unless forced single-sided
+    fn build_double_sided_override(
+        &mut self,
+        ui: &Ui,
+        material_name: &str,
+        errors: &mut Vec<Error>,
+    ) {
+        if !self.scene_renderer.borrow().is_material_open(material_name) {
+            return;
+        }
+        let _id = ui.push_id(material_name);
+        let mut single_sided = !self
+            .scene_renderer
+            .borrow()
+            .is_material_double_sided(material_name);
+        ui.text("open surface, drawn double-sided");
+        if ui.checkbox(im_str!("force single-sided"), &mut single_sided) {
+            if let Err(e) = self
+                .scene_renderer
+                .borrow_mut()
+                .set_material_single_sided(material_name, single_sided)
+            {
+                errors.push(e.chain_err(|| {
+                    format!("fail to change the sides of the material {}", material_name)
+                }));
+            }
+        }
+    }
+
+    // the scroll and the rotation of the texture of a textured material
+    fn build_uv_animation_editor(&mut self, ui: &Ui, material_name: &str, errors: &mut Vec<Error>) {
+        let _id = ui.push_id(material_name);
+        let mut animation = self.scene_renderer.borrow().get_uv_animation(material_name);
+        let mut changed = Drag::new(im_str!("uv scroll per second"))
+            .speed(0.01)
+            .build_array(ui, &mut animation.scroll_velocity);
+        changed |= Drag::new(im_str!("uv rotation per second"))
+            .speed(0.01)
+            .display_format(im_str!("%.2f rad"))
+            .build(ui, &mut animation.rotation_speed);
+        if changed {
+            if let Err(e) = self
+                .scene_renderer
+                .borrow_mut()
+                .set_uv_animation(material_name, animation)
+            {
+                errors.push(e.chain_err(|| {
+                    format!(
+                        "fail to animate the texture of the material {}",
+                        material_name
+                    )
+                }));
+            }
+        }
+    }
+
+    // the raster state of the scene, and the per object overrides
+    fn build_objects_window(&mut self, ui: &Ui, errors: &mut Vec<Error>) {
+        fn raster_state_name(raster_state: &Option<RasterState>) -> Cow<'_, ImStr> {
+            match raster_state {
+                Some(raster_state) => Cow::Owned(ImString::new(format!(
+                    "{}, {}",
+                    raster_state.cull_mode.get_name(),
+                    raster_state.front_face.get_name()
+                ))),
+                None => Cow::Borrowed(im_str!("the scene")),
+            }
+        }
+        // the first option draws the object with the raster state of the scene
+        let options: Vec<_> = std::iter::once(None)
+            .chain(CullMode::ALL.iter().flat_map(|cull_mode| {
+                FrontFace::ALL.iter().map(move |front_face| {
+                    Some(RasterState {
+                        cull_mode: *cull_mode,
+                        front_face: *front_face,
+                        ..Default::default()
+                    })
+                })
+            }))
+            .collect();
+        Window::new(im_str!("Objects"))
+            .size([300.0, 200.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let mut scene_renderer = self.scene_renderer.borrow_mut();
+                let mut raster_state = scene_renderer.get_raster_state();
+                let mut cull_mode = CullMode::ALL
+                    .iter()
+                    .position(|cull_mode| *cull_mode == raster_state.cull_mode)
+                    .unwrap_or(0);
+                let mut front_face = FrontFace::ALL
+                    .iter()
+                    .position(|front_face| *front_face == raster_state.front_face)
+                    .unwrap_or(0);
+                let cull_mode_changed = ComboBox::new(im_str!("cull mode")).build_simple(
+                    ui,
+                    &mut cull_mode,
+                    &CullMode::ALL,
+                    &|cull_mode| Cow::Owned(ImString::new(cull_mode.get_name())),
+                );
+                let front_face_changed = ComboBox::new(im_str!("front face")).build_simple(
+                    ui,
+                    &mut front_face,
+                    &FrontFace::ALL,
+                    &|front_face| Cow::Owned(ImString::new(front_face.get_name())),
+                );
+                // draw the geometry crossing the near plane when flying close to the model
+                let depth_clamp_changed = if scene_renderer.is_depth_clamp_supported() {
+                    ui.checkbox(im_str!("depth clamp"), &mut raster_state.depth_clamp)
+                } else {
+                    ui.text_disabled(im_str!("depth clamp unsupported"));
+                    false
+                };
+                let wireframe_changed = if scene_renderer.is_wireframe_supported() {
+                    ui.checkbox(im_str!("wireframe"), &mut raster_state.wireframe)
+                } else {
+                    ui.text_disabled(im_str!("wireframe unsupported"));
+                    false
+                };
+                if cull_mode_changed
+                    || front_face_changed
+                    || depth_clamp_changed
+                    || wireframe_changed
+                {
+                    raster_state.cull_mode = CullMode::ALL[cull_mode];
+                    raster_state.front_face = FrontFace::ALL[front_face];
+                    scene_renderer.set_raster_state(raster_state);
+                }
+                let draw_call_groups = scene_renderer.get_draw_call_groups();
+                for (i, groups) in draw_call_groups.iter().enumerate() {
+                    let _id = ui.push_id(i as i32);
+                    let names: Vec<_> = groups.iter().map(|group| group.name.as_str()).collect();
+                    ui.text(format!("{}: {}", i, names.join(", ")));
+                    let current = scene_renderer.get_object_raster_state(i);
+                    let mut selected = options
+                        .iter()
+                        .position(|option| *option == current)
+                        .unwrap_or(0);
+                    if ComboBox::new(im_str!("raster state")).build_simple(
+                        ui,
+                        &mut selected,
+                        &options,
+                        &raster_state_name,
+                    ) {
+                        if let Err(e) = scene_renderer.set_object_raster_state(i, options[selected])
+                        {
+                            errors.push(e);
+                        }
+                    }
+                    if ui.small_button(im_str!("flip winding")) {
+                        if let Err(e) = scene_renderer.flip_object_winding(i) {
+                            errors.push(e);
+                        }
+                    }
+                }
+            });
+    }
+
+    fn save_camera(&mut self) -> Result<()> {
+        let json = self
+            .get_camera_mut()
+            .chain_err(|| "fail to retrieve the camera to save")?
+            .to_config()
+            .to_json()?;
+        std::fs::write(CAMERA_CONFIG_PATH, json)
+            .chain_err(|| format!("fail to write {}", CAMERA_CONFIG_PATH))
+    }
+
+    // The projection follows the loaded camera, while the aspect ratio still follows the scene
+    // image. The near and far are kept unless they're fit to the scene.
+    fn load_camera(&mut self) -> Result<()> {
+        let json = std::fs::read_to_string(CAMERA_CONFIG_PATH)
+            .chain_err(|| ErrorKind::AssetNotFound(CAMERA_CONFIG_PATH.to_owned()))?;
+        let config = CameraConfig::from_json(&json)
+            .chain_err(|| ErrorKind::AssetParse(CAMERA_CONFIG_PATH.to_owned()))?;
+        let camera = Camera::from_config(&config)
+            .chain_err(|| format!("invalid camera in {}", CAMERA_CONFIG_PATH))?;
+        self.use_loaded_camera(camera);
+        Ok(())
+    }
+
+    // the same as load_camera, from the matrices of the camera instead
+    fn import_camera_matrices(&mut self) -> Result<()> {
+        let json = std::fs::read_to_string(CAMERA_MATRICES_PATH)
+            .chain_err(|| ErrorKind::AssetNotFound(CAMERA_MATRICES_PATH.to_owned()))?;
+        let matrices = CameraMatrices::from_json(&json)
+            .chain_err(|| ErrorKind::AssetParse(CAMERA_MATRICES_PATH.to_owned()))?;
+        let camera = Camera::from_view_matrix(
+            Transform3D::from_arrays(matrices.view),
+            Transform3D::from_arrays(matrices.projection),
+        )
+        .chain_err(|| format!("invalid camera in {}", CAMERA_MATRICES_PATH))?;
+        self.use_loaded_camera(camera);
+        Ok(())
+    }
+
+    fn use_loaded_camera(&mut self, camera: Camera) {
+        self.orthographic_camera = camera.is_orthographic();
+        if let Some(fov) = camera.get_fov() {
+            self.fov = fov;
+        }
+        let (near, far) = camera.get_near_far();
+        self.near = near;
+        self.far = far;
+        self.clamp_camera_pitch = camera.get_max_pitch().is_some();
+        self.camera_velocity = Vector3D::zero();
+        self.camera = Some(camera);
+    }
+
+    fn save_path_traced_image(&mut self) -> Result<()> {
+        let path = match tinyfiledialogs::save_file_dialog_with_filter(
+            "save the path traced image",
+            "path_traced.png",
+            &["*.png"],
+            "",
+        ) {
+            Some(path) => path,
+            None => return Ok(()), /* do nothing, the user cancel the operation */
+        };
+        let state = support::ApplicationT::get_scene_state(self)
+            .chain_err(|| "fail to get the scene state")?;
+        // half of the window resolution in physical pixels, the path tracer is slow
+        let inner_size = self.surface.window().inner_size();
+        let denoise_params = DenoiseParams::default();
+        let image = self
+            .scene_renderer
+            .borrow()
+            .path_trace(
+                &state,
+                (inner_size.width / 2).max(1),
+                (inner_size.height / 2).max(1),
+                DEFAULT_PATH_TRACE_SAMPLES,
+                if self.denoise_path_traced_image {
+                    Some(&denoise_params)
+                } else {
+                    None
+                },
+            )
+            .chain_err(|| "fail to path trace the scene")?;
+        image
+            .save(&path)
+            .chain_err(|| format!("fail to save the image to {}", path))?;
+        Ok(())
+    }
+
+    // the screenshots are numbered in the order they are taken, and saved in the working directory
+    fn next_screenshot_path(&mut self, prefix: &str) -> PathBuf {
+        self.screenshot_count += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        PathBuf::from(format!(
+            "{}-{}-{:04}.png",
+            prefix, timestamp, self.screenshot_count
+        ))
+    }
+
+    // Unlike the screenshots, rendered again offscreen at the window resolution in physical pixels
+    // and saved once the GPU finishes.
+    fn save_rendered_image(&mut self) -> Result<()> {
+        let state = support::ApplicationT::get_scene_state(self)
+            .chain_err(|| "fail to get the scene state")?;
+        let inner_size = self.surface.window().inner_size();
+        let image = self
+            .scene_renderer
+            .borrow_mut()
+            .render_to_image(&state, inner_size.width.max(1), inner_size.height.max(1))
+            .chain_err(|| "fail to render the scene")?;
+        let path = self.next_screenshot_path("render");
+        image
+            .save(&path)
+            .chain_err(|| format!("fail to save the image to {:?}", path))
+    }
+
     fn update_camera_from_key_state(
         &mut self,
         key_state: &[bool; 512],
         elapsed: Duration,
     ) -> Result<()> {
-        let keycode2direction = {
-            use CameraDirection::*;
-            use VirtualKeyCode::{A, D, S, W, X, Z};
-            vec![
-                (W, Forward),
-                (S, Backward),
-                (A, Left),
-                (D, Right),
-                (Z, Up),
-                (X, Down),
-            ]
-        };
-        for (virtual_keycode, direction) in keycode2direction {
-            if key_state[virtual_keycode as usize] {
-                self.move_camera(direction, elapsed).chain_err(|| {
-                    format!("fail to move camera when moving towards {:?}", direction)
-                })?;
+        let key_bindings = self.settings.key_bindings;
+        for (key, roll) in [
+            (ROLL_LEFT_KEY, RollDirection::Left),
+            (ROLL_RIGHT_KEY, RollDirection::Right),
+        ]
+        .iter()
+        {
+            if key_state[*key as usize] && !key_bindings.is_bound(*key) {
+                self.roll_camera(*roll, elapsed)
+                    .chain_err(|| format!("fail to roll the camera to the {:?}", roll))?;
             }
         }
-        Ok(())
+        let directions = key_bindings.get_directions(key_state);
+        self.tick(&directions, elapsed)
+    }
+}
+
+#[derive(Default)]
+struct CommandLineOptions {
+    noise_seed: Option<u64>,
+    dump_shader_reflection: bool,
+    reuse_command_buffers: bool,
+    frames_in_flight: Option<usize>,
+    max_uploads_in_flight: Option<usize>,
+    profile_dump: Option<PathBuf>,
+}
+
+impl CommandLineOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let seed = args
+                        .next()
+                        .ok_or_else(|| -> Error { "--seed requires a value".into() })?;
+                    options.noise_seed = Some(
+                        seed.parse()
+                            .chain_err(|| format!("invalid noise seed: {}", seed))?,
+                    );
+                }
+                "--frames-in-flight" => {
+                    let frames_in_flight = args
+                        .next()
+                        .ok_or_else(|| -> Error { "--frames-in-flight requires a value".into() })?;
+                    options.frames_in_flight = Some(frames_in_flight.parse().chain_err(|| {
+                        format!("invalid number of frames in flight: {}", frames_in_flight)
+                    })?);
+                }
+                "--max-uploads-in-flight" => {
+                    let max_uploads = args.next().ok_or_else(|| -> Error {
+                        "--max-uploads-in-flight requires a value".into()
+                    })?;
+                    options.max_uploads_in_flight = Some(max_uploads.parse().chain_err(|| {
+                        format!("invalid number of uploads in flight: {}", max_uploads)
+                    })?);
+                }
+                "--profile-dump" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| -> Error { "--profile-dump requires a path".into() })?;
+                    options.profile_dump = Some(PathBuf::from(path));
+                }
+                "--dump-shader-reflection" => options.dump_shader_reflection = true,
+                "--reuse-command-buffers" => options.reuse_command_buffers = true,
+                _ => return Err(format!("unknown command line argument: {}", arg).into()),
+            }
+        }
+        Ok(options)
     }
 }
 
 fn main() {
     if let Err(ref e) = run() {
         eprint_chained_err(e);
-        std::process::exit(1);
+        std::process::exit(exit_code(e));
     }
 }
 
 fn run() -> Result<()> {
-    let system = support::init(file!())?;
+    let options = CommandLineOptions::parse(std::env::args().skip(1))
+        .chain_err(|| "fail to parse the command line arguments")?;
+    let mut system = support::init(file!())?;
+    if let Some(frames_in_flight) = options.frames_in_flight {
+        system
+            .set_frames_in_flight(frames_in_flight)
+            .chain_err(|| "fail to set the frames in flight")?;
+    }
+    if let Some(path) = options.profile_dump {
+        system.set_profile_dump(path);
+    }
+    if options.dump_shader_reflection {
+        dump_shader_reflection(system.device.clone())
+            .chain_err(|| "fail to dump the shader reflection")?;
+    }
+    if let Some(max_uploads_in_flight) = options.max_uploads_in_flight {
+        system
+            .scene_renderer
+            .borrow_mut()
+            .set_max_uploads_in_flight(max_uploads_in_flight)
+            .chain_err(|| "fail to set the uploads in flight")?;
+    }
+    if let Some(seed) = options.noise_seed {
+        system.scene_renderer.borrow_mut().set_noise_seed(seed);
+    }
+    system
+        .scene_renderer
+        .borrow_mut()
+        .set_command_buffer_reuse(options.reuse_command_buffers);
 
     system.main_loop::<Application>();
 }
@@ -0,0 +1,361 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs,
+    path::Path,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::errors::*;
+
+// about 5 seconds at 60 FPS
+pub const MAX_PROFILED_FRAMES: usize = 300;
+
+// Times the rest of the enclosing block into the current frame of the thread, e.g.
+// profile_scope!("build bvh"). Only an atomic load when the profiler is disabled.
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profile::ProfileScopeGuard::new($name);
+    };
+}
+
+// why the scopes are recorded, they aren't unless for any of the reasons
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileReason {
+    Panel = 1,
+    Dump = 2,
+}
+
+static ENABLED_REASONS: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_enabled(reason: ProfileReason, enabled: bool) {
+    if enabled {
+        ENABLED_REASONS.fetch_or(reason as u8, Ordering::Relaxed);
+    } else {
+        ENABLED_REASONS.fetch_and(!(reason as u8), Ordering::Relaxed);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED_REASONS.load(Ordering::Relaxed) != 0
+}
+
+// A scope is nested in the closest scope before it with a smaller depth, as the scopes are in the
+// order they start.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProfileScope {
+    pub name: &'static str,
+    pub depth: usize,
+    // since the start of the frame
+    pub start_us: u64,
+    pub duration_us: u64,
+}
+
+// The sibling scopes of the same name merged, e.g. the scopes in a loop, as in a flame graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedScope {
+    pub name: &'static str,
+    pub count: usize,
+    pub duration_us: u64,
+    pub children: Vec<MergedScope>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameProfile {
+    pub frame: u64,
+    pub scopes: Vec<ProfileScope>,
+}
+
+impl FrameProfile {
+    // the time spent in the top level scopes
+    pub fn get_total_us(&self) -> u64 {
+        self.scopes
+            .iter()
+            .filter(|scope| scope.depth == 0)
+            .map(|scope| scope.duration_us)
+            .sum()
+    }
+
+    // the indices of the scopes directly nested in the parent, or of the top level scopes
+    pub fn get_children(&self, parent: Option<usize>) -> Vec<usize> {
+        let (depth, begin) = match parent {
+            Some(parent) => (self.scopes[parent].depth + 1, parent + 1),
+            None => (0, 0),
+        };
+        self.scopes[begin..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, scope)| scope.depth >= depth)
+            .filter(|(_, scope)| scope.depth == depth)
+            .map(|(i, _)| begin + i)
+            .collect()
+    }
+
+    // the top level scopes merged, in the order they first start
+    pub fn merge_scopes(&self) -> Vec<MergedScope> {
+        self.merge_siblings(&self.get_children(None))
+    }
+
+    fn merge_siblings(&self, siblings: &[usize]) -> Vec<MergedScope> {
+        let mut merged: Vec<(MergedScope, Vec<usize>)> = vec![];
+        for &i in siblings {
+            let scope = &self.scopes[i];
+            match merged
+                .iter_mut()
+                .find(|(merged, _)| merged.name == scope.name)
+            {
+                Some((merged, indices)) => {
+                    merged.count += 1;
+                    merged.duration_us += scope.duration_us;
+                    indices.push(i);
+                }
+                None => merged.push((
+                    MergedScope {
+                        name: scope.name,
+                        count: 1,
+                        duration_us: scope.duration_us,
+                        children: vec![],
+                    },
+                    vec![i],
+                )),
+            }
+        }
+        merged
+            .into_iter()
+            .map(|(mut merged, indices)| {
+                let children: Vec<_> = indices
+                    .iter()
+                    .flat_map(|i| self.get_children(Some(*i)))
+                    .collect();
+                merged.children = self.merge_siblings(&children);
+                merged
+            })
+            .collect()
+    }
+}
+
+struct Profiler {
+    frames: VecDeque<FrameProfile>,
+    current: FrameProfile,
+    frame_start: Instant,
+    depth: usize,
+}
+
+impl Profiler {
+    fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            current: FrameProfile {
+                frame: 0,
+                scopes: vec![],
+            },
+            frame_start: Instant::now(),
+            depth: 0,
+        }
+    }
+
+    fn begin_frame(&mut self) {
+        let frame = self.current.frame + 1;
+        let previous = std::mem::replace(
+            &mut self.current,
+            FrameProfile {
+                frame,
+                scopes: vec![],
+            },
+        );
+        if !previous.scopes.is_empty() {
+            if self.frames.len() == MAX_PROFILED_FRAMES {
+                self.frames.pop_front();
+            }
+            self.frames.push_back(previous);
+        }
+        self.frame_start = Instant::now();
+    }
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler::new());
+}
+
+// Called at the start of every frame, keeps the scopes recorded since the last call if any.
+pub fn begin_frame() {
+    PROFILER.with(|profiler| profiler.borrow_mut().begin_frame());
+}
+
+pub struct ProfileScopeGuard {
+    // the index of the scope in the current frame, None when the profiler is disabled
+    scope: Option<(usize, Instant)>,
+}
+
+impl ProfileScopeGuard {
+    pub fn new(name: &'static str) -> Self {
+        if !is_enabled() {
+            return Self { scope: None };
+        }
+        let scope = PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+            let start = Instant::now();
+            let start_us = start.duration_since(profiler.frame_start).as_micros() as u64;
+            let depth = profiler.depth;
+            profiler.depth += 1;
+            profiler.current.scopes.push(ProfileScope {
+                name,
+                depth,
+                start_us,
+                duration_us: 0,
+            });
+            (profiler.current.scopes.len() - 1, start)
+        });
+        Self { scope: Some(scope) }
+    }
+}
+
+impl Drop for ProfileScopeGuard {
+    fn drop(&mut self) {
+        let (index, start) = match self.scope {
+            Some(scope) => scope,
+            None => return,
+        };
+        let duration_us = start.elapsed().as_micros() as u64;
+        PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+            profiler.depth = profiler.depth.saturating_sub(1);
+            // gone if the frame ended before the scope did
+            if let Some(scope) = profiler.current.scopes.get_mut(index) {
+                scope.duration_us = duration_us;
+            }
+        });
+    }
+}
+
+pub fn get_last_frame() -> Option<FrameProfile> {
+    PROFILER.with(|profiler| profiler.borrow().frames.back().cloned())
+}
+
+// among the kept frames
+pub fn get_slowest_frame() -> Option<FrameProfile> {
+    PROFILER.with(|profiler| {
+        profiler
+            .borrow()
+            .frames
+            .iter()
+            .max_by_key(|frame| frame.get_total_us())
+            .cloned()
+    })
+}
+
+// the kept frames of the thread, oldest first
+pub fn to_json() -> Result<String> {
+    PROFILER.with(|profiler| {
+        serde_json::to_string_pretty(&profiler.borrow().frames)
+            .chain_err(|| "fail to serialize the profiled frames")
+    })
+}
+
+pub fn dump(path: &Path) -> Result<()> {
+    let json = to_json()?;
+    fs::write(path, json).chain_err(|| format!("fail to write the profile to {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_scopes() {
+        set_enabled(ProfileReason::Dump, true);
+        begin_frame();
+        {
+            profile_scope!("load");
+            {
+                profile_scope!("dedup");
+                profile_scope!("hash");
+            }
+            profile_scope!("upload");
+        }
+        {
+            profile_scope!("draw");
+        }
+        begin_frame();
+        set_enabled(ProfileReason::Dump, false);
+
+        let frame = get_last_frame().unwrap();
+        let names: Vec<_> = frame
+            .scopes
+            .iter()
+            .map(|scope| (scope.name, scope.depth))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("load", 0),
+                ("dedup", 1),
+                ("hash", 2),
+                ("upload", 1),
+                ("draw", 0)
+            ]
+        );
+        assert_eq!(frame.get_children(None), vec![0, 4]);
+        assert_eq!(frame.get_children(Some(0)), vec![1, 3]);
+        assert_eq!(frame.get_children(Some(1)), vec![2]);
+        assert!(frame.get_children(Some(2)).is_empty());
+        assert!(frame.scopes[0].duration_us >= frame.scopes[1].duration_us);
+        assert_eq!(
+            frame.get_total_us(),
+            frame.scopes[0].duration_us + frame.scopes[4].duration_us
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["scopes"][2]["name"], "hash");
+        assert_eq!(json[0]["scopes"][2]["depth"], 2);
+    }
+
+    fn scope(name: &'static str, depth: usize, duration_us: u64) -> ProfileScope {
+        ProfileScope {
+            name,
+            depth,
+            start_us: 0,
+            duration_us,
+        }
+    }
+
+    #[test]
+    fn test_merge_the_scopes_of_a_loop() {
+        let frame = FrameProfile {
+            frame: 1,
+            scopes: vec![
+                scope("load", 0, 100),
+                scope("group", 1, 40),
+                scope("dedup", 2, 30),
+                scope("group", 1, 50),
+                scope("dedup", 2, 20),
+                scope("upload", 2, 10),
+                scope("draw", 0, 5),
+            ],
+        };
+        let merged = frame.merge_scopes();
+        assert_eq!(merged.len(), 2);
+        assert_eq!((merged[0].name, merged[0].count), ("load", 1));
+        let group = &merged[0].children[0];
+        assert_eq!(
+            (group.name, group.count, group.duration_us),
+            ("group", 2, 90)
+        );
+        let children: Vec<_> = group
+            .children
+            .iter()
+            .map(|scope| (scope.name, scope.count, scope.duration_us))
+            .collect();
+        assert_eq!(children, vec![("dedup", 2, 50), ("upload", 1, 10)]);
+        assert!(merged[1].children.is_empty());
+        assert_eq!(frame.get_total_us(), 105);
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use image::RgbaImage;
+use vulkano::{
+    buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
+    device::Device,
+    format::Format,
+    image::ImageAccess,
+    memory::Content,
+};
+
+use crate::errors::*;
+
+// the buffers of other sizes are dropped when the size of the read back images changes
+const MAX_FREE_BUFFERS: usize = 4;
+
+type Job = Box<dyn FnOnce() -> Result<()> + Send>;
+
+// Runs the jobs one by one in the order they are submitted on a background thread.
+struct Worker {
+    job_sender: Option<mpsc::Sender<Job>>,
+    error_receiver: mpsc::Receiver<Error>,
+    // the jobs submitted but not finished
+    pending: Arc<AtomicUsize>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn spawn() -> Result<Self> {
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let (error_sender, error_receiver) = mpsc::channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let thread_pending = pending.clone();
+        let thread = thread::Builder::new()
+            .name("readback".to_owned())
+            .spawn(move || {
+                for job in job_receiver {
+                    if let Err(e) = job() {
+                        // the receiver is only gone when the queue is dropped
+                        let _ = error_sender.send(e);
+                    }
+                    thread_pending.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+            .chain_err(|| "fail to spawn the readback worker thread")?;
+        Ok(Self {
+            job_sender: Some(job_sender),
+            error_receiver,
+            pending,
+            thread: Some(thread),
+        })
+    }
+
+    fn submit(&self, job: Job) -> Result<()> {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.job_sender
+            .as_ref()
+            .expect("the job sender is only taken when the worker is dropped")
+            .send(job)
+            .map_err(|_| -> Error {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                "the readback worker thread has exited".into()
+            })
+    }
+
+    fn get_pending_count(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    fn take_errors(&self) -> Vec<Error> {
+        self.error_receiver.try_iter().collect()
+    }
+}
+
+impl Drop for Worker {
+    // finish the submitted jobs, e.g. so that no screenshot is lost when exiting
+    fn drop(&mut self) {
+        self.job_sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the pixels of the 8 bit RGBA and BGRA formats as RGBA
+pub fn to_rgba_image(
+    format: Format,
+    [width, height]: [u32; 2],
+    mut pixels: Vec<u8>,
+) -> Result<RgbaImage> {
+    match format {
+        Format::R8G8B8A8Unorm | Format::R8G8B8A8Srgb => (),
+        Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        _ => {
+            return Err(ErrorKind::UnsupportedFeature(format!(
+                "reading back images of the format {:?}",
+                format
+            ))
+            .into())
+        }
+    }
+    RgbaImage::from_raw(width, height, pixels).ok_or_else(|| {
+        format!(
+            "the read back pixels don't match the image size {}x{}",
+            width, height
+        )
+        .into()
+    })
+}
+
+type Callback<T> = Box<dyn FnOnce(<T as ToOwned>::Owned) -> Result<()> + Send>;
+
+struct Readback<T: ?Sized + ToOwned> {
+    buffer: Arc<CpuAccessibleBuffer<T>>,
+    on_complete: Callback<T>,
+}
+
+// Reads buffers back to the CPU without waiting for the GPU. The commands that write the buffer,
+// e.g. the copy of an image, are recorded into the command buffer of the frame, the buffer is
+// checked in the following frames, and the content is handed to the callback on a worker thread
+// once the GPU is done with it. The callbacks are called in the order the readbacks are recorded.
+// The content is the pixels of the read back images by default.
+pub struct ReadbackQueue<T: ?Sized + ToOwned = [u8]> {
+    device: Arc<Device>,
+    free_buffers: Vec<Arc<CpuAccessibleBuffer<T>>>,
+    // recorded into the command buffer of the current frame, which is not submitted yet
+    recorded: Vec<Readback<T>>,
+    // submitted to the GPU, in the order they are recorded
+    in_flight: VecDeque<Readback<T>>,
+    worker: Worker,
+}
+
+impl<T> ReadbackQueue<T>
+where
+    T: ?Sized + ToOwned + Content + Send + Sync + 'static,
+    T::Owned: Send + 'static,
+{
+    pub fn new(device: Arc<Device>) -> Result<Self> {
+        Ok(Self {
+            device,
+            free_buffers: vec![],
+            recorded: vec![],
+            in_flight: Default::default(),
+            worker: Worker::spawn().chain_err(|| "fail to create the readback worker")?,
+        })
+    }
+
+    // A buffer of the size in bytes released by the finished readbacks, None if there is none and
+    // a new one should be created with the usages of the commands that write it.
+    pub fn take_free_buffer(&mut self, size: usize) -> Option<Arc<CpuAccessibleBuffer<T>>> {
+        self.free_buffers.retain(|buffer| buffer.size() == size);
+        self.free_buffers.pop()
+    }
+
+    fn release_buffer(&mut self, buffer: Arc<CpuAccessibleBuffer<T>>) {
+        if self.free_buffers.len() < MAX_FREE_BUFFERS {
+            self.free_buffers.push(buffer);
+        }
+    }
+
+    // Read the buffer back once the commands writing it, which are recorded into the command
+    // buffer of the current frame, are done. The callback is called on the worker thread with the
+    // content of the buffer.
+    pub fn record_buffer(
+        &mut self,
+        buffer: Arc<CpuAccessibleBuffer<T>>,
+        on_complete: impl FnOnce(T::Owned) -> Result<()> + Send + 'static,
+    ) {
+        self.recorded.push(Readback {
+            buffer,
+            on_complete: Box::new(on_complete),
+        });
+    }
+
+    // the command buffer with the recorded readbacks is submitted
+    pub fn submitted(&mut self) {
+        self.in_flight.extend(self.recorded.drain(..));
+    }
+
+    // the command buffer with the recorded readbacks fails to be submitted
+    pub fn discard_recorded(&mut self) {
+        for readback in std::mem::take(&mut self.recorded) {
+            self.release_buffer(readback.buffer);
+        }
+    }
+
+    // Hand the finished readbacks to the worker, and return the errors of the callbacks finished
+    // since the last poll. Never waits for the GPU.
+    pub fn poll(&mut self) -> Vec<Error> {
+        let mut errors = vec![];
+        while let Some(readback) = self.in_flight.front() {
+            // the buffer is locked by the GPU until the frame that writes it has finished
+            let content = match readback.buffer.read() {
+                Ok(content) => (*content).to_owned(),
+                Err(_) => break,
+            };
+            let Readback {
+                buffer,
+                on_complete,
+            } = self
+                .in_flight
+                .pop_front()
+                .expect("the front readback has been checked");
+            self.release_buffer(buffer);
+            if let Err(e) = self.worker.submit(Box::new(move || on_complete(content))) {
+                errors.push(e);
+            }
+        }
+        errors.extend(self.worker.take_errors());
+        errors
+    }
+
+    // Wait for the callbacks of the readbacks handed to the worker, and return their errors. The
+    // readbacks still in flight on the GPU are not waited.
+    pub fn finish(&mut self) -> Vec<Error> {
+        let mut errors = self.poll();
+        while self.worker.get_pending_count() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        errors.extend(self.worker.take_errors());
+        errors
+    }
+
+    // the readbacks recorded but whose callbacks haven't finished
+    pub fn get_in_flight_count(&self) -> usize {
+        self.recorded.len() + self.in_flight.len() + self.worker.get_pending_count()
+    }
+}
+
+impl ReadbackQueue {
+    fn get_buffer(&mut self, len: usize) -> Result<Arc<CpuAccessibleBuffer<[u8]>>> {
+        if let Some(buffer) = self.take_free_buffer(len) {
+            return Ok(buffer);
+        }
+        CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_destination(),
+            true,
+            (0..len).map(|_| 0u8),
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource(format!("fail to create a readback buffer of {} bytes", len))
+        })
+    }
+
+    // Record the copy of the image into the command buffer, the callback is called on the worker
+    // thread with the RGBA pixels. Only the 8 bit RGBA and BGRA formats are supported.
+    pub fn record<I>(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        image: I,
+        on_complete: impl FnOnce(RgbaImage) -> Result<()> + Send + 'static,
+    ) -> Result<()>
+    where
+        I: ImageAccess + Send + Sync + 'static,
+    {
+        let dimensions = image.dimensions();
+        let dimensions = [dimensions.width(), dimensions.height()];
+        self.record_region(cmd_buf_builder, image, [0, 0], dimensions, on_complete)
+    }
+
+    // the same as record, but only the region of the size at the offset is copied
+    pub fn record_region<I>(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        image: I,
+        offset: [u32; 2],
+        dimensions: [u32; 2],
+        on_complete: impl FnOnce(RgbaImage) -> Result<()> + Send + 'static,
+    ) -> Result<()>
+    where
+        I: ImageAccess + Send + Sync + 'static,
+    {
+        let format = image.format();
+        if format.size() != Some(4) {
+            return Err(ErrorKind::UnsupportedFeature(format!(
+                "reading back images of the format {:?}",
+                format
+            ))
+            .into());
+        }
+        let buffer = self
+            .get_buffer(dimensions[0] as usize * dimensions[1] as usize * 4)
+            .chain_err(|| "fail to get a buffer for the readback")?;
+        cmd_buf_builder
+            .copy_image_to_buffer_dimensions(
+                image,
+                buffer.clone(),
+                [offset[0], offset[1], 0],
+                [dimensions[0], dimensions[1], 1],
+                0,
+                1,
+                0,
+            )
+            .chain_err(|| "fail to add the copy image to buffer command to the command builder")?;
+        self.record_buffer(buffer, move |pixels| {
+            on_complete(to_rgba_image(format, dimensions, pixels)?)
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use vulkano::{
+        command_buffer::CommandBuffer,
+        image::{AttachmentImage, ImageUsage},
+        sync::GpuFuture,
+    };
+
+    use crate::gpu_test::create_queue;
+
+    #[test]
+    fn test_readbacks_complete_in_order() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        let image = AttachmentImage::with_usage(
+            device.clone(),
+            [4, 2],
+            Format::B8G8R8A8Unorm,
+            ImageUsage {
+                transfer_source: true,
+                transfer_destination: true,
+                ..ImageUsage::none()
+            },
+        )
+        .unwrap();
+        let mut readback_queue = ReadbackQueue::new(device.clone()).unwrap();
+        let results = Arc::new(Mutex::new(vec![]));
+        // every frame clears the image with a different color and reads it back
+        for frame in 0..3u8 {
+            let mut cmd_buf_builder =
+                AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+                    .unwrap();
+            cmd_buf_builder
+                .clear_color_image(
+                    image.clone(),
+                    [f32::from(frame) / 255.0, 0.0, 1.0, 1.0].into(),
+                )
+                .unwrap();
+            let results = results.clone();
+            readback_queue
+                .record(&mut cmd_buf_builder, image.clone(), move |image| {
+                    results.lock().unwrap().push(image);
+                    Ok(())
+                })
+                .unwrap();
+            let future = cmd_buf_builder
+                .build()
+                .unwrap()
+                .execute(queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap();
+            readback_queue.submitted();
+            assert!(readback_queue.get_in_flight_count() > 0);
+            future.wait(None).unwrap();
+        }
+        assert!(readback_queue.finish().is_empty());
+        assert_eq!(readback_queue.get_in_flight_count(), 0);
+        let results = results.lock().unwrap();
+        assert_eq!(results.len(), 3);
+        for (frame, image) in results.iter().enumerate() {
+            assert_eq!(image.dimensions(), (4, 2));
+            for pixel in image.pixels() {
+                assert_eq!(pixel.0, [frame as u8, 0, 255, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_buffer_readback() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        let mut readback_queue = ReadbackQueue::<[u32; 4]>::new(device.clone()).unwrap();
+        assert!(readback_queue.take_free_buffer(16).is_none());
+        let buffer = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::transfer_destination(),
+            true,
+            [0u32; 4],
+        )
+        .unwrap();
+        let mut cmd_buf_builder =
+            AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+                .unwrap();
+        cmd_buf_builder
+            .update_buffer(buffer.clone(), [1u32, 2, 3, 4])
+            .unwrap();
+        let results = Arc::new(Mutex::new(vec![]));
+        let callback_results = results.clone();
+        readback_queue.record_buffer(buffer, move |content| {
+            callback_results.lock().unwrap().push(content);
+            Ok(())
+        });
+        cmd_buf_builder
+            .build()
+            .unwrap()
+            .execute(queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        readback_queue.submitted();
+        assert!(readback_queue.finish().is_empty());
+        assert_eq!(*results.lock().unwrap(), vec![[1, 2, 3, 4]]);
+        // the buffer is released for the next readback
+        assert!(readback_queue.take_free_buffer(16).is_some());
+    }
+
+    #[test]
+    fn test_to_rgba_image() {
+        let pixels = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let image = to_rgba_image(Format::R8G8B8A8Unorm, [2, 1], pixels.clone()).unwrap();
+        assert_eq!(image.into_raw(), pixels);
+        let image = to_rgba_image(Format::B8G8R8A8Srgb, [1, 2], pixels.clone()).unwrap();
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(image.into_raw(), vec![3, 2, 1, 4, 7, 6, 5, 8]);
+        assert!(to_rgba_image(Format::R8G8B8A8Unorm, [2, 2], pixels.clone()).is_err());
+        let e = to_rgba_image(Format::R16G16Sfloat, [2, 1], pixels).unwrap_err();
+        assert!(
+            matches!(e.kind(), ErrorKind::UnsupportedFeature(_)),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_worker_runs_the_jobs_in_order() {
+        let results = Arc::new(Mutex::new(vec![]));
+        let worker = Worker::spawn().unwrap();
+        for i in 0..16 {
+            let results = results.clone();
+            worker
+                .submit(Box::new(move || {
+                    results.lock().unwrap().push(i);
+                    if i % 5 == 0 {
+                        return Err(format!("job {} fails", i).into());
+                    }
+                    Ok(())
+                }))
+                .unwrap();
+        }
+        while worker.get_pending_count() > 0 {
+            thread::yield_now();
+        }
+        assert_eq!(*results.lock().unwrap(), (0..16).collect::<Vec<_>>());
+        let errors: Vec<_> = worker.take_errors().iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec!["job 0 fails", "job 5 fails", "job 10 fails", "job 15 fails"]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use euclid::{Point3D, Vector3D};
+
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+const RAY_EPSILON: f32 = 1e-6;
+
+pub struct Ray<S> {
+    pub origin: Point3D<f32, S>,
+    // doesn't need to be normalized, the hit distance is measured in the unit of the direction
+    pub direction: Vector3D<f32, S>,
+    pub max_distance: f32,
+}
+
+impl<S> Ray<S> {
+    pub fn new(origin: Point3D<f32, S>, direction: Vector3D<f32, S>) -> Self {
+        Self {
+            origin,
+            direction,
+            max_distance: f32::INFINITY,
+        }
+    }
+
+    pub fn with_max_distance(self, max_distance: f32) -> Self {
+        Self {
+            max_distance,
+            ..self
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Point3D<f32, S> {
+        self.origin + self.direction * t
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub distance: f32,
+    pub triangle_index: usize,
+    // the barycentric coordinates of the hit point with respect to the second and the third
+    // vertices of the triangle
+    pub barycentric: (f32, f32),
+}
+
+pub struct Aabb<S> {
+    pub min: Point3D<f32, S>,
+    pub max: Point3D<f32, S>,
+}
+
+impl<S> Clone for Aabb<S> {
+    fn clone(&self) -> Self {
+        Self {
+            min: self.min,
+            max: self.max,
+        }
+    }
+}
+
+impl<S> Aabb<S> {
+    pub fn corners(&self) -> [Point3D<f32, S>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3D::new(min.x, min.y, min.z),
+            Point3D::new(max.x, min.y, min.z),
+            Point3D::new(min.x, max.y, min.z),
+            Point3D::new(max.x, max.y, min.z),
+            Point3D::new(min.x, min.y, max.z),
+            Point3D::new(max.x, min.y, max.z),
+            Point3D::new(min.x, max.y, max.z),
+            Point3D::new(max.x, max.y, max.z),
+        ]
+    }
+
+    fn empty() -> Self {
+        Self {
+            min: Point3D::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Point3D::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    fn grow(&mut self, point: &Point3D<f32, S>) {
+        self.min = self.min.min(*point);
+        self.max = self.max.max(*point);
+    }
+
+    fn largest_axis(&self) -> usize {
+        let extent = self.max - self.min;
+        if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // slab test, returns whether the ray enters the box before max_distance
+    fn intersect(
+        &self,
+        ray: &Ray<S>,
+        inverse_direction: &Vector3D<f32, S>,
+        max_distance: f32,
+    ) -> bool {
+        let mut t_min = 0.0_f32;
+        let mut t_max = max_distance;
+        for axis in 0..3 {
+            let (origin, inverse_direction, min, max) = match axis {
+                0 => (ray.origin.x, inverse_direction.x, self.min.x, self.max.x),
+                1 => (ray.origin.y, inverse_direction.y, self.min.y, self.max.y),
+                _ => (ray.origin.z, inverse_direction.z, self.min.z, self.max.z),
+            };
+            let t0 = (min - origin) * inverse_direction;
+            let t1 = (max - origin) * inverse_direction;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            // written so that a NaN from 0 * inf doesn't reject the box
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_min > t_max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn axis_of<S>(point: &Point3D<f32, S>, axis: usize) -> f32 {
+    match axis {
+        0 => point.x,
+        1 => point.y,
+        _ => point.z,
+    }
+}
+
+enum Node<S> {
+    Leaf {
+        bounds: Aabb<S>,
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: Aabb<S>,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl<S> Node<S> {
+    fn bounds(&self) -> &Aabb<S> {
+        match self {
+            Self::Leaf { bounds, .. } | Self::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+// A bounding volume hierarchy over triangles built with median splits along the largest axis of
+// the centroid bounds, S is the space the triangles and the rays are in.
+pub struct Bvh<S> {
+    triangles: Vec<[Point3D<f32, S>; 3]>,
+    // the triangles referenced by leaves, leaves point to a contiguous range of this list
+    triangle_order: Vec<usize>,
+    nodes: Vec<Node<S>>,
+}
+
+impl<S> Bvh<S> {
+    pub fn new(triangles: Vec<[Point3D<f32, S>; 3]>) -> Self {
+        let mut bvh = Self {
+            triangle_order: (0..triangles.len()).collect(),
+            triangles,
+            nodes: vec![],
+        };
+        if !bvh.triangles.is_empty() {
+            bvh.build_node(0, bvh.triangles.len());
+        }
+        bvh
+    }
+
+    fn centroid(&self, triangle_index: usize) -> Point3D<f32, S> {
+        let [a, b, c] = &self.triangles[triangle_index];
+        Point3D::new(
+            (a.x + b.x + c.x) / 3.0,
+            (a.y + b.y + c.y) / 3.0,
+            (a.z + b.z + c.z) / 3.0,
+        )
+    }
+
+    // builds the node for triangle_order[first..first + count] and returns its index
+    fn build_node(&mut self, first: usize, count: usize) -> usize {
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &i in self.triangle_order[first..first + count].iter() {
+            for vertex in self.triangles[i].iter() {
+                bounds.grow(vertex);
+            }
+            centroid_bounds.grow(&self.centroid(i));
+        }
+
+        let node_index = self.nodes.len();
+        self.nodes.push(Node::Leaf {
+            bounds: bounds.clone(),
+            first,
+            count,
+        });
+        if count <= MAX_TRIANGLES_PER_LEAF {
+            return node_index;
+        }
+
+        let axis = centroid_bounds.largest_axis();
+        let mut order = std::mem::take(&mut self.triangle_order);
+        order[first..first + count].sort_by(|a, b| {
+            axis_of(&self.centroid(*a), axis)
+                .partial_cmp(&axis_of(&self.centroid(*b), axis))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.triangle_order = order;
+
+        let left_count = count / 2;
+        let left = self.build_node(first, left_count);
+        let right = self.build_node(first + left_count, count - left_count);
+        self.nodes[node_index] = Node::Interior {
+            bounds,
+            left,
+            right,
+        };
+        node_index
+    }
+
+    pub fn get_bounds(&self) -> Option<&Aabb<S>> {
+        self.nodes.first().map(Node::bounds)
+    }
+
+    pub fn get_triangle(&self, triangle_index: usize) -> &[Point3D<f32, S>; 3] {
+        &self.triangles[triangle_index]
+    }
+
+    // Möller–Trumbore, returns the distance and the barycentric coordinates
+    fn intersect_triangle(&self, ray: &Ray<S>, triangle_index: usize) -> Option<(f32, (f32, f32))> {
+        let [a, b, c] = &self.triangles[triangle_index];
+        let edge1 = *b - *a;
+        let edge2 = *c - *a;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < RAY_EPSILON {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+        let s = ray.origin - *a;
+        let u = s.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = ray.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inverse_determinant;
+        if t <= RAY_EPSILON || t > ray.max_distance {
+            return None;
+        }
+        Some((t, (u, v)))
+    }
+
+    // visits the triangles in the leaves the ray may hit, on_triangle returns the new max distance
+    // when the triangle is hit, a negative distance stops the traversal
+    fn traverse(&self, ray: &Ray<S>, mut on_triangle: impl FnMut(usize, f32) -> Option<f32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let inverse_direction = Vector3D::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let mut max_distance = ray.max_distance;
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !node
+                .bounds()
+                .intersect(ray, &inverse_direction, max_distance)
+            {
+                continue;
+            }
+            match node {
+                Node::Leaf { first, count, .. } => {
+                    for &triangle_index in self.triangle_order[*first..*first + *count].iter() {
+                        match on_triangle(triangle_index, max_distance) {
+                            Some(distance) if distance < 0.0 => return,
+                            Some(distance) => max_distance = distance,
+                            None => {}
+                        }
+                    }
+                }
+                Node::Interior { left, right, .. } => {
+                    stack.push(*left);
+                    stack.push(*right);
+                }
+            }
+        }
+    }
+
+    // the closest hit along the ray within the max distance of the ray
+    pub fn intersect(&self, ray: &Ray<S>) -> Option<Hit> {
+        let mut closest: Option<Hit> = None;
+        self.traverse(ray, |triangle_index, max_distance| {
+            let ray = Ray {
+                origin: ray.origin,
+                direction: ray.direction,
+                max_distance,
+            };
+            let (distance, barycentric) = self.intersect_triangle(&ray, triangle_index)?;
+            closest = Some(Hit {
+                distance,
+                triangle_index,
+                barycentric,
+            });
+            Some(distance)
+        });
+        closest
+    }
+
+    // whether the ray hits anything within the max distance of the ray, cheaper than intersect
+    // because it stops at the first hit
+    pub fn any_hit(&self, ray: &Ray<S>) -> bool {
+        let mut hit = false;
+        self.traverse(ray, |triangle_index, _| {
+            self.intersect_triangle(ray, triangle_index)?;
+            hit = true;
+            Some(-1.0)
+        });
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::{point3, vec3};
+
+    use super::*;
+
+    struct TestSpace;
+
+    fn unit_triangle(z: f32) -> [Point3D<f32, TestSpace>; 3] {
+        [
+            point3(0.0, 0.0, z),
+            point3(1.0, 0.0, z),
+            point3(0.0, 1.0, z),
+        ]
+    }
+
+    #[test]
+    fn test_ray_through_triangle_hits() {
+        let bvh = Bvh::new(vec![unit_triangle(-1.0)]);
+        let hit = bvh
+            .intersect(&Ray::new(point3(0.25, 0.25, 0.0), vec3(0.0, 0.0, -1.0)))
+            .unwrap();
+        assert_eq!(hit.triangle_index, 0);
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        assert!((hit.barycentric.0 - 0.25).abs() < 1e-5);
+        assert!((hit.barycentric.1 - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_ray_missing_returns_none() {
+        let bvh = Bvh::new(vec![unit_triangle(-1.0)]);
+        assert!(bvh
+            .intersect(&Ray::new(point3(2.0, 2.0, 0.0), vec3(0.0, 0.0, -1.0)))
+            .is_none());
+        assert!(bvh
+            .intersect(&Ray::new(point3(0.25, 0.25, 0.0), vec3(0.0, 0.0, 1.0)))
+            .is_none());
+        assert!(Bvh::<TestSpace>::new(vec![])
+            .intersect(&Ray::new(point3(0.25, 0.25, 0.0), vec3(0.0, 0.0, -1.0)))
+            .is_none());
+    }
+
+    #[test]
+    fn test_intersect_returns_the_closest_hit() {
+        let triangles: Vec<_> = (1..=20).map(|i| unit_triangle(-(i as f32))).rev().collect();
+        let bvh = Bvh::new(triangles);
+        let hit = bvh
+            .intersect(&Ray::new(point3(0.25, 0.25, 0.0), vec3(0.0, 0.0, -1.0)))
+            .unwrap();
+        assert_eq!(hit.triangle_index, 19);
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_any_hit_respects_max_distance() {
+        let bvh = Bvh::new(vec![unit_triangle(-2.0)]);
+        let ray = Ray::new(point3(0.25, 0.25, 0.0), vec3(0.0, 0.0, -1.0));
+        assert!(bvh.any_hit(&ray));
+        assert!(!bvh.any_hit(&ray.with_max_distance(1.0)));
+    }
+
+    #[test]
+    fn test_matches_brute_force_on_a_grid() {
+        let mut triangles: Vec<[Point3D<f32, TestSpace>; 3]> = vec![];
+        for i in 0..10 {
+            for j in 0..10 {
+                let (x, y, z) = (i as f32, j as f32, -((i * 10 + j) as f32) * 0.1 - 1.0);
+                triangles.push([
+                    point3(x, y, z),
+                    point3(x + 1.0, y, z),
+                    point3(x, y + 1.0, z),
+                ]);
+            }
+        }
+        let bvh = Bvh::new(triangles);
+        for i in 0..10 {
+            for j in 0..10 {
+                let ray = Ray::new(
+                    point3(i as f32 + 0.2, j as f32 + 0.3, 0.0),
+                    vec3(0.0, 0.0, -1.0),
+                );
+                let hit = bvh.intersect(&ray).unwrap();
+                assert_eq!(hit.triangle_index, i * 10 + j);
+            }
+        }
+        let bounds = bvh.get_bounds().unwrap();
+        assert_eq!(bounds.min, point3(0.0, 0.0, -99.0 * 0.1 - 1.0));
+        assert_eq!(bounds.max, point3(10.0, 10.0, -1.0));
+    }
+}
@@ -1,17 +1,295 @@
 use std::time::Duration;
 
-use euclid::{approxeq::ApproxEq, point3, vec3, Angle, Point2D, Point3D, Transform3D, Vector3D};
+use euclid::{
+    approxeq::ApproxEq, point2, point3, size2, vec3, Angle, Box2D, Box3D, Point2D, Point3D,
+    Transform3D, Vector3D,
+};
 
-use super::{NDCSpace, ViewSpace, WorldSpace};
+use serde::{Deserialize, Serialize};
+
+use super::{bvh::Ray, NDCSpace, ViewSpace, WorldSpace};
 use crate::errors::*;
 
+// the automatic near plane doesn't get closer than this in meters, see SceneUnits
+pub const MIN_AUTO_NEAR: f32 = 0.01;
+// the depth buffer only has 16 bits, so the near plane is pushed away on large scenes instead
+const MAX_AUTO_FAR_NEAR_RATIO: f32 = 1000.0;
+// the orbit and the mouse look stop short of the poles, where the heading around up is lost
+const MAX_CONTROL_PITCH: Angle<f32> = Angle {
+    radians: 89.0 * std::f32::consts::PI / 180.0,
+};
+// the fov at which the movement speed isn't scaled, the default fov of the application
+const REFERENCE_FOV: Angle<f32> = Angle {
+    radians: std::f32::consts::FRAC_PI_4,
+};
+// the time for the automatic near and far to get about 2/3 of the way when the range narrows
+const AUTO_NEAR_FAR_SMOOTHING: Duration = Duration::from_millis(300);
+// per second, a quarter turn
+const ROLL_SPEED: Angle<f32> = Angle {
+    radians: std::f32::consts::FRAC_PI_2,
+};
+// a longer frame, e.g. after a hitch, only moves the camera this long, so that it doesn't teleport
+const MAX_CAMERA_TIME_STEP: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+enum Projection {
+    Perspective { fov: Angle<f32>, aspect_ratio: f32 },
+    // the rays through the screen are parallel
+    Orthographic { extents: Box2D<f32, ViewSpace> },
+}
+
+impl Projection {
+    fn to_transform(self, near: f32, far: f32) -> Transform3D<f32, ViewSpace, NDCSpace> {
+        match self {
+            Projection::Perspective { fov, aspect_ratio } => {
+                let t = near * (fov / 2.0).radians.tan();
+                let b = -t;
+                let r = t * aspect_ratio;
+                let l = -r;
+
+                Transform3D::from_arrays([
+                    [2.0 * near / (r - l), 0.0, (r + l) / (r - l), 0.0],
+                    [0.0, -2.0 * near / (t - b), (t + b) / (t - b), 0.0],
+                    [0.0, 0.0, -far / (far - near), -1.0],
+                    [0.0, 0.0, -far * near / (far - near), 0.0],
+                ])
+            }
+            Projection::Orthographic { extents } => {
+                let (l, r, b, t) = (extents.min.x, extents.max.x, extents.min.y, extents.max.y);
+                Transform3D::from_arrays([
+                    [2.0 / (r - l), 0.0, 0.0, 0.0],
+                    [0.0, -2.0 / (t - b), 0.0, 0.0],
+                    [0.0, 0.0, -1.0 / (far - near), 0.0],
+                    [
+                        -(r + l) / (r - l),
+                        (t + b) / (t - b),
+                        -near / (far - near),
+                        1.0,
+                    ],
+                ])
+            }
+        }
+    }
+
+    // The projection and the near and far of a transform made by to_transform, e.g. by the same
+    // convention in another tool. The other projections, e.g. off center, are a Validation error.
+    fn from_transform(
+        transform: &Transform3D<f32, ViewSpace, NDCSpace>,
+    ) -> Result<(Self, f32, f32)> {
+        let m = transform;
+        let (projection, near, far) = if m.m34.approx_eq(&-1.0) && m.m44.approx_eq(&0.0) {
+            // m33 = -far / (far - near) and m43 = m33 * near
+            let near = m.m43 / m.m33;
+            let far = m.m33 * near / (m.m33 + 1.0);
+            let fov = Angle::radians(2.0 * (-1.0 / m.m22).atan());
+            validate_fov(fov)?;
+            (
+                Projection::Perspective {
+                    fov,
+                    aspect_ratio: -m.m22 / m.m11,
+                },
+                near,
+                far,
+            )
+        } else if m.m34.approx_eq(&0.0) && m.m44.approx_eq(&1.0) {
+            // m33 = -1 / (far - near) and m43 = m33 * near
+            let near = m.m43 / m.m33;
+            let far = near - 1.0 / m.m33;
+            let (width, height) = (2.0 / m.m11, -2.0 / m.m22);
+            let center: Point2D<f32, ViewSpace> =
+                point2(-m.m41 * width / 2.0, m.m42 * height / 2.0);
+            let half_size = size2(width / 2.0, height / 2.0);
+            (
+                Projection::Orthographic {
+                    extents: Box2D::new(center - half_size, center + half_size),
+                },
+                near,
+                far,
+            )
+        } else {
+            bail!(ErrorKind::Validation(format!(
+                "the projection is neither perspective nor orthographic, projection = {:?}",
+                transform
+            )));
+        };
+        validate_near_far(near, far)?;
+        if !projection
+            .to_transform(near, far)
+            .approx_eq_eps(transform, &1e-4)
+        {
+            bail!(ErrorKind::Validation(format!(
+                "the projection isn't a symmetric perspective or an orthographic projection \
+                with the depth from 0 to 1 and the y pointing down, projection = {:?}",
+                transform
+            )));
+        }
+        Ok((projection, near, far))
+    }
+}
+
+// The points p with normal.dot(p) + d >= 0 are inside. The normal is normalized, so the left hand
+// side is the signed distance to the plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3D<f32, WorldSpace>,
+    pub d: f32,
+}
+
+impl Plane {
+    pub fn signed_distance(&self, point: &Point3D<f32, WorldSpace>) -> f32 {
+        self.normal.dot(point.to_vector()) + self.d
+    }
+}
+
+// Conservative, false only if all the points are outside one of the planes, e.g. the corners of a
+// bounding box away from the view.
+pub fn may_be_in_frustum(planes: &[Plane; 6], points: &[Point3D<f32, WorldSpace>]) -> bool {
+    planes.iter().all(|plane| {
+        points
+            .iter()
+            .any(|point| plane.signed_distance(point) >= 0.0)
+    })
+}
+
+// The state of a camera which can be saved as JSON and restored, with the angles in degrees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraConfig {
+    pub projection: ProjectionConfig,
+    pub near: f32,
+    pub far: f32,
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    pub up: [f32; 3],
+    #[serde(default)]
+    pub max_pitch: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProjectionConfig {
+    Perspective { fov: f32, aspect_ratio: f32 },
+    // the left, bottom, right and top of the view volume in the view space
+    Orthographic { extents: [f32; 4] },
+}
+
+impl CameraConfig {
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).chain_err(|| "fail to serialize the camera config")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .chain_err(|| ErrorKind::Validation("fail to deserialize the camera config".to_owned()))
+    }
+}
+
+// The matrices of a camera exported from another tool, see Camera::from_view_matrix. The rows of
+// the transforms, with the points as row vectors like euclid.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraMatrices {
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+}
+
+impl CameraMatrices {
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).chain_err(|| {
+            ErrorKind::Validation("fail to deserialize the camera matrices".to_owned())
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Camera {
     projection_transform: Transform3D<f32, ViewSpace, NDCSpace>,
+    projection: Projection,
+    near: f32,
+    far: f32,
     position: Point3D<f32, WorldSpace>,
     // a normalized vector from the camera position to the look at target
     direction: Vector3D<f32, WorldSpace>,
     up: Vector3D<f32, WorldSpace>,
+    // the largest angle the direction can be above or below the plane perpendicular to up
+    max_pitch: Option<Angle<f32>>,
+}
+
+fn validate_near_far(near: f32, far: f32) -> Result<()> {
+    if near.is_nan() || far.is_nan() || far <= near {
+        return Err(ErrorKind::Validation(format!(
+            "far should be greater than near, far = {}, near = {}",
+            far, near
+        ))
+        .into());
+    }
+    if near < f32::approx_epsilon() {
+        return Err(ErrorKind::Validation(format!(
+            "near should be greater than zero, near = {}",
+            near
+        ))
+        .into());
+    }
+    if far.is_infinite() {
+        return Err(ErrorKind::Validation("far should be finite".to_owned()).into());
+    }
+    Ok(())
+}
+
+fn validate_fov(fov: Angle<f32>) -> Result<()> {
+    if fov.radians.is_nan()
+        || fov.radians < Angle::approx_epsilon()
+        || fov.radians > Angle::<f32>::pi().radians - Angle::<f32>::approx_epsilon()
+    {
+        return Err(ErrorKind::Validation(format!(
+            "fov = {}, is not within the range of 0 and pi",
+            fov.radians
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+fn validate_aspect_ratio(aspect_ratio: f32) -> Result<()> {
+    if aspect_ratio < f32::approx_epsilon() {
+        return Err(ErrorKind::Validation(format!(
+            "aspect ratio should be greater than zero, aspect ratio = {}",
+            aspect_ratio
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// the normalized direction from the position to the look at target
+fn validate_view(
+    near: f32,
+    far: f32,
+    position: &Point3D<f32, WorldSpace>,
+    look_at: &Point3D<f32, WorldSpace>,
+    up: &Vector3D<f32, WorldSpace>,
+) -> Result<Vector3D<f32, WorldSpace>> {
+    validate_near_far(near, far)?;
+    let direction = (*look_at - *position).normalize();
+
+    if position.approx_eq(look_at) {
+        return Err(ErrorKind::Validation(format!(
+            "camera look at target shouldn't be too close to the camera, \
+            look at = {:?}, camera position = {:?}",
+            look_at, position
+        ))
+        .into());
+    }
+    if up.approx_eq(&Vector3D::zero()) {
+        return Err(ErrorKind::Validation("up shouldn't be zero".into()).into());
+    }
+    if up.angle_to(direction).approx_eq(&Angle::zero()) {
+        return Err(ErrorKind::Validation(format!(
+            "camera direction and up vector shouldn't be colinear, \
+            up = {:?}, position = {:?}, look at = {:?}",
+            up, position, look_at
+        ))
+        .into());
+    }
+    Ok(direction)
 }
 
 impl Camera {
@@ -24,88 +302,258 @@ impl Camera {
         look_at: &Point3D<f32, WorldSpace>,
         up: &Vector3D<f32, WorldSpace>,
     ) -> Result<Self> {
-        if fov.radians < Angle::approx_epsilon()
-            || fov.radians > Angle::<f32>::pi().radians - Angle::<f32>::approx_epsilon()
-        {
-            return Err(
-                format!("fov = {}, is not within the range of 0 and pi", fov.radians).into(),
-            );
-        }
-        if far <= near {
-            return Err(format!(
-                "far should be greater than near, far = {}, near = {}",
-                far, near
-            )
-            .into());
-        }
-        if near < f32::approx_epsilon() {
-            return Err(format!("near should be greater than zero, near = {}", near).into());
-        }
-        if aspect_ratio < f32::approx_epsilon() {
-            return Err(format!(
-                "aspect ratio should be greater than zero, aspect ratio = {}",
-                aspect_ratio
-            )
-            .into());
-        }
-        let direction = (*look_at - *position).normalize();
-        let up = *up;
+        validate_fov(fov)?;
+        validate_aspect_ratio(aspect_ratio)?;
+        let direction = validate_view(near, far, position, look_at, up)?;
+        let projection = Projection::Perspective { fov, aspect_ratio };
+        Ok(Camera {
+            projection_transform: projection.to_transform(near, far),
+            projection,
+            near,
+            far,
+            position: *position,
+            direction,
+            up: *up,
+            max_pitch: None,
+        })
+    }
 
-        if position.approx_eq(look_at) {
-            return Err(format!(
-                "camera look at target shouldn't be too close to the camera, \
-                look at = {:?}, camera position = {:?}",
-                look_at, position
-            )
-            .into());
-        }
-        if up.approx_eq(&Vector3D::zero()) {
-            return Err("up shouldn't be zero".into());
-        }
-        if up.angle_to(direction).approx_eq(&Angle::zero()) {
-            return Err(format!(
-                "camera direction and up vector shouldn't be colinear, \
-                up = {:?}, position = {:?}, look at = {:?}",
-                up, position, look_at
-            )
+    // The view volume is the extents, i.e. left to right and bottom to top in the view space, from
+    // near to far in front of the camera. The aspect ratio is the one of the extents.
+    pub fn new_orthographic(
+        extents: &Box2D<f32, ViewSpace>,
+        near: f32,
+        far: f32,
+        position: &Point3D<f32, WorldSpace>,
+        look_at: &Point3D<f32, WorldSpace>,
+        up: &Vector3D<f32, WorldSpace>,
+    ) -> Result<Self> {
+        if extents.width() < f32::approx_epsilon() || extents.height() < f32::approx_epsilon() {
+            return Err(ErrorKind::Validation(format!(
+                "the extents should have a width and a height, extents = {:?}",
+                extents
+            ))
             .into());
         }
-
-        let t = near * (fov / 2.0).radians.tan();
-        let b = -t;
-        let r = t * aspect_ratio;
-        let l = -r;
-
-        let projection_transform = Transform3D::from_arrays([
-            [2.0 * near / (r - l), 0.0, (r + l) / (r - l), 0.0],
-            [0.0, -2.0 * near / (t - b), (t + b) / (t - b), 0.0],
-            [0.0, 0.0, -far / (far - near), -1.0],
-            [0.0, 0.0, -far * near / (far - near), 0.0],
-        ]);
+        let direction = validate_view(near, far, position, look_at, up)?;
+        let projection = Projection::Orthographic { extents: *extents };
         Ok(Camera {
-            projection_transform,
+            projection_transform: projection.to_transform(near, far),
+            projection,
+            near,
+            far,
             position: *position,
             direction,
-            up,
+            up: *up,
+            max_pitch: None,
         })
     }
 
     pub fn look_at(&mut self, target: &Point3D<f32, WorldSpace>) -> Result<()> {
         if target.approx_eq(&self.position) {
-            return Err("the look at target is too close to the camera".into());
+            return Err(ErrorKind::Validation(
+                "the look at target is too close to the camera".into(),
+            )
+            .into());
+        }
+        let mut direction = (*target - self.position).normalize();
+        if let Some(max_pitch) = self.max_pitch {
+            direction = self.clamp_pitch(direction, max_pitch);
         }
-        let direction = *target - self.position;
         if direction.angle_to(self.up).approx_eq(&Angle::zero()) {
-            return Err(
+            return Err(ErrorKind::Validation(
                 "the camera direction shouldn't be colinear to the up vector when setting look at \
                 target"
                     .into(),
-            );
+            )
+            .into());
         }
-        self.direction = direction.normalize();
+        self.direction = direction;
         Ok(())
     }
 
+    // Limits how far look_at can turn the camera up or down, so that the direction never gets
+    // close to up, where the view flips. None removes the limit.
+    pub fn set_max_pitch(&mut self, max_pitch: Option<Angle<f32>>) -> Result<()> {
+        if let Some(max_pitch) = max_pitch {
+            if max_pitch.radians <= 0.0 || max_pitch.radians >= Angle::frac_pi_2().radians {
+                return Err(ErrorKind::Validation(format!(
+                    "the max pitch should be in the range of 0 and pi / 2, max pitch = {:?}",
+                    max_pitch
+                ))
+                .into());
+            }
+            self.direction = self.clamp_pitch(self.direction, max_pitch);
+        }
+        self.max_pitch = max_pitch;
+        Ok(())
+    }
+
+    pub fn get_max_pitch(&self) -> Option<Angle<f32>> {
+        self.max_pitch
+    }
+
+    pub fn to_config(&self) -> CameraConfig {
+        CameraConfig {
+            projection: match self.projection {
+                Projection::Perspective { fov, aspect_ratio } => ProjectionConfig::Perspective {
+                    fov: fov.to_degrees(),
+                    aspect_ratio,
+                },
+                Projection::Orthographic { extents } => ProjectionConfig::Orthographic {
+                    extents: [extents.min.x, extents.min.y, extents.max.x, extents.max.y],
+                },
+            },
+            near: self.near,
+            far: self.far,
+            position: self.position.to_array(),
+            direction: self.direction.to_array(),
+            up: self.up.to_array(),
+            max_pitch: self.max_pitch.map(|max_pitch| max_pitch.to_degrees()),
+        }
+    }
+
+    // validated the same as new and new_orthographic, looking along the direction
+    pub fn from_config(config: &CameraConfig) -> Result<Self> {
+        let position = Point3D::from(config.position);
+        let look_at = position + Vector3D::from(config.direction);
+        let up = Vector3D::from(config.up);
+        let mut camera = match config.projection {
+            ProjectionConfig::Perspective { fov, aspect_ratio } => Self::new(
+                Angle::degrees(fov),
+                aspect_ratio,
+                config.near,
+                config.far,
+                &position,
+                &look_at,
+                &up,
+            ),
+            ProjectionConfig::Orthographic {
+                extents: [left, bottom, right, top],
+            } => Self::new_orthographic(
+                &Box2D::new(point2(left, bottom), point2(right, top)),
+                config.near,
+                config.far,
+                &position,
+                &look_at,
+                &up,
+            ),
+        }?;
+        camera.set_max_pitch(config.max_pitch.map(Angle::degrees))?;
+        Ok(camera)
+    }
+
+    // For the cameras exported from other tools. The view transform should be rigid, and the
+    // projection should follow the convention of get_projection_transform, from which the fov,
+    // the extents and the near and far are recovered.
+    pub fn from_view_matrix(
+        view: Transform3D<f32, WorldSpace, ViewSpace>,
+        projection: Transform3D<f32, ViewSpace, NDCSpace>,
+    ) -> Result<Self> {
+        let view_inverse = view.inverse().ok_or_else(|| -> Error {
+            ErrorKind::Validation(format!(
+                "the view transform isn't invertible, view = {:?}",
+                view
+            ))
+            .into()
+        })?;
+        let view_x = view_inverse.transform_vector3d(vec3(1.0, 0.0, 0.0));
+        let view_y = view_inverse.transform_vector3d(vec3(0.0, 1.0, 0.0));
+        let view_z = view_inverse.transform_vector3d(vec3(0.0, 0.0, 1.0));
+        let orthonormal = [view_x, view_y, view_z]
+            .iter()
+            .all(|axis| axis.length().approx_eq_eps(&1.0, &1e-4))
+            && view_x.dot(view_y).approx_eq_eps(&0.0, &1e-4)
+            && view_y.dot(view_z).approx_eq_eps(&0.0, &1e-4)
+            && view_z.dot(view_x).approx_eq_eps(&0.0, &1e-4)
+            && view_x.cross(view_y).dot(view_z) > 0.0;
+        let position = match view_inverse.transform_point3d(Point3D::origin()) {
+            Some(position) if orthonormal => position,
+            _ => bail!(ErrorKind::Validation(format!(
+                "the view transform should be a rotation and a translation, view = {:?}",
+                view
+            ))),
+        };
+        let (decomposed, near, far) = Projection::from_transform(&projection)
+            .chain_err(|| "fail to decompose the projection")?;
+        // the camera looks along -z of the view space, with y up
+        let look_at = position - view_z;
+        let mut camera = match decomposed {
+            Projection::Perspective { fov, aspect_ratio } => {
+                Self::new(fov, aspect_ratio, near, far, &position, &look_at, &view_y)
+            }
+            Projection::Orthographic { extents } => {
+                Self::new_orthographic(&extents, near, far, &position, &look_at, &view_y)
+            }
+        }?;
+        // the same up to the rounding, keep the one supplied
+        camera.projection_transform = projection;
+        Ok(camera)
+    }
+
+    // The left, right, top, bottom, near and far planes of the view frustum in world space, whose
+    // normals point inwards. With the points as row vectors, the clip coordinates are the dot
+    // products with the columns of the view projection matrix, and the Vulkan clip volume is
+    // -w <= x <= w, -w <= y <= w and 0 <= z <= w. The y of the NDC points down.
+    pub fn get_frustum_planes(&self) -> [Plane; 6] {
+        let m = self
+            .get_view_transform()
+            .then(&self.get_projection_transform())
+            .to_arrays();
+        let column = |j: usize| [m[0][j], m[1][j], m[2][j], m[3][j]];
+        let combine = |a: [f32; 4], sign: f32, b: [f32; 4]| {
+            let [x, y, z, w] = [
+                a[0] + sign * b[0],
+                a[1] + sign * b[1],
+                a[2] + sign * b[2],
+                a[3] + sign * b[3],
+            ];
+            let normal: Vector3D<f32, WorldSpace> = vec3(x, y, z);
+            let length = normal.length();
+            Plane {
+                normal: normal / length,
+                d: w / length,
+            }
+        };
+        let (x, y, z, w) = (column(0), column(1), column(2), column(3));
+        [
+            combine(w, 1.0, x),
+            combine(w, -1.0, x),
+            combine(w, 1.0, y),
+            combine(w, -1.0, y),
+            combine(z, 0.0, z),
+            combine(w, -1.0, z),
+        ]
+    }
+
+    // The direction pulled back to max_pitch from the plane perpendicular to up, keeping its
+    // heading. A direction along up keeps the heading of the current direction.
+    fn clamp_pitch(
+        &self,
+        direction: Vector3D<f32, WorldSpace>,
+        max_pitch: Angle<f32>,
+    ) -> Vector3D<f32, WorldSpace> {
+        let up = self.up.normalize();
+        let sin_pitch = direction.dot(up);
+        if sin_pitch.abs() <= max_pitch.radians.sin() {
+            return direction;
+        }
+        let horizontal = |v: Vector3D<f32, WorldSpace>| {
+            let horizontal = v - up * v.dot(up);
+            if horizontal.length() > f32::approx_epsilon() {
+                Some(horizontal.normalize())
+            } else {
+                None
+            }
+        };
+        let heading = horizontal(direction)
+            .or_else(|| horizontal(self.direction))
+            .or_else(|| horizontal(vec3(0.0, 0.0, -1.0)))
+            .unwrap_or_else(|| vec3(1.0, 0.0, 0.0));
+        let pitch = max_pitch.radians.copysign(sin_pitch);
+        heading * pitch.cos() + up * pitch.sin()
+    }
+
     pub fn set_position(&mut self, position: &Point3D<f32, WorldSpace>) {
         self.position = *position;
     }
@@ -136,10 +584,209 @@ impl Camera {
         self.direction
     }
 
+    pub fn get_up(&self) -> Vector3D<f32, WorldSpace> {
+        self.up
+    }
+
+    // The up the orbit and the mouse look turn around, and the top of the view leans towards. The
+    // direction is pulled back to the max pitch from the new up if there is one.
+    pub fn set_up(&mut self, up: &Vector3D<f32, WorldSpace>) -> Result<()> {
+        if up.x.is_nan() || up.y.is_nan() || up.z.is_nan() || up.approx_eq(&Vector3D::zero()) {
+            bail!(ErrorKind::Validation(format!(
+                "up shouldn't be zero or NaN, up = {:?}",
+                up
+            )));
+        }
+        if up.normalize().cross(self.direction).length() <= f32::approx_epsilon() {
+            bail!(ErrorKind::Validation(format!(
+                "the camera direction and the up vector shouldn't be colinear, up = {:?}, \
+                direction = {:?}",
+                up, self.direction
+            )));
+        }
+        self.up = *up;
+        if let Some(max_pitch) = self.max_pitch {
+            self.direction = self.clamp_pitch(self.direction, max_pitch);
+        }
+        Ok(())
+    }
+
+    // Rotates up around the direction, positive angles lean the top of the view to the left. Up
+    // ends up perpendicular to the direction.
+    pub fn roll(&mut self, angle: Angle<f32>) {
+        let view_transform = self.get_view_transform();
+        let view_to_world = |v| {
+            view_transform
+                .inverse()
+                .expect("the inverse of the view transform should always exist")
+                .transform_vector3d(v)
+        };
+        let view_x = view_to_world(vec3(1.0, 0.0, 0.0));
+        let view_y = view_to_world(vec3(0.0, 1.0, 0.0));
+        self.up = (view_y * angle.radians.cos() - view_x * angle.radians.sin()).normalize();
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self.projection, Projection::Orthographic { .. })
+    }
+
+    // None for the orthographic cameras
+    pub fn get_fov(&self) -> Option<Angle<f32>> {
+        match self.projection {
+            Projection::Perspective { fov, .. } => Some(fov),
+            Projection::Orthographic { .. } => None,
+        }
+    }
+
+    pub fn get_near_far(&self) -> (f32, f32) {
+        (self.near, self.far)
+    }
+
+    // keeps the rest of the projection
+    pub fn set_near_far(&mut self, near: f32, far: f32) -> Result<()> {
+        validate_near_far(near, far)?;
+        self.near = near;
+        self.far = far;
+        self.projection_transform = self.projection.to_transform(near, far);
+        Ok(())
+    }
+
+    // keeps the rest of the projection, a Validation error for the orthographic cameras
+    pub fn set_fov(&mut self, fov: Angle<f32>) -> Result<()> {
+        validate_fov(fov)?;
+        match &mut self.projection {
+            Projection::Perspective {
+                fov: projection_fov,
+                ..
+            } => *projection_fov = fov,
+            Projection::Orthographic { .. } => {
+                return Err(ErrorKind::Validation(
+                    "the orthographic cameras have no fov".to_owned(),
+                )
+                .into())
+            }
+        }
+        self.projection_transform = self.projection.to_transform(self.near, self.far);
+        Ok(())
+    }
+
+    // Keeps the rest of the projection. The extents of the orthographic cameras keep their height
+    // and center, and are widened or narrowed to the aspect ratio.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) -> Result<()> {
+        validate_aspect_ratio(aspect_ratio)?;
+        match &mut self.projection {
+            Projection::Perspective {
+                aspect_ratio: projection_aspect_ratio,
+                ..
+            } => *projection_aspect_ratio = aspect_ratio,
+            Projection::Orthographic { extents } => {
+                let center = extents.center();
+                let half_width = extents.height() * aspect_ratio / 2.0;
+                extents.min.x = center.x - half_width;
+                extents.max.x = center.x + half_width;
+            }
+        }
+        self.projection_transform = self.projection.to_transform(self.near, self.far);
+        Ok(())
+    }
+
+    // The near and far just enclosing the bounds with a small margin, with the near no closer than
+    // the min near. None if the bounds are entirely behind the camera.
+    pub fn fit_near_far(
+        &self,
+        bounds: &Box3D<f32, WorldSpace>,
+        min_near: f32,
+    ) -> Option<(f32, f32)> {
+        let view_transform = self.get_view_transform();
+        let (min_depth, max_depth) = (0..8)
+            .map(|i| {
+                point3(
+                    if i & 1 == 0 {
+                        bounds.min.x
+                    } else {
+                        bounds.max.x
+                    },
+                    if i & 2 == 0 {
+                        bounds.min.y
+                    } else {
+                        bounds.max.y
+                    },
+                    if i & 4 == 0 {
+                        bounds.min.z
+                    } else {
+                        bounds.max.z
+                    },
+                )
+            })
+            // the camera looks at -z in the view space
+            .map(|corner| -view_transform.transform_point3d(corner).unwrap().z)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), depth| {
+                (min.min(depth), max.max(depth))
+            });
+        if max_depth <= min_near {
+            return None;
+        }
+        let far = max_depth * 1.01;
+        let near = (min_depth * 0.99)
+            .max(min_near)
+            .max(far / MAX_AUTO_FAR_NEAR_RATIO);
+        Some((near, far))
+    }
+
     pub fn get_aspect_ratio(&self) -> f32 {
         let proj = self.get_projection_transform();
         -proj.m22 / proj.m11
     }
+
+    // the ray from the camera position through the given point on the screen, (-1, -1) is the top
+    // left corner of the screen and (1, 1) is the bottom right corner. For the orthographic
+    // cameras, the ray starts from the point on the near plane along the camera direction.
+    pub fn screen_ray(&self, screen_point: Point2D<f32, NDCSpace>) -> Ray<WorldSpace> {
+        let target = self
+            .projection_transform
+            .inverse()
+            .expect("the projection transform should always be invertible")
+            .transform_point3d(point3(screen_point.x, screen_point.y, 0.0))
+            .expect("the point on the near plane should always be in front of the camera");
+        let view_transform_inverse = self
+            .get_view_transform()
+            .inverse()
+            .expect("the inverse of the view transform should always exist");
+        if self.is_orthographic() {
+            let origin = view_transform_inverse
+                .transform_point3d(target)
+                .expect("the inverse of the view transform should always make sense");
+            return Ray::new(origin, self.direction);
+        }
+        let direction = view_transform_inverse
+            .transform_vector3d(target.to_vector())
+            .normalize();
+        Ray::new(self.position, direction)
+    }
+}
+
+// Moves the near and far towards the target ones. The range widens at once so that nothing is
+// clipped, and narrows smoothly in the log space so that the depth precision doesn't pop.
+pub fn smooth_near_far(
+    current: (f32, f32),
+    target: (f32, f32),
+    time_elapsed: Duration,
+) -> (f32, f32) {
+    let t = 1.0 - (-time_elapsed.as_secs_f32() / AUTO_NEAR_FAR_SMOOTHING.as_secs_f32()).exp();
+    let approach =
+        |current: f32, target: f32| (current.ln() + (target.ln() - current.ln()) * t).exp();
+    let near = if target.0 < current.0 {
+        target.0
+    } else {
+        approach(current.0, target.0)
+    };
+    let far = if target.1 > current.1 {
+        target.1
+    } else {
+        approach(current.1, target.1)
+    };
+    // the far may still be shrinking while the near has jumped past it
+    (near, far.max(near * 2.0))
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -152,61 +799,292 @@ pub enum Direction {
     Backward,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollDirection {
+    // the top of the view leans to the left
+    Left,
+    Right,
+}
+
+// the unit vector in world space of the direction relative to the view of the camera
+fn get_world_direction(camera: &Camera, direction: Direction) -> Vector3D<f32, WorldSpace> {
+    let sign = match direction {
+        Direction::Backward | Direction::Up | Direction::Right => 1.0,
+        Direction::Forward | Direction::Down | Direction::Left => -1.0,
+    };
+    let view_transform_inverse = camera.get_view_transform().inverse().unwrap();
+    let direction = match direction {
+        Direction::Backward | Direction::Forward => vec3(0.0, 0.0, 1.0),
+        Direction::Left | Direction::Right => vec3(1.0, 0.0, 0.0),
+        Direction::Up | Direction::Down => vec3(0.0, 1.0, 0.0),
+    };
+    view_transform_inverse.transform_vector3d(direction) * sign
+}
+
+// the max pitch of the camera if it's lower than MAX_CONTROL_PITCH
+fn get_control_max_pitch(camera: &Camera) -> Angle<f32> {
+    match camera.get_max_pitch() {
+        Some(max_pitch) if max_pitch.radians < MAX_CONTROL_PITCH.radians => max_pitch,
+        _ => MAX_CONTROL_PITCH,
+    }
+}
+
+// The factor on the movement speed of the camera with the fov, tan(fov / 2) / tan(45° / 2). The
+// width of the view at a distance is proportional to tan(fov / 2), so the scene crosses the view at
+// the same rate at any zoom, slower when zoomed in. The orthographic cameras aren't scaled.
+pub fn fov_speed_scale(camera: &Camera) -> f32 {
+    match camera.get_fov() {
+        Some(fov) => (fov.radians / 2.0).tan() / (REFERENCE_FOV.radians / 2.0).tan(),
+        None => 1.0,
+    }
+}
+
 pub trait CameraControl {
     fn get_camera_mut(&mut self) -> Result<&mut Camera>;
     // unit per second
     fn get_speed(&self) -> f32;
+    // scale the speed by fov_speed_scale
+    fn is_speed_scaled_by_fov(&self) -> bool {
+        false
+    }
+    // the acceleration and the damping of move_camera_smoothly, None for the abrupt movement
+    fn get_smoothing(&self) -> Option<(Duration, Duration)> {
+        None
+    }
+    // of the smoothed movement in world space, kept by the control between the ticks
+    fn get_velocity(&self) -> Vector3D<f32, WorldSpace> {
+        Vector3D::zero()
+    }
+    fn set_velocity(&mut self, _velocity: Vector3D<f32, WorldSpace>) {}
+
+    // Moves the camera towards the held directions for a frame, smoothly with the velocity of the
+    // control if get_smoothing is Some. The opposing directions cancel out, so the velocity settles
+    // to zero, and the time step is clamped to MAX_CAMERA_TIME_STEP.
+    fn tick(&mut self, directions: &[Direction], time_elapsed: Duration) -> Result<()> {
+        let time_elapsed = time_elapsed.min(MAX_CAMERA_TIME_STEP);
+        match self.get_smoothing() {
+            Some((acceleration, damping)) => {
+                let mut velocity = self.get_velocity();
+                self.move_camera_smoothly(
+                    &mut velocity,
+                    directions,
+                    acceleration,
+                    damping,
+                    time_elapsed,
+                )
+                .chain_err(|| format!("fail to move camera smoothly towards {:?}", directions))?;
+                self.set_velocity(velocity);
+            }
+            None => {
+                self.set_velocity(Vector3D::zero());
+                for direction in directions {
+                    self.move_camera(*direction, time_elapsed).chain_err(|| {
+                        format!("fail to move camera when moving towards {:?}", direction)
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
 
     fn move_camera(&mut self, direction: Direction, time_elapsed: Duration) -> Result<()> {
         let speed = self.get_speed();
+        let scaled_by_fov = self.is_speed_scaled_by_fov();
         let camera = self
             .get_camera_mut()
             .chain_err(|| "fail to retrieve the camera")?;
-        let pos = camera.get_position();
-        let sign = match direction {
-            Direction::Backward | Direction::Up | Direction::Right => 1.0,
-            Direction::Forward | Direction::Down | Direction::Left => -1.0,
+        let speed = if scaled_by_fov {
+            speed * fov_speed_scale(camera)
+        } else {
+            speed
         };
-        let view_transform_inverse = camera.get_view_transform().inverse().unwrap();
-        let direction = match direction {
-            Direction::Backward | Direction::Forward => vec3(0.0, 0.0, 1.0),
-            Direction::Left | Direction::Right => vec3(1.0, 0.0, 0.0),
-            Direction::Up | Direction::Down => vec3(0.0, 1.0, 0.0),
-        };
-        let direction = view_transform_inverse.transform_vector3d(direction) * sign;
+        let pos = camera.get_position();
+        let direction = get_world_direction(camera, direction);
         let dist = speed * time_elapsed.as_secs_f32();
         camera.set_position(&(pos + direction * dist));
         Ok(())
     }
 
-    // the parameter is the target normalized camera direction projected on the x-y plane of the
-    // view space
-    fn rotate_camera_to(&mut self, projected_target: Point2D<f32, ViewSpace>) -> Result<()> {
-        let projected_target = projected_target.to_vector();
-        if projected_target.length() > 1.0 {
-            return Err("the projected target is outside the unit circle".into());
+    // Moves the camera with the velocity, in world space, eased towards the velocity of the held
+    // directions by exponential damping, so the movement eases in and out. The acceleration is the
+    // time for the velocity to get about 2/3 of the way while directions are held, and the damping
+    // the same while none is, zero for the abrupt movement of move_camera. The damping is
+    // integrated exactly, so the camera ends up where move_camera would have moved it without
+    // overshooting, at the same speed once it has caught up.
+    fn move_camera_smoothly(
+        &mut self,
+        velocity: &mut Vector3D<f32, WorldSpace>,
+        directions: &[Direction],
+        acceleration: Duration,
+        damping: Duration,
+        time_elapsed: Duration,
+    ) -> Result<()> {
+        let speed = self.get_speed();
+        let scaled_by_fov = self.is_speed_scaled_by_fov();
+        let camera = self
+            .get_camera_mut()
+            .chain_err(|| "fail to retrieve the camera")?;
+        let speed = if scaled_by_fov {
+            speed * fov_speed_scale(camera)
+        } else {
+            speed
+        };
+        let input_velocity = directions
+            .iter()
+            .map(|direction| get_world_direction(camera, *direction) * speed)
+            .fold(Vector3D::zero(), |sum, v| sum + v);
+        let dt = time_elapsed.as_secs_f32();
+        let tau = if directions.is_empty() {
+            damping
+        } else {
+            acceleration
         }
-        let z = -(1.0 - projected_target.length()).sqrt();
-        assert!(!z.is_nan());
-        let target: Point3D<_, ViewSpace> = point3(projected_target.x, projected_target.y, z);
+        .as_secs_f32();
+        let (displacement, new_velocity) = if tau > 0.0 {
+            let decay = (-dt / tau).exp();
+            (
+                input_velocity * dt + (*velocity - input_velocity) * (tau * (1.0 - decay)),
+                input_velocity + (*velocity - input_velocity) * decay,
+            )
+        } else {
+            (input_velocity * dt, input_velocity)
+        };
+        *velocity = new_velocity;
+        camera.set_position(&(camera.get_position() + displacement));
+        Ok(())
+    }
+
+    // Turns the camera in place by the yaw around up and the pitch towards up. The pitch is clamped
+    // below the poles, and the max pitch of the camera if it's lower.
+    fn turn_camera(&mut self, yaw_delta: Angle<f32>, pitch_delta: Angle<f32>) -> Result<()> {
         let camera = self
             .get_camera_mut()
             .chain_err(|| "fail to retrieve camera")?;
-        let target = camera
-            .get_view_transform()
-            .inverse()
-            .expect("the inverse of the view transform should always exist")
-            .transform_point3d(target)
-            .expect("the inverse of the view transform should always make sense");
+        let up = camera.get_up().normalize();
+        let direction = camera.get_direction().normalize();
+        let pitch = direction.dot(up).clamp(-1.0, 1.0).asin();
+        let heading = direction - up * direction.dot(up);
+        // looking straight up or down, the heading is where the top of the view points to
+        let heading = if heading.length() > f32::approx_epsilon() {
+            heading.normalize()
+        } else {
+            let view_up = camera
+                .get_view_transform()
+                .inverse()
+                .expect("the inverse of the view transform should always exist")
+                .transform_vector3d(vec3(0.0, 1.0, 0.0));
+            (view_up - up * view_up.dot(up)).normalize() * -pitch.signum()
+        };
+        let heading =
+            heading * yaw_delta.radians.cos() + up.cross(heading) * yaw_delta.radians.sin();
+        let max_pitch = get_control_max_pitch(camera);
+        let pitch = (pitch + pitch_delta.radians).clamp(-max_pitch.radians, max_pitch.radians);
+        let target = camera.get_position() + heading * pitch.cos() + up * pitch.sin();
         camera
             .look_at(&target)
-            .chain_err(|| format!("fail to set the camera look at target to {:?}", target))?;
+            .chain_err(|| format!("fail to turn the camera to {:?}", target))?;
+        Ok(())
+    }
+
+    // Rolls the camera around its direction at ROLL_SPEED.
+    fn roll_camera(&mut self, direction: RollDirection, time_elapsed: Duration) -> Result<()> {
+        let camera = self
+            .get_camera_mut()
+            .chain_err(|| "fail to retrieve camera")?;
+        let angle = ROLL_SPEED * time_elapsed.as_secs_f32();
+        camera.roll(match direction {
+            RollDirection::Left => angle,
+            RollDirection::Right => -angle,
+        });
+        Ok(())
+    }
+
+    // Moves the camera on the sphere around the target through its position, turning it by the
+    // yaw around up and the pitch towards up, and looks at the target. The pitch is clamped below
+    // the poles, and the max pitch of the camera if it's lower.
+    fn orbit_around(
+        &mut self,
+        target: &Point3D<f32, WorldSpace>,
+        yaw_delta: Angle<f32>,
+        pitch_delta: Angle<f32>,
+    ) -> Result<()> {
+        let camera = self
+            .get_camera_mut()
+            .chain_err(|| "fail to retrieve camera")?;
+        let offset = camera.get_position() - *target;
+        let radius = offset.length();
+        if radius < f32::approx_epsilon() {
+            return Err(ErrorKind::Validation(format!(
+                "the camera is too close to the orbit target {:?}",
+                target
+            ))
+            .into());
+        }
+        let up = camera.get_up().normalize();
+        let pitch = (offset.dot(up) / radius).clamp(-1.0, 1.0).asin();
+        let heading = offset - up * offset.dot(up);
+        // at the poles, the camera still looks along its heading
+        let heading = if heading.length() > f32::approx_epsilon() {
+            heading.normalize()
+        } else {
+            let direction = camera.get_direction();
+            -(direction - up * direction.dot(up)).normalize()
+        };
+        let heading =
+            heading * yaw_delta.radians.cos() + up.cross(heading) * yaw_delta.radians.sin();
+        let max_pitch = get_control_max_pitch(camera);
+        let pitch = (pitch + pitch_delta.radians).clamp(-max_pitch.radians, max_pitch.radians);
+        camera.set_position(&(*target + (heading * pitch.cos() + up * pitch.sin()) * radius));
+        camera
+            .look_at(target)
+            .chain_err(|| format!("fail to look at the orbit target {:?}", target))?;
+        Ok(())
+    }
+
+    // the turntable mode of orbit_around, e.g. around the centroid of the model
+    fn orbit_camera(
+        &mut self,
+        pivot: Point3D<f32, WorldSpace>,
+        yaw: Angle<f32>,
+        pitch: Angle<f32>,
+    ) -> Result<()> {
+        self.orbit_around(&pivot, yaw, pitch)
+    }
+
+    // Moves the camera along its direction, scaling its distance to the target along the
+    // direction by the factor per step, positive steps towards the target. The camera stops
+    // min_distance before the target, and a target behind the camera counts as min_distance away,
+    // so it's never passed however far the camera is zoomed in.
+    fn dolly_camera(
+        &mut self,
+        target: Point3D<f32, WorldSpace>,
+        steps: f32,
+        factor_per_step: f32,
+        min_distance: f32,
+    ) -> Result<()> {
+        if !(factor_per_step > 0.0 && factor_per_step < 1.0 && min_distance > 0.0) {
+            bail!(ErrorKind::Validation(format!(
+                "the dolly factor should be in (0, 1) and the min distance positive, found {} and {}",
+                factor_per_step, min_distance
+            )));
+        }
+        let camera = self
+            .get_camera_mut()
+            .chain_err(|| "fail to retrieve camera")?;
+        let direction = camera.get_direction().normalize();
+        let distance = (target - camera.get_position())
+            .dot(direction)
+            .max(min_distance);
+        let new_distance = (distance * factor_per_step.powf(steps)).max(min_distance);
+        camera.set_position(&(camera.get_position() + direction * (distance - new_distance)));
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use euclid::point2;
+
     use super::*;
 
     #[test]
@@ -242,6 +1120,22 @@ mod tests {
         ])));
     }
 
+    #[test]
+    fn test_invalid_parameters_are_validation_errors() {
+        let e = Camera::new(
+            Angle::pi() / 3.0,
+            2.0,
+            0.0,
+            5.0,
+            &point3(1.0, 0.0, 1.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+    }
+
     #[test]
     fn test_fov_should_be_in_range_of_0_and_pi() {
         assert!(Camera::new(
@@ -562,4 +1456,909 @@ mod tests {
         .unwrap();
         assert!(camera.get_aspect_ratio().approx_eq(&aspect_ratio));
     }
+
+    fn orthographic_camera(near: f32, far: f32) -> Result<Camera> {
+        Camera::new_orthographic(
+            &Box2D::new(point2(-2.0, -1.0), point2(4.0, 2.0)),
+            near,
+            far,
+            &point3(1.0, 0.0, 1.0),
+            &point3(1.0, 0.0, -1.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+    }
+
+    #[test]
+    fn test_orthographic_projection_transform() {
+        let camera = orthographic_camera(1.0, 5.0).unwrap();
+        let projection_transform = camera.get_projection_transform();
+        // the corners of the view volume, the top is at -1 in NDC like the perspective cameras
+        for (view, ndc) in [
+            (point3(-2.0, 2.0, -1.0), point3(-1.0, -1.0, 0.0)),
+            (point3(4.0, -1.0, -5.0), point3(1.0, 1.0, 1.0)),
+            (point3(1.0, 0.5, -3.0), point3(0.0, 0.0, 0.5)),
+        ]
+        .iter()
+        {
+            let transformed = projection_transform.transform_point3d(*view).unwrap();
+            assert!(
+                transformed.approx_eq(ndc),
+                "{:?} -> {:?}",
+                view,
+                transformed
+            );
+        }
+        assert!(camera.get_aspect_ratio().approx_eq(&2.0));
+        assert!(camera.is_orthographic());
+    }
+
+    #[test]
+    fn test_orthographic_invalid_parameters() {
+        assert!(orthographic_camera(1.0, 0.5).is_err());
+        assert!(orthographic_camera(0.0, 5.0).is_err());
+        let e = Camera::new_orthographic(
+            &Box2D::new(point2(1.0, -1.0), point2(1.0, 1.0)),
+            1.0,
+            5.0,
+            &point3(1.0, 0.0, 1.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+        // up is colinear with the direction
+        assert!(Camera::new_orthographic(
+            &Box2D::new(point2(-1.0, -1.0), point2(1.0, 1.0)),
+            1.0,
+            5.0,
+            &point3(0.0, 0.0, 0.0),
+            &point3(0.0, 2.0, 0.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_orthographic_screen_ray() {
+        let camera = orthographic_camera(1.0, 5.0).unwrap();
+        let center = camera.screen_ray(Point2D::origin());
+        assert!(center.origin.approx_eq(&point3(2.0, 0.5, 0.0)));
+        assert!(center.direction.approx_eq(&vec3(0.0, 0.0, -1.0)));
+        // the rays are parallel
+        let corner = camera.screen_ray(point2(-1.0, -1.0));
+        assert!(corner.origin.approx_eq(&point3(-1.0, 2.0, 0.0)));
+        assert!(corner.direction.approx_eq(&center.direction));
+    }
+
+    #[test]
+    fn test_screen_ray() {
+        let fov = Angle::pi() / 2.0;
+        let position = point3(1.0, 0.0, 1.0);
+        let target = point3(1.0, 0.0, -1.0);
+        let camera =
+            Camera::new(fov, 2.0, 1.0, 5.0, &position, &target, &vec3(0.0, 1.0, 0.0)).unwrap();
+
+        let center = camera.screen_ray(Point2D::origin());
+        assert!(center.origin.approx_eq(&position));
+        assert!(center.direction.approx_eq(&camera.get_direction()));
+
+        // the top of the screen is at the up side, half of the fov away from the center
+        let top = camera.screen_ray(point2(0.0, -1.0));
+        assert!(top.direction.approx_eq(&vec3(0.0, 1.0, -1.0).normalize()));
+        // the right of the screen is at the right side, the width is twice the height
+        let right = camera.screen_ray(point2(1.0, 0.0));
+        assert!(right.direction.approx_eq(&vec3(2.0, 0.0, -1.0).normalize()));
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut camera = Camera::new(
+            Angle::degrees(60.0),
+            1.5,
+            0.5,
+            50.0,
+            &point3(1.0, 2.0, 3.0),
+            &point3(0.0, 1.0, 0.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        camera.set_max_pitch(Some(Angle::degrees(80.0))).unwrap();
+        let json = camera.to_config().to_json().unwrap();
+        let restored = Camera::from_config(&CameraConfig::from_json(&json).unwrap()).unwrap();
+        assert!(restored
+            .get_view_transform()
+            .approx_eq(&camera.get_view_transform()));
+        assert!(restored
+            .get_projection_transform()
+            .approx_eq(&camera.get_projection_transform()));
+        assert!(restored
+            .get_max_pitch()
+            .unwrap()
+            .radians
+            .approx_eq(&camera.get_max_pitch().unwrap().radians));
+
+        let orthographic = Camera::new_orthographic(
+            &Box2D::new(point2(-2.0, -1.0), point2(2.0, 1.0)),
+            1.0,
+            10.0,
+            &point3(0.0, 0.0, 5.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        let restored = Camera::from_config(&orthographic.to_config()).unwrap();
+        assert!(restored.is_orthographic());
+        assert!(restored
+            .get_projection_transform()
+            .approx_eq(&orthographic.get_projection_transform()));
+    }
+
+    #[test]
+    fn test_invalid_config_is_a_validation_error() {
+        let mut config = looking_down_z().to_config();
+        config.up = config.direction;
+        let e = Camera::from_config(&config).err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+        let mut config = looking_down_z().to_config();
+        config.projection = ProjectionConfig::Perspective {
+            fov: 180.0,
+            aspect_ratio: 1.0,
+        };
+        assert!(Camera::from_config(&config).is_err());
+        let e = CameraConfig::from_json("{\"near\": 1.0}").err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+    }
+
+    fn looking_down_z() -> Camera {
+        Camera::new(
+            Angle::pi() / 2.0,
+            1.0,
+            1.0,
+            100.0,
+            &point3(0.0, 0.0, 10.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_set_near_far_keeps_the_rest_of_the_projection() {
+        let mut camera = looking_down_z();
+        camera.set_near_far(2.0, 50.0).unwrap();
+        assert_eq!(camera.get_near_far(), (2.0, 50.0));
+        assert!(camera.get_aspect_ratio().approx_eq(&1.0));
+        let projection_transform = camera.get_projection_transform();
+        assert!(projection_transform
+            .transform_point3d(point3(0.0, 0.0, -50.0))
+            .unwrap()
+            .z
+            .approx_eq(&1.0));
+        assert!(camera.set_near_far(2.0, 1.0).is_err());
+        assert!(camera.set_near_far(0.0, 1.0).is_err());
+        assert!(camera.set_near_far(f32::NAN, 1.0).is_err());
+        assert!(camera.set_near_far(1.0, f32::INFINITY).is_err());
+        assert_eq!(camera.get_near_far(), (2.0, 50.0));
+    }
+
+    #[test]
+    fn test_from_view_matrix_round_trip() {
+        let perspective = Camera::new(
+            Angle::degrees(50.0),
+            1.5,
+            0.5,
+            80.0,
+            &point3(1.0, 2.0, 3.0),
+            &point3(-1.0, 0.0, 0.5),
+            &vec3(0.2, 1.0, 0.0),
+        )
+        .unwrap();
+        let orthographic = Camera::new_orthographic(
+            &Box2D::new(point2(-3.0, -1.0), point2(1.0, 2.0)),
+            1.0,
+            20.0,
+            &point3(0.0, 5.0, 0.0),
+            &Point3D::origin(),
+            &vec3(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        for camera in [perspective.clone(), orthographic].iter() {
+            let restored = Camera::from_view_matrix(
+                camera.get_view_transform(),
+                camera.get_projection_transform(),
+            )
+            .unwrap();
+            assert!(restored
+                .get_view_transform()
+                .approx_eq_eps(&camera.get_view_transform(), &1e-5));
+            assert_eq!(
+                restored.get_projection_transform(),
+                camera.get_projection_transform()
+            );
+            assert_eq!(restored.is_orthographic(), camera.is_orthographic());
+            let (near, far) = restored.get_near_far();
+            let (expected_near, expected_far) = camera.get_near_far();
+            assert!(near.approx_eq_eps(&expected_near, &1e-4), "{}", near);
+            assert!(far.approx_eq_eps(&expected_far, &1e-2), "{}", far);
+        }
+        let fov = Camera::from_view_matrix(
+            perspective.get_view_transform(),
+            perspective.get_projection_transform(),
+        )
+        .unwrap()
+        .get_fov()
+        .unwrap();
+        assert!(fov.to_degrees().approx_eq_eps(&50.0, &1e-3));
+
+        // a scaled view and an off center projection
+        let e = Camera::from_view_matrix(
+            perspective.get_view_transform().then_scale(2.0, 2.0, 2.0),
+            perspective.get_projection_transform(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+        let mut off_center = perspective.get_projection_transform();
+        off_center.m31 = 0.5;
+        let e = Camera::from_view_matrix(perspective.get_view_transform(), off_center)
+            .err()
+            .unwrap();
+        assert!(
+            matches!(find_error_kind(&e), Some(ErrorKind::Validation(_))),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_camera_matrices_from_json() {
+        let camera = looking_down_z();
+        let json = format!(
+            "{{\"view\": {:?}, \"projection\": {:?}}}",
+            camera.get_view_transform().to_arrays(),
+            camera.get_projection_transform().to_arrays()
+        );
+        let matrices = CameraMatrices::from_json(&json).unwrap();
+        let restored = Camera::from_view_matrix(
+            Transform3D::from_arrays(matrices.view),
+            Transform3D::from_arrays(matrices.projection),
+        )
+        .unwrap();
+        assert!(restored.get_position().approx_eq(&point3(0.0, 0.0, 10.0)));
+        let e = CameraMatrices::from_json("{\"view\": []}").err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+    }
+
+    #[test]
+    fn test_set_fov_and_aspect_ratio_keep_the_view() {
+        let mut camera = looking_down_z();
+        let view_transform = camera.get_view_transform();
+        let (position, direction, up) = (
+            camera.get_position(),
+            camera.get_direction(),
+            camera.get_up(),
+        );
+        camera.set_fov(Angle::degrees(60.0)).unwrap();
+        camera.set_aspect_ratio(2.0).unwrap();
+        assert!(camera.get_view_transform().approx_eq(&view_transform));
+        assert_eq!(camera.get_position(), position);
+        assert_eq!(camera.get_direction(), direction);
+        assert_eq!(camera.get_up(), up);
+        assert_eq!(camera.get_near_far(), (1.0, 100.0));
+        let expected = Camera::new(
+            Angle::degrees(60.0),
+            2.0,
+            1.0,
+            100.0,
+            &point3(0.0, 0.0, 10.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        assert!(camera
+            .get_projection_transform()
+            .approx_eq(&expected.get_projection_transform()));
+        assert!(camera.set_fov(Angle::pi()).is_err());
+        assert!(camera.set_fov(Angle::radians(f32::NAN)).is_err());
+        assert_eq!(camera.get_fov(), Some(Angle::degrees(60.0)));
+        assert!(camera.set_aspect_ratio(0.0).is_err());
+        assert!(camera.get_aspect_ratio().approx_eq(&2.0));
+
+        let mut orthographic = Camera::new_orthographic(
+            &Box2D::new(point2(-1.0, -1.0), point2(1.0, 1.0)),
+            1.0,
+            10.0,
+            &point3(0.0, 0.0, 5.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        orthographic.set_aspect_ratio(2.0).unwrap();
+        assert!(orthographic.get_aspect_ratio().approx_eq(&2.0));
+        // the height is kept
+        let top = orthographic
+            .get_projection_transform()
+            .transform_point3d(point3(2.0, 1.0, -5.0))
+            .unwrap();
+        assert!(top.x.approx_eq(&1.0) && top.y.approx_eq(&-1.0), "{:?}", top);
+        let e = orthographic.set_fov(Angle::degrees(60.0)).err().unwrap();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{}", e);
+    }
+
+    #[test]
+    fn test_fit_near_far() {
+        let camera = looking_down_z();
+        // from 9 to 11 units in front of the camera
+        let (near, far) = camera
+            .fit_near_far(
+                &Box3D::new(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0)),
+                MIN_AUTO_NEAR,
+            )
+            .unwrap();
+        assert!(near < 9.0 && near > 8.8, "{}", near);
+        assert!(far > 11.0 && far < 11.2, "{}", far);
+        // the camera is inside the bounds
+        let (near, far) = camera
+            .fit_near_far(
+                &Box3D::new(point3(-1.0, -1.0, 5.0), point3(1.0, 1.0, 20.0)),
+                MIN_AUTO_NEAR,
+            )
+            .unwrap();
+        assert_eq!(near, MIN_AUTO_NEAR);
+        assert!(far > 5.0);
+        // the scene in millimeters keeps the near 1 cm away
+        let (near, _) = camera
+            .fit_near_far(
+                &Box3D::new(point3(-1.0, -1.0, -20.0), point3(1.0, 1.0, 20.0)),
+                10.0,
+            )
+            .unwrap();
+        assert_eq!(near, 10.0);
+        // the near is pushed away to keep the depth precision
+        let (near, far) = camera
+            .fit_near_far(
+                &Box3D::new(point3(-1.0, -1.0, -5000.0), point3(1.0, 1.0, 9.99)),
+                MIN_AUTO_NEAR,
+            )
+            .unwrap();
+        assert!(near.approx_eq(&(far / MAX_AUTO_FAR_NEAR_RATIO)));
+        // behind the camera
+        assert!(camera
+            .fit_near_far(
+                &Box3D::new(point3(-1.0, -1.0, 11.0), point3(1.0, 1.0, 12.0)),
+                MIN_AUTO_NEAR,
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_smooth_near_far() {
+        // widens at once
+        assert_eq!(
+            smooth_near_far((1.0, 10.0), (0.5, 20.0), Duration::from_millis(1)),
+            (0.5, 20.0)
+        );
+        // narrows part of the way
+        let (near, far) =
+            smooth_near_far((1.0, 100.0), (10.0, 10.0 * 2.0), Duration::from_millis(16));
+        assert!(near > 1.0 && near < 10.0, "{}", near);
+        assert!(far < 100.0 && far > 20.0, "{}", far);
+        // and gets there eventually
+        let (near, far) = smooth_near_far((1.0, 100.0), (10.0, 20.0), Duration::from_secs(60));
+        assert!(near.approx_eq(&10.0) && far.approx_eq(&20.0));
+        assert!(far > near);
+    }
+
+    #[test]
+    fn test_max_pitch_keeps_the_direction_from_crossing_up() {
+        let max_pitch = Angle::degrees(89.0);
+        let mut camera = looking_down_z();
+        camera.set_max_pitch(Some(max_pitch)).unwrap();
+        let position = camera.get_position();
+        let up = vec3(0.0, 1.0, 0.0);
+        // sweep the target from the front over the top to the back
+        for i in 0..=180 {
+            let angle = Angle::degrees(i as f32);
+            let target = position + vec3(0.0, angle.radians.sin(), -angle.radians.cos());
+            camera.look_at(&target).unwrap();
+            let direction = camera.get_direction();
+            let pitch = direction.dot(up).asin();
+            assert!(
+                pitch <= max_pitch.radians + 1e-4,
+                "the pitch {} exceeds the max pitch at {:?}",
+                pitch.to_degrees(),
+                angle
+            );
+            assert!(direction.length().approx_eq(&1.0));
+            // the top of the screen stays on the up side, i.e. the view doesn't flip
+            let view_up = camera
+                .get_view_transform()
+                .inverse()
+                .unwrap()
+                .transform_vector3d(vec3(0.0, 1.0, 0.0));
+            assert!(view_up.dot(up) > 0.0, "the view flipped at {:?}", angle);
+        }
+        // straight down is clamped too
+        camera.look_at(&(position - up)).unwrap();
+        assert!(camera.get_direction().dot(up).asin() >= -max_pitch.radians - 1e-4);
+    }
+
+    #[test]
+    fn test_invalid_max_pitch() {
+        let mut camera = looking_down_z();
+        for max_pitch in [0.0, 90.0, -10.0].iter() {
+            let e = camera
+                .set_max_pitch(Some(Angle::degrees(*max_pitch)))
+                .unwrap_err();
+            assert!(matches!(e.kind(), ErrorKind::Validation(_)));
+        }
+        assert_eq!(camera.get_max_pitch(), None);
+    }
+
+    struct TestControl(Camera);
+
+    impl CameraControl for TestControl {
+        fn get_camera_mut(&mut self) -> Result<&mut Camera> {
+            Ok(&mut self.0)
+        }
+
+        fn get_speed(&self) -> f32 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn test_orbit_around_keeps_the_radius_and_looks_at_the_target() {
+        let target = point3(1.0, 2.0, 0.0);
+        let mut control = TestControl(
+            Camera::new(
+                Angle::pi() / 2.0,
+                1.0,
+                1.0,
+                100.0,
+                &point3(1.0, 2.0, 5.0),
+                &target,
+                &vec3(0.0, 1.0, 0.0),
+            )
+            .unwrap(),
+        );
+        // a quarter turn to the side
+        control
+            .orbit_around(&target, Angle::frac_pi_2(), Angle::zero())
+            .unwrap();
+        assert!(control.0.get_position().approx_eq(&point3(6.0, 2.0, 0.0)));
+        for _ in 0..10 {
+            control
+                .orbit_around(&target, Angle::degrees(7.0), Angle::degrees(5.0))
+                .unwrap();
+            let offset = control.0.get_position() - target;
+            assert!((offset.length() - 5.0).abs() < 1e-4);
+            assert!(control
+                .0
+                .get_direction()
+                .approx_eq_eps(&(-offset / 5.0), &vec3(1e-4, 1e-4, 1e-4)));
+        }
+    }
+
+    #[test]
+    fn test_orbit_around_stops_before_the_poles() {
+        let target = Point3D::origin();
+        let mut control = TestControl(looking_down_z());
+        for _ in 0..20 {
+            control
+                .orbit_around(&target, Angle::zero(), Angle::degrees(10.0))
+                .unwrap();
+            let offset = control.0.get_position() - target;
+            let pitch = (offset.y / offset.length()).asin();
+            assert!(pitch <= MAX_CONTROL_PITCH.radians + 1e-4);
+            // still on the front side instead of flipping over the pole
+            assert!(offset.z > 0.0);
+        }
+        let mut camera = looking_down_z();
+        camera.set_max_pitch(Some(Angle::degrees(45.0))).unwrap();
+        let mut control = TestControl(camera);
+        control
+            .orbit_around(&target, Angle::zero(), Angle::degrees(80.0))
+            .unwrap();
+        let offset = control.0.get_position() - target;
+        assert!(((offset.y / offset.length()).asin() - Angle::degrees(45.0).radians).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_orbit_camera_at_the_pivot() {
+        let mut control = TestControl(looking_down_z());
+        let pivot = control.0.get_position();
+        let err = control
+            .orbit_camera(pivot, Angle::degrees(10.0), Angle::zero())
+            .unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Validation(_)));
+        assert_eq!(control.0.get_position(), pivot);
+    }
+
+    #[test]
+    fn test_dolly_camera_towards_the_target() {
+        let mut control = TestControl(looking_down_z());
+        let target = Point3D::origin();
+        control.dolly_camera(target, 1.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 5.0).abs() < 1e-4);
+        control.dolly_camera(target, -2.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 20.0).abs() < 1e-4);
+        // never through the target
+        control.dolly_camera(target, 100.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 1.0).abs() < 1e-4);
+        control.dolly_camera(target, 1.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 1.0).abs() < 1e-4);
+        assert_eq!(control.0.get_direction().normalize(), vec3(0.0, 0.0, -1.0));
+        // a target behind the camera only lets it back off
+        let behind = point3(0.0, 0.0, 5.0);
+        control.dolly_camera(behind, 1.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 1.0).abs() < 1e-4);
+        control.dolly_camera(behind, -1.0, 0.5, 1.0).unwrap();
+        assert!((control.0.get_position().z - 2.0).abs() < 1e-4);
+        let e = control.dolly_camera(target, 1.0, 1.5, 1.0).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)));
+    }
+
+    #[test]
+    fn test_roll_camera() {
+        let eps = vec3(1e-4, 1e-4, 1e-4);
+        let mut camera = Camera::new(
+            Angle::pi() / 3.0,
+            1.0,
+            1.0,
+            100.0,
+            &point3(1.0, 2.0, 3.0),
+            &point3(4.0, 0.0, -1.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        let view_axes = |camera: &Camera| {
+            let view_to_world = camera.get_view_transform().inverse().unwrap();
+            (
+                view_to_world.transform_vector3d(vec3(1.0, 0.0, 0.0)),
+                view_to_world.transform_vector3d(vec3(0.0, 1.0, 0.0)),
+                view_to_world.transform_vector3d(vec3(0.0, 0.0, 1.0)),
+            )
+        };
+        let (x, y, z) = view_axes(&camera);
+        let direction = camera.get_direction();
+        camera.roll(Angle::frac_pi_2());
+        let (rolled_x, rolled_y, rolled_z) = view_axes(&camera);
+        assert!(rolled_x.approx_eq_eps(&y, &eps), "{:?} {:?}", rolled_x, y);
+        assert!(rolled_y.approx_eq_eps(&-x, &eps), "{:?} {:?}", rolled_y, x);
+        assert!(rolled_z.approx_eq_eps(&z, &eps));
+        assert_eq!(camera.get_direction(), direction);
+        // still orthonormal
+        for (a, b) in [
+            (rolled_x, rolled_y),
+            (rolled_y, rolled_z),
+            (rolled_z, rolled_x),
+        ]
+        .iter()
+        {
+            assert!(a.dot(*b).abs() < 1e-4);
+            assert!((a.length() - 1.0).abs() < 1e-4);
+        }
+
+        // a second of rolling each way gets back where it started
+        let mut control = TestControl(camera);
+        control
+            .roll_camera(RollDirection::Right, Duration::from_secs(1))
+            .unwrap();
+        let (x_back, y_back, _) = view_axes(&control.0);
+        assert!(x_back.approx_eq_eps(&x, &eps));
+        assert!(y_back.approx_eq_eps(&y, &eps));
+        control
+            .roll_camera(RollDirection::Left, Duration::from_millis(500))
+            .unwrap();
+        let (x_half, _, _) = view_axes(&control.0);
+        assert!((x_half.dot(x) - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_set_up() {
+        let mut camera = looking_down_z();
+        camera.set_up(&vec3(1.0, 0.0, 0.0)).unwrap();
+        let view_to_world = camera.get_view_transform().inverse().unwrap();
+        assert!(view_to_world
+            .transform_vector3d(vec3(0.0, 1.0, 0.0))
+            .approx_eq_eps(&vec3(1.0, 0.0, 0.0), &vec3(1e-4, 1e-4, 1e-4)));
+        for up in [
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, -2.0),
+            Vector3D::zero(),
+            vec3(f32::NAN, 1.0, 0.0),
+        ]
+        .iter()
+        {
+            let e = camera.set_up(up).unwrap_err();
+            assert!(matches!(e.kind(), ErrorKind::Validation(_)), "{:?}", up);
+        }
+        assert_eq!(camera.get_up(), vec3(1.0, 0.0, 0.0));
+        // the direction is kept below the max pitch from the new up
+        camera.set_max_pitch(Some(Angle::degrees(45.0))).unwrap();
+        camera.set_up(&vec3(0.0, 1.0, 1.0)).unwrap();
+        let sin_pitch = camera
+            .get_direction()
+            .normalize()
+            .dot(vec3(0.0, 1.0, 1.0).normalize());
+        assert!(sin_pitch.abs() <= Angle::degrees(45.0_f32).radians.sin() + 1e-4);
+    }
+
+    #[test]
+    fn test_turn_camera_by_large_deltas() {
+        let mut control = TestControl(looking_down_z());
+        let position = control.0.get_position();
+        let eps = vec3(1e-4, 1e-4, 1e-4);
+        // a fast flick turns all the way instead of being capped
+        control.turn_camera(Angle::pi(), Angle::zero()).unwrap();
+        assert!(control
+            .0
+            .get_direction()
+            .approx_eq_eps(&vec3(0.0, 0.0, 1.0), &eps));
+        // positive yaw turns to the left around up
+        control
+            .turn_camera(Angle::frac_pi_2(), Angle::zero())
+            .unwrap();
+        assert!(control
+            .0
+            .get_direction()
+            .approx_eq_eps(&vec3(1.0, 0.0, 0.0), &eps));
+        assert_eq!(control.0.get_position(), position);
+        for _ in 0..20 {
+            control
+                .turn_camera(Angle::zero(), Angle::degrees(10.0))
+                .unwrap();
+            let direction = control.0.get_direction().normalize();
+            assert!(direction.y.asin() <= MAX_CONTROL_PITCH.radians + 1e-4);
+            // still facing the same side instead of flipping over the pole
+            assert!(direction.x > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_smooth_movement_approaches_without_overshooting() {
+        let mut control = TestControl(looking_down_z());
+        let start = control.0.get_position();
+        let step = Duration::from_millis(50);
+        let smoothing = Duration::from_millis(300);
+        let mut velocity = Vector3D::zero();
+        // forward at 1 unit per second for 1 second, then released
+        let target = start + vec3(0.0, 0.0, -1.0);
+        let mut last_distance = (target - start).length();
+        for i in 0..100 {
+            let directions: &[Direction] = if i < 20 { &[Direction::Forward] } else { &[] };
+            control
+                .move_camera_smoothly(&mut velocity, directions, smoothing, smoothing, step)
+                .unwrap();
+            let position = control.0.get_position();
+            // eases in behind the abrupt movement
+            if i < 20 {
+                let abrupt = start + vec3(0.0, 0.0, -0.05 * (i + 1) as f32);
+                assert!(position.z > abrupt.z, "step {}", i);
+            }
+            let distance = position.z - target.z;
+            assert!(distance >= -1e-5, "overshoot at step {}: {}", i, distance);
+            assert!(distance <= last_distance + 1e-6, "step {}", i);
+            last_distance = distance;
+        }
+        assert!(last_distance < 1e-3);
+        assert!(velocity.length() < 1e-3);
+
+        // no smoothing is the abrupt movement
+        let mut velocity = Vector3D::zero();
+        control
+            .move_camera_smoothly(
+                &mut velocity,
+                &[Direction::Right],
+                Duration::from_secs(0),
+                Duration::from_secs(0),
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!(control
+            .0
+            .get_position()
+            .approx_eq_eps(&(target + vec3(1.0, 0.0, 0.0)), &point3(1e-4, 1e-4, 1e-4)));
+    }
+
+    struct SmoothControl {
+        camera: Camera,
+        velocity: Vector3D<f32, WorldSpace>,
+    }
+
+    impl CameraControl for SmoothControl {
+        fn get_camera_mut(&mut self) -> Result<&mut Camera> {
+            Ok(&mut self.camera)
+        }
+
+        fn get_speed(&self) -> f32 {
+            1.0
+        }
+
+        fn get_smoothing(&self) -> Option<(Duration, Duration)> {
+            Some((Duration::from_millis(200), Duration::from_millis(200)))
+        }
+
+        fn get_velocity(&self) -> Vector3D<f32, WorldSpace> {
+            self.velocity
+        }
+
+        fn set_velocity(&mut self, velocity: Vector3D<f32, WorldSpace>) {
+            self.velocity = velocity;
+        }
+    }
+
+    #[test]
+    fn test_tick_settles_opposing_directions() {
+        let mut control = SmoothControl {
+            camera: looking_down_z(),
+            velocity: Vector3D::zero(),
+        };
+        let step = Duration::from_millis(16);
+        for _ in 0..100 {
+            control.tick(&[Direction::Forward], step).unwrap();
+        }
+        assert!((control.velocity.length() - 1.0).abs() < 1e-3);
+        // forward and backward together brake to a stop without swinging back and forth
+        let mut last_speed = control.velocity.z;
+        for i in 0..200 {
+            control
+                .tick(&[Direction::Forward, Direction::Backward], step)
+                .unwrap();
+            assert!(control.velocity.z <= 0.0, "swings back at step {}", i);
+            assert!(control.velocity.z >= last_speed, "speeds up at step {}", i);
+            last_speed = control.velocity.z;
+        }
+        assert!(control.velocity.length() < 1e-3);
+    }
+
+    #[test]
+    fn test_tick_clamps_hitches() {
+        let mut control = SmoothControl {
+            camera: looking_down_z(),
+            velocity: vec3(0.0, 0.0, -1.0),
+        };
+        let start = control.camera.get_position();
+        control
+            .tick(&[Direction::Forward], Duration::from_secs(5))
+            .unwrap();
+        let distance = (control.camera.get_position() - start).length();
+        assert!(distance <= MAX_CAMERA_TIME_STEP.as_secs_f32() + 1e-5);
+        // the same for the abrupt movement, which also drops the velocity
+        let mut control = TestControl(looking_down_z());
+        let start = control.0.get_position();
+        control
+            .tick(&[Direction::Forward], Duration::from_secs(5))
+            .unwrap();
+        let distance = (control.0.get_position() - start).length();
+        assert!((distance - MAX_CAMERA_TIME_STEP.as_secs_f32()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_smooth_movement_accelerates_and_damps_separately() {
+        let step = Duration::from_millis(10);
+        let fast = Duration::from_millis(50);
+        let slow = Duration::from_millis(500);
+        let speed_after = |acceleration, damping, held_steps, released_steps| {
+            let mut control = TestControl(looking_down_z());
+            let mut velocity = Vector3D::zero();
+            for i in 0..held_steps + released_steps {
+                let directions: &[Direction] = if i < held_steps {
+                    &[Direction::Forward]
+                } else {
+                    &[]
+                };
+                control
+                    .move_camera_smoothly(&mut velocity, directions, acceleration, damping, step)
+                    .unwrap();
+            }
+            velocity.length()
+        };
+        // the same steady state speed as move_camera
+        assert!((speed_after(fast, slow, 100, 0) - 1.0).abs() < 1e-3);
+        assert!((speed_after(slow, fast, 500, 0) - 1.0).abs() < 1e-3);
+        // the acceleration only ramps up, the damping only slows down
+        assert!(speed_after(fast, slow, 10, 0) > speed_after(slow, fast, 10, 0));
+        assert!(speed_after(fast, slow, 100, 10) > speed_after(slow, fast, 500, 10));
+    }
+
+    #[test]
+    fn test_fov_speed_scale() {
+        let camera_with_fov = |fov| {
+            Camera::new(
+                fov,
+                1.0,
+                1.0,
+                100.0,
+                &point3(0.0, 0.0, 10.0),
+                &Point3D::origin(),
+                &vec3(0.0, 1.0, 0.0),
+            )
+            .unwrap()
+        };
+        assert!((fov_speed_scale(&camera_with_fov(Angle::frac_pi_4())) - 1.0).abs() < 1e-5);
+        let zoomed_in = fov_speed_scale(&camera_with_fov(Angle::degrees(10.0)));
+        let expected = 5.0f32.to_radians().tan() / 22.5f32.to_radians().tan();
+        assert!((zoomed_in - expected).abs() < 1e-5);
+        let orthographic = Camera::new_orthographic(
+            &Box2D::new(point2(-1.0, -1.0), point2(1.0, 1.0)),
+            1.0,
+            100.0,
+            &point3(0.0, 0.0, 10.0),
+            &Point3D::origin(),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap();
+        assert_eq!(fov_speed_scale(&orthographic), 1.0);
+
+        // the narrow fov moves the camera a smaller distance in the same time
+        struct ScaledControl(Camera);
+        impl CameraControl for ScaledControl {
+            fn get_camera_mut(&mut self) -> Result<&mut Camera> {
+                Ok(&mut self.0)
+            }
+
+            fn get_speed(&self) -> f32 {
+                1.0
+            }
+
+            fn is_speed_scaled_by_fov(&self) -> bool {
+                true
+            }
+        }
+        let mut control = ScaledControl(camera_with_fov(Angle::degrees(10.0)));
+        control
+            .move_camera(Direction::Forward, Duration::from_secs(1))
+            .unwrap();
+        assert!((control.0.get_position().z - (10.0 - zoomed_in)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_frustum_planes() {
+        let mut cameras = vec![looking_down_z()];
+        cameras.push(
+            Camera::new_orthographic(
+                &Box2D::new(point2(-2.0, -1.0), point2(2.0, 1.0)),
+                1.0,
+                100.0,
+                &point3(0.0, 0.0, 10.0),
+                &Point3D::origin(),
+                &vec3(0.0, 1.0, 0.0),
+            )
+            .unwrap(),
+        );
+        for camera in cameras {
+            let planes = camera.get_frustum_planes();
+            let (near, far) = camera.get_near_far();
+            for plane in planes.iter() {
+                assert!((plane.normal.length() - 1.0).abs() < 1e-5);
+            }
+            // the camera position is behind the near plane by the near distance
+            let position = camera.get_position();
+            assert!((planes[4].signed_distance(&position) + near).abs() < 1e-4);
+            // a point on the view axis between near and far is inside all of them
+            let inside = position + camera.get_direction() * (near + far) / 2.0;
+            assert!(planes
+                .iter()
+                .all(|plane| plane.signed_distance(&inside) > 0.0));
+            let behind = position - camera.get_direction() * 1000.0;
+            assert!(planes[4].signed_distance(&behind) < 0.0);
+            let beyond = position + camera.get_direction() * (far + 1.0);
+            assert!(planes[5].signed_distance(&beyond) < 0.0);
+            // far to the left and the top of the view
+            assert!(planes[0].signed_distance(&(inside + vec3(-1000.0, 0.0, 0.0))) < 0.0);
+            assert!(planes[1].signed_distance(&(inside + vec3(1000.0, 0.0, 0.0))) < 0.0);
+            assert!(planes[2].signed_distance(&(inside + vec3(0.0, 1000.0, 0.0))) < 0.0);
+            assert!(planes[3].signed_distance(&(inside + vec3(0.0, -1000.0, 0.0))) < 0.0);
+            assert!(may_be_in_frustum(&planes, &[behind, inside]));
+            // the segment through the frustum may be in it, the points beyond far are not
+            assert!(may_be_in_frustum(&planes, &[behind, beyond]));
+            assert!(!may_be_in_frustum(
+                &planes,
+                &[beyond, beyond + vec3(1.0, 1.0, 0.0)]
+            ));
+        }
+    }
 }
@@ -0,0 +1,59 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+// The colors picked in the UI are sRGB, while the scene uniforms, e.g. the light color and the
+// ambient colors, are linear. The shaders encode their output with a gamma of 2.2, which is close
+// to the sRGB curve.
+
+pub fn srgb_to_linear(color: [f32; 3]) -> [f32; 3] {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let [r, g, b] = color;
+    [channel(r), channel(g), channel(b)]
+}
+
+pub fn linear_to_srgb(color: [f32; 3]) -> [f32; 3] {
+    let channel = |c: f32| {
+        if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let [r, g, b] = color;
+    [channel(r), channel(g), channel(b)]
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::approxeq::ApproxEq;
+
+    use super::*;
+
+    #[test]
+    fn test_srgb_to_linear() {
+        assert_eq!(srgb_to_linear([0.0, 1.0, 0.0]), [0.0, 1.0, 0.0]);
+        // the linear segment near black
+        assert!(srgb_to_linear([0.02, 0.0, 0.0])[0].approx_eq_eps(&(0.02 / 12.92), &1e-6));
+        // sRGB 0.5 is about 21.4% of the light
+        assert!(srgb_to_linear([0.5, 0.0, 0.0])[0].approx_eq_eps(&0.214, &1e-3));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for i in 0..=20 {
+            let c = i as f32 / 20.0;
+            let [r, g, b] = linear_to_srgb(srgb_to_linear([c, c / 2.0, 1.0 - c]));
+            assert!(r.approx_eq_eps(&c, &1e-5));
+            assert!(g.approx_eq_eps(&(c / 2.0), &1e-5));
+            assert!(b.approx_eq_eps(&(1.0 - c), &1e-5));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use image::{Rgba, RgbaImage};
+
+use crate::errors::*;
+
+// B3 spline
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseParams {
+    // every iteration doubles the footprint of the filter
+    pub iterations: u32,
+    // how different the colors of two pixels can be before they stop being averaged
+    pub sigma_color: f32,
+    // the exponent applied to the dot product of the normals
+    pub sigma_normal: f32,
+    // how different the depths of two pixels can be relative to the depth of the center pixel
+    pub sigma_depth: f32,
+}
+
+impl Default for DenoiseParams {
+    fn default() -> Self {
+        Self {
+            iterations: 4,
+            sigma_color: 0.5,
+            sigma_normal: 64.0,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+// Edge-avoiding À-Trous wavelet filter over a gamma encoded image. The normals and the depth of
+// the first hit of every pixel, row major, are used to stop the blur at geometry edges, pixels
+// that hit nothing have an infinite depth.
+pub fn denoise(
+    color: &RgbaImage,
+    normals: &[[f32; 3]],
+    depth: &[f32],
+    params: &DenoiseParams,
+) -> Result<RgbaImage> {
+    let (width, height) = color.dimensions();
+    let pixel_count = (width * height) as usize;
+    if normals.len() != pixel_count || depth.len() != pixel_count {
+        return Err(format!(
+            "the guide buffers don't match the {}x{} image, {} normals and {} depths",
+            width,
+            height,
+            normals.len(),
+            depth.len()
+        )
+        .into());
+    }
+    let to_linear = |c: u8| (f32::from(c) / 255.0).powf(2.2);
+    let mut current: Vec<[f32; 3]> = color
+        .pixels()
+        .map(|p| [to_linear(p[0]), to_linear(p[1]), to_linear(p[2])])
+        .collect();
+    let mut next = current.clone();
+    let sigma_color_2 = params.sigma_color * params.sigma_color;
+    for iteration in 0..params.iterations {
+        let step = 1i64 << iteration;
+        for y in 0..i64::from(height) {
+            for x in 0..i64::from(width) {
+                let center = (y * i64::from(width) + x) as usize;
+                let mut sum = [0.0; 3];
+                let mut weight_sum = 0.0;
+                for (j, kernel_y) in KERNEL.iter().enumerate() {
+                    let sample_y = y + (j as i64 - 2) * step;
+                    if sample_y < 0 || sample_y >= i64::from(height) {
+                        continue;
+                    }
+                    for (i, kernel_x) in KERNEL.iter().enumerate() {
+                        let sample_x = x + (i as i64 - 2) * step;
+                        if sample_x < 0 || sample_x >= i64::from(width) {
+                            continue;
+                        }
+                        let sample = (sample_y * i64::from(width) + sample_x) as usize;
+                        let color_distance_2: f32 = (0..3)
+                            .map(|c| current[center][c] - current[sample][c])
+                            .map(|d| d * d)
+                            .sum();
+                        let weight = kernel_x
+                            * kernel_y
+                            * (-color_distance_2 / sigma_color_2).exp()
+                            * normal_weight(&normals[center], &normals[sample], params)
+                            * depth_weight(depth[center], depth[sample], params);
+                        for (c, sum) in sum.iter_mut().enumerate() {
+                            *sum += current[sample][c] * weight;
+                        }
+                        weight_sum += weight;
+                    }
+                }
+                // the weight of the center pixel itself is never zero
+                next[center] = [
+                    sum[0] / weight_sum,
+                    sum[1] / weight_sum,
+                    sum[2] / weight_sum,
+                ];
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+    }
+    let to_gamma = |c: f32| (c.max(0.0).powf(1.0 / 2.2).min(1.0) * 255.0).round() as u8;
+    Ok(RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b] = current[(y * width + x) as usize];
+        Rgba([
+            to_gamma(r),
+            to_gamma(g),
+            to_gamma(b),
+            color.get_pixel(x, y)[3],
+        ])
+    }))
+}
+
+fn normal_weight(a: &[f32; 3], b: &[f32; 3], params: &DenoiseParams) -> f32 {
+    // also covers the pixels that hit nothing, whose normals are zero
+    if a == b {
+        return 1.0;
+    }
+    let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    dot.max(0.0).powf(params.sigma_normal)
+}
+
+fn depth_weight(a: f32, b: f32, params: &DenoiseParams) -> f32 {
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => (-(a - b).abs() / (params.sigma_depth * a.abs()).max(f32::EPSILON)).exp(),
+        (false, false) => 1.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::scene::noise::Rng;
+
+    const SIZE: u32 = 32;
+
+    fn noisy_image(rng: &mut Rng, color: impl Fn(u32, u32) -> f32) -> RgbaImage {
+        RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+            let value = (color(x, y) + (rng.next_f32() - 0.5) * 0.4).clamp(0.0, 1.0);
+            let value = (value * 255.0).round() as u8;
+            Rgba([value, value, value, 255])
+        })
+    }
+
+    fn mean_squared_error(image: &RgbaImage, expected: impl Fn(u32, u32) -> f32) -> f32 {
+        image
+            .enumerate_pixels()
+            .map(|(x, y, p)| {
+                let d = f32::from(p[0]) / 255.0 - expected(x, y);
+                d * d
+            })
+            .sum::<f32>()
+            / (SIZE * SIZE) as f32
+    }
+
+    #[test]
+    fn test_reduces_noise_on_a_flat_surface() {
+        let mut rng = Rng::new(1);
+        let expected = |_, _| 0.5;
+        let color = noisy_image(&mut rng, expected);
+        let normals = vec![[0.0, 0.0, 1.0]; (SIZE * SIZE) as usize];
+        let depth = vec![1.0; (SIZE * SIZE) as usize];
+        let denoised = denoise(&color, &normals, &depth, &DenoiseParams::default()).unwrap();
+        assert!(
+            mean_squared_error(&denoised, expected) < mean_squared_error(&color, expected) / 4.0
+        );
+    }
+
+    #[test]
+    fn test_preserves_geometry_edges() {
+        let mut rng = Rng::new(2);
+        // two walls facing different directions, the left one is dark and the right one is bright
+        let expected = |x, _| if x < SIZE / 2 { 0.2 } else { 0.8 };
+        let color = noisy_image(&mut rng, expected);
+        let normals: Vec<_> = (0..SIZE * SIZE)
+            .map(|i| {
+                if i % SIZE < SIZE / 2 {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 0.0, 1.0]
+                }
+            })
+            .collect();
+        let depth = vec![1.0; (SIZE * SIZE) as usize];
+        let denoised = denoise(&color, &normals, &depth, &DenoiseParams::default()).unwrap();
+        for y in 0..SIZE {
+            let left = f32::from(denoised.get_pixel(SIZE / 2 - 1, y)[0]) / 255.0;
+            let right = f32::from(denoised.get_pixel(SIZE / 2, y)[0]) / 255.0;
+            assert!((left - 0.2).abs() < 0.1, "left = {}", left);
+            assert!((right - 0.8).abs() < 0.1, "right = {}", right);
+        }
+    }
+
+    #[test]
+    fn test_background_is_not_blended_into_the_surface() {
+        let color = RgbaImage::from_fn(SIZE, SIZE, |x, _| {
+            if x < SIZE / 2 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([200, 200, 200, 255])
+            }
+        });
+        let normals = vec![[0.0, 0.0, 1.0]; (SIZE * SIZE) as usize];
+        let depth: Vec<_> = (0..SIZE * SIZE)
+            .map(|i| {
+                if i % SIZE < SIZE / 2 {
+                    f32::INFINITY
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        let params = DenoiseParams {
+            sigma_color: 100.0,
+            ..Default::default()
+        };
+        let denoised = denoise(&color, &normals, &depth, &params).unwrap();
+        assert_eq!(denoised.get_pixel(SIZE / 2 - 1, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(denoised.get_pixel(SIZE / 2, 0), &Rgba([200, 200, 200, 255]));
+    }
+
+    #[test]
+    fn test_mismatched_guides_are_rejected() {
+        let color = RgbaImage::new(4, 4);
+        assert!(denoise(&color, &[[0.0; 3]; 15], &[0.0; 16], &Default::default()).is_err());
+        assert!(denoise(&color, &[[0.0; 3]; 16], &[0.0; 15], &Default::default()).is_err());
+    }
+}
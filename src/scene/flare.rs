@@ -0,0 +1,444 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{ops::Range, sync::Arc};
+
+use euclid::Transform3D;
+use image::{Rgba, RgbaImage};
+use vulkano::{
+    command_buffer::{
+        pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder, SubpassContents,
+    },
+    device::{Device, Queue},
+    format::{ClearValue, D16Unorm, Format, R8G8B8A8Unorm},
+    framebuffer::{Framebuffer, RenderPassAbstract, Subpass},
+    image::{immutable::ImmutableImage, traits::ImageViewAccess, Dimensions, MipmapsCount},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+use super::{
+    material::{Material, SetCamera},
+    renderer::{BlendMode, LayerView, Mesh, MeshData, MeshRenderer, MeshT, RasterState},
+    shaders::{
+        flare::{Shaders as FlareShaders, Uniform as FlareUniform},
+        ShadersT, Texture, UniformsT,
+    },
+    Camera, WorldSpace,
+};
+use crate::errors::*;
+
+const FLARE_TEXTURE_SIZE: u32 = 128;
+
+// the procedural images the flare sprite can be drawn with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlareTexture {
+    RadialGradient,
+    Ring,
+    Star,
+}
+
+impl FlareTexture {
+    pub const ALL: [Self; 3] = [Self::RadialGradient, Self::Ring, Self::Star];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::RadialGradient => "radial gradient",
+            Self::Ring => "ring",
+            Self::Star => "star",
+        }
+    }
+
+    // white, with the shape in the alpha channel, which is 0 outside the inscribed circle
+    fn create_image(&self, size: u32) -> RgbaImage {
+        RgbaImage::from_fn(size, size, |x, y| {
+            let to_centered = |i: u32| (i as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let (x, y) = (to_centered(x), to_centered(y));
+            let r = (x * x + y * y).sqrt();
+            let falloff = (1.0 - r).max(0.0);
+            let alpha = match self {
+                Self::RadialGradient => falloff * falloff,
+                Self::Ring => {
+                    let ring = (-((r - 0.7) / 0.1).powi(2)).exp();
+                    if r < 1.0 {
+                        ring.max(falloff * falloff * 0.5)
+                    } else {
+                        0.0
+                    }
+                }
+                Self::Star => {
+                    let streak = |d: f32| (1.0 - d.abs() * 16.0).max(0.0);
+                    (falloff * falloff).max((streak(x) + streak(y)).min(1.0) * falloff)
+                }
+            };
+            Rgba([255, 255, 255, (alpha.min(1.0) * 255.0).round() as u8])
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlareSettings {
+    pub enabled: bool,
+    pub texture: FlareTexture,
+    // the radius of the sprite in the world space at the intensity of 1
+    pub size: f32,
+}
+
+impl Default for FlareSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            texture: FlareTexture::RadialGradient,
+            size: 0.5,
+        }
+    }
+}
+
+pub struct FlareMaterial {
+    flare_texture: Texture,
+    scene_depth: Texture,
+}
+
+type FlareUniforms = <FlareShaders as ShadersT>::Uniforms;
+
+impl Material for FlareMaterial {
+    type Shaders = FlareShaders;
+
+    fn create_uniforms(&self, device: Arc<Device>, queue: Arc<Queue>) -> Result<FlareUniforms> {
+        FlareUniforms::new(
+            device,
+            queue,
+            FlareUniform {
+                model: Default::default(),
+                view: Default::default(),
+                proj: Default::default(),
+                inv_proj: Default::default(),
+                color: Default::default(),
+                viewport: Default::default(),
+                size: 0.0,
+            },
+            self.flare_texture.clone(),
+            self.scene_depth.clone(),
+        )
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+pub struct FlareVertex {
+    corner: [f32; 2],
+}
+
+vulkano::impl_vertex!(FlareVertex, corner);
+
+pub type FlareRenderer = MeshRenderer<FlareVertex, FlareMaterial>;
+
+// A camera facing sprite drawn at the point light with additive blending, in a pass after the
+// scene. It reads the depth of the scene to fade out softly where it intersects the geometry.
+pub struct Flare<S> {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    mesh_renderer: Arc<FlareRenderer>,
+    mesh: Mesh<FlareVertex, FlareMaterial, S>,
+    uniforms: FlareUniforms,
+    scene_depth: Arc<LayerView<D16Unorm>>,
+    viewport: [f32; 4],
+    settings: FlareSettings,
+}
+
+impl<S> Flare<S> {
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        format: Format,
+        width: u32,
+        height: u32,
+        depth_range: Range<f32>,
+        scene_depth: Arc<LayerView<D16Unorm>>,
+    ) -> Result<Self> {
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Load,
+                        store: Store,
+                        format: format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .chain_err(|| {
+                ErrorKind::GpuResource("fail to create the render pass for the flare".to_owned())
+            })?,
+        );
+        let subpass = Subpass::from(render_pass.clone(), 0)
+            .expect("fail to retrieve the first subpass from the renderpass");
+        let viewport = [
+            width as f32,
+            height as f32,
+            depth_range.start,
+            depth_range.end,
+        ];
+        let mesh_renderer = Arc::new(
+            FlareRenderer::init(
+                device.clone(),
+                queue.clone(),
+                subpass,
+                width,
+                height,
+                depth_range,
+                BlendMode::Additive,
+            )
+            .chain_err(|| "fail to create the flare renderer")?,
+        );
+        let settings = FlareSettings::default();
+        let (mesh, uniforms) = Self::create_mesh(
+            device.clone(),
+            queue.clone(),
+            &mesh_renderer,
+            scene_depth.clone(),
+            settings.texture,
+        )?;
+        Ok(Self {
+            device,
+            queue,
+            render_pass,
+            mesh_renderer,
+            mesh,
+            uniforms,
+            scene_depth,
+            viewport,
+            settings,
+        })
+    }
+
+    fn create_mesh(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        mesh_renderer: &Arc<FlareRenderer>,
+        scene_depth: Arc<LayerView<D16Unorm>>,
+        texture: FlareTexture,
+    ) -> Result<(Mesh<FlareVertex, FlareMaterial, S>, FlareUniforms)> {
+        let image = texture.create_image(FLARE_TEXTURE_SIZE);
+        let (flare_image, flare_image_init) = ImmutableImage::from_iter(
+            image.pixels().map(|p| p.0),
+            Dimensions::Dim2d {
+                width: image.width(),
+                height: image.height(),
+            },
+            MipmapsCount::One,
+            R8G8B8A8Unorm,
+            queue,
+        )
+        .chain_err(|| "fail to create the image for the flare texture")?;
+        flare_image_init
+            .then_signal_fence_and_flush()
+            .chain_err(|| "fail to signal the fence and flush when initializing the flare texture")?
+            .wait(None)
+            .chain_err(|| "fail to wait for the flare texture being initialized")?;
+        let clamp_to_edge = SamplerAddressMode::ClampToEdge;
+        let material = FlareMaterial {
+            flare_texture: Texture {
+                image: flare_image,
+                sampler: Sampler::new(
+                    device.clone(),
+                    Filter::Linear,
+                    Filter::Linear,
+                    MipmapMode::Nearest,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    0.0,
+                    1.0,
+                    0.0,
+                    0.0,
+                )
+                .chain_err(|| "fail to create the sampler for the flare texture")?,
+            },
+            // the depth isn't interpolated, the sprite is compared against the nearest texel
+            scene_depth: Texture {
+                image: scene_depth,
+                sampler: Sampler::new(
+                    device,
+                    Filter::Nearest,
+                    Filter::Nearest,
+                    MipmapMode::Nearest,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    0.0,
+                    1.0,
+                    0.0,
+                    0.0,
+                )
+                .chain_err(|| "fail to create the sampler for the scene depth")?,
+            },
+        };
+        let quad = MeshData::create(
+            vec![
+                FlareVertex {
+                    corner: [-1.0, -1.0],
+                },
+                FlareVertex {
+                    corner: [1.0, -1.0],
+                },
+                FlareVertex { corner: [1.0, 1.0] },
+                FlareVertex {
+                    corner: [-1.0, 1.0],
+                },
+            ],
+            vec![0, 1, 2, 0, 2, 3],
+        )
+        .chain_err(|| "fail to create the quad of the flare")?;
+        mesh_renderer
+            .create_mesh(quad, &material)
+            .chain_err(|| "fail to create the flare mesh")
+    }
+
+    // the scene depth is of the new size as well
+    pub fn resize(
+        &mut self,
+        width: u32,
+        height: u32,
+        scene_depth: Arc<LayerView<D16Unorm>>,
+    ) -> Result<()> {
+        self.mesh_renderer.resize(width, height);
+        self.viewport[0] = width as f32;
+        self.viewport[1] = height as f32;
+        let (mesh, uniforms) = Self::create_mesh(
+            self.device.clone(),
+            self.queue.clone(),
+            &self.mesh_renderer,
+            scene_depth.clone(),
+            self.settings.texture,
+        )
+        .chain_err(|| "fail to bind the resized scene depth to the flare")?;
+        self.mesh = mesh;
+        self.uniforms = uniforms;
+        self.scene_depth = scene_depth;
+        Ok(())
+    }
+
+    pub fn get_settings(&self) -> FlareSettings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: FlareSettings) -> Result<()> {
+        if settings.size <= 0.0 {
+            return Err(ErrorKind::Validation(format!(
+                "the flare size should be greater than zero, size = {}",
+                settings.size
+            ))
+            .into());
+        }
+        if settings.texture != self.settings.texture {
+            let (mesh, uniforms) = Self::create_mesh(
+                self.device.clone(),
+                self.queue.clone(),
+                &self.mesh_renderer,
+                self.scene_depth.clone(),
+                settings.texture,
+            )
+            .chain_err(|| format!("fail to create the {} flare", settings.texture.get_name()))?;
+            self.mesh = mesh;
+            self.uniforms = uniforms;
+        }
+        self.settings = settings;
+        Ok(())
+    }
+
+    // The sprite is scaled and tinted by the light. The scene depth has to be written by the
+    // commands recorded before.
+    pub fn draw_commands(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        image: Arc<impl ImageViewAccess + Send + Sync + 'static>,
+        light_transform: &Transform3D<f32, S, WorldSpace>,
+        light_intensity: f32,
+        light_color: [f32; 3],
+        camera: &Camera,
+    ) -> Result<()> {
+        if !self.settings.enabled {
+            return Ok(());
+        }
+        let framebuffer = Arc::new(
+            Framebuffer::start(self.render_pass.clone())
+                .add(image)
+                .chain_err(|| "fail to add the color attachment to the framebuffer")?
+                .build()
+                .chain_err(|| "fail to create the framebuffer to draw the flare on")?,
+        );
+        self.uniforms.set_model_matrix(light_transform.to_array());
+        self.uniforms.set_view_proj_matrix_from_camera(camera);
+        let [r, g, b] = light_color;
+        self.uniforms.uniform.color = [
+            r * light_intensity,
+            g * light_intensity,
+            b * light_intensity,
+            1.0,
+        ];
+        self.uniforms.uniform.viewport = self.viewport;
+        self.uniforms.uniform.size = self.settings.size * light_intensity;
+        self.uniforms
+            .update_buffers(cmd_buf_builder)
+            .chain_err(|| {
+                "fail to add the update buffer for uniforms command to the command builder"
+            })?;
+        cmd_buf_builder
+            .begin_render_pass(framebuffer, SubpassContents::Inline, vec![ClearValue::None])
+            .chain_err(|| "fail to add the begin renderpass command to the command builder")?;
+        self.mesh
+            .draw_commands(cmd_buf_builder, RasterState::default())
+            .chain_err(|| "fail to issue draw commands for the flare mesh")?;
+        cmd_buf_builder
+            .end_render_pass()
+            .chain_err(|| "fail to add the end renderpass command to the command builder")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alpha_at(image: &RgbaImage, x: u32, y: u32) -> u8 {
+        image.get_pixel(x, y)[3]
+    }
+
+    #[test]
+    fn test_flare_textures_fade_out_at_the_border() {
+        for texture in FlareTexture::ALL.iter() {
+            let image = texture.create_image(64);
+            assert_eq!(alpha_at(&image, 0, 0), 0, "{:?}", texture);
+            assert_eq!(alpha_at(&image, 63, 16), 0, "{:?}", texture);
+            assert_eq!(
+                alpha_at(&image, 10, 20),
+                alpha_at(&image, 53, 43),
+                "{:?} should be symmetric",
+                texture
+            );
+        }
+    }
+
+    #[test]
+    fn test_radial_gradient_is_brightest_at_the_center() {
+        let image = FlareTexture::RadialGradient.create_image(64);
+        assert!(alpha_at(&image, 32, 32) > 240);
+        assert!(alpha_at(&image, 32, 32) > alpha_at(&image, 32, 48));
+        assert!(alpha_at(&image, 32, 48) > alpha_at(&image, 32, 60));
+    }
+
+    #[test]
+    fn test_ring_is_brighter_on_the_ring_than_at_the_center() {
+        let image = FlareTexture::Ring.create_image(64);
+        // 0.7 of the radius from the center
+        assert!(alpha_at(&image, 32, 54) > alpha_at(&image, 32, 32));
+    }
+}
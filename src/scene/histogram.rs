@@ -0,0 +1,256 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    mem::size_of,
+    sync::{mpsc, Arc},
+};
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
+    descriptor::{
+        descriptor_set::PersistentDescriptorSet,
+        pipeline_layout::{PipelineLayout, PipelineLayoutAbstract},
+    },
+    device::{Device, DeviceOwned},
+    image::AttachmentImage,
+    pipeline::ComputePipeline,
+    sampler::Sampler,
+};
+
+use super::shaders::histogram::compute_shader;
+use crate::{errors::*, readback::ReadbackQueue};
+
+// the luminance of [0, 1] is split evenly, the same as the shader
+pub const HISTOGRAM_BINS: usize = 256;
+// the size of the workgroups in both dimensions, the same as the shader
+const WORKGROUP_SIZE: u32 = 16;
+// the histograms recorded but whose stats aren't computed yet, more than the frames in flight, the
+// frames beyond are skipped
+const MAX_IN_FLIGHT_HISTOGRAMS: usize = 5;
+
+type HistogramPipeline = ComputePipeline<PipelineLayout<compute_shader::Layout>>;
+
+// the storage buffer of the shader
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct HistogramData {
+    bins: [u32; HISTOGRAM_BINS],
+    clipped_count: u32,
+    // the bits of the luminance, which are ordered the same as the non negative floats
+    min_luminance: u32,
+    max_luminance: u32,
+}
+
+impl Default for HistogramData {
+    fn default() -> Self {
+        Self {
+            bins: [0; HISTOGRAM_BINS],
+            clipped_count: 0,
+            min_luminance: u32::MAX,
+            max_luminance: 0,
+        }
+    }
+}
+
+// The luminance of the linear colors of the scene image, decoded from the gamma of the shaders if
+// it's encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuminanceStats {
+    // the fraction of the pixels in each bin
+    pub bins: Vec<f32>,
+    pub min: f32,
+    // approximated by the centers of the bins
+    pub average: f32,
+    pub max: f32,
+    // the fraction of the pixels with a channel saturated in the scene image
+    pub clipped: f32,
+}
+
+impl LuminanceStats {
+    // None for an empty image
+    fn from_histogram(histogram: &HistogramData) -> Option<Self> {
+        let pixel_count: u64 = histogram.bins.iter().map(|&count| u64::from(count)).sum();
+        if pixel_count == 0 {
+            return None;
+        }
+        let pixel_count = pixel_count as f64;
+        let weighted_sum: f64 = histogram
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| (i as f64 + 0.5) / HISTOGRAM_BINS as f64 * f64::from(count))
+            .sum();
+        Some(Self {
+            bins: histogram
+                .bins
+                .iter()
+                .map(|&count| (f64::from(count) / pixel_count) as f32)
+                .collect(),
+            min: f32::from_bits(histogram.min_luminance),
+            average: (weighted_sum / pixel_count) as f32,
+            max: f32::from_bits(histogram.max_luminance),
+            clipped: (f64::from(histogram.clipped_count) / pixel_count) as f32,
+        })
+    }
+}
+
+// Builds the luminance histogram of the scene image with a compute pass, which is read back
+// without waiting for the GPU by a readback queue the same way as the screenshots.
+pub struct LuminanceHistogram {
+    pipeline: Arc<HistogramPipeline>,
+    sampler: Arc<Sampler>,
+    readbacks: ReadbackQueue<HistogramData>,
+    stats_sender: mpsc::Sender<Option<LuminanceStats>>,
+    stats_receiver: mpsc::Receiver<Option<LuminanceStats>>,
+}
+
+impl LuminanceHistogram {
+    pub fn new(device: Arc<Device>) -> Result<Self> {
+        let shader = compute_shader::Shader::load(device.clone())
+            .chain_err(|| "fail to load the histogram compute shader")?;
+        let pipeline = Arc::new(
+            ComputePipeline::new(device.clone(), &shader.main_entry_point(), &(), None).chain_err(
+                || ErrorKind::GpuResource("fail to create the histogram pipeline".to_owned()),
+            )?,
+        );
+        let (stats_sender, stats_receiver) = mpsc::channel();
+        Ok(Self {
+            sampler: Sampler::simple_repeat_linear_no_mipmap(device.clone()),
+            readbacks: ReadbackQueue::new(device)
+                .chain_err(|| "fail to create the histogram readback queue")?,
+            pipeline,
+            stats_sender,
+            stats_receiver,
+        })
+    }
+
+    fn get_buffer(&mut self) -> Result<Arc<CpuAccessibleBuffer<HistogramData>>> {
+        if let Some(buffer) = self.readbacks.take_free_buffer(size_of::<HistogramData>()) {
+            return Ok(buffer);
+        }
+        CpuAccessibleBuffer::from_data(
+            self.pipeline.device().clone(),
+            BufferUsage {
+                storage_buffer: true,
+                transfer_destination: true,
+                ..BufferUsage::none()
+            },
+            true,
+            HistogramData::default(),
+        )
+        .chain_err(|| ErrorKind::GpuResource("fail to create the histogram buffer".to_owned()))
+    }
+
+    // Record the compute pass over the image, which should be sampled, and is gamma encoded if
+    // the gamma correction of the renderer is enabled. Skipped when too many histograms are still
+    // in flight.
+    pub fn record(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        image: Arc<AttachmentImage>,
+        gamma_correction: bool,
+    ) -> Result<()> {
+        if self.readbacks.get_in_flight_count() >= MAX_IN_FLIGHT_HISTOGRAMS {
+            return Ok(());
+        }
+        let [width, height] = AttachmentImage::dimensions(&image);
+        let buffer = self.get_buffer()?;
+        let layout = self
+            .pipeline
+            .descriptor_set_layout(0)
+            .ok_or_else(|| -> Error { "can't find the descriptor set at the index 0".into() })?;
+        let descriptor_set = Arc::new(
+            PersistentDescriptorSet::start(layout.clone())
+                .add_sampled_image(image, self.sampler.clone())
+                .chain_err(|| "fail to add the scene image to the histogram descriptor set")?
+                .add_buffer(buffer.clone())
+                .chain_err(|| "fail to add the histogram buffer to the descriptor set")?
+                .build()
+                .chain_err(|| "fail to create the histogram descriptor set")?,
+        );
+        cmd_buf_builder
+            // the reused buffers hold the histograms of the previous frames
+            .update_buffer(buffer.clone(), HistogramData::default())
+            .chain_err(|| "fail to add the clear of the histogram buffer to the command builder")?
+            .dispatch(
+                [
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ],
+                self.pipeline.clone(),
+                descriptor_set,
+                compute_shader::ty::PushConstants {
+                    gamma_correction: gamma_correction as u32,
+                },
+            )
+            .chain_err(|| "fail to add the histogram dispatch to the command builder")?;
+        let stats_sender = self.stats_sender.clone();
+        self.readbacks.record_buffer(buffer, move |histogram| {
+            // the receiver is only gone when the histogram is dropped
+            let _ = stats_sender.send(LuminanceStats::from_histogram(&histogram));
+            Ok(())
+        });
+        Ok(())
+    }
+
+    // the command buffer with the recorded histogram is submitted
+    pub fn submitted(&mut self) {
+        self.readbacks.submitted();
+    }
+
+    // the command buffer with the recorded histogram fails to be submitted
+    pub fn discard_recorded(&mut self) {
+        self.readbacks.discard_recorded();
+    }
+
+    // Hand the finished histograms to the readback worker, and return the errors since the last
+    // poll. Never waits for the GPU.
+    pub fn poll(&mut self) -> Vec<Error> {
+        self.readbacks.poll()
+    }
+
+    // the stats of the latest histogram read back, None if no histogram has been read back since
+    // the last call or the image is empty
+    pub fn take_stats(&mut self) -> Option<LuminanceStats> {
+        self.stats_receiver.try_iter().last().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_from_histogram() {
+        let mut histogram = HistogramData::default();
+        assert_eq!(LuminanceStats::from_histogram(&histogram), None);
+        histogram.bins[0] = 3;
+        histogram.bins[HISTOGRAM_BINS - 1] = 1;
+        histogram.clipped_count = 1;
+        histogram.min_luminance = 0.0f32.to_bits();
+        histogram.max_luminance = 1.0f32.to_bits();
+        let stats = LuminanceStats::from_histogram(&histogram).unwrap();
+        assert_eq!(stats.bins.len(), HISTOGRAM_BINS);
+        assert_eq!(stats.bins[0], 0.75);
+        assert_eq!(stats.bins[HISTOGRAM_BINS - 1], 0.25);
+        assert_eq!(stats.bins.iter().sum::<f32>(), 1.0);
+        assert_eq!((stats.min, stats.max), (0.0, 1.0));
+        assert_eq!(stats.clipped, 0.25);
+        let expected_average = (0.5 * 3.0 + (HISTOGRAM_BINS as f32 - 0.5)) / 4.0 / 256.0;
+        assert!((stats.average - expected_average).abs() < 1e-6);
+    }
+
+    // the float bits of the luminance are compared as uints by the shader
+    #[test]
+    fn test_luminance_bits_are_ordered() {
+        let luminance = [0.0f32, 1e-6, 0.25, 0.5, 0.75, 1.0];
+        for pair in luminance.windows(2) {
+            assert!(pair[0].to_bits() < pair[1].to_bits());
+        }
+    }
+}
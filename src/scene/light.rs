@@ -5,7 +5,7 @@
 
 use std::sync::Arc;
 
-use euclid::{Point3D, Transform3D};
+use euclid::{vec3, Angle, Point3D, Transform3D, Vector3D};
 use vulkano::{
     command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
     device::{Device, Queue},
@@ -25,6 +25,10 @@ use crate::errors::*;
 pub struct EmissiveMaterial {
     light_intensity: f32,
     light_color: [f32; 3],
+    // draw the raw color without the intensity, as a marker of the light
+    unlit: bool,
+    // see Renderer::set_gamma_correction
+    gamma_correction: bool,
 }
 
 impl EmissiveMaterial {
@@ -32,6 +36,8 @@ impl EmissiveMaterial {
         Self {
             light_intensity,
             light_color,
+            unlit: false,
+            gamma_correction: true,
         }
     }
 }
@@ -49,13 +55,15 @@ impl Material for EmissiveMaterial {
                 model: Default::default(),
                 view: Default::default(),
                 proj: Default::default(),
-                light_intensity: self.light_intensity,
                 light_color: [
                     self.light_color[0],
                     self.light_color[1],
                     self.light_color[2],
                     1.0,
                 ],
+                light_intensity: self.light_intensity,
+                unlit: self.unlit as u32,
+                gamma_correction: self.gamma_correction as u32,
             },
         )
     }
@@ -76,8 +84,54 @@ impl SimpleVertex for PointLightVertex {
     }
 }
 
+// the tessellation of the sphere drawn at a point light
+const POINT_LIGHT_SPHERE_RINGS: u32 = 12;
+const POINT_LIGHT_SPHERE_SECTORS: u32 = 24;
+
 pub type PointLightRenderer = MeshRenderer<PointLightVertex, EmissiveMaterial>;
 
+// the size of the light arrays of the phong shaders, MAX_POINT_LIGHTS there
+pub const MAX_POINT_LIGHTS: usize = 8;
+
+// a vec4 per point light
+pub type LightArray = [[f32; 4]; MAX_POINT_LIGHTS];
+
+// a point light as the phong shaders see it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightInfo {
+    pub position: Point3D<f32, WorldSpace>,
+    // linear
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl LightInfo {
+    // The positions and the colors premultiplied by the intensities of the lights, padded to
+    // MAX_POINT_LIGHTS, as the phong shaders expect.
+    pub fn to_uniform(lights: &[LightInfo]) -> Result<(LightArray, LightArray)> {
+        if lights.len() > MAX_POINT_LIGHTS {
+            bail!(ErrorKind::Validation(format!(
+                "{} point lights, at most {} are supported",
+                lights.len(),
+                MAX_POINT_LIGHTS
+            )));
+        }
+        let mut positions = [[0.0; 4]; MAX_POINT_LIGHTS];
+        let mut colors = [[0.0; 4]; MAX_POINT_LIGHTS];
+        for (i, light) in lights.iter().enumerate() {
+            let [r, g, b] = light.color;
+            positions[i] = [light.position.x, light.position.y, light.position.z, 1.0];
+            colors[i] = [
+                r * light.intensity,
+                g * light.intensity,
+                b * light.intensity,
+                0.0,
+            ];
+        }
+        Ok((positions, colors))
+    }
+}
+
 pub struct PointLight<S> {
     pub material: EmissiveMaterial,
     pub mesh: Mesh<PointLightVertex, EmissiveMaterial, S>,
@@ -91,8 +145,13 @@ impl<S> PointLight<S> {
         light_color: [f32; 3],
     ) -> Result<Self> {
         let material = EmissiveMaterial::new(light_intensity, light_color);
+        // the gizmo drawn at the light
+        let mesh_data = MeshData::<PointLightVertex>::uv_sphere(
+            POINT_LIGHT_SPHERE_RINGS,
+            POINT_LIGHT_SPHERE_SECTORS,
+        )?;
         let (mesh, uniforms) = mesh_renderer
-            .create_mesh(MeshData::<PointLightVertex>::cube(), &material)
+            .create_mesh(mesh_data, &material)
             .chain_err(|| "fail to create mesh")?;
         Ok(Self {
             material,
@@ -126,4 +185,217 @@ impl<S> PointLight<S> {
     pub fn get_intensity(&self) -> f32 {
         self.uniforms.uniform.light_intensity
     }
+
+    pub fn get_color(&self) -> [f32; 3] {
+        self.material.light_color
+    }
+
+    pub fn set_color_and_intensity(&mut self, color: [f32; 3], intensity: f32) {
+        self.material.light_color = color;
+        self.material.light_intensity = intensity;
+        self.uniforms.uniform.light_color = [color[0], color[1], color[2], 1.0];
+        self.uniforms.uniform.light_intensity = intensity;
+    }
+
+    pub fn get_light_info(&self) -> Result<LightInfo> {
+        Ok(LightInfo {
+            position: self.get_position()?,
+            color: self.get_color(),
+            intensity: self.get_intensity(),
+        })
+    }
+
+    pub fn set_unlit(&mut self, unlit: bool) {
+        self.material.unlit = unlit;
+        self.uniforms.uniform.unlit = unlit as u32;
+    }
+
+    pub fn set_gamma_correction(&mut self, enabled: bool) {
+        self.material.gamma_correction = enabled;
+        self.uniforms.uniform.gamma_correction = enabled as u32;
+    }
+}
+
+// An ambient light that fades from the ground color below to the sky color above, a cheap
+// approximation of the light bounced from the environment. When disabled, the phong shaders fall
+// back to the flat ambient.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HemisphereAmbient {
+    pub enabled: bool,
+    // linear colors
+    pub sky_color: [f32; 3],
+    pub ground_color: [f32; 3],
+    pub intensity: f32,
+    pub up: Vector3D<f32, WorldSpace>,
+}
+
+impl Default for HemisphereAmbient {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sky_color: [0.6, 0.7, 1.0],
+            ground_color: [0.4, 0.3, 0.2],
+            intensity: 0.3,
+            up: vec3(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl HemisphereAmbient {
+    // The sky color and the ground color premultiplied by the intensity, and the up direction, as
+    // the phong shaders expect. Like the flat ambient, the shaders multiply the interpolated color
+    // with kd or the texture.
+    pub fn to_uniform(self) -> [[f32; 4]; 3] {
+        let premultiply = |[r, g, b]: [f32; 3]| {
+            [
+                r * self.intensity,
+                g * self.intensity,
+                b * self.intensity,
+                0.0,
+            ]
+        };
+        [
+            premultiply(self.sky_color),
+            premultiply(self.ground_color),
+            [self.up.x, self.up.y, self.up.z, 0.0],
+        ]
+    }
+}
+
+// A light infinitely far away like the sun, lighting every point from the same direction without
+// falloff. The phong shaders add it to the point light, so a zero intensity turns it off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    // the direction the light travels in, from the light towards the scene
+    pub direction: Vector3D<f32, WorldSpace>,
+    // linear
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            direction: vec3(-1.0, -1.0, -1.0),
+            color: [1.0, 1.0, 1.0],
+            intensity: 0.0,
+        }
+    }
+}
+
+impl DirectionalLight {
+    // The light from the point in the sky at the elevation above the x-z plane, and the azimuth
+    // around y from z towards x. The y is up like the default of the hemisphere ambient.
+    pub fn from_sky(
+        azimuth: Angle<f32>,
+        elevation: Angle<f32>,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        let (sin_azimuth, cos_azimuth) = azimuth.sin_cos();
+        let (sin_elevation, cos_elevation) = elevation.sin_cos();
+        Self {
+            direction: -vec3(
+                sin_azimuth * cos_elevation,
+                sin_elevation,
+                cos_azimuth * cos_elevation,
+            ),
+            color,
+            intensity,
+        }
+    }
+
+    // The normalized direction towards the light, and the color premultiplied by the intensity,
+    // as the phong shaders expect.
+    pub fn to_uniform(self) -> [[f32; 4]; 2] {
+        let to_light = -self
+            .direction
+            .try_normalize()
+            .unwrap_or_else(Vector3D::zero);
+        let [r, g, b] = self.color;
+        [
+            [to_light.x, to_light.y, to_light.z, 0.0],
+            [
+                r * self.intensity,
+                g * self.intensity,
+                b * self.intensity,
+                0.0,
+            ],
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::approxeq::ApproxEq;
+
+    #[test]
+    fn test_hemisphere_ambient_uniform() {
+        let ambient = HemisphereAmbient {
+            enabled: true,
+            sky_color: [0.0, 0.25, 1.0],
+            ground_color: [1.0, 0.5, 0.0],
+            intensity: 2.0,
+            up: vec3(0.0, 0.0, 3.0),
+        };
+        assert_eq!(
+            ambient.to_uniform(),
+            [
+                [0.0, 0.5, 2.0, 0.0],
+                [2.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 3.0, 0.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_directional_light_uniform() {
+        let light = DirectionalLight {
+            direction: vec3(0.0, -2.0, 0.0),
+            color: [1.0, 0.5, 0.0],
+            intensity: 2.0,
+        };
+        assert_eq!(
+            light.to_uniform(),
+            [[0.0, 1.0, 0.0, 0.0], [2.0, 1.0, 0.0, 0.0]]
+        );
+    }
+
+    #[test]
+    fn test_directional_light_from_sky() {
+        let eps = vec3(1e-5, 1e-5, 1e-5);
+        // straight above
+        let light = DirectionalLight::from_sky(Angle::zero(), Angle::frac_pi_2(), [1.0; 3], 1.0);
+        assert!(light.direction.approx_eq_eps(&vec3(0.0, -1.0, 0.0), &eps));
+        // on the horizon towards x
+        let light = DirectionalLight::from_sky(Angle::frac_pi_2(), Angle::zero(), [1.0; 3], 1.0);
+        assert!(light.direction.approx_eq_eps(&vec3(-1.0, 0.0, 0.0), &eps));
+    }
+
+    #[test]
+    fn test_light_info_uniform() {
+        let lights = [
+            LightInfo {
+                position: Point3D::new(1.0, 2.0, 3.0),
+                color: [1.0, 0.5, 0.0],
+                intensity: 2.0,
+            },
+            LightInfo {
+                position: Point3D::origin(),
+                color: [0.0, 0.0, 1.0],
+                intensity: 0.5,
+            },
+        ];
+        let (positions, colors) = LightInfo::to_uniform(&lights).unwrap();
+        assert_eq!(positions[0], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(colors[0], [2.0, 1.0, 0.0, 0.0]);
+        assert_eq!(colors[1], [0.0, 0.0, 0.5, 0.0]);
+        assert_eq!(colors[2], [0.0; 4]);
+
+        let too_many = vec![lights[0]; MAX_POINT_LIGHTS + 1];
+        let e = LightInfo::to_uniform(&too_many).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)));
+    }
 }
@@ -3,17 +3,117 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use std::sync::Arc;
+use std::{f32::consts::PI, sync::Arc, time::Duration};
 
+use euclid::Transform3D;
 use vulkano::device::{Device, Queue};
 
 use super::{shaders::ShadersT, Camera};
 use crate::errors::*;
 
+// Scales the variance of the normal over a pixel, which widens the specular highlights of the
+// phong materials against their shimmering on the curved and finely tessellated surfaces. 0
+// disables the specular anti-aliasing.
+pub const DEFAULT_SPECULAR_AA_STRENGTH: f32 = 1.0;
+pub const MAX_SPECULAR_AA_STRENGTH: f32 = 4.0;
+
+// a Validation error if the strength is outside of [0, MAX_SPECULAR_AA_STRENGTH]
+pub fn validate_specular_aa_strength(strength: f32) -> Result<()> {
+    if !(0.0..=MAX_SPECULAR_AA_STRENGTH).contains(&strength) {
+        bail!(ErrorKind::Validation(format!(
+            "the specular anti-aliasing strength {} is not within [0, {}]",
+            strength, MAX_SPECULAR_AA_STRENGTH
+        )));
+    }
+    Ok(())
+}
+
+// What the phong shaders color the objects by, the shading or a debug view of the geometry. The
+// light markers and the skybox are drawn the same in every mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    #[default]
+    Shaded,
+    // the world space normal, after the normal map, mapped from [-1, 1] to [0, 1]
+    Normals,
+    // the distance to the camera, black at the near plane and white at the far plane
+    Depth,
+}
+
+impl RenderMode {
+    pub const ALL: [Self; 3] = [Self::Shaded, Self::Normals, Self::Depth];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Shaded => "shaded",
+            Self::Normals => "normals",
+            Self::Depth => "depth",
+        }
+    }
+
+    // the render_mode of the phong fragment uniforms, the same as RENDER_MODE_* in the shader
+    pub fn to_uniform(self) -> u32 {
+        match self {
+            Self::Shaded => 0,
+            Self::Normals => 1,
+            Self::Depth => 2,
+        }
+    }
+}
+
+// The exponent of the highlights of the materials without Ns, the one the phong shaders used before
+// the materials had their own. Ns is clamped to [MIN_SHININESS, MAX_SHININESS], the range of the
+// mtl files but 0, which would light the whole surface with the highlight.
+pub const DEFAULT_SHININESS: f32 = 35.0;
+pub const MIN_SHININESS: f32 = 1.0;
+pub const MAX_SHININESS: f32 = 1000.0;
+
+// a Validation error if the shininess is outside of [MIN_SHININESS, MAX_SHININESS]
+pub fn validate_shininess(shininess: f32) -> Result<()> {
+    if !(MIN_SHININESS..=MAX_SHININESS).contains(&shininess) {
+        bail!(ErrorKind::Validation(format!(
+            "the shininess {} is not within [{}, {}]",
+            shininess, MIN_SHININESS, MAX_SHININESS
+        )));
+    }
+    Ok(())
+}
+
+// How the phong shaders shape the highlights, the same exponent gives a wider highlight with
+// Blinn-Phong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpecularModel {
+    // the reflected light direction against the view direction
+    #[default]
+    Phong,
+    // the half vector between the light and the view directions against the normal
+    BlinnPhong,
+}
+
+impl SpecularModel {
+    pub const ALL: [Self; 2] = [Self::Phong, Self::BlinnPhong];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Phong => "Phong",
+            Self::BlinnPhong => "Blinn-Phong",
+        }
+    }
+
+    // the specular_model of the phong fragment uniforms, the same as SPECULAR_MODEL_* in the shader
+    pub fn to_uniform(self) -> u32 {
+        match self {
+            Self::Phong => 0,
+            Self::BlinnPhong => 1,
+        }
+    }
+}
+
 pub trait SetCamera {
     fn set_model_matrix(&mut self, mat: [f32; 16]);
     fn set_view_matrix(&mut self, mat: [f32; 16]);
     fn set_proj_matrix(&mut self, mat: [f32; 16]);
+    fn set_normal_matrix(&mut self, mat: [f32; 16]);
 
     fn set_view_proj_matrix_from_camera(&mut self, camera: &Camera) {
         self.set_view_matrix(camera.get_view_transform().to_array());
@@ -21,6 +121,51 @@ pub trait SetCamera {
     }
 }
 
+// The inverse transpose of the upper 3x3 of the model matrix, which keeps the normals
+// perpendicular to the surfaces under non-uniform scale. None if the model matrix is singular.
+pub fn normal_matrix<Src, Dst>(
+    model: &Transform3D<f32, Src, Dst>,
+) -> Option<Transform3D<f32, Src, Dst>> {
+    let [x, y, z, _] = model.inverse()?.to_arrays_transposed();
+    Some(Transform3D::from_arrays([
+        [x[0], x[1], x[2], 0.0],
+        [y[0], y[1], y[2], 0.0],
+        [z[0], z[1], z[2], 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]))
+}
+
+// Scrolls and rotates the texture coordinates of a textured material over the scene time, for
+// effects like a moving caustics layer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct UvAnimation {
+    // in texture coordinates per second
+    pub scroll_velocity: [f32; 2],
+    // in radians per second, around the center of the texture
+    pub rotation_speed: f32,
+}
+
+impl UvAnimation {
+    pub fn is_static(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // The rows of the 2x3 affine transform of the texture coordinates, rotating them around the
+    // center and then scrolling them. The offset and the angle wrap around, so that they don't
+    // lose precision as the time grows.
+    pub fn transform_at(&self, time: Duration) -> [[f32; 4]; 2] {
+        let t = time.as_secs_f64();
+        let wrap = |speed: f32, period: f64| ((f64::from(speed) * t) % period) as f32;
+        let (sin, cos) = wrap(self.rotation_speed, 2.0 * f64::from(PI)).sin_cos();
+        let offset_u = wrap(self.scroll_velocity[0], 1.0);
+        let offset_v = wrap(self.scroll_velocity[1], 1.0);
+        [
+            [cos, -sin, 0.5 - 0.5 * cos + 0.5 * sin + offset_u, 0.0],
+            [sin, cos, 0.5 - 0.5 * sin - 0.5 * cos + offset_v, 0.0],
+        ]
+    }
+}
+
 pub trait Material {
     type Shaders: ShadersT;
 
@@ -30,3 +175,124 @@ pub trait Material {
         queue: Arc<Queue>,
     ) -> Result<<Self::Shaders as ShadersT>::Uniforms>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::{point3, vec3, Angle, Point3D};
+
+    use crate::scene::{TriangleSpace, WorldSpace};
+
+    #[test]
+    fn test_normals_stay_perpendicular_under_non_uniform_scale() {
+        let model = Transform3D::<f32, TriangleSpace, WorldSpace>::scale(1.0, 4.0, 0.5)
+            .then_rotate(1.0, 1.0, 0.0, Angle::radians(0.7))
+            .then_translate(vec3(3.0, -2.0, 1.0));
+        let triangle: [Point3D<f32, TriangleSpace>; 3] = [
+            point3(0.0, 0.0, 0.0),
+            point3(1.0, 2.0, 0.0),
+            point3(0.0, 1.0, 3.0),
+        ];
+        let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+        let [a, b, c] = [
+            model.transform_point3d(triangle[0]).unwrap(),
+            model.transform_point3d(triangle[1]).unwrap(),
+            model.transform_point3d(triangle[2]).unwrap(),
+        ];
+        let transformed_normal = normal_matrix(&model)
+            .unwrap()
+            .transform_vector3d(normal)
+            .normalize();
+        for edge in [b - a, c - a, c - b].iter() {
+            assert!(
+                transformed_normal.dot(edge.normalize()).abs() < 1e-5,
+                "{:?} is not perpendicular to {:?}",
+                transformed_normal,
+                edge
+            );
+        }
+        // the model matrix itself skews the normal
+        let skewed_normal = model.transform_vector3d(normal).normalize();
+        assert!(skewed_normal.dot((b - a).normalize()).abs() > 1e-2);
+    }
+
+    fn apply_uv_transform([u, v]: [f32; 2], transform: [[f32; 4]; 2]) -> [f32; 2] {
+        let [row_u, row_v] = transform;
+        [
+            row_u[0] * u + row_u[1] * v + row_u[2],
+            row_v[0] * u + row_v[1] * v + row_v[2],
+        ]
+    }
+
+    #[test]
+    fn test_uv_animation() {
+        let still = UvAnimation::default().transform_at(Duration::from_secs(10));
+        assert_eq!(apply_uv_transform([0.25, 0.75], still), [0.25, 0.75]);
+
+        let scroll = UvAnimation {
+            scroll_velocity: [0.1, -0.2],
+            rotation_speed: 0.0,
+        };
+        let [u, v] = apply_uv_transform([0.0, 0.0], scroll.transform_at(Duration::from_secs(2)));
+        assert!(
+            (u - 0.2).abs() < 1e-5 && (v + 0.4).abs() < 1e-5,
+            "{} {}",
+            u,
+            v
+        );
+
+        // a quarter turn around the center
+        let rotation = UvAnimation {
+            scroll_velocity: [0.0, 0.0],
+            rotation_speed: PI / 2.0,
+        };
+        let transform = rotation.transform_at(Duration::from_secs(1));
+        let [u, v] = apply_uv_transform([0.5, 0.5], transform);
+        assert!((u - 0.5).abs() < 1e-5 && (v - 0.5).abs() < 1e-5);
+        let [u, v] = apply_uv_transform([1.0, 0.5], transform);
+        assert!(
+            (u - 0.5).abs() < 1e-5 && (v - 1.0).abs() < 1e-5,
+            "{} {}",
+            u,
+            v
+        );
+    }
+
+    #[test]
+    fn test_validate_specular_aa_strength() {
+        assert!(validate_specular_aa_strength(0.0).is_ok());
+        assert!(validate_specular_aa_strength(DEFAULT_SPECULAR_AA_STRENGTH).is_ok());
+        assert!(validate_specular_aa_strength(MAX_SPECULAR_AA_STRENGTH).is_ok());
+        assert!(validate_specular_aa_strength(-0.1).is_err());
+        assert!(validate_specular_aa_strength(MAX_SPECULAR_AA_STRENGTH + 0.1).is_err());
+        assert!(validate_specular_aa_strength(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_normal_matrix_of_singular_model() {
+        let model = Transform3D::<f32, TriangleSpace, WorldSpace>::scale(1.0, 0.0, 1.0);
+        assert!(normal_matrix(&model).is_none());
+    }
+
+    #[test]
+    fn test_validate_shininess() {
+        assert!(validate_shininess(MIN_SHININESS).is_ok());
+        assert!(validate_shininess(DEFAULT_SHININESS).is_ok());
+        assert!(validate_shininess(MAX_SHININESS).is_ok());
+        assert!(validate_shininess(0.0).is_err());
+        assert!(validate_shininess(MAX_SHININESS + 1.0).is_err());
+        assert!(validate_shininess(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_render_mode_uniforms() {
+        // the shader shades every value but the debug views
+        assert_eq!(RenderMode::default().to_uniform(), 0);
+        let uniforms: Vec<_> = RenderMode::ALL
+            .iter()
+            .map(|mode| mode.to_uniform())
+            .collect();
+        assert_eq!(uniforms, vec![0, 1, 2]);
+    }
+}
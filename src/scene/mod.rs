@@ -1,14 +1,51 @@
+mod bvh;
 mod camera;
+mod color;
+mod denoise;
+mod flare;
+mod histogram;
 mod light;
 mod material;
+mod noise;
 mod object;
+mod path_tracer;
+mod pixel_probe;
 mod renderer;
+mod sh;
 mod shaders;
+mod shadow;
+mod skybox;
+mod units;
+mod upload;
+mod viewport;
 
-pub use camera::{Camera, CameraControl, Direction as CameraDirection};
+pub use camera::{
+    smooth_near_far, Camera, CameraConfig, CameraControl, CameraMatrices,
+    Direction as CameraDirection, RollDirection, MIN_AUTO_NEAR,
+};
+pub use color::{linear_to_srgb, srgb_to_linear};
+pub use denoise::DenoiseParams;
+pub use flare::FlareTexture;
+pub use histogram::{LuminanceHistogram, LuminanceStats};
+pub use light::{DirectionalLight, HemisphereAmbient, MAX_POINT_LIGHTS};
+pub use material::{
+    RenderMode, SpecularModel, DEFAULT_SHININESS, MAX_SHININESS, MAX_SPECULAR_AA_STRENGTH,
+    MIN_SHININESS,
+};
+pub use path_tracer::DEFAULT_PATH_TRACE_SAMPLES;
+pub use pixel_probe::{PixelProbe, ProbedPixel};
+pub use shaders::dump_shader_reflection;
+pub use shadow::{ShadowConfig, ShadowFilter, MAX_PCF_KERNEL_RADIUS};
+pub use skybox::{find_skybox_faces, MAX_AMBIENT_STRENGTH};
+pub use units::SceneUnits;
+pub use viewport::{LogicalPixelSpace, ViewportMapper};
 
 pub struct NDCSpace;
 pub struct ViewSpace;
 pub struct WorldSpace;
 pub struct TriangleSpace;
-pub use renderer::{ModelAndTexture, Renderer, State};
+pub use renderer::{
+    CullMode, FailedResource, FrontFace, ImportOptions, LengthUnit, ModelAndTexture,
+    PointLightState, RasterState, Renderer, State, UpAxis, DEFAULT_DEPTH_RANGE,
+    DEFAULT_LIGHT_INTENSITY,
+};
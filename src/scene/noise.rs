@@ -0,0 +1,262 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use image::{ImageBuffer, Rgba};
+
+pub const DEFAULT_NOISE_SEED: u64 = 202;
+
+const SSAO_KERNEL_SIZE: usize = 64;
+// POISSON_DISK_SIZE and ROTATION_NOISE_SIZE in the phong fragment shader
+pub const POISSON_DISK_SIZE: usize = 32;
+pub const ROTATION_NOISE_SIZE: usize = 4;
+const POISSON_DISK_MAX_ATTEMPTS: usize = 10000;
+
+// SplitMix64, small enough to keep the sequences identical across platforms and crate versions
+#[derive(Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// the samples of the Poisson disk, two in each element, as the phong shaders expect
+pub type PoissonDiskArray = [[f32; 4]; POISSON_DISK_SIZE / 2];
+// the cosines and the sines of the rotations, row major, as the phong shaders expect
+pub type RotationNoiseArray = [[f32; 4]; ROTATION_NOISE_SIZE * ROTATION_NOISE_SIZE];
+// tiled over the screen, the cosine and the sine of a random rotation around +z in rg, ba unused
+pub type RotationNoiseTexture = ImageBuffer<Rgba<f32>, Vec<f32>>;
+
+// All the random sequences used by the stochastic effects, derived from a single seed so that two
+// runs with the same seed produce bit-identical results.
+pub struct NoiseResources {
+    seed: u64,
+    // xyz is a sample inside the unit hemisphere around +z, w is unused
+    ssao_kernel: Vec<[f32; 4]>,
+    // ROTATION_NOISE_SIZE x ROTATION_NOISE_SIZE, rotates the SSAO kernel and the PCF taps
+    rotation_noise: RotationNoiseTexture,
+    // samples inside the unit disk, the offsets of the PCF taps
+    poisson_disk: Vec<[f32; 2]>,
+    // mixed with the frame index, see jitter
+    jitter_seed: u64,
+}
+
+impl NoiseResources {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let ssao_kernel = Self::generate_ssao_kernel(&mut rng);
+        let rotation_noise = Self::generate_rotation_noise(&mut rng);
+        let poisson_disk = Self::generate_poisson_disk(&mut rng);
+        let jitter_seed = rng.next_u64();
+        Self {
+            seed,
+            ssao_kernel,
+            rotation_noise,
+            poisson_disk,
+            jitter_seed,
+        }
+    }
+
+    fn generate_ssao_kernel(rng: &mut Rng) -> Vec<[f32; 4]> {
+        (0..SSAO_KERNEL_SIZE)
+            .map(|i| {
+                let x = rng.next_f32() * 2.0 - 1.0;
+                let y = rng.next_f32() * 2.0 - 1.0;
+                let z = rng.next_f32();
+                let length = (x * x + y * y + z * z).sqrt().max(f32::EPSILON);
+                // push the samples closer to the origin
+                let t = i as f32 / SSAO_KERNEL_SIZE as f32;
+                let scale = (0.1 + 0.9 * t * t) * rng.next_f32();
+                [
+                    x / length * scale,
+                    y / length * scale,
+                    z / length * scale,
+                    0.0,
+                ]
+            })
+            .collect()
+    }
+
+    fn generate_rotation_noise(rng: &mut Rng) -> RotationNoiseTexture {
+        let size = ROTATION_NOISE_SIZE as u32;
+        ImageBuffer::from_fn(size, size, |_, _| {
+            let angle = rng.next_f32() * 2.0 * std::f32::consts::PI;
+            Rgba([angle.cos(), angle.sin(), 0.0, 0.0])
+        })
+    }
+
+    fn generate_poisson_disk(rng: &mut Rng) -> Vec<[f32; 2]> {
+        // dart throwing with a minimum distance that roughly packs POISSON_DISK_SIZE samples
+        let min_distance = 0.75 / (POISSON_DISK_SIZE as f32).sqrt();
+        let mut samples: Vec<[f32; 2]> = vec![];
+        for _ in 0..POISSON_DISK_MAX_ATTEMPTS {
+            if samples.len() == POISSON_DISK_SIZE {
+                break;
+            }
+            let candidate = [rng.next_f32() * 2.0 - 1.0, rng.next_f32() * 2.0 - 1.0];
+            if candidate[0] * candidate[0] + candidate[1] * candidate[1] > 1.0 {
+                continue;
+            }
+            let too_close = samples.iter().any(|sample| {
+                let dx = sample[0] - candidate[0];
+                let dy = sample[1] - candidate[1];
+                dx * dx + dy * dy < min_distance * min_distance
+            });
+            if !too_close {
+                samples.push(candidate);
+            }
+        }
+        samples
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_poisson_disk_size(&self) -> usize {
+        self.poisson_disk.len()
+    }
+
+    // a sub-pixel offset in [-0.5, 0.5) for the frame, only depends on the seed and the frame index
+    pub fn jitter(&self, frame: u64) -> [f32; 2] {
+        let mut rng = Rng::new(self.jitter_seed ^ frame.wrapping_mul(0x2545_f491_4f6c_dd1d));
+        [rng.next_f32() - 0.5, rng.next_f32() - 0.5]
+    }
+
+    // The tables of the PCF taps, and the count of the samples of the disk, the dart throwing may
+    // give up before POISSON_DISK_SIZE. The missing samples are zeros. The rotation noise texture is
+    // copied into the uniforms rather than bound as a sampler, so that reseeding doesn't recreate
+    // the descriptor sets of every material.
+    pub fn to_uniform(&self) -> (PoissonDiskArray, u32, RotationNoiseArray) {
+        let mut poisson_disk = PoissonDiskArray::default();
+        for (i, [x, y]) in self.poisson_disk.iter().enumerate() {
+            let j = i % 2 * 2;
+            poisson_disk[i / 2][j] = *x;
+            poisson_disk[i / 2][j + 1] = *y;
+        }
+        let mut rotation_noise = RotationNoiseArray::default();
+        for (texel, pixel) in rotation_noise.iter_mut().zip(self.rotation_noise.pixels()) {
+            *texel = pixel.0;
+        }
+        (poisson_disk, self.poisson_disk.len() as u32, rotation_noise)
+    }
+
+    // hash over the bit patterns of every generated table, used to check the reproducibility
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.seed.hash(&mut hasher);
+        for v in self.ssao_kernel.iter() {
+            v.iter().for_each(|c| c.to_bits().hash(&mut hasher));
+        }
+        for c in self.rotation_noise.iter() {
+            c.to_bits().hash(&mut hasher);
+        }
+        for v in self.poisson_disk.iter() {
+            v.iter().for_each(|c| c.to_bits().hash(&mut hasher));
+        }
+        self.jitter_seed.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_produces_identical_noise() {
+        let a = NoiseResources::new(42);
+        let b = NoiseResources::new(42);
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert_eq!(a.ssao_kernel, b.ssao_kernel);
+        assert_eq!(a.rotation_noise, b.rotation_noise);
+        assert_eq!(a.to_uniform(), b.to_uniform());
+        assert_eq!(a.jitter(7), b.jitter(7));
+    }
+
+    #[test]
+    fn test_different_seeds_produce_different_noise() {
+        assert_ne!(
+            NoiseResources::new(1).content_hash(),
+            NoiseResources::new(2).content_hash()
+        );
+    }
+
+    #[test]
+    fn test_ssao_kernel_is_inside_unit_hemisphere() {
+        let noise = NoiseResources::new(DEFAULT_NOISE_SEED);
+        assert_eq!(noise.ssao_kernel.len(), SSAO_KERNEL_SIZE);
+        for [x, y, z, _] in noise.ssao_kernel {
+            assert!(z >= 0.0);
+            assert!(x * x + y * y + z * z <= 1.0 + f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_rotation_noise_is_of_unit_vectors() {
+        let noise = NoiseResources::new(DEFAULT_NOISE_SEED);
+        let size = ROTATION_NOISE_SIZE as u32;
+        assert_eq!(noise.rotation_noise.dimensions(), (size, size));
+        for Rgba([x, y, _, _]) in noise.rotation_noise.pixels() {
+            assert!((x * x + y * y - 1.0).abs() < 1e-5);
+        }
+        let (_, _, rotation_noise) = noise.to_uniform();
+        assert_eq!(rotation_noise[1], noise.rotation_noise.get_pixel(1, 0).0);
+        assert_eq!(rotation_noise[4], noise.rotation_noise.get_pixel(0, 1).0);
+    }
+
+    #[test]
+    fn test_jitter_is_within_half_pixel() {
+        let noise = NoiseResources::new(DEFAULT_NOISE_SEED);
+        for frame in 0..100 {
+            let [x, y] = noise.jitter(frame);
+            assert!((-0.5..0.5).contains(&x));
+            assert!((-0.5..0.5).contains(&y));
+        }
+        assert_ne!(noise.jitter(0), noise.jitter(1));
+    }
+
+    #[test]
+    fn test_poisson_disk_is_inside_unit_disk() {
+        let noise = NoiseResources::new(DEFAULT_NOISE_SEED);
+        assert!(!noise.poisson_disk.is_empty());
+        for [x, y] in noise.poisson_disk {
+            assert!(x * x + y * y <= 1.0);
+        }
+    }
+
+    #[test]
+    fn test_poisson_disk_uniform() {
+        let noise = NoiseResources::new(DEFAULT_NOISE_SEED);
+        let (poisson_disk, size, _) = noise.to_uniform();
+        assert_eq!(size as usize, noise.poisson_disk.len());
+        for (i, [x, y]) in noise.poisson_disk.iter().enumerate() {
+            let j = i % 2 * 2;
+            assert_eq!(poisson_disk[i / 2][j], *x);
+            assert_eq!(poisson_disk[i / 2][j + 1], *y);
+        }
+    }
+}
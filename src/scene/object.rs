@@ -3,35 +3,64 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use std::{collections::HashMap, hash::Hash, marker::PhantomData, sync::Arc};
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryFrom,
+    hash::Hash,
+    marker::PhantomData,
+    ops::Range,
+    rc::Rc,
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread,
+};
 
-use euclid::{Point3D, Transform3D};
+use euclid::{point3, vec3, Point3D, Transform3D, UnknownUnit, Vector3D};
 use image::RgbaImage;
-use obj::{Group, IndexTuple};
+use obj::{Group, IndexTuple, ObjData, SimplePolygon};
 use ordered_float::OrderedFloat;
 use vulkano::{
-    command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
+    command_buffer::{
+        pool::standard::StandardCommandPoolBuilder, AutoCommandBuffer, AutoCommandBufferBuilder,
+        CommandBufferExecFuture,
+    },
     device::{Device, Queue},
-    format::R8G8B8A8Unorm,
+    format::{Format, R8G8B8A8Unorm},
     framebuffer::{RenderPassAbstract, Subpass},
     image::{immutable::ImmutableImage, Dimensions, MipmapsCount},
     pipeline::vertex::Vertex,
     sampler::Sampler,
-    sync::GpuFuture,
+    sync::{GpuFuture, NowFuture},
 };
 
 use super::{
-    light::PointLight,
-    material::{Material, SetCamera},
-    renderer::{Mesh, MeshData, MeshRenderer, MeshT},
+    bvh::{Bvh, Hit},
+    color::srgb_to_linear,
+    light::{DirectionalLight, HemisphereAmbient, LightInfo, MAX_POINT_LIGHTS},
+    material::{
+        normal_matrix, Material, RenderMode, SetCamera, SpecularModel, DEFAULT_SPECULAR_AA_STRENGTH,
+    },
+    noise::NoiseResources,
+    path_tracer::TracedSurface,
+    renderer::{
+        BlendMode, CullMode, Indices, Mesh, MeshData, MeshRenderer, MeshT, RasterState,
+        SimpleVertex, SubMesh,
+    },
     shaders::{
-        phong::no_texture::{
-            FsUniform as NoTexturePhongFsUniform, Shaders as NoTexturePhongShaders,
-        },
-        phong::with_texture::{FsUniform as TexturePhongFsUniform, Shaders as TexturePhongShaders},
+        phong::no_texture::Shaders as NoTexturePhongShaders,
+        phong::with_normal_map::Shaders as NormalMapPhongShaders,
+        phong::with_texture::Shaders as TexturePhongShaders,
+        phong::{FsUniform as PhongFsUniform, PhongUniforms},
         ShadersT, Texture, UniformsT,
     },
-    Camera, WorldSpace,
+    shadow::{ShadowConfig, ShadowMap, SHADOW_MAP_SIZE},
+    skybox::{IrradianceMap, DEFAULT_AMBIENT_STRENGTH},
+    upload::Upload,
+    Camera, NDCSpace, WorldSpace,
 };
 use crate::errors::*;
 
@@ -40,13 +69,16 @@ pub struct ObjectWithTextureVertex {
     in_position: [f32; 4],
     in_normal: [f32; 4],
     in_texture_coord: [f32; 2],
+    // only generated for the objects with a normal map, see generate_tangents
+    in_tangent: [f32; 4],
 }
 
 vulkano::impl_vertex!(
     ObjectWithTextureVertex,
     in_position,
     in_normal,
-    in_texture_coord
+    in_texture_coord,
+    in_tangent
 );
 
 #[derive(Default, Copy, Clone)]
@@ -57,55 +89,213 @@ pub struct ObjectWithNoTextureVertex {
 
 vulkano::impl_vertex!(ObjectWithNoTextureVertex, in_position, in_normal);
 
+// without the normal, given by the object, e.g. Object::plane
+impl SimpleVertex for ObjectWithNoTextureVertex {
+    fn create_from_position(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            in_position: [x, y, z, 1.0],
+            in_normal: Default::default(),
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct VSUniform {
     model: [f32; 16],
     view: [f32; 16],
     proj: [f32; 16],
+    normal_matrix: [f32; 16],
 }
 
 #[derive(Clone)]
 pub struct FSUniform {
     kd: [f32; 4],
     ks: [f32; 4],
-    light_pos: [f32; 4],
+    light_positions: [[f32; 4]; MAX_POINT_LIGHTS],
+    light_colors: [[f32; 4]; MAX_POINT_LIGHTS],
     camera_pos: [f32; 4],
-    light_intensity: f32,
+    ambient_sky_color: [f32; 4],
+    ambient_ground_color: [f32; 4],
+    ambient_up: [f32; 4],
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+    light_count: u32,
+    hemisphere_ambient: u32,
+}
+
+type TextureInit = CommandBufferExecFuture<NowFuture, AutoCommandBuffer>;
+
+// The texture can't be sampled until the init finishes. The colors are uploaded as sRGB, so that
+// the sampler decodes them to the linear space, while the data like the normals are UNORM.
+fn create_texture(
+    renderer: &ObjectRenderer,
+    image: &RgbaImage,
+    format: Format,
+) -> Result<(Texture, TextureInit)> {
+    let (texture, init) = ImmutableImage::from_iter(
+        image.pixels().map(|p| p.0),
+        Dimensions::Dim2d {
+            width: image.width(),
+            height: image.height(),
+        },
+        MipmapsCount::One,
+        format,
+        renderer.upload_queue.clone(),
+    )
+    .chain_err(|| "fail to create the image")?;
+    Ok((
+        Texture {
+            image: texture,
+            sampler: Sampler::simple_repeat_linear(renderer.device.clone()),
+        },
+        init,
+    ))
+}
+
+// The specular map joined to the init of the other textures of the material, or the white texture
+// of the renderer, which leaves ks as it is, for the materials without map_ks.
+fn with_specular_map(
+    renderer: &ObjectRenderer,
+    specular_map: Option<&RgbaImage>,
+    init: impl GpuFuture + Send + Sync + 'static,
+) -> Result<(Texture, Box<dyn GpuFuture + Send + Sync>)> {
+    match specular_map {
+        Some(specular_map) => {
+            let (specular_map, specular_map_init) =
+                create_texture(renderer, specular_map, Format::R8G8B8A8Unorm)
+                    .chain_err(|| "fail to create texture for the specular map")?;
+            Ok((specular_map, Box::new(init.join(specular_map_init))))
+        }
+        None => Ok((renderer.white_texture.clone(), Box::new(init))),
+    }
+}
+
+// the 1x1 white texture, created once and shared by the materials without a specular map
+fn create_white_texture(device: Arc<Device>, queue: Arc<Queue>) -> Result<Texture> {
+    let (image, init) = ImmutableImage::from_iter(
+        std::iter::once([255u8; 4]),
+        Dimensions::Dim2d {
+            width: 1,
+            height: 1,
+        },
+        MipmapsCount::One,
+        R8G8B8A8Unorm,
+        queue,
+    )
+    .chain_err(|| ErrorKind::GpuResource("fail to create the white texture".to_owned()))?;
+    init.then_signal_fence_and_flush()
+        .chain_err(|| "fail to signal the fence and flush when initializing the white texture")?
+        .wait(None)
+        .chain_err(|| "fail to wait for the white texture being initialized")?;
+    Ok(Texture {
+        image,
+        sampler: Sampler::simple_repeat_linear(device),
+    })
 }
 
 pub struct TextureObjectMaterial {
     texture: Texture,
+    // scales ks, see with_specular_map
+    specular_map: Texture,
+    shadow_map: Texture,
+    irradiance_map: Texture,
+    // the CPU copy of the texture, used by the path tracer
+    image: Arc<RgbaImage>,
     ks: [f32; 3],
     kd: [f32; 3],
+    // the Ns of the material, see DEFAULT_SHININESS
+    shininess: f32,
 }
 
 impl TextureObjectMaterial {
-    pub fn new(renderer: &ObjectRenderer, texture: &RgbaImage, ks: [f32; 3]) -> Result<Self> {
-        let mesh_renderer = &renderer.with_texture_renderer;
-        let (image, image_init) = ImmutableImage::from_iter(
-            texture.pixels().map(|p| p.0),
-            Dimensions::Dim2d {
-                width: texture.width(),
-                height: texture.height(),
-            },
-            MipmapsCount::One,
-            R8G8B8A8Unorm,
-            mesh_renderer.get_queue(),
-        )
-        .chain_err(|| "fail to create texture for the texture")?;
-        image_init
-            .then_signal_fence_and_flush()
-            .chain_err(|| "fail to signal the fence and flush when initializing the texture image")?
-            .wait(None)
-            .chain_err(|| "fail to wait for the texture image being initialized")?;
-        Ok(Self {
-            texture: Texture {
-                image,
-                sampler: Sampler::simple_repeat_linear(mesh_renderer.get_device()),
-            },
+    // the textures can't be sampled until the upload finishes
+    pub fn new(
+        renderer: &ObjectRenderer,
+        texture: Arc<RgbaImage>,
+        specular_map: Option<Arc<RgbaImage>>,
+        ks: [f32; 3],
+        shininess: f32,
+    ) -> Result<(Self, Upload)> {
+        let (image, image_init) = create_texture(renderer, &texture, Format::R8G8B8A8Srgb)
+            .chain_err(|| "fail to create texture for the texture")?;
+        let (specular_map, init) =
+            with_specular_map(renderer, specular_map.as_deref(), image_init)?;
+        let upload = Upload::submit(init).chain_err(|| "fail to upload the texture images")?;
+        Ok((
+            Self::from_texture(renderer, image, specular_map, texture, ks, shininess),
+            upload,
+        ))
+    }
+
+    fn from_texture(
+        renderer: &ObjectRenderer,
+        texture: Texture,
+        specular_map: Texture,
+        image: Arc<RgbaImage>,
+        ks: [f32; 3],
+        shininess: f32,
+    ) -> Self {
+        Self {
+            texture,
+            specular_map,
+            shadow_map: renderer.shadow_map.clone(),
+            irradiance_map: renderer.irradiance_map.get_texture(),
+            image,
             kd: Default::default(),
             ks,
-        })
+            shininess,
+        }
+    }
+
+    // nearest sampling with the repeat address mode, decoded from sRGB like the sampler of the
+    // phong shader
+    fn sample_albedo(&self, [u, v]: [f32; 2]) -> [f32; 3] {
+        let width = self.image.width();
+        let height = self.image.height();
+        let x = ((u - u.floor()) * width as f32) as u32;
+        let y = ((v - v.floor()) * height as f32) as u32;
+        let pixel = self.image.get_pixel(x.min(width - 1), y.min(height - 1));
+        let to_unit = |c: u8| f32::from(c) / 255.0;
+        srgb_to_linear([to_unit(pixel[0]), to_unit(pixel[1]), to_unit(pixel[2])])
+    }
+}
+
+// the fragment uniforms of a phong material before the lights and the camera are set, shared by
+// all the permutations
+fn create_fs_uniform(kd: [f32; 3], ks: [f32; 3], shininess: f32) -> PhongFsUniform {
+    PhongFsUniform {
+        kd: [kd[0], kd[1], kd[2], 0.0],
+        ks: [ks[0], ks[1], ks[2], 0.0],
+        light_positions: Default::default(),
+        light_colors: Default::default(),
+        camera_pos: Default::default(),
+        ambient_sky_color: Default::default(),
+        ambient_ground_color: Default::default(),
+        ambient_up: Default::default(),
+        sun_direction: Default::default(),
+        sun_color: Default::default(),
+        light_space: Default::default(),
+        uv_transform_u: [1.0, 0.0, 0.0, 0.0],
+        uv_transform_v: [0.0, 1.0, 0.0, 0.0],
+        poisson_disk: Default::default(),
+        rotation_noise: Default::default(),
+        light_count: Default::default(),
+        hemisphere_ambient: Default::default(),
+        shadow_bias: ShadowConfig::default().bias,
+        shadow_enabled: Default::default(),
+        shadow_pcf_radius: ShadowConfig::default().filter.get_kernel_radius(),
+        shadow_filter: ShadowConfig::default().filter.to_uniform(),
+        shadow_texel_size: 1.0 / SHADOW_MAP_SIZE as f32,
+        poisson_disk_size: Default::default(),
+        specular_aa_strength: DEFAULT_SPECULAR_AA_STRENGTH,
+        diffuse_ibl: Default::default(),
+        ambient_strength: DEFAULT_AMBIENT_STRENGTH,
+        render_mode: RenderMode::default().to_uniform(),
+        camera_near: Default::default(),
+        camera_far: Default::default(),
+        shininess,
+        specular_model: SpecularModel::default().to_uniform(),
+        gamma_correction: true as u32,
     }
 }
 
@@ -121,14 +311,81 @@ impl Material for TextureObjectMaterial {
             device,
             queue,
             Default::default(),
-            TexturePhongFsUniform {
-                kd: [self.kd[0], self.kd[1], self.kd[2], 0.0],
-                ks: [self.ks[0], self.ks[1], self.ks[2], 0.0],
-                light_pos: Default::default(),
-                camera_pos: Default::default(),
-                light_intensity: Default::default(),
-            },
+            create_fs_uniform(self.kd, self.ks, self.shininess),
             self.texture.clone(),
+            self.shadow_map.clone(),
+            self.irradiance_map.clone(),
+            self.specular_map.clone(),
+        )
+    }
+}
+
+// the textured material whose normals are perturbed by a tangent space normal map
+pub struct NormalMapObjectMaterial {
+    material: TextureObjectMaterial,
+    normal_map: Texture,
+}
+
+impl NormalMapObjectMaterial {
+    // none of the textures can be sampled until the upload finishes
+    pub fn new(
+        renderer: &ObjectRenderer,
+        texture: Arc<RgbaImage>,
+        specular_map: Option<Arc<RgbaImage>>,
+        normal_map: Arc<RgbaImage>,
+        ks: [f32; 3],
+        shininess: f32,
+    ) -> Result<(Self, Upload)> {
+        let (image, image_init) = create_texture(renderer, &texture, Format::R8G8B8A8Srgb)
+            .chain_err(|| "fail to create texture for the texture")?;
+        let (normal_map, normal_map_init) =
+            create_texture(renderer, &normal_map, Format::R8G8B8A8Unorm)
+                .chain_err(|| "fail to create texture for the normal map")?;
+        let (specular_map, init) = with_specular_map(
+            renderer,
+            specular_map.as_deref(),
+            image_init.join(normal_map_init),
+        )?;
+        let upload = Upload::submit(init)
+            .chain_err(|| "fail to upload the texture and the normal map images")?;
+        Ok((
+            Self {
+                material: TextureObjectMaterial::from_texture(
+                    renderer,
+                    image,
+                    specular_map,
+                    texture,
+                    ks,
+                    shininess,
+                ),
+                normal_map,
+            },
+            upload,
+        ))
+    }
+}
+
+impl Material for NormalMapObjectMaterial {
+    type Shaders = NormalMapPhongShaders;
+
+    fn create_uniforms(
+        &self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<<NormalMapPhongShaders as ShadersT>::Uniforms> {
+        let TextureObjectMaterial {
+            kd, ks, shininess, ..
+        } = self.material;
+        <NormalMapPhongShaders as ShadersT>::Uniforms::new(
+            device,
+            queue,
+            Default::default(),
+            create_fs_uniform(kd, ks, shininess),
+            self.material.texture.clone(),
+            self.material.shadow_map.clone(),
+            self.material.irradiance_map.clone(),
+            self.material.specular_map.clone(),
+            self.normal_map.clone(),
         )
     }
 }
@@ -136,11 +393,26 @@ impl Material for TextureObjectMaterial {
 pub struct NoTextureObjectMaterial {
     ks: [f32; 3],
     kd: [f32; 3],
+    // the Ns of the material, see DEFAULT_SHININESS
+    shininess: f32,
+    shadow_map: Texture,
+    irradiance_map: Texture,
 }
 
 impl NoTextureObjectMaterial {
-    pub fn new(kd: [f32; 3], ks: [f32; 3]) -> Result<Self> {
-        Ok(Self { kd, ks })
+    pub fn new(
+        renderer: &ObjectRenderer,
+        kd: [f32; 3],
+        ks: [f32; 3],
+        shininess: f32,
+    ) -> Result<Self> {
+        Ok(Self {
+            kd,
+            ks,
+            shininess,
+            shadow_map: renderer.shadow_map.clone(),
+            irradiance_map: renderer.irradiance_map.get_texture(),
+        })
     }
 }
 
@@ -156,61 +428,356 @@ impl Material for NoTextureObjectMaterial {
             device,
             queue,
             Default::default(),
-            NoTexturePhongFsUniform {
-                kd: [self.kd[0], self.kd[1], self.kd[2], 0.0],
-                ks: [self.ks[0], self.ks[1], self.ks[2], 0.0],
-                light_pos: Default::default(),
-                camera_pos: Default::default(),
-                light_intensity: Default::default(),
-            },
+            create_fs_uniform(self.kd, self.ks, self.shininess),
+            self.shadow_map.clone(),
+            self.irradiance_map.clone(),
         )
     }
 }
 
+bitflags::bitflags! {
+    // The features of the phong shader permutations, every permutation is compiled ahead of time
+    // with the matching defines.
+    pub struct ShaderFeatures: u32 {
+        const TEXTURE = 0b1;
+        // only with TEXTURE, the tangents follow the texture coordinates
+        const NORMAL_MAP = 0b10;
+    }
+}
+
+impl ShaderFeatures {
+    pub fn get_name(&self) -> String {
+        if self.is_empty() {
+            return "phong".to_owned();
+        }
+        let mut names = vec!["phong"];
+        if self.contains(Self::TEXTURE) {
+            names.push("texture");
+        }
+        if self.contains(Self::NORMAL_MAP) {
+            names.push("normal map");
+        }
+        names.join(" + ")
+    }
+}
+
+impl ShaderFeatures {
+    // the permutation compiled for the features, the normal maps are only sampled with the
+    // textures
+    fn get_permutation(self) -> Self {
+        if self.contains(Self::NORMAL_MAP) {
+            Self::TEXTURE | Self::NORMAL_MAP
+        } else {
+            self & Self::TEXTURE
+        }
+    }
+}
+
+// the material drawn by the permutation of the features
+trait PermutationMaterial: Material + Sized + 'static {
+    type Vertex: Vertex;
+    const FEATURES: ShaderFeatures;
+}
+
+impl PermutationMaterial for TextureObjectMaterial {
+    type Vertex = ObjectWithTextureVertex;
+    const FEATURES: ShaderFeatures = ShaderFeatures::TEXTURE;
+}
+
+impl PermutationMaterial for NormalMapObjectMaterial {
+    type Vertex = ObjectWithTextureVertex;
+    const FEATURES: ShaderFeatures = ShaderFeatures::from_bits_truncate(
+        ShaderFeatures::TEXTURE.bits() | ShaderFeatures::NORMAL_MAP.bits(),
+    );
+}
+
+impl PermutationMaterial for NoTextureObjectMaterial {
+    type Vertex = ObjectWithNoTextureVertex;
+    const FEATURES: ShaderFeatures = ShaderFeatures::empty();
+}
+
+type PermutationRenderer<M> = MeshRenderer<<M as PermutationMaterial>::Vertex, M>;
+
+// the renderer of a permutation with the vertex and the material types erased
+trait Permutation {
+    fn resize(&self, width: u32, height: u32);
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<M: PermutationMaterial> Permutation for Arc<PermutationRenderer<M>> {
+    fn resize(&self, width: u32, height: u32) {
+        MeshRenderer::resize(self, width, height);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn downcast_permutation<M: PermutationMaterial>(
+    permutation: &dyn Permutation,
+) -> Result<Arc<PermutationRenderer<M>>> {
+    permutation
+        .as_any()
+        .downcast_ref::<Arc<PermutationRenderer<M>>>()
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "the renderer of {} is of another material",
+                M::FEATURES.get_name()
+            )
+            .into()
+        })
+}
+
+type PermutationCache = Rc<RefCell<BTreeMap<ShaderFeatures, Box<dyn Permutation>>>>;
+
+// installs a permutation built by the warm-up
+type WarmPermutation = Box<dyn FnOnce(&ObjectRenderer) -> Result<()> + Send>;
+
+type WarmUpResult = Result<Vec<WarmPermutation>>;
+
+// the permutations being built on a background thread, see ObjectRenderer::start_warm_up
+pub struct WarmUp {
+    receiver: mpsc::Receiver<WarmUpResult>,
+}
+
+// the renderer of a permutation with the pipeline of the raster state created
+fn create_warm_mesh_renderer<V: Vertex, M: Material>(
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    [width, height]: [u32; 2],
+    depth_range: Range<f32>,
+    raster_state: RasterState,
+) -> Result<MeshRenderer<V, M>> {
+    let mesh_renderer = MeshRenderer::init(
+        device,
+        queue,
+        subpass,
+        width,
+        height,
+        depth_range,
+        BlendMode::Opaque,
+    )?;
+    mesh_renderer
+        .warm_up(raster_state)
+        .chain_err(|| format!("fail to create the pipeline of {:?}", raster_state))?;
+    Ok(mesh_renderer)
+}
+
+fn create_warm_permutation<M>(
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    size: [u32; 2],
+    depth_range: Range<f32>,
+    raster_state: RasterState,
+) -> Result<WarmPermutation>
+where
+    M: PermutationMaterial + Send,
+    M::Shaders: Send,
+{
+    let mesh_renderer = create_warm_mesh_renderer::<M::Vertex, M>(
+        device,
+        queue,
+        subpass,
+        size,
+        depth_range,
+        raster_state,
+    )
+    .chain_err(|| format!("fail to initialize renderer for {}", M::FEATURES.get_name()))?;
+    Ok(Box::new(move |object_renderer: &ObjectRenderer| {
+        object_renderer.install::<M>(mesh_renderer).map(|_| ())
+    }))
+}
+
+// The pipelines of the shader permutations are only created when the first object using them is
+// loaded, or ahead of it by a warm-up on a background thread.
 #[derive(Clone)]
 pub struct ObjectRenderer {
-    with_texture_renderer: Arc<MeshRenderer<ObjectWithTextureVertex, TextureObjectMaterial>>,
-    no_texture_renderer: Arc<MeshRenderer<ObjectWithNoTextureVertex, NoTextureObjectMaterial>>,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    // the meshes and the textures are uploaded through it, see set_upload_queue
+    upload_queue: Arc<Queue>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    width: u32,
+    height: u32,
+    depth_range: Range<f32>,
+    // the shadow map of the first point light, sampled by all the materials
+    shadow_map: Texture,
+    // the irradiance of the skybox, sampled by all the materials
+    irradiance_map: Rc<IrradianceMap>,
+    // the specular map of the textured materials without map_ks
+    white_texture: Texture,
+    // the renderers of the permutations keyed on their features
+    permutations: PermutationCache,
 }
 
 impl ObjectRenderer {
     pub fn init(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        subpass: Subpass<impl RenderPassAbstract + Send + Sync + Clone + 'static>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
         width: u32,
         height: u32,
+        depth_range: Range<f32>,
+        shadow_map: Texture,
     ) -> Result<Self> {
-        let with_texture_renderer = Arc::new(
-            MeshRenderer::init(
-                device.clone(),
-                queue.clone(),
-                subpass.clone(),
-                width,
-                height,
-            )
-            .chain_err(|| "fail to initialize renderer for object with textures")?,
-        );
-        let no_texture_renderer = Arc::new(
-            MeshRenderer::init(device, queue, subpass, width, height)
-                .chain_err(|| "fail to initialize renderer for object without textures")?,
+        let irradiance_map = Rc::new(
+            IrradianceMap::new(device.clone(), &queue)
+                .chain_err(|| "fail to create the irradiance map")?,
         );
+        let white_texture = create_white_texture(device.clone(), queue.clone())?;
         Ok(Self {
-            with_texture_renderer,
-            no_texture_renderer,
+            device,
+            upload_queue: queue.clone(),
+            queue,
+            subpass,
+            width,
+            height,
+            depth_range,
+            shadow_map,
+            irradiance_map,
+            white_texture,
+            permutations: Default::default(),
         })
     }
+
+    pub fn get_irradiance_map(&self) -> &IrradianceMap {
+        &self.irradiance_map
+    }
+
+    // the same as MeshRenderer::set_upload_queue, for the textures and all the permutations
+    pub fn set_upload_queue(&mut self, upload_queue: Arc<Queue>) {
+        self.upload_queue = upload_queue;
+    }
+
+    // the renderers of the permutations created later are also of the new size
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        for permutation in self.permutations.borrow().values() {
+            permutation.resize(width, height);
+        }
+    }
+
+    fn get_renderer<M: PermutationMaterial>(&self) -> Result<Arc<PermutationRenderer<M>>> {
+        if let Some(permutation) = self.permutations.borrow().get(&M::FEATURES) {
+            return downcast_permutation::<M>(permutation.as_ref());
+        }
+        let mesh_renderer = MeshRenderer::init(
+            self.device.clone(),
+            self.queue.clone(),
+            self.subpass.clone(),
+            self.width,
+            self.height,
+            self.depth_range.clone(),
+            BlendMode::Opaque,
+        )
+        .chain_err(|| format!("fail to initialize renderer for {}", M::FEATURES.get_name()))?;
+        self.install::<M>(mesh_renderer)
+    }
+
+    // the renderers created elsewhere, e.g. by the warm-up, may be of an old size
+    fn install<M: PermutationMaterial>(
+        &self,
+        mut mesh_renderer: PermutationRenderer<M>,
+    ) -> Result<Arc<PermutationRenderer<M>>> {
+        mesh_renderer.resize(self.width, self.height);
+        mesh_renderer.set_upload_queue(self.upload_queue.clone());
+        let mut permutations = self.permutations.borrow_mut();
+        let permutation = permutations
+            .entry(M::FEATURES)
+            .or_insert_with(|| Box::new(Arc::new(mesh_renderer)));
+        downcast_permutation::<M>(permutation.as_ref())
+    }
+
+    // Builds the missing permutations among the features on a background thread, vulkano creates
+    // the pipelines from any thread. The pipeline of the raster state is created with them, so
+    // that the first draw doesn't stall on it. None if no permutation is missing.
+    pub fn start_warm_up(
+        &self,
+        features: &[ShaderFeatures],
+        raster_state: RasterState,
+    ) -> Result<Option<WarmUp>> {
+        let missing = {
+            let permutations = self.permutations.borrow();
+            features
+                .iter()
+                .map(|features| features.get_permutation())
+                .filter(|features| !permutations.contains_key(features))
+                .collect::<BTreeSet<_>>()
+        };
+        if missing.is_empty() {
+            return Ok(None);
+        }
+        let (sender, receiver) = mpsc::channel();
+        let device = self.device.clone();
+        let queue = self.queue.clone();
+        let subpass = self.subpass.clone();
+        let size = [self.width, self.height];
+        let depth_range = self.depth_range.clone();
+        thread::Builder::new()
+            .name("warm-up".to_owned())
+            .spawn(move || {
+                let init = || -> WarmUpResult {
+                    missing
+                        .into_iter()
+                        .map(|features| {
+                            let create = if features == NormalMapObjectMaterial::FEATURES {
+                                create_warm_permutation::<NormalMapObjectMaterial>
+                            } else if features == TextureObjectMaterial::FEATURES {
+                                create_warm_permutation::<TextureObjectMaterial>
+                            } else {
+                                create_warm_permutation::<NoTextureObjectMaterial>
+                            };
+                            create(
+                                device.clone(),
+                                queue.clone(),
+                                subpass.clone(),
+                                size,
+                                depth_range.clone(),
+                                raster_state,
+                            )
+                        })
+                        .collect()
+                };
+                // the receiver is only gone when the warm-up is abandoned
+                let _ = sender.send(init());
+            })
+            .chain_err(|| "fail to spawn the warm-up thread")?;
+        Ok(Some(WarmUp { receiver }))
+    }
+
+    // Installs the permutations built by the warm-up once it finishes, false while it's running.
+    pub fn finish_warm_up(&self, warm_up: &WarmUp) -> Result<bool> {
+        let permutations = match warm_up.receiver.try_recv() {
+            Ok(result) => result.chain_err(|| "fail to build the shader permutations")?,
+            Err(TryRecvError::Empty) => return Ok(false),
+            Err(TryRecvError::Disconnected) => bail!("the warm-up thread has exited"),
+        };
+        for install in permutations {
+            install(self)?;
+        }
+        Ok(true)
+    }
+
+    // the permutations whose pipelines have been created
+    pub fn get_live_permutations(&self) -> Vec<ShaderFeatures> {
+        self.permutations.borrow().keys().copied().collect()
+    }
 }
 
 fn vertex_attributes_to_indexed_vertex_attributes<V, F, K>(
     vertices: impl Iterator<Item = Result<V>>,
     to_key: F,
-) -> Result<(Vec<V>, Vec<u16>)>
+) -> Result<(Vec<V>, Vec<u32>)>
 where
     F: Fn(&V) -> K,
     K: Eq + Hash,
 {
-    let mut vertex2index: HashMap<K, u16> = Default::default();
+    let mut vertex2index: HashMap<K, u32> = Default::default();
     let mut res_indices = vec![];
     let mut res_vertices = vec![];
     for v in vertices {
@@ -219,13 +786,15 @@ where
         let i = match vertex2index.get(&key) {
             Some(i) => *i,
             None => {
-                let i = res_vertices.len() as u16;
+                let i = u32::try_from(res_vertices.len()).chain_err(|| {
+                    format!("more than {} unique vertices can't be indexed", u32::MAX)
+                })?;
                 res_vertices.push(v);
                 vertex2index.insert(key, i);
                 i
             }
         };
-        res_indices.push(i as u16);
+        res_indices.push(i);
     }
     Ok((res_vertices, res_indices))
 }
@@ -278,39 +847,285 @@ fn create_index_to_vertex_map<'a>(
     }
 }
 
+// the attribute of every triangle of the group, polygons with more than 3 vertices are fanned
+fn group_triangle_attributes<T: Copy>(
+    group: &Group,
+    to_attribute: impl Fn(&IndexTuple) -> Result<T>,
+) -> Result<Vec<[T; 3]>> {
+    let mut triangles = vec![];
+    for poly in group.polys.iter() {
+        let vertices = poly
+            .0
+            .iter()
+            .map(&to_attribute)
+            .collect::<Result<Vec<_>>>()?;
+        for i in 1..vertices.len().saturating_sub(1) {
+            triangles.push([vertices[0], vertices[i], vertices[i + 1]]);
+        }
+    }
+    Ok(triangles)
+}
+
+// the triangles of the group in model space
+fn group_triangles<S>(position: &[[f32; 3]], group: &Group) -> Result<Vec<[Point3D<f32, S>; 3]>> {
+    group_triangle_attributes(group, |IndexTuple(position_index, _, _)| {
+        position
+            .get(*position_index)
+            .map(|[x, y, z]| point3(*x, *y, *z))
+            .ok_or_else(|| -> Error { "fail to find position with given index".into() })
+    })
+}
+
+fn group_triangle_texture_coords(
+    texture_coord: &[[f32; 2]],
+    group: &Group,
+) -> Result<Vec<[[f32; 2]; 3]>> {
+    group_triangle_attributes(group, |IndexTuple(_, texture_index, _)| {
+        texture_index
+            .and_then(|i| texture_coord.get(i))
+            .copied()
+            .ok_or_else(|| -> Error { "fail to find texture coord with given index".into() })
+    })
+}
+
+// the position indices of the triangles of the group, polygons with more than 3 vertices are fanned
+pub fn group_position_triangles(group: &Group) -> Vec<[usize; 3]> {
+    let mut triangles = vec![];
+    for poly in group.polys.iter() {
+        let vertices: Vec<_> = poly.0.iter().map(|IndexTuple(i, _, _)| *i).collect();
+        for i in 1..vertices.len().saturating_sub(1) {
+            triangles.push([vertices[0], vertices[i], vertices[i + 1]]);
+        }
+    }
+    triangles
+}
+
+// Whether the triangles enclose a volume, i.e. every edge is shared by exactly two triangles. The
+// back faces of a closed surface are hidden, unlike those of an open one, e.g. a leaf or a cloth.
+// The degenerate triangles have no edges.
+pub fn is_closed_manifold(triangles: &[[usize; 3]]) -> bool {
+    let mut edge_counts = HashMap::new();
+    for &[a, b, c] in triangles {
+        if a == b || b == c || c == a {
+            continue;
+        }
+        for &(from, to) in [(a, b), (b, c), (c, a)].iter() {
+            *edge_counts.entry((from.min(to), from.max(to))).or_insert(0) += 1;
+        }
+    }
+    edge_counts.values().all(|count| *count == 2)
+}
+
+// Fills in the normals missing from the faces, which many sculpting tools don't export. The normal
+// of a position is the sum of the normals of the triangles around it weighted by their area, the
+// zero-area triangles are skipped rather than producing NaN. The generated normals are appended
+// after the normals of the file.
+pub fn generate_missing_normals(data: &mut ObjData) -> Result<()> {
+    let mut sums = HashMap::new();
+    let groups = || data.objects.iter().flat_map(|object| object.groups.iter());
+    for IndexTuple(position_index, _, normal_index) in groups()
+        .flat_map(|group| group.polys.iter())
+        .flat_map(|poly| poly.0.iter())
+    {
+        if normal_index.is_none() {
+            sums.entry(*position_index).or_insert_with(Vector3D::zero);
+        }
+    }
+    if sums.is_empty() {
+        return Ok(());
+    }
+
+    for group in groups() {
+        let triangles = group_triangle_attributes(group, |IndexTuple(position_index, _, _)| {
+            data.position
+                .get(*position_index)
+                .map(|[x, y, z]| (*position_index, point3::<_, UnknownUnit>(*x, *y, *z)))
+                .ok_or_else(|| -> Error { "fail to find position with given index".into() })
+        })?;
+        for [(ia, a), (ib, b), (ic, c)] in triangles {
+            // twice as long as the area of the triangle
+            let normal = (b - a).cross(c - a);
+            let square_length = normal.square_length();
+            if !square_length.is_finite() || square_length <= 0.0 {
+                continue;
+            }
+            for i in [ia, ib, ic].iter() {
+                if let Some(sum) = sums.get_mut(i) {
+                    *sum += normal;
+                }
+            }
+        }
+    }
+
+    let mut normal_indices = HashMap::new();
+    for (position_index, sum) in sums {
+        // only on degenerate triangles, any direction is as good
+        let normal = sum.try_normalize().unwrap_or_else(|| vec3(0.0, 0.0, 1.0));
+        normal_indices.insert(position_index, data.normal.len());
+        data.normal.push(normal.to_array());
+    }
+    for poly in data
+        .objects
+        .iter_mut()
+        .flat_map(|object| object.groups.iter_mut())
+        .flat_map(|group| group.polys.iter_mut())
+    {
+        for IndexTuple(position_index, _, normal_index) in poly.0.iter_mut() {
+            if normal_index.is_none() {
+                *normal_index = normal_indices.get(position_index).copied();
+            }
+        }
+    }
+    Ok(())
+}
+
+// the lights shading the objects
+pub struct ObjectLights<'a> {
+    pub points: &'a [LightInfo],
+    pub ambient: &'a HemisphereAmbient,
+    pub sun: &'a DirectionalLight,
+    // the light space of the shadow map of the first point light, None if it's not drawn
+    pub light_space: Option<Transform3D<f32, WorldSpace, NDCSpace>>,
+    pub shadow: ShadowConfig,
+    // the taps of the PCF of the shadow
+    pub noise: &'a NoiseResources,
+    // see DEFAULT_SPECULAR_AA_STRENGTH
+    pub specular_aa_strength: f32,
+    // whether the irradiance of the skybox replaces the ambient, see IrradianceMap
+    pub diffuse_ibl: bool,
+    // see DEFAULT_AMBIENT_STRENGTH
+    pub ambient_strength: f32,
+    pub specular_model: SpecularModel,
+    // replaces the shininess of every material if set
+    pub shininess_override: Option<f32>,
+    // see Renderer::set_gamma_correction
+    pub gamma_correction: bool,
+}
+
 pub trait ObjectUniforms: UniformsT + SetCamera {
-    fn set_light_pos(&mut self, _light_pos: &Point3D<f32, WorldSpace>);
+    // a Validation error if there are more than MAX_POINT_LIGHTS lights
+    fn set_lights(&mut self, _lights: &[LightInfo]) -> Result<()>;
     fn set_camera_pos(&mut self, _camera: &Camera);
-    fn set_light_intensity(&mut self, _light_intensity: f32);
+    fn set_hemisphere_ambient(&mut self, _ambient: &HemisphereAmbient);
+    fn set_directional_light(
+        &mut self,
+        _direction: &Vector3D<f32, WorldSpace>,
+        _color: [f32; 3],
+        _intensity: f32,
+    );
+    // ignored by the materials without the texture coordinates to animate
+    fn set_uv_transform(&mut self, _transform: [[f32; 4]; 2]);
+    // the objects are unshadowed if None
+    fn set_light_space_matrix(&mut self, _mat: Option<[f32; 16]>);
+    fn set_shadow_config(&mut self, _config: &ShadowConfig);
+    fn set_shadow_noise(&mut self, _noise: &NoiseResources);
+    fn set_specular_aa_strength(&mut self, _strength: f32);
+    fn set_diffuse_ibl(&mut self, _enabled: bool);
+    fn set_ambient_strength(&mut self, _strength: f32);
+    fn set_render_mode(&mut self, _mode: RenderMode);
+    fn set_shininess(&mut self, _shininess: f32);
+    fn set_specular_model(&mut self, _model: SpecularModel);
+    fn set_gamma_correction(&mut self, _enabled: bool);
 }
 
-impl ObjectUniforms for <NoTexturePhongShaders as ShadersT>::Uniforms {
-    fn set_light_pos(&mut self, light_pos: &Point3D<f32, WorldSpace>) {
-        self.fs_uniform.light_pos = [light_pos.x, light_pos.y, light_pos.z, 1.0];
+impl<U: PhongUniforms + UniformsT + SetCamera> ObjectUniforms for U {
+    fn set_lights(&mut self, lights: &[LightInfo]) -> Result<()> {
+        let (light_positions, light_colors) = LightInfo::to_uniform(lights)?;
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.light_positions = light_positions;
+        fs_uniform.light_colors = light_colors;
+        fs_uniform.light_count = lights.len() as u32;
+        Ok(())
     }
 
     fn set_camera_pos(&mut self, camera: &Camera) {
         let camera_pos = camera.get_position();
-        self.fs_uniform.camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z, 1.0];
+        let (near, far) = camera.get_near_far();
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z, 1.0];
+        fs_uniform.camera_near = near;
+        fs_uniform.camera_far = far;
     }
 
-    fn set_light_intensity(&mut self, light_intensity: f32) {
-        self.fs_uniform.light_intensity = light_intensity;
+    fn set_hemisphere_ambient(&mut self, ambient: &HemisphereAmbient) {
+        let [ambient_sky_color, ambient_ground_color, ambient_up] = ambient.to_uniform();
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.ambient_sky_color = ambient_sky_color;
+        fs_uniform.ambient_ground_color = ambient_ground_color;
+        fs_uniform.ambient_up = ambient_up;
+        fs_uniform.hemisphere_ambient = ambient.enabled as u32;
+    }
+
+    fn set_directional_light(
+        &mut self,
+        direction: &Vector3D<f32, WorldSpace>,
+        color: [f32; 3],
+        intensity: f32,
+    ) {
+        let [sun_direction, sun_color] = DirectionalLight {
+            direction: *direction,
+            color,
+            intensity,
+        }
+        .to_uniform();
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.sun_direction = sun_direction;
+        fs_uniform.sun_color = sun_color;
     }
-}
 
-impl ObjectUniforms for <TexturePhongShaders as ShadersT>::Uniforms {
-    fn set_light_pos(&mut self, light_pos: &Point3D<f32, WorldSpace>) {
-        self.fs_uniform.light_pos = [light_pos.x, light_pos.y, light_pos.z, 1.0];
+    fn set_uv_transform(&mut self, [transform_u, transform_v]: [[f32; 4]; 2]) {
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.uv_transform_u = transform_u;
+        fs_uniform.uv_transform_v = transform_v;
     }
 
-    fn set_camera_pos(&mut self, camera: &Camera) {
-        let camera_pos = camera.get_position();
-        self.fs_uniform.camera_pos = [camera_pos.x, camera_pos.y, camera_pos.z, 1.0];
+    fn set_light_space_matrix(&mut self, mat: Option<[f32; 16]>) {
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.shadow_enabled = mat.is_some() as u32;
+        fs_uniform.light_space = mat.unwrap_or_default();
+    }
+
+    fn set_shadow_config(&mut self, config: &ShadowConfig) {
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.shadow_bias = config.bias;
+        fs_uniform.shadow_pcf_radius = config.filter.get_kernel_radius();
+        fs_uniform.shadow_filter = config.filter.to_uniform();
+    }
+
+    fn set_shadow_noise(&mut self, noise: &NoiseResources) {
+        let (poisson_disk, poisson_disk_size, rotation_noise) = noise.to_uniform();
+        let fs_uniform = self.get_fs_uniform_mut();
+        fs_uniform.poisson_disk = poisson_disk;
+        fs_uniform.poisson_disk_size = poisson_disk_size;
+        fs_uniform.rotation_noise = rotation_noise;
+    }
+
+    fn set_specular_aa_strength(&mut self, strength: f32) {
+        self.get_fs_uniform_mut().specular_aa_strength = strength;
+    }
+
+    fn set_diffuse_ibl(&mut self, enabled: bool) {
+        self.get_fs_uniform_mut().diffuse_ibl = enabled as u32;
+    }
+
+    fn set_ambient_strength(&mut self, strength: f32) {
+        self.get_fs_uniform_mut().ambient_strength = strength;
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.get_fs_uniform_mut().render_mode = mode.to_uniform();
     }
 
-    fn set_light_intensity(&mut self, light_intensity: f32) {
-        self.fs_uniform.light_intensity = light_intensity;
+    fn set_shininess(&mut self, shininess: f32) {
+        self.get_fs_uniform_mut().shininess = shininess;
+    }
+
+    fn set_specular_model(&mut self, model: SpecularModel) {
+        self.get_fs_uniform_mut().specular_model = model.to_uniform();
+    }
+
+    fn set_gamma_correction(&mut self, enabled: bool) {
+        self.get_fs_uniform_mut().gamma_correction = enabled as u32;
     }
 }
 
@@ -323,9 +1138,18 @@ struct VertexAttributes<'a> {
 pub struct ObjectImpl<V: Vertex, M: Material, S> {
     mesh: Mesh<V, M, S>,
     uniforms: <<M as Material>::Shaders as ShadersT>::Uniforms,
+    material: Arc<M>,
+    bvh: Bvh<S>,
+    // indexed by the triangle index of the BVH, empty if the object doesn't have textures
+    triangle_texture_coords: Vec<[[f32; 2]; 3]>,
+    // overrides the raster state of the scene
+    raster_state: Option<RasterState>,
+    // drawn without culling unless the raster state is overridden, for the open surfaces
+    double_sided: bool,
 }
 
 type TextureObject<S> = ObjectImpl<ObjectWithTextureVertex, TextureObjectMaterial, S>;
+type NormalMapObject<S> = ObjectImpl<ObjectWithTextureVertex, NormalMapObjectMaterial, S>;
 type NoTextureObject<S> = ObjectImpl<ObjectWithNoTextureVertex, NoTextureObjectMaterial, S>;
 
 impl<V: Vertex, M: Material, S> ObjectImpl<V, M, S>
@@ -335,13 +1159,13 @@ where
     fn new<K>(
         mesh_renderer: Arc<MeshRenderer<V, M>>,
         vertex_attributes: VertexAttributes<'_>,
-        group: &Group,
+        groups: &[&Group],
         material: Arc<M>,
-        vertex_to_struct: impl Fn(
-            Result<(&[f32; 3], Option<&[f32; 2]>, Option<&[f32; 3]>)>,
-        ) -> Result<V>,
+        vertex_to_struct: impl Fn(Result<VertexAttributeRefs>) -> Result<V>,
         vertex_to_key: impl Fn(&V) -> K,
-    ) -> Result<Self>
+        // fills in the attributes derived from the deduplicated triangles
+        complete_vertices: impl Fn(&mut [V], &[u32]),
+    ) -> Result<(Self, Upload)>
     where
         V: Vertex,
         K: Hash + Eq,
@@ -353,45 +1177,193 @@ where
             texture_coord,
             normal,
         } = vertex_attributes;
-        let vertex_data = group
-            .polys
-            .iter()
-            .flat_map(|poly| poly.0.iter())
-            .map(create_index_to_vertex_map(position, texture_coord, normal))
-            .map(vertex_to_struct);
-        let (vertex_data, indices) =
-            vertex_attributes_to_indexed_vertex_attributes(vertex_data, vertex_to_key)
-                .chain_err(|| "fail to generte indexed vertex attributes from vertex attributes")?;
-        let mesh_data =
-            MeshData::create(vertex_data, indices).chain_err(|| "fail to load vertex data")?;
-        let (mesh, uniforms) = mesh_renderer
-            .create_mesh(mesh_data, material.as_ref())
-            .chain_err(|| "fail to create mesh")?;
-        Ok(Self { mesh, uniforms })
+        let mut triangles = vec![];
+        let mut triangle_texture_coords = vec![];
+        let mut group_mesh_data = vec![];
+        for group in groups {
+            {
+                profile_scope!("collect triangles");
+                triangles.extend(
+                    group_triangles(position, group)
+                        .chain_err(|| "fail to collect the triangles to build the BVH")?,
+                );
+                if let Some(texture_coord) = texture_coord {
+                    triangle_texture_coords.extend(
+                        group_triangle_texture_coords(texture_coord, group)
+                            .chain_err(|| "fail to collect the texture coords of the triangles")?,
+                    );
+                }
+            }
+            let vertex_data = group
+                .polys
+                .iter()
+                .flat_map(|poly| poly.0.iter())
+                .map(create_index_to_vertex_map(position, texture_coord, normal))
+                .map(&vertex_to_struct);
+            let (mut vertex_data, indices) = {
+                profile_scope!("deduplicate vertices");
+                vertex_attributes_to_indexed_vertex_attributes(vertex_data, &vertex_to_key)
+                    .chain_err(|| {
+                        "fail to generte indexed vertex attributes from vertex attributes"
+                    })?
+            };
+            complete_vertices(&mut vertex_data, &indices);
+            let mesh_data = MeshData::create(vertex_data, indices)
+                .chain_err(|| format!("fail to load vertex data of the group {}", group.name))?;
+            group_mesh_data.push((group.name.clone(), mesh_data));
+        }
+        let mesh_data = {
+            profile_scope!("merge groups");
+            MeshData::merge(group_mesh_data)
+                .chain_err(|| "fail to merge the vertex data of the groups")?
+        };
+        let (mesh, uniforms, upload) = {
+            profile_scope!("upload mesh");
+            mesh_renderer
+                .upload_mesh(mesh_data, material.as_ref())
+                .chain_err(|| "fail to create mesh")?
+        };
+        let bvh = {
+            profile_scope!("build bvh");
+            Bvh::new(triangles)
+        };
+        Ok((
+            Self {
+                mesh,
+                uniforms,
+                material,
+                bvh,
+                triangle_texture_coords,
+                raster_state: None,
+                double_sided: false,
+            },
+            upload,
+        ))
+    }
+}
+
+impl<V: Vertex, M: Material, S> ObjectImpl<V, M, S> {
+    // the texture coords at the hit, only for the objects with textures
+    fn get_texture_coord(&self, hit: &Hit) -> [f32; 2] {
+        let [a, b, c] = self.triangle_texture_coords[hit.triangle_index];
+        let (u, v) = hit.barycentric;
+        let w = 1.0 - u - v;
+        [
+            a[0] * w + b[0] * u + c[0] * v,
+            a[1] * w + b[1] * u + c[1] * v,
+        ]
+    }
+}
+
+// the position, the texture coord and the normal of a vertex, see create_index_to_vertex_map
+type VertexAttributeRefs<'a> = (&'a [f32; 3], Option<&'a [f32; 2]>, Option<&'a [f32; 3]>);
+
+fn texture_vertex_to_struct(v: Result<VertexAttributeRefs>) -> Result<ObjectWithTextureVertex> {
+    let (position, texture, normal) = v?;
+    let normal =
+        normal.ok_or_else(|| -> Error { "object without normals not supported".into() })?;
+    let texture =
+        texture.ok_or_else(|| -> Error { "object without textures not supported".into() })?;
+    Ok(ObjectWithTextureVertex {
+        in_position: [position[0], position[1], position[2], 1.0],
+        in_normal: [normal[0], normal[1], normal[2], 0.0],
+        in_texture_coord: *texture,
+        in_tangent: Default::default(),
+    })
+}
+
+type TextureVertexKey = (
+    [OrderedFloat<f32>; 4],
+    [OrderedFloat<f32>; 4],
+    [OrderedFloat<f32>; 2],
+);
+
+fn texture_vertex_to_key(v: &ObjectWithTextureVertex) -> TextureVertexKey {
+    (
+        Convert::<[f32; 4], _>::to(&v.in_position),
+        Convert::<[f32; 4], _>::to(&v.in_normal),
+        Convert::<[f32; 2], _>::to(&v.in_texture_coord),
+    )
+}
+
+// Per-vertex tangents for the normal maps (Lengyel 2001): the directions of increasing u of the
+// triangles around a vertex are summed, then orthogonalized against its normal. The v of the
+// texture coords is flipped on load, so the handedness in w is negated for the bitangent to point
+// towards +v of the file like the OpenGL normal maps. The triangles without an area in the
+// texture space are skipped, and a vertex without any gets a tangent perpendicular to the normal.
+fn generate_tangents(vertices: &mut [ObjectWithTextureVertex], indices: &[u32]) {
+    let to_vector = |[x, y, z, _]: [f32; 4]| Vector3D::<f32, UnknownUnit>::new(x, y, z);
+    let mut sums = vec![(Vector3D::zero(), Vector3D::zero()); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        let origin = to_vector(vertices[a].in_position);
+        let e1 = to_vector(vertices[b].in_position) - origin;
+        let e2 = to_vector(vertices[c].in_position) - origin;
+        let [u0, v0] = vertices[a].in_texture_coord;
+        let [u1, v1] = vertices[b].in_texture_coord;
+        let [u2, v2] = vertices[c].in_texture_coord;
+        let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+        let r = du1 * dv2 - du2 * dv1;
+        if r == 0.0 {
+            continue;
+        }
+        let tangent = (e1 * dv2 - e2 * dv1) / r;
+        let bitangent = (e2 * du1 - e1 * du2) / r;
+        for i in [a, b, c].iter() {
+            sums[*i].0 += tangent;
+            sums[*i].1 += bitangent;
+        }
+    }
+    for (vertex, (tangent, bitangent)) in vertices.iter_mut().zip(sums) {
+        let normal = to_vector(vertex.in_normal).normalize();
+        let mut t = tangent - normal * normal.dot(tangent);
+        let square_length = t.square_length();
+        if square_length.is_nan() || square_length <= 1e-12 {
+            let axis = if normal.x.abs() < 0.9 {
+                vec3(1.0, 0.0, 0.0)
+            } else {
+                vec3(0.0, 1.0, 0.0)
+            };
+            t = normal.cross(axis);
+        }
+        let t = t.normalize();
+        let w = if normal.cross(t).dot(bitangent) > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.in_tangent = [t.x, t.y, t.z, w];
     }
 }
 
 pub enum Object<S> {
     WithTexture(TextureObject<S>),
+    WithNormalMap(NormalMapObject<S>),
     NoTexture(NoTextureObject<S>),
 }
 
 impl<S> Object<S> {
+    // All the groups are drawn with a single draw call, hence should share the same material.
+    // The object can't be drawn until the upload of its mesh finishes.
     pub fn without_texture(
         renderer: ObjectRenderer,
         position: &[[f32; 3]],
         normal: &[[f32; 3]],
-        group: &Group,
+        groups: &[&Group],
         material: Arc<NoTextureObjectMaterial>,
-    ) -> Result<Self> {
+    ) -> Result<(Self, Upload)> {
         NoTextureObject::new(
-            renderer.no_texture_renderer,
+            renderer.get_renderer::<NoTextureObjectMaterial>()?,
             VertexAttributes {
                 position,
                 texture_coord: None,
                 normal,
             },
-            group,
+            groups,
             material,
             |v| {
                 let (position, _, normal) = v?;
@@ -408,9 +1380,42 @@ impl<S> Object<S> {
                     Convert::<[f32; 4], _>::to(&v.in_normal),
                 )
             },
+            |_, _| {},
         )
         .chain_err(|| "fail to create an object without textures")
-        .map(Self::NoTexture)
+        .map(|(object, upload)| (Self::NoTexture(object), upload))
+    }
+
+    // the grid of MeshData::plane facing up, e.g. the ground under the model
+    pub fn plane(
+        renderer: ObjectRenderer,
+        width_segments: u32,
+        depth_segments: u32,
+        material: Arc<NoTextureObjectMaterial>,
+    ) -> Result<(Self, Upload)> {
+        let plane = MeshData::<ObjectWithNoTextureVertex>::plane(width_segments, depth_segments)?;
+        let position: Vec<_> = plane
+            .get_vertices()
+            .iter()
+            .map(|vertex| {
+                let [x, y, z, _] = vertex.in_position;
+                [x, y, z]
+            })
+            .collect();
+        let indices: Vec<_> = plane.get_indices().iter().collect();
+        let mut group = Group::new("plane".to_owned());
+        group.polys = indices
+            .chunks_exact(3)
+            .map(|triangle| {
+                SimplePolygon(
+                    triangle
+                        .iter()
+                        .map(|i| IndexTuple(*i as usize, None, Some(0)))
+                        .collect(),
+                )
+            })
+            .collect();
+        Self::without_texture(renderer, &position, &[[0.0, 1.0, 0.0]], &[&group], material)
     }
 
     pub fn with_texture(
@@ -418,61 +1423,98 @@ impl<S> Object<S> {
         position: &[[f32; 3]],
         texture_coord: &[[f32; 2]],
         normal: &[[f32; 3]],
-        group: &Group,
+        groups: &[&Group],
         material: Arc<TextureObjectMaterial>,
-    ) -> Result<Self> {
+    ) -> Result<(Self, Upload)> {
         TextureObject::new(
-            renderer.with_texture_renderer,
+            renderer.get_renderer::<TextureObjectMaterial>()?,
             VertexAttributes {
                 position,
                 texture_coord: Some(texture_coord),
                 normal,
             },
-            group,
+            groups,
             material,
-            |v| {
-                let (position, texture, normal) = v?;
-                let normal = normal
-                    .ok_or_else(|| -> Error { "object without normals not supported".into() })?;
-                let texture = texture
-                    .ok_or_else(|| -> Error { "object without textures not supported".into() })?;
-                Ok(ObjectWithTextureVertex {
-                    in_position: [position[0], position[1], position[2], 1.0],
-                    in_normal: [normal[0], normal[1], normal[2], 0.0],
-                    in_texture_coord: *texture,
-                })
-            },
-            |v| {
-                (
-                    Convert::<[f32; 4], _>::to(&v.in_position),
-                    Convert::<[f32; 4], _>::to(&v.in_normal),
-                    Convert::<[f32; 2], _>::to(&v.in_texture_coord),
-                )
-            },
+            texture_vertex_to_struct,
+            texture_vertex_to_key,
+            |_, _| {},
         )
         .chain_err(|| "fail to create an object with textures")
-        .map(Self::WithTexture)
+        .map(|(object, upload)| (Self::WithTexture(object), upload))
+    }
+
+    // the tangents are generated from the texture coords of the triangles
+    pub fn with_normal_map(
+        renderer: ObjectRenderer,
+        position: &[[f32; 3]],
+        texture_coord: &[[f32; 2]],
+        normal: &[[f32; 3]],
+        groups: &[&Group],
+        material: Arc<NormalMapObjectMaterial>,
+    ) -> Result<(Self, Upload)> {
+        NormalMapObject::new(
+            renderer.get_renderer::<NormalMapObjectMaterial>()?,
+            VertexAttributes {
+                position,
+                texture_coord: Some(texture_coord),
+                normal,
+            },
+            groups,
+            material,
+            texture_vertex_to_struct,
+            texture_vertex_to_key,
+            generate_tangents,
+        )
+        .chain_err(|| "fail to create an object with normal maps")
+        .map(|(object, upload)| (Self::WithNormalMap(object), upload))
     }
 
-    pub fn prepare_draw_commands<T>(
+    pub fn prepare_draw_commands(
         &mut self,
         cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
         model_transform: &Transform3D<f32, S, WorldSpace>,
         camera: &Camera,
-        light: &PointLight<T>,
+        lights: &ObjectLights,
+        uv_transform: [[f32; 4]; 2],
+        render_mode: RenderMode,
     ) -> Result<()> {
-        let uniforms: &mut dyn ObjectUniforms = match self {
-            Self::WithTexture(ref mut obj) => &mut obj.uniforms,
-            Self::NoTexture(ref mut obj) => &mut obj.uniforms,
+        let (uniforms, shininess): (&mut dyn ObjectUniforms, _) = match self {
+            Self::WithTexture(ref mut obj) => (&mut obj.uniforms, obj.material.shininess),
+            Self::WithNormalMap(ref mut obj) => {
+                (&mut obj.uniforms, obj.material.material.shininess)
+            }
+            Self::NoTexture(ref mut obj) => (&mut obj.uniforms, obj.material.shininess),
         };
-        uniforms.set_light_pos(
-            &light
-                .get_position()
-                .chain_err(|| "fail to get light position")?,
-        );
+        uniforms
+            .set_lights(lights.points)
+            .chain_err(|| "fail to set the point lights")?;
         uniforms.set_camera_pos(camera);
-        uniforms.set_light_intensity(light.get_intensity());
+        uniforms.set_hemisphere_ambient(lights.ambient);
+        uniforms.set_directional_light(
+            &lights.sun.direction,
+            lights.sun.color,
+            lights.sun.intensity,
+        );
+        uniforms.set_uv_transform(uv_transform);
+        uniforms
+            .set_light_space_matrix(lights.light_space.map(|light_space| light_space.to_array()));
+        uniforms.set_shadow_config(&lights.shadow);
+        uniforms.set_shadow_noise(lights.noise);
+        uniforms.set_specular_aa_strength(lights.specular_aa_strength);
+        uniforms.set_diffuse_ibl(lights.diffuse_ibl);
+        uniforms.set_ambient_strength(lights.ambient_strength);
+        uniforms.set_render_mode(render_mode);
+        uniforms.set_shininess(lights.shininess_override.unwrap_or(shininess));
+        uniforms.set_specular_model(lights.specular_model);
+        uniforms.set_gamma_correction(lights.gamma_correction);
         uniforms.set_model_matrix(model_transform.to_array());
+        uniforms.set_normal_matrix(
+            normal_matrix(model_transform)
+                .ok_or_else(|| -> Error {
+                    ErrorKind::Validation("the model transform is not invertible".to_owned()).into()
+                })?
+                .to_array(),
+        );
         uniforms.set_view_proj_matrix_from_camera(camera);
         uniforms.update_buffers(cmd_buf_builder).chain_err(|| {
             "fail to add the update buffer for uniforms command to the command builder"
@@ -480,14 +1522,518 @@ impl<S> Object<S> {
         Ok(())
     }
 
+    // drawn with the raster state of the object if overridden, otherwise the one of the scene,
+    // without culling if the object is double-sided
     pub fn draw_commands(
         &self,
         cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        scene_raster_state: RasterState,
     ) -> Result<()> {
-        let mesh: &dyn MeshT<S> = match self {
-            Self::WithTexture(ref obj) => &obj.mesh,
-            Self::NoTexture(ref obj) => &obj.mesh,
+        let (mesh, raster_state, double_sided): (&dyn MeshT<S>, _, _) = match self {
+            Self::WithTexture(ref obj) => (&obj.mesh, obj.raster_state, obj.double_sided),
+            Self::WithNormalMap(ref obj) => (&obj.mesh, obj.raster_state, obj.double_sided),
+            Self::NoTexture(ref obj) => (&obj.mesh, obj.raster_state, obj.double_sided),
         };
-        mesh.draw_commands(cmd_buf_builder)
+        // the depth clamp and the wireframe are set for the whole scene
+        let raster_state = match raster_state {
+            Some(raster_state) => RasterState {
+                depth_clamp: scene_raster_state.depth_clamp,
+                wireframe: scene_raster_state.wireframe,
+                ..raster_state
+            },
+            None if double_sided => RasterState {
+                cull_mode: CullMode::None,
+                ..scene_raster_state
+            },
+            None => scene_raster_state,
+        };
+        mesh.draw_commands(cmd_buf_builder, raster_state)
+    }
+
+    // the depth of the object seen from the light, within the pass of the shadow map
+    pub fn shadow_draw_commands(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        shadow_map: &ShadowMap,
+        light_space_model: &Transform3D<f32, S, NDCSpace>,
+    ) -> Result<()> {
+        let (mesh, pipeline): (&dyn MeshT<S>, _) = match self {
+            Self::WithTexture(ref obj) => (&obj.mesh, shadow_map.get_with_texture_pipeline()),
+            Self::WithNormalMap(ref obj) => (&obj.mesh, shadow_map.get_with_texture_pipeline()),
+            Self::NoTexture(ref obj) => (&obj.mesh, shadow_map.get_no_texture_pipeline()),
+        };
+        mesh.draw_with_pipeline(cmd_buf_builder, pipeline, light_space_model.to_arrays())
+    }
+
+    pub fn get_raster_state(&self) -> Option<RasterState> {
+        match self {
+            Self::WithTexture(ref obj) => obj.raster_state,
+            Self::WithNormalMap(ref obj) => obj.raster_state,
+            Self::NoTexture(ref obj) => obj.raster_state,
+        }
+    }
+
+    // None to draw with the raster state of the scene
+    pub fn set_raster_state(&mut self, raster_state: Option<RasterState>) {
+        match self {
+            Self::WithTexture(ref mut obj) => obj.raster_state = raster_state,
+            Self::WithNormalMap(ref mut obj) => obj.raster_state = raster_state,
+            Self::NoTexture(ref mut obj) => obj.raster_state = raster_state,
+        }
+    }
+
+    pub fn set_double_sided(&mut self, double_sided: bool) {
+        match self {
+            Self::WithTexture(ref mut obj) => obj.double_sided = double_sided,
+            Self::WithNormalMap(ref mut obj) => obj.double_sided = double_sided,
+            Self::NoTexture(ref mut obj) => obj.double_sided = double_sided,
+        }
+    }
+
+    pub fn flip_winding(&mut self) -> Result<Upload> {
+        let mesh: &mut dyn MeshT<S> = match self {
+            Self::WithTexture(ref mut obj) => &mut obj.mesh,
+            Self::WithNormalMap(ref mut obj) => &mut obj.mesh,
+            Self::NoTexture(ref mut obj) => &mut obj.mesh,
+        };
+        mesh.flip_winding()
+    }
+
+    // the groups merged into the object
+    pub fn get_sub_meshes(&self) -> &[SubMesh] {
+        match self {
+            Self::WithTexture(ref obj) => obj.mesh.get_sub_meshes(),
+            Self::WithNormalMap(ref obj) => obj.mesh.get_sub_meshes(),
+            Self::NoTexture(ref obj) => obj.mesh.get_sub_meshes(),
+        }
+    }
+
+    pub fn get_indices(&self) -> &Indices {
+        match self {
+            Self::WithTexture(ref obj) => obj.mesh.get_indices(),
+            Self::WithNormalMap(ref obj) => obj.mesh.get_indices(),
+            Self::NoTexture(ref obj) => obj.mesh.get_indices(),
+        }
+    }
+}
+
+impl<S> TracedSurface<S> for Object<S> {
+    fn get_bvh(&self) -> &Bvh<S> {
+        match self {
+            Self::WithTexture(ref obj) => &obj.bvh,
+            Self::WithNormalMap(ref obj) => &obj.bvh,
+            Self::NoTexture(ref obj) => &obj.bvh,
+        }
+    }
+
+    fn get_albedo(&self, hit: &Hit) -> [f32; 3] {
+        match self {
+            Self::WithTexture(ref obj) => obj.material.sample_albedo(obj.get_texture_coord(hit)),
+            Self::WithNormalMap(ref obj) => obj
+                .material
+                .material
+                .sample_albedo(obj.get_texture_coord(hit)),
+            Self::NoTexture(ref obj) => obj.material.kd,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::Angle;
+    use obj::{ObjData, SimplePolygon};
+    use vulkano::{
+        buffer::{BufferUsage, CpuAccessibleBuffer},
+        command_buffer::{CommandBuffer, SubpassContents},
+        format::{ClearValue, Format},
+        framebuffer::Framebuffer,
+        image::{AttachmentImage, ImageUsage},
+    };
+
+    use super::super::{
+        material::DEFAULT_SHININESS, noise::DEFAULT_NOISE_SEED, renderer::DEFAULT_DEPTH_RANGE,
+    };
+    use crate::gpu_test::create_queue;
+
+    struct TriangleSpace;
+
+    // the triangles don't share any vertex, so every corner is a unique vertex
+    fn separate_triangles(triangle_count: usize) -> (Vec<[f32; 3]>, Group) {
+        let positions = (0..triangle_count * 3)
+            .map(|i| [i as f32, (i % 3) as f32, 0.0])
+            .collect();
+        let mut group = Group::new("separate".to_owned());
+        group.polys = (0..triangle_count)
+            .map(|t| {
+                SimplePolygon(
+                    (t * 3..t * 3 + 3)
+                        .map(|i| IndexTuple(i, Some(0), Some(0)))
+                        .collect(),
+                )
+            })
+            .collect();
+        (positions, group)
+    }
+
+    #[test]
+    fn test_indices_beyond_u16() {
+        let vertex_count = u16::MAX as usize + 2;
+        let (vertices, indices) = vertex_attributes_to_indexed_vertex_attributes(
+            (0..vertex_count).chain(0..2).map(Ok),
+            |v| *v,
+        )
+        .unwrap();
+        assert_eq!(vertices.len(), vertex_count);
+        assert_eq!(indices.len(), vertex_count + 2);
+        assert_eq!(indices[vertex_count - 1], vertex_count as u32 - 1);
+        // the duplicates point back to the first ones
+        assert_eq!(&indices[vertex_count..], &[0, 1]);
+    }
+
+    #[test]
+    fn test_object_with_more_vertices_than_u16() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: Format::R8G8B8A8Unorm,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        let shadow_map = ShadowMap::new(queue.clone()).unwrap();
+        let renderer = ObjectRenderer::init(
+            device,
+            queue,
+            Subpass::from(render_pass, 0).unwrap(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            shadow_map.get_texture(),
+        )
+        .unwrap();
+        let (material, _) = TextureObjectMaterial::new(
+            &renderer,
+            Arc::new(RgbaImage::new(1, 1)),
+            None,
+            [0.0; 3],
+            DEFAULT_SHININESS,
+        )
+        .unwrap();
+        let material = Arc::new(material);
+        for (triangle_count, expected_type) in [(100, "u16"), (100_000, "u32")].iter().cloned() {
+            let (positions, group) = separate_triangles(triangle_count);
+            let (object, _) = Object::<TriangleSpace>::with_texture(
+                renderer.clone(),
+                &positions,
+                &[[0.0, 0.0]],
+                &[[0.0, 0.0, 1.0]],
+                &[&group],
+                material.clone(),
+            )
+            .unwrap();
+            let indices = object.get_indices();
+            assert_eq!(indices.get_type_name(), expected_type);
+            assert!(indices.iter().eq(0..(triangle_count * 3) as u32));
+        }
+    }
+
+    const CUBE_WITHOUT_NORMALS: &str = "\
+v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1
+f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 3 4 8 7\nf 2 3 7 6\nf 1 5 8 4
+";
+
+    #[test]
+    fn test_generate_normals_of_cube() {
+        let mut data = ObjData::load_buf(CUBE_WITHOUT_NORMALS.as_bytes()).unwrap();
+        assert!(data.normal.is_empty());
+        generate_missing_normals(&mut data).unwrap();
+        assert_eq!(data.normal.len(), 8);
+        for group in data.objects.iter().flat_map(|object| object.groups.iter()) {
+            for IndexTuple(position_index, _, normal_index) in
+                group.polys.iter().flat_map(|poly| poly.0.iter())
+            {
+                let [x, y, z] = data.normal[normal_index.unwrap()];
+                let normal = vec3::<_, UnknownUnit>(x, y, z);
+                assert!((normal.length() - 1.0).abs() < 1e-5, "{:?}", normal);
+                // the cube is centered at the origin
+                let [x, y, z] = data.position[*position_index];
+                assert!(normal.dot(vec3(x, y, z)) > 0.0, "{:?}", normal);
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_normals_skips_degenerate_faces() {
+        let mut data =
+            ObjData::load_buf("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 2 0 0\nf 1 2 3\nf 1 2 4\n".as_bytes())
+                .unwrap();
+        generate_missing_normals(&mut data).unwrap();
+        let normal_of = |position_index: usize| {
+            data.objects[0].groups[0]
+                .polys
+                .iter()
+                .flat_map(|poly| poly.0.iter())
+                .find(|IndexTuple(i, _, _)| *i == position_index)
+                .and_then(|IndexTuple(_, _, normal_index)| *normal_index)
+                .map(|i| data.normal[i])
+                .unwrap()
+        };
+        // the collinear face adds nothing to the normals of the shared positions
+        assert_eq!(normal_of(0), [0.0, 0.0, 1.0]);
+        assert_eq!(normal_of(1), [0.0, 0.0, 1.0]);
+        // the position only on the collinear face still gets a finite normal
+        assert!(normal_of(3).iter().all(|c| c.is_finite()));
+    }
+
+    #[test]
+    fn test_closed_manifold() {
+        let cube = ObjData::load_buf(CUBE_WITHOUT_NORMALS.as_bytes()).unwrap();
+        let triangles = group_position_triangles(&cube.objects[0].groups[0]);
+        assert_eq!(triangles.len(), 12);
+        assert!(is_closed_manifold(&triangles));
+        // without the top, the edges around the hole are only on one triangle
+        assert!(!is_closed_manifold(&triangles[2..]));
+        // a quad, e.g. a leaf
+        assert!(!is_closed_manifold(&[[0, 1, 2], [0, 2, 3]]));
+        // an edge shared by more than two triangles, e.g. fins
+        let mut fins = triangles.clone();
+        fins.push([0, 1, 8]);
+        fins.push([1, 0, 8]);
+        assert!(!is_closed_manifold(&fins));
+        // the degenerate triangles don't open the surface
+        let mut degenerate = triangles;
+        degenerate.push([0, 0, 1]);
+        assert!(is_closed_manifold(&degenerate));
+        assert!(is_closed_manifold(&[]));
+    }
+
+    #[test]
+    fn test_generate_normals_keeps_existing_normals() {
+        let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 -1\nf 1//1 2//1 3//1\n";
+        let mut data = ObjData::load_buf(text.as_bytes()).unwrap();
+        generate_missing_normals(&mut data).unwrap();
+        assert_eq!(data.normal, vec![[0.0, 0.0, -1.0]]);
+    }
+
+    // a unit quad facing +z with the texture coords as flipped on load
+    fn quad_vertices(to_uv: impl Fn(f32, f32) -> [f32; 2]) -> Vec<ObjectWithTextureVertex> {
+        [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]
+            .iter()
+            .map(|&[x, y]| ObjectWithTextureVertex {
+                in_position: [x, y, 0.0, 1.0],
+                in_normal: [0.0, 0.0, 1.0, 0.0],
+                in_texture_coord: to_uv(x, y),
+                in_tangent: Default::default(),
+            })
+            .collect()
+    }
+
+    const QUAD_INDICES: [u32; 6] = [0, 1, 2, 0, 2, 3];
+
+    #[test]
+    fn test_generate_tangents() {
+        // +v of the file is +y, which the bitangent cross(normal, tangent) * w should be
+        let mut vertices = quad_vertices(|x, y| [x, 1.0 - y]);
+        generate_tangents(&mut vertices, &QUAD_INDICES);
+        for vertex in vertices.iter() {
+            assert_eq!(vertex.in_tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+        // mirrored horizontally, the bitangent still points towards +y
+        let mut vertices = quad_vertices(|x, y| [1.0 - x, 1.0 - y]);
+        generate_tangents(&mut vertices, &QUAD_INDICES);
+        for vertex in vertices.iter() {
+            assert_eq!(vertex.in_tangent, [-1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn test_generate_tangents_without_texture_area() {
+        let mut vertices = quad_vertices(|_, _| [0.5, 0.5]);
+        generate_tangents(&mut vertices, &QUAD_INDICES);
+        for vertex in vertices.iter() {
+            let [x, y, z, w] = vertex.in_tangent;
+            assert!((x * x + y * y + z * z - 1.0).abs() < 1e-6);
+            assert_eq!(z, 0.0);
+            assert!(w == 1.0 || w == -1.0);
+        }
+    }
+
+    // The normal view of a double-sided plane seen from above and from below, the normal faces the
+    // camera on both sides.
+    #[test]
+    fn test_back_face_normal() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::R8G8B8A8Unorm,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        let shadow_map = ShadowMap::new(queue.clone()).unwrap();
+        let renderer = ObjectRenderer::init(
+            device.clone(),
+            queue.clone(),
+            Subpass::from(render_pass.clone(), 0).unwrap(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            shadow_map.get_texture(),
+        )
+        .unwrap();
+        let material =
+            NoTextureObjectMaterial::new(&renderer, [1.0; 3], [0.0; 3], DEFAULT_SHININESS).unwrap();
+        let (mut plane, upload) =
+            Object::<TriangleSpace>::plane(renderer, 1, 1, Arc::new(material)).unwrap();
+        upload.wait().unwrap();
+        plane.set_double_sided(true);
+        let image = AttachmentImage::with_usage(
+            device.clone(),
+            [1, 1],
+            Format::R8G8B8A8Unorm,
+            ImageUsage {
+                transfer_source: true,
+                ..ImageUsage::color_attachment()
+            },
+        )
+        .unwrap();
+        let depth = AttachmentImage::transient(device.clone(), [1, 1], Format::D16Unorm).unwrap();
+        let framebuffer = Arc::new(
+            Framebuffer::start(render_pass)
+                .add(image.clone())
+                .unwrap()
+                .add(depth)
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        // the normal encoded as normal * 0.5 + 0.5
+        for (eye_y, expected) in [(2.0, [128, 255, 128]), (-2.0, [128, 0, 128])].iter() {
+            let camera = Camera::new(
+                Angle::degrees(60.0),
+                1.0,
+                0.1,
+                10.0,
+                &point3(0.0, *eye_y, 0.0),
+                &point3(0.0, 0.0, 0.0),
+                &vec3(0.0, 0.0, 1.0),
+            )
+            .unwrap();
+            let buffer = CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::transfer_destination(),
+                false,
+                [0u8; 4].iter().cloned(),
+            )
+            .unwrap();
+            let mut builder =
+                AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family())
+                    .unwrap();
+            plane
+                .prepare_draw_commands(
+                    &mut builder,
+                    &Transform3D::identity(),
+                    &camera,
+                    &ObjectLights {
+                        points: &[],
+                        ambient: &HemisphereAmbient::default(),
+                        sun: &DirectionalLight::default(),
+                        light_space: None,
+                        shadow: ShadowConfig::default(),
+                        noise: &NoiseResources::new(DEFAULT_NOISE_SEED),
+                        specular_aa_strength: DEFAULT_SPECULAR_AA_STRENGTH,
+                        diffuse_ibl: false,
+                        ambient_strength: DEFAULT_AMBIENT_STRENGTH,
+                        specular_model: SpecularModel::default(),
+                        shininess_override: None,
+                        gamma_correction: true,
+                    },
+                    [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]],
+                    RenderMode::Normals,
+                )
+                .unwrap();
+            builder
+                .begin_render_pass(
+                    framebuffer.clone(),
+                    SubpassContents::Inline,
+                    vec![ClearValue::Float([0.0; 4]), ClearValue::Depth(1.0)],
+                )
+                .unwrap();
+            plane
+                .draw_commands(&mut builder, RasterState::default())
+                .unwrap();
+            builder.end_render_pass().unwrap();
+            builder
+                .copy_image_to_buffer(image.clone(), buffer.clone())
+                .unwrap();
+            builder
+                .build()
+                .unwrap()
+                .execute(queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+            let pixel = buffer.read().unwrap();
+            for (channel, expected) in pixel.iter().zip(expected.iter()) {
+                assert!(
+                    (i32::from(*channel) - expected).abs() <= 1,
+                    "{:?} seen from y = {}",
+                    &pixel[..],
+                    eye_y
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,444 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::f32::consts::PI;
+
+use euclid::{point2, Point3D, Transform3D, Vector3D};
+use image::{Rgba, RgbaImage};
+
+use super::{
+    bvh::{Bvh, Hit, Ray},
+    noise::Rng,
+    Camera, WorldSpace,
+};
+
+pub const DEFAULT_PATH_TRACE_SAMPLES: u32 = 4;
+const MAX_BOUNCES: u32 = 3;
+// offset along the normal for the secondary rays, so that they don't hit the surface they start at
+const SURFACE_OFFSET: f32 = 1e-4;
+
+// A surface the path tracer can shoot rays at, the triangles are in the model space S.
+pub trait TracedSurface<S> {
+    fn get_bvh(&self) -> &Bvh<S>;
+    // linear diffuse reflectance at the hit point
+    fn get_albedo(&self, hit: &Hit) -> [f32; 3];
+}
+
+pub struct PointLightSource {
+    pub position: Point3D<f32, WorldSpace>,
+    pub intensity: f32,
+    pub color: [f32; 3],
+}
+
+// the position of a hit and the normal of the triangle, facing the ray
+pub type SurfacePoint = (Point3D<f32, WorldSpace>, Vector3D<f32, WorldSpace>);
+
+struct SurfaceHit {
+    distance: f32,
+    position: Point3D<f32, WorldSpace>,
+    // faces the incoming ray
+    normal: Vector3D<f32, WorldSpace>,
+    albedo: [f32; 3],
+}
+
+pub struct PathTracer<'a, S> {
+    surfaces: Vec<&'a dyn TracedSurface<S>>,
+    model_transform: Transform3D<f32, S, WorldSpace>,
+    inverse_model_transform: Transform3D<f32, WorldSpace, S>,
+    lights: Vec<PointLightSource>,
+}
+
+impl<'a, S> PathTracer<'a, S> {
+    // None if the model transform is not invertible
+    pub fn new(
+        surfaces: Vec<&'a dyn TracedSurface<S>>,
+        model_transform: Transform3D<f32, S, WorldSpace>,
+        lights: Vec<PointLightSource>,
+    ) -> Option<Self> {
+        Some(Self {
+            surfaces,
+            inverse_model_transform: model_transform.inverse()?,
+            model_transform,
+            lights,
+        })
+    }
+
+    fn intersect(&self, ray: &Ray<WorldSpace>) -> Option<SurfaceHit> {
+        // the model transform is affine, so the distances along the ray are the same in both
+        // spaces
+        let model_ray = Ray::new(
+            self.inverse_model_transform.transform_point3d(ray.origin)?,
+            self.inverse_model_transform
+                .transform_vector3d(ray.direction),
+        )
+        .with_max_distance(ray.max_distance);
+        let (surface, hit) = self
+            .surfaces
+            .iter()
+            .filter_map(|surface| Some((surface, surface.get_bvh().intersect(&model_ray)?)))
+            .min_by(|(_, a), (_, b)| {
+                a.distance
+                    .partial_cmp(&b.distance)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })?;
+        let [a, b, c] = surface.get_bvh().get_triangle(hit.triangle_index);
+        let a = self.model_transform.transform_point3d(*a)?;
+        let b = self.model_transform.transform_point3d(*b)?;
+        let c = self.model_transform.transform_point3d(*c)?;
+        let normal = (b - a).cross(c - a).normalize();
+        let normal = if normal.dot(ray.direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+        Some(SurfaceHit {
+            distance: hit.distance,
+            position: ray.at(hit.distance),
+            normal,
+            albedo: surface.get_albedo(&hit),
+        })
+    }
+
+    // the closest hit of the ray, without shading it
+    pub fn probe(&self, ray: &Ray<WorldSpace>) -> Option<SurfacePoint> {
+        self.intersect(ray).map(|hit| (hit.position, hit.normal))
+    }
+
+    fn occluded(&self, ray: &Ray<WorldSpace>) -> bool {
+        let model_ray = match self.inverse_model_transform.transform_point3d(ray.origin) {
+            Some(origin) => Ray::new(
+                origin,
+                self.inverse_model_transform
+                    .transform_vector3d(ray.direction),
+            )
+            .with_max_distance(ray.max_distance),
+            None => return false,
+        };
+        self.surfaces
+            .iter()
+            .any(|surface| surface.get_bvh().any_hit(&model_ray))
+    }
+
+    // The light falls off the same way as in the phong shader, i.e. linearly with the distance
+    // and without the 1 / pi of the lambertian BRDF, so that the direct lighting can be compared
+    // with the rasterized result.
+    fn direct_lighting(&self, hit: &SurfaceHit) -> [f32; 3] {
+        let mut lighting = [0.0; 3];
+        for light in self.lights.iter() {
+            let to_light = light.position - hit.position;
+            let distance = to_light.length();
+            let cos_theta = hit.normal.dot(to_light) / distance;
+            if cos_theta <= 0.0 {
+                continue;
+            }
+            let shadow_ray = Ray::new(hit.position + hit.normal * SURFACE_OFFSET, to_light)
+                .with_max_distance(1.0 - SURFACE_OFFSET);
+            if self.occluded(&shadow_ray) {
+                continue;
+            }
+            let scale = cos_theta * light.intensity / distance;
+            for (sum, c) in lighting.iter_mut().zip(light.color.iter()) {
+                *sum += c * scale;
+            }
+        }
+        lighting
+    }
+
+    fn radiance(&self, mut ray: Ray<WorldSpace>, rng: &mut Rng) -> [f32; 3] {
+        let mut radiance = [0.0; 3];
+        let mut throughput = [1.0; 3];
+        for _ in 0..MAX_BOUNCES {
+            let hit = match self.intersect(&ray) {
+                Some(hit) => hit,
+                None => break,
+            };
+            let direct = self.direct_lighting(&hit);
+            for i in 0..3 {
+                throughput[i] *= hit.albedo[i];
+                radiance[i] += throughput[i] * direct[i];
+            }
+            // cosine weighted sampling of the hemisphere, the pdf cancels out the cosine and the
+            // 1 / pi of the lambertian BRDF, leaving only the albedo in the throughput
+            ray = Ray::new(
+                hit.position + hit.normal * SURFACE_OFFSET,
+                sample_cosine_hemisphere(&hit.normal, rng),
+            );
+        }
+        radiance
+    }
+
+    pub fn render(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        samples: u32,
+        seed: u64,
+    ) -> RgbaImage {
+        let samples = samples.max(1);
+        RgbaImage::from_fn(width, height, |x, y| {
+            let mut rng = Rng::new(seed ^ (u64::from(y) << 32 | u64::from(x)));
+            let mut color = [0.0; 3];
+            for _ in 0..samples {
+                let screen_point = point2(
+                    (x as f32 + rng.next_f32()) / width as f32 * 2.0 - 1.0,
+                    (y as f32 + rng.next_f32()) / height as f32 * 2.0 - 1.0,
+                );
+                let radiance = self.radiance(camera.screen_ray(screen_point), &mut rng);
+                for i in 0..3 {
+                    color[i] += radiance[i] / samples as f32;
+                }
+            }
+            let [r, g, b] = color;
+            Rgba([to_srgb_u8(r), to_srgb_u8(g), to_srgb_u8(b), 255])
+        })
+    }
+
+    // the normal and the distance of the first hit through the center of every pixel, row major,
+    // the guides for the denoiser
+    pub fn render_guides(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+    ) -> (Vec<[f32; 3]>, Vec<f32>) {
+        let mut normals = Vec::with_capacity((width * height) as usize);
+        let mut depth = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let screen_point = point2(
+                    (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+                    (y as f32 + 0.5) / height as f32 * 2.0 - 1.0,
+                );
+                match self.intersect(&camera.screen_ray(screen_point)) {
+                    Some(hit) => {
+                        normals.push(hit.normal.to_array());
+                        depth.push(hit.distance);
+                    }
+                    None => {
+                        normals.push([0.0; 3]);
+                        depth.push(f32::INFINITY);
+                    }
+                }
+            }
+        }
+        (normals, depth)
+    }
+}
+
+fn sample_cosine_hemisphere(
+    normal: &Vector3D<f32, WorldSpace>,
+    rng: &mut Rng,
+) -> Vector3D<f32, WorldSpace> {
+    let radius = rng.next_f32().sqrt();
+    let theta = 2.0 * PI * rng.next_f32();
+    let (x, y) = (radius * theta.cos(), radius * theta.sin());
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    // an orthonormal basis around the normal
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3D::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3D::new(1.0, 0.0, 0.0)
+    };
+    let tangent = helper.cross(*normal).normalize();
+    let bitangent = normal.cross(tangent);
+    tangent * x + bitangent * y + *normal * z
+}
+
+// the same gamma the phong shader uses
+fn to_srgb_u8(linear: f32) -> u8 {
+    (linear.max(0.0).powf(1.0 / 2.2).min(1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::{point3, vec3, Angle};
+
+    use super::*;
+
+    struct TestSpace;
+
+    struct Quad {
+        bvh: Bvh<TestSpace>,
+        albedo: [f32; 3],
+    }
+
+    impl TracedSurface<TestSpace> for Quad {
+        fn get_bvh(&self) -> &Bvh<TestSpace> {
+            &self.bvh
+        }
+
+        fn get_albedo(&self, _: &Hit) -> [f32; 3] {
+            self.albedo
+        }
+    }
+
+    // a 2x2 quad on the z = 0 plane centered at the origin
+    fn quad(albedo: [f32; 3]) -> Quad {
+        let a = point3(-1.0, -1.0, 0.0);
+        let b = point3(1.0, -1.0, 0.0);
+        let c = point3(1.0, 1.0, 0.0);
+        let d = point3(-1.0, 1.0, 0.0);
+        Quad {
+            bvh: Bvh::new(vec![[a, b, c], [a, c, d]]),
+            albedo,
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera::new(
+            Angle::pi() / 4.0,
+            1.0,
+            0.1,
+            100.0,
+            &point3(0.0, 0.0, 5.0),
+            &point3(0.0, 0.0, 0.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_direct_lighting_matches_the_phong_diffuse_term() {
+        let quad = quad([0.5, 0.5, 0.5]);
+        let tracer = PathTracer::new(
+            vec![&quad],
+            Transform3D::identity(),
+            vec![PointLightSource {
+                position: point3(0.0, 0.0, 2.0),
+                intensity: 1.0,
+                color: [1.0, 1.0, 1.0],
+            }],
+        )
+        .unwrap();
+        let image = tracer.render(&camera(), 9, 9, 1, 0);
+        // the center pixel faces the light, albedo * intensity / distance = 0.25
+        let expected = to_srgb_u8(0.25);
+        let center = image.get_pixel(4, 4);
+        assert!((i32::from(center[0]) - i32::from(expected)).abs() <= 2);
+        // the corners miss the quad
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_direct_lighting_sums_the_lights() {
+        let quad = quad([0.5, 0.5, 0.5]);
+        // half as bright as in the test above each, red and green
+        let light = |color| PointLightSource {
+            position: point3(0.0, 0.0, 2.0),
+            intensity: 0.5,
+            color,
+        };
+        let tracer = PathTracer::new(
+            vec![&quad],
+            Transform3D::identity(),
+            vec![light([1.0, 0.0, 0.0]), light([1.0, 1.0, 0.0])],
+        )
+        .unwrap();
+        let image = tracer.render(&camera(), 9, 9, 1, 0);
+        let center = image.get_pixel(4, 4);
+        assert!((i32::from(center[0]) - i32::from(to_srgb_u8(0.25))).abs() <= 2);
+        assert!((i32::from(center[1]) - i32::from(to_srgb_u8(0.125))).abs() <= 2);
+        assert_eq!(center[2], 0);
+    }
+
+    #[test]
+    fn test_occluded_light_contributes_nothing() {
+        let quad = quad([1.0, 1.0, 1.0]);
+        // a black wall on the x = 2 plane between the quad and the light, outside of the view
+        let a = point3(2.0, -1.0, -1.0);
+        let b = point3(2.0, 1.0, -1.0);
+        let c = point3(2.0, 1.0, 1.0);
+        let d = point3(2.0, -1.0, 1.0);
+        let wall = Quad {
+            bvh: Bvh::new(vec![[a, b, c], [a, c, d]]),
+            albedo: [0.0, 0.0, 0.0],
+        };
+        let tracer = PathTracer::new(
+            vec![&quad, &wall],
+            Transform3D::identity(),
+            vec![PointLightSource {
+                position: point3(3.0, 0.0, 0.5),
+                intensity: 1.0,
+                color: [1.0, 1.0, 1.0],
+            }],
+        )
+        .unwrap();
+        let image = tracer.render(&camera(), 9, 9, 4, 0);
+        assert_eq!(image.get_pixel(4, 4), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_model_transform_is_applied() {
+        let quad = quad([1.0, 1.0, 1.0]);
+        let tracer = PathTracer::new(
+            vec![&quad],
+            Transform3D::identity().then_translate(vec3(10.0, 0.0, 0.0)),
+            vec![PointLightSource {
+                position: point3(0.0, 0.0, 2.0),
+                intensity: 1.0,
+                color: [1.0, 1.0, 1.0],
+            }],
+        )
+        .unwrap();
+        let image = tracer.render(&camera(), 9, 9, 1, 0);
+        assert_eq!(image.get_pixel(4, 4), &Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_guides_of_a_quad_facing_the_camera() {
+        let quad = quad([1.0, 1.0, 1.0]);
+        let tracer = PathTracer::new(
+            vec![&quad],
+            Transform3D::identity(),
+            vec![PointLightSource {
+                position: point3(0.0, 0.0, 2.0),
+                intensity: 1.0,
+                color: [1.0, 1.0, 1.0],
+            }],
+        )
+        .unwrap();
+        let (normals, depth) = tracer.render_guides(&camera(), 9, 9);
+        let center = 4 * 9 + 4;
+        assert_eq!(normals[center], [0.0, 0.0, 1.0]);
+        assert!((depth[center] - 5.0).abs() < 1e-4);
+        assert_eq!(normals[0], [0.0; 3]);
+        assert_eq!(depth[0], f32::INFINITY);
+    }
+
+    #[test]
+    fn test_probe_the_quad_from_behind() {
+        let quad = quad([1.0, 1.0, 1.0]);
+        let tracer = PathTracer::new(
+            vec![&quad],
+            Transform3D::identity().then_translate(vec3(0.0, 0.0, 1.0)),
+            vec![],
+        )
+        .unwrap();
+        let ray = Ray::new(point3(0.5, 0.0, -5.0), vec3(0.0, 0.0, 1.0));
+        let (position, normal) = tracer.probe(&ray).unwrap();
+        assert!((position - point3(0.5, 0.0, 1.0)).length() < 1e-4);
+        // the normal faces the ray
+        assert_eq!(normal, vec3(0.0, 0.0, -1.0));
+        let miss = Ray::new(point3(5.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0));
+        assert_eq!(tracer.probe(&miss), None);
+    }
+
+    #[test]
+    fn test_same_seed_renders_the_same_image() {
+        let quad = quad([0.8, 0.2, 0.2]);
+        let light = || PointLightSource {
+            position: point3(0.5, 0.5, 1.0),
+            intensity: 2.0,
+            color: [1.0, 1.0, 1.0],
+        };
+        let a = PathTracer::new(vec![&quad], Transform3D::identity(), vec![light()]).unwrap();
+        let b = PathTracer::new(vec![&quad], Transform3D::identity(), vec![light()]).unwrap();
+        assert_eq!(
+            a.render(&camera(), 8, 8, 2, 7).into_raw(),
+            b.render(&camera(), 8, 8, 2, 7).into_raw()
+        );
+    }
+}
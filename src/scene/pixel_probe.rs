@@ -0,0 +1,134 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::sync::{mpsc, Arc};
+
+use image::RgbaImage;
+use vulkano::{
+    command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
+    device::Device,
+    image::attachment::AttachmentImage,
+};
+
+use crate::{errors::*, readback::ReadbackQueue};
+
+// the probes past it are skipped until the GPU catches up
+const MAX_IN_FLIGHT_PROBES: usize = 3;
+// the shaders encode the scene image with the gamma of 1 / 2.2 if the gamma correction is enabled
+const SCENE_IMAGE_GAMMA: f32 = 2.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbedPixel {
+    // in the scene image
+    pub pixel: [u32; 2],
+    // linear, the gamma of the scene image undone if it's encoded
+    pub color: [f32; 3],
+}
+
+// the linear color of the pixel read back from the scene image, see Renderer::set_gamma_correction
+fn decode_color(image: &RgbaImage, gamma_correction: bool) -> [f32; 3] {
+    let [r, g, b, _] = image.get_pixel(0, 0).0;
+    let gamma = if gamma_correction {
+        SCENE_IMAGE_GAMMA
+    } else {
+        1.0
+    };
+    let to_linear = |c: u8| (f32::from(c) / 255.0).powf(gamma);
+    [to_linear(r), to_linear(g), to_linear(b)]
+}
+
+// Reads a pixel of the scene image back, e.g. the one under the cursor, without waiting for the
+// GPU through a readback queue the same way as the luminance histogram, so the color is at least
+// a frame late.
+pub struct PixelProbe {
+    readbacks: ReadbackQueue,
+    probed_sender: mpsc::Sender<ProbedPixel>,
+    probed_receiver: mpsc::Receiver<ProbedPixel>,
+}
+
+impl PixelProbe {
+    pub fn new(device: Arc<Device>) -> Result<Self> {
+        let (probed_sender, probed_receiver) = mpsc::channel();
+        Ok(Self {
+            readbacks: ReadbackQueue::new(device)
+                .chain_err(|| "fail to create the pixel probe readback queue")?,
+            probed_sender,
+            probed_receiver,
+        })
+    }
+
+    // Record the copy of the pixel of the image. Skipped when the pixel is outside of the image,
+    // or too many probes are still in flight. Only the 8 bit RGBA and BGRA formats are supported,
+    // which are gamma encoded if the gamma correction of the renderer is enabled.
+    pub fn record(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        image: Arc<AttachmentImage>,
+        pixel: [u32; 2],
+        gamma_correction: bool,
+    ) -> Result<()> {
+        let [width, height] = AttachmentImage::dimensions(&image);
+        if self.readbacks.get_in_flight_count() >= MAX_IN_FLIGHT_PROBES
+            || pixel[0] >= width
+            || pixel[1] >= height
+        {
+            return Ok(());
+        }
+        let probed_sender = self.probed_sender.clone();
+        self.readbacks
+            .record_region(cmd_buf_builder, image, pixel, [1, 1], move |image| {
+                // the receiver is only gone when the probe is dropped
+                let _ = probed_sender.send(ProbedPixel {
+                    pixel,
+                    color: decode_color(&image, gamma_correction),
+                });
+                Ok(())
+            })
+            .chain_err(|| "fail to record the copy of the probed pixel")
+    }
+
+    // the command buffer with the recorded probe is submitted
+    pub fn submitted(&mut self) {
+        self.readbacks.submitted();
+    }
+
+    // the command buffer with the recorded probe fails to be submitted
+    pub fn discard_recorded(&mut self) {
+        self.readbacks.discard_recorded();
+    }
+
+    // Hand the finished probes to the readback worker, and return the errors since the last poll.
+    // Never waits for the GPU.
+    pub fn poll(&mut self) -> Vec<Error> {
+        self.readbacks.poll()
+    }
+
+    // the latest probe read back, None if no probe has been read back since the last call
+    pub fn take_probed(&mut self) -> Option<ProbedPixel> {
+        self.probed_receiver.try_iter().last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_color() {
+        let decode = |pixel, gamma_correction| {
+            decode_color(
+                &RgbaImage::from_pixel(1, 1, image::Rgba(pixel)),
+                gamma_correction,
+            )
+        };
+        assert_eq!(decode([255, 0, 0, 255], true), [1.0, 0.0, 0.0]);
+        // the mid gray of the gamma encoded image is about 22% of the light
+        let [r, _, _] = decode([128, 128, 128, 255], true);
+        assert!((r - 0.2195).abs() < 1e-3, "{}", r);
+        // the linear colors are written as they are without the gamma correction
+        let [r, _, _] = decode([128, 128, 128, 255], false);
+        assert!((r - 128.0 / 255.0).abs() < 1e-6, "{}", r);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use euclid::{approxeq::ApproxEq, Transform3D};
+use obj::{Group, SimplePolygon};
+use serde::{Deserialize, Serialize};
+
+use super::super::TriangleSpace;
+
+// the axis pointing up in the exported model, the scene is Y up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    pub const ALL: [UpAxis; 2] = [UpAxis::Y, UpAxis::Z];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            UpAxis::Y => "y-up",
+            UpAxis::Z => "z-up",
+        }
+    }
+}
+
+// the unit of the lengths in the exported model, the scene is in meters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LengthUnit {
+    Meter,
+    Centimeter,
+    Inch,
+}
+
+impl LengthUnit {
+    pub const ALL: [LengthUnit; 3] = [LengthUnit::Meter, LengthUnit::Centimeter, LengthUnit::Inch];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            LengthUnit::Meter => "m",
+            LengthUnit::Centimeter => "cm",
+            LengthUnit::Inch => "inch",
+        }
+    }
+
+    // in meters
+    pub fn get_scale(&self) -> f32 {
+        match self {
+            LengthUnit::Meter => 1.0,
+            LengthUnit::Centimeter => 0.01,
+            LengthUnit::Inch => 0.0254,
+        }
+    }
+}
+
+// How the model is exported, baked into the positions and the normals when it's loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    pub up_axis: UpAxis,
+    pub unit: LengthUnit,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            up_axis: UpAxis::Y,
+            unit: LengthUnit::Meter,
+        }
+    }
+}
+
+impl ImportOptions {
+    // from the exported model to the Y up model in meters
+    pub fn get_bake_transform(&self) -> Transform3D<f32, TriangleSpace, TriangleSpace> {
+        let scale = self.unit.get_scale();
+        let scale = Transform3D::scale(scale, scale, scale);
+        match self.up_axis {
+            UpAxis::Y => scale,
+            // the same as the Y up export of blender, z goes to y and y goes to -z
+            UpAxis::Z => scale.then(&Transform3D::new(
+                1.0, 0.0, 0.0, 0.0, //
+                0.0, 0.0, -1.0, 0.0, //
+                0.0, 1.0, 0.0, 0.0, //
+                0.0, 0.0, 0.0, 1.0,
+            )),
+        }
+    }
+}
+
+// Bakes the transform into the vertices. The normals are transformed by the inverse transpose so
+// that they stay perpendicular to the surfaces, and normalized again.
+pub fn bake_positions_and_normals(
+    transform: &Transform3D<f32, TriangleSpace, TriangleSpace>,
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+) -> (Vec<[f32; 3]>, Vec<[f32; 3]>) {
+    let positions = positions
+        .iter()
+        .map(|position| {
+            transform
+                .transform_point3d((*position).into())
+                .expect("the bake transform should be affine")
+                .to_array()
+        })
+        .collect();
+    let inverse = transform
+        .inverse()
+        .expect("the bake transform should be invertible");
+    let normal_transform =
+        Transform3D::<f32, TriangleSpace, TriangleSpace>::from_array(inverse.to_array_transposed());
+    let normals = normals
+        .iter()
+        .map(|normal| {
+            let normal = normal_transform.transform_vector3d((*normal).into());
+            normal.try_normalize().unwrap_or(normal).to_array()
+        })
+        .collect();
+    (positions, normals)
+}
+
+// the transform mirrors the model, which turns the front faces into back faces
+pub fn is_winding_flipped(transform: &Transform3D<f32, TriangleSpace, TriangleSpace>) -> bool {
+    let determinant = transform.determinant();
+    determinant < 0.0 && !determinant.approx_eq(&0.0)
+}
+
+// the same group with the vertices of all the polygons in the reverse order
+pub fn flip_winding(group: &Group) -> Group {
+    Group {
+        polys: group
+            .polys
+            .iter()
+            .map(|poly| SimplePolygon(poly.0.iter().rev().cloned().collect()))
+            .collect(),
+        ..group.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::{point3, vec3, Point3D, Vector3D};
+
+    #[test]
+    fn test_z_up_model_stands_upright() {
+        let options = ImportOptions {
+            up_axis: UpAxis::Z,
+            unit: LengthUnit::Centimeter,
+        };
+        let transform = options.get_bake_transform();
+        assert!(!is_winding_flipped(&transform));
+        // the top of a 100 cm tall model, facing -y in blender, which is the front in Y up
+        let (positions, normals) =
+            bake_positions_and_normals(&transform, &[[0.0, 0.0, 100.0]], &[[0.0, -1.0, 0.0]]);
+        assert!(Point3D::<f32, TriangleSpace>::from(positions[0]).approx_eq(&point3(0.0, 1.0, 0.0)));
+        assert!(Vector3D::<f32, TriangleSpace>::from(normals[0]).approx_eq(&vec3(0.0, 0.0, 1.0)));
+        assert_eq!(
+            ImportOptions::default().get_bake_transform(),
+            Transform3D::identity()
+        );
+    }
+
+    #[test]
+    fn test_normals_stay_perpendicular_and_mirrors_flip_winding() {
+        // a non uniform mirror, the normal of the plane x = y isn't just the transformed normal
+        let transform = Transform3D::scale(-2.0, 1.0, 1.0);
+        assert!(is_winding_flipped(&transform));
+        let normal = vec3::<f32, TriangleSpace>(1.0, -1.0, 0.0).normalize();
+        let (_, normals) = bake_positions_and_normals(&transform, &[], &[normal.to_array()]);
+        let normal = Vector3D::<f32, TriangleSpace>::from(normals[0]);
+        // the plane x = y becomes x = -2y
+        let tangent = transform.transform_vector3d(vec3(1.0, 1.0, 0.0));
+        assert!(normal.dot(tangent).abs() < 1e-6);
+        assert!((normal.length() - 1.0).abs() < 1e-6);
+
+        let group = Group {
+            name: "group".to_owned(),
+            index: 0,
+            material: None,
+            polys: vec![SimplePolygon(vec![
+                obj::IndexTuple(0, None, None),
+                obj::IndexTuple(1, None, None),
+                obj::IndexTuple(2, None, None),
+            ])],
+        };
+        let flipped = flip_winding(&group);
+        let indices: Vec<_> = flipped.polys[0].0.iter().map(|tuple| tuple.0).collect();
+        assert_eq!(indices, vec![2, 1, 0]);
+        assert_eq!(flip_winding(&flipped), group);
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use image::RgbaImage;
+use obj::Material as MtlMaterial;
+
+use super::super::{
+    material::{DEFAULT_SHININESS, MAX_SHININESS, MIN_SHININESS},
+    object::{
+        NoTextureObjectMaterial, NormalMapObjectMaterial, ObjectRenderer, TextureObjectMaterial,
+    },
+    upload::Upload,
+};
+use crate::errors::*;
+
+// the decoded textures of a model, keyed by the map_kd, the map_bump and the map_ks of the materials
+pub type TextureCache = HashMap<String, Arc<RgbaImage>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaterialDiffuse {
+    // the map_kd, present in the texture cache
+    Texture(String),
+    Color([f32; 3]),
+}
+
+// A material of the mtl files resolved to what the object renderer supports, without touching the
+// GPU. map_kd takes precedence over kd, ks defaults to black and Ns to DEFAULT_SHININESS. The normal and the specular maps
+// are sampled with the texture coords of map_kd, so map_bump and map_ks are ignored with a warning
+// without it, see warnings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub diffuse: MaterialDiffuse,
+    pub ks: [f32; 3],
+    // the Ns, clamped to [MIN_SHININESS, MAX_SHININESS]
+    pub shininess: f32,
+    // the map_bump in the tangent space, present in the texture cache
+    pub normal_map: Option<String>,
+    // the map_ks scaling ks, present in the texture cache
+    pub specular_map: Option<String>,
+    // what of the mtl entry is drawn differently from it, for the UI
+    pub warnings: Vec<String>,
+}
+
+// the map of the kind sampled with the texture coords of map_kd, None without it
+fn resolve_texture_map(
+    material_name: &str,
+    kind: &str,
+    map: &Option<String>,
+    diffuse: &MaterialDiffuse,
+    textures: &TextureCache,
+    warnings: &mut Vec<String>,
+) -> Result<Option<String>> {
+    match (map, diffuse) {
+        (Some(map_name), MaterialDiffuse::Texture(_)) => {
+            if !textures.contains_key(map_name) {
+                bail!(ErrorKind::AssetNotFound(map_name.clone()));
+            }
+            Ok(Some(map_name.clone()))
+        }
+        (Some(map_name), MaterialDiffuse::Color(_)) => {
+            warnings.push(format!(
+                "the material {} has no texture coords for the {} {} without map_kd, drawn \
+                 without it",
+                material_name, kind, map_name
+            ));
+            Ok(None)
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+impl MaterialDesc {
+    pub fn resolve(material: &MtlMaterial, textures: &TextureCache) -> Result<Self> {
+        let name = &material.name;
+        let diffuse = match (&material.map_kd, material.kd) {
+            (Some(texture_name), _) => {
+                if !textures.contains_key(texture_name) {
+                    bail!(ErrorKind::AssetNotFound(texture_name.clone()));
+                }
+                MaterialDiffuse::Texture(texture_name.clone())
+            }
+            (None, Some(kd)) => MaterialDiffuse::Color(kd),
+            (None, None) => bail!(ErrorKind::UnsupportedFeature(format!(
+                "the material {} with neither map_kd nor kd is not supported",
+                name
+            ))),
+        };
+        let mut warnings = vec![];
+        let normal_map = resolve_texture_map(
+            name,
+            "normal map",
+            &material.map_bump,
+            &diffuse,
+            textures,
+            &mut warnings,
+        )?;
+        let specular_map = resolve_texture_map(
+            name,
+            "specular map",
+            &material.map_ks,
+            &diffuse,
+            textures,
+            &mut warnings,
+        )?;
+        let shininess = match material.ns {
+            Some(ns) if (MIN_SHININESS..=MAX_SHININESS).contains(&ns) => ns,
+            Some(ns) => {
+                let clamped = if ns > MAX_SHININESS {
+                    MAX_SHININESS
+                } else {
+                    MIN_SHININESS
+                };
+                warnings.push(format!(
+                    "the Ns {} of the material {} is not within [{}, {}], drawn with {}",
+                    ns, name, MIN_SHININESS, MAX_SHININESS, clamped
+                ));
+                clamped
+            }
+            None => DEFAULT_SHININESS,
+        };
+        Ok(Self {
+            name: name.clone(),
+            diffuse,
+            ks: material.ks.unwrap_or([0.0, 0.0, 0.0]),
+            shininess,
+            normal_map,
+            specular_map,
+            warnings,
+        })
+    }
+
+    // the materials of all the mtl files, whose names should be unique
+    pub fn resolve_all<'a>(
+        materials: impl IntoIterator<Item = &'a MtlMaterial>,
+        textures: &TextureCache,
+    ) -> Result<Vec<Self>> {
+        let mut names = HashSet::new();
+        let mut descs = vec![];
+        for material in materials {
+            if !names.insert(material.name.as_str()) {
+                bail!(ErrorKind::UnsupportedFeature(format!(
+                    "materials with duplicate name {} not supproted",
+                    material.name
+                )));
+            }
+            descs.push(Self::resolve(material, textures)?);
+        }
+        Ok(descs)
+    }
+}
+
+#[derive(Clone)]
+pub enum ObjectMaterial {
+    Texture(Arc<TextureObjectMaterial>),
+    NormalMap(Arc<NormalMapObjectMaterial>),
+    NoTexture(Arc<NoTextureObjectMaterial>),
+}
+
+impl ObjectMaterial {
+    // creates the GPU resources of the material, i.e. uploads the textures if it has any
+    pub fn upload(
+        desc: &MaterialDesc,
+        textures: &TextureCache,
+        renderer: &ObjectRenderer,
+    ) -> Result<(Self, Option<Upload>)> {
+        let get_texture = |name: &String| {
+            textures
+                .get(name)
+                .cloned()
+                .ok_or_else(|| -> Error { ErrorKind::AssetNotFound(name.clone()).into() })
+        };
+        match &desc.diffuse {
+            MaterialDiffuse::Texture(texture_name) => {
+                let texture = get_texture(texture_name)?;
+                let specular_map = desc.specular_map.as_ref().map(get_texture).transpose()?;
+                if let Some(normal_map_name) = &desc.normal_map {
+                    let normal_map = get_texture(normal_map_name)?;
+                    let (material, upload) = NormalMapObjectMaterial::new(
+                        renderer,
+                        texture,
+                        specular_map,
+                        normal_map,
+                        desc.ks,
+                        desc.shininess,
+                    )?;
+                    return Ok((ObjectMaterial::NormalMap(Arc::new(material)), Some(upload)));
+                }
+                let (material, upload) = TextureObjectMaterial::new(
+                    renderer,
+                    texture,
+                    specular_map,
+                    desc.ks,
+                    desc.shininess,
+                )?;
+                Ok((ObjectMaterial::Texture(Arc::new(material)), Some(upload)))
+            }
+            MaterialDiffuse::Color(kd) => Ok((
+                ObjectMaterial::NoTexture(Arc::new(NoTextureObjectMaterial::new(
+                    renderer,
+                    *kd,
+                    desc.ks,
+                    desc.shininess,
+                )?)),
+                None,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use obj::Mtl;
+
+    use crate::errors::find_error_kind;
+
+    fn parse_mtl(text: &str) -> Vec<MtlMaterial> {
+        let mut mtl = Mtl::new("test.mtl".to_owned());
+        mtl.reload(text.as_bytes()).unwrap();
+        mtl.materials.iter().map(|m| (**m).clone()).collect()
+    }
+
+    fn textures(names: &[&str]) -> TextureCache {
+        names
+            .iter()
+            .map(|name| (name.to_string(), Arc::new(RgbaImage::new(1, 1))))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_kd_and_ks() {
+        let materials =
+            parse_mtl("newmtl red\nKd 1 0 0\nKs 0.5 0.5 0.5\nNs 10\nnewmtl plain\nKd 0 1 0\n");
+        let descs = MaterialDesc::resolve_all(&materials, &textures(&[])).unwrap();
+        assert_eq!(
+            descs,
+            vec![
+                MaterialDesc {
+                    name: "red".to_owned(),
+                    diffuse: MaterialDiffuse::Color([1.0, 0.0, 0.0]),
+                    ks: [0.5, 0.5, 0.5],
+                    shininess: 10.0,
+                    normal_map: None,
+                    specular_map: None,
+                    warnings: vec![],
+                },
+                MaterialDesc {
+                    name: "plain".to_owned(),
+                    diffuse: MaterialDiffuse::Color([0.0, 1.0, 0.0]),
+                    ks: [0.0, 0.0, 0.0],
+                    shininess: DEFAULT_SHININESS,
+                    normal_map: None,
+                    specular_map: None,
+                    warnings: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shininess_is_clamped() {
+        let materials =
+            parse_mtl("newmtl dull\nKd 1 1 1\nNs 0\nnewmtl mirror\nKd 1 1 1\nNs 5000\n");
+        let descs = MaterialDesc::resolve_all(&materials, &textures(&[])).unwrap();
+        assert_eq!(descs[0].shininess, MIN_SHININESS);
+        assert_eq!(descs[1].shininess, MAX_SHININESS);
+        assert_eq!(descs[0].warnings.len(), 1);
+        assert_eq!(descs[1].warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_map_kd_takes_precedence_over_kd() {
+        let materials = parse_mtl("newmtl wood\nKd 1 1 1\nmap_Kd wood.png\n");
+        let descs = MaterialDesc::resolve_all(&materials, &textures(&["wood.png"])).unwrap();
+        assert_eq!(
+            descs[0].diffuse,
+            MaterialDiffuse::Texture("wood.png".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_normal_map_needs_map_kd() {
+        let materials = parse_mtl(
+            "newmtl brick\nmap_Kd brick.png\nmap_bump brick_normal.png\n\
+             newmtl plaster\nKd 1 1 1\nbump plaster_normal.png\n",
+        );
+        let descs = MaterialDesc::resolve_all(
+            &materials,
+            &textures(&["brick.png", "brick_normal.png", "plaster_normal.png"]),
+        )
+        .unwrap();
+        assert_eq!(descs[0].normal_map, Some("brick_normal.png".to_owned()));
+        assert!(descs[0].warnings.is_empty());
+        // without the texture coords of map_kd, drawn as if it had no bump map
+        assert_eq!(descs[1].normal_map, None);
+        assert_eq!(descs[1].diffuse, MaterialDiffuse::Color([1.0, 1.0, 1.0]));
+        assert_eq!(descs[1].warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_specular_map_needs_map_kd() {
+        let materials = parse_mtl(
+            "newmtl tiles\nKs 1 1 1\nmap_Kd tiles.png\nmap_Ks checker.png\n\
+             newmtl plastic\nKd 1 0 0\nmap_Ks checker.png\n",
+        );
+        let descs = MaterialDesc::resolve_all(&materials, &textures(&["tiles.png", "checker.png"]))
+            .unwrap();
+        assert_eq!(descs[0].specular_map, Some("checker.png".to_owned()));
+        assert_eq!(descs[0].normal_map, None);
+        // without the texture coords of map_kd, only ks is left
+        assert_eq!(descs[1].specular_map, None);
+        let e = MaterialDesc::resolve_all(&materials, &textures(&["tiles.png"])).unwrap_err();
+        assert!(matches!(
+            find_error_kind(&e),
+            Some(ErrorKind::AssetNotFound(name)) if name == "checker.png"
+        ));
+    }
+
+    #[test]
+    fn test_missing_normal_map_entry() {
+        let materials = parse_mtl("newmtl brick\nmap_Kd brick.png\nmap_bump brick_normal.png\n");
+        let e = MaterialDesc::resolve_all(&materials, &textures(&["brick.png"])).unwrap_err();
+        assert!(matches!(
+            find_error_kind(&e),
+            Some(ErrorKind::AssetNotFound(name)) if name == "brick_normal.png"
+        ));
+    }
+
+    #[test]
+    fn test_missing_kd() {
+        let materials = parse_mtl("newmtl empty\nKs 1 1 1\n");
+        let e = MaterialDesc::resolve_all(&materials, &textures(&[])).unwrap_err();
+        assert!(matches!(
+            find_error_kind(&e),
+            Some(ErrorKind::UnsupportedFeature(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_texture_entry() {
+        let materials = parse_mtl("newmtl wood\nmap_Kd wood.png\n");
+        let e = MaterialDesc::resolve_all(&materials, &textures(&["stone.png"])).unwrap_err();
+        assert!(matches!(
+            find_error_kind(&e),
+            Some(ErrorKind::AssetNotFound(name)) if name == "wood.png"
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        // also when only one of them is textured
+        for text in &[
+            "newmtl a\nKd 1 1 1\nnewmtl a\nKd 0 0 0\n",
+            "newmtl a\nmap_Kd a.png\nnewmtl a\nKd 0 0 0\n",
+        ] {
+            let materials = parse_mtl(text);
+            let e = MaterialDesc::resolve_all(&materials, &textures(&["a.png"])).unwrap_err();
+            assert!(
+                matches!(find_error_kind(&e), Some(ErrorKind::UnsupportedFeature(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
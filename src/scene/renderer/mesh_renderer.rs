@@ -3,7 +3,13 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use std::{marker::PhantomData, sync::Arc};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    marker::PhantomData,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
 
 use vulkano::{
     buffer::{immutable::ImmutableBuffer, BufferAccess, BufferUsage},
@@ -15,10 +21,13 @@ use vulkano::{
         pipeline_layout::{PipelineLayout, PipelineLayoutAbstract},
     },
     device::{Device, Queue},
+    format::FormatTy,
     framebuffer::{RenderPassAbstract, Subpass},
     pipeline::{
-        depth_stencil::DepthStencil,
-        vertex::Vertex as VertexT,
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        depth_stencil::{Compare, DepthStencil},
+        shader::{GraphicsEntryPointAbstract, ShaderInterfaceDef},
+        vertex::{Vertex as VertexT, VertexMemberTy},
         viewport::{Scissor, Viewport},
         GraphicsPipeline, GraphicsPipelineAbstract,
     },
@@ -26,7 +35,10 @@ use vulkano::{
 };
 
 use super::{
-    super::shaders::{ShadersT, UniformsT},
+    super::{
+        shaders::{ShadersT, UniformsT},
+        upload::Upload,
+    },
     Material, SetCamera,
 };
 use crate::errors::*;
@@ -35,13 +47,191 @@ pub trait SimpleVertex: VertexT {
     fn create_from_position(x: f32, y: f32, z: f32) -> Self;
 }
 
+// The NDC depth produced by Camera is 0 on the near plane and 1 on the far plane, the viewport
+// maps it to the depth range before the depth test. A narrower range draws the meshes into a
+// slice of the depth buffer, e.g. to keep an overlay in front of the scene.
+pub const DEFAULT_DEPTH_RANGE: Range<f32> = 0.0..1.0;
+
+// without VK_EXT_depth_range_unrestricted the depth range must be in [0, 1]
+pub fn check_depth_range(depth_range: &Range<f32>) -> Result<()> {
+    let in_unit_range = |depth: f32| (0.0..=1.0).contains(&depth);
+    if !in_unit_range(depth_range.start) || !in_unit_range(depth_range.end) {
+        return Err(format!(
+            "the depth range {:?} should be within 0.0..1.0",
+            depth_range
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+// The rasterization states a mesh can be drawn with, every one of them is a pipeline variant
+// created the first time it's used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RasterState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    // Clamp the depth of the fragments beyond the near and far planes instead of clipping them,
+    // ignored if the device doesn't support it.
+    pub depth_clamp: bool,
+    // Draw the edges of the triangles instead of filling them, to inspect the topology of the
+    // meshes, ignored if the device doesn't support it.
+    pub wireframe: bool,
+}
+
+impl Default for RasterState {
+    fn default() -> Self {
+        Self {
+            cull_mode: CullMode::None,
+            front_face: FrontFace::CounterClockwise,
+            depth_clamp: false,
+            wireframe: false,
+        }
+    }
+}
+
+// How the fragments of the meshes are written to the attachments of the subpass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    // depth tested and written, replacing the color
+    Opaque,
+    // added to the color without the depth test, for the subpasses without a depth attachment
+    Additive,
+    // replacing the color where the depth is still cleared, without writing the depth, for the
+    // backdrop drawn on the far plane before the scene
+    Background,
+}
+
+impl CullMode {
+    pub const ALL: [Self; 3] = [Self::None, Self::Back, Self::Front];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::None => "cull none",
+            Self::Back => "cull back",
+            Self::Front => "cull front",
+        }
+    }
+}
+
+impl FrontFace {
+    pub const ALL: [Self; 2] = [Self::CounterClockwise, Self::Clockwise];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::CounterClockwise => "counter-clockwise",
+            Self::Clockwise => "clockwise",
+        }
+    }
+}
+
+// reverse the winding of every triangle, so that the front faces become the back faces
+pub fn flip_winding<T>(indices: &mut [T]) {
+    for triangle in indices.chunks_exact_mut(3) {
+        triangle.swap(1, 2);
+    }
+}
+
+// the meshes with at most this many vertices are indexed with u16 to save memory
+const MAX_U16_INDEXED_VERTICES: usize = u16::MAX as usize + 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    // the narrowest type that can index the vertices
+    fn new(indices: Vec<u32>, vertex_count: usize) -> Self {
+        if vertex_count <= MAX_U16_INDEXED_VERTICES {
+            Indices::U16(indices.into_iter().map(|i| i as u16).collect())
+        } else {
+            Indices::U32(indices)
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = u32> + '_> {
+        match self {
+            Indices::U16(indices) => Box::new(indices.iter().map(|i| u32::from(*i))),
+            Indices::U32(indices) => Box::new(indices.iter().cloned()),
+        }
+    }
+
+    pub fn get_type_name(&self) -> &'static str {
+        match self {
+            Indices::U16(_) => "u16",
+            Indices::U32(_) => "u32",
+        }
+    }
+
+    fn flip_winding(&mut self) {
+        match self {
+            Indices::U16(indices) => flip_winding(indices),
+            Indices::U32(indices) => flip_winding(indices),
+        }
+    }
+}
+
+enum IndexBuffer {
+    U16(Arc<ImmutableBuffer<[u16]>>),
+    U32(Arc<ImmutableBuffer<[u32]>>),
+}
+
+fn create_index_buffer(
+    indices: &Indices,
+    queue: Arc<Queue>,
+) -> Result<(IndexBuffer, Box<dyn GpuFuture + Send + Sync>)> {
+    Ok(match indices {
+        Indices::U16(indices) => {
+            let (buffer, init) = ImmutableBuffer::from_iter(
+                indices.iter().cloned(),
+                BufferUsage::index_buffer(),
+                queue,
+            )
+            .chain_err(|| "fail to create index buffer")?;
+            (IndexBuffer::U16(buffer), Box::new(init))
+        }
+        Indices::U32(indices) => {
+            let (buffer, init) = ImmutableBuffer::from_iter(
+                indices.iter().cloned(),
+                BufferUsage::index_buffer(),
+                queue,
+            )
+            .chain_err(|| "fail to create index buffer")?;
+            (IndexBuffer::U32(buffer), Box::new(init))
+        }
+    })
+}
+
+// A named range of the index buffer of a mesh, e.g. one of the obj groups merged into the mesh
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubMesh {
+    pub name: String,
+    pub indices: Range<u32>,
+}
+
 pub struct MeshData<V: VertexT> {
     vertices: Vec<V>,
-    indices: Vec<u16>,
+    indices: Indices,
+    sub_meshes: Vec<SubMesh>,
 }
 
 impl<V: VertexT> MeshData<V> {
-    pub fn create(vertices: Vec<V>, indices: Vec<u16>) -> Result<Self> {
+    pub fn create(vertices: Vec<V>, indices: Vec<u32>) -> Result<Self> {
         for index in indices.iter() {
             if *index as usize >= vertices.len() {
                 return Err(format!(
@@ -52,7 +242,52 @@ impl<V: VertexT> MeshData<V> {
                 .into());
             }
         }
-        Ok(Self { vertices, indices })
+        let sub_meshes = vec![SubMesh {
+            name: Default::default(),
+            indices: 0..indices.len() as u32,
+        }];
+        Ok(Self {
+            indices: Indices::new(indices, vertices.len()),
+            vertices,
+            sub_meshes,
+        })
+    }
+
+    // Concatenate the meshes into one, so that they can be drawn with a single draw call. Every
+    // mesh becomes a sub-mesh with the given name.
+    pub fn merge(meshes: Vec<(String, Self)>) -> Result<Self> {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut sub_meshes = vec![];
+        for (name, mesh) in meshes {
+            let offset = u32::try_from(vertices.len()).chain_err(|| {
+                format!(
+                    "fail to merge the mesh {}, more than {} vertices can't be indexed",
+                    name,
+                    u32::MAX
+                )
+            })?;
+            let first_index = indices.len() as u32;
+            vertices.extend(mesh.vertices);
+            indices.extend(mesh.indices.iter().map(|i| i + offset));
+            sub_meshes.push(SubMesh {
+                name,
+                indices: first_index..indices.len() as u32,
+            });
+        }
+        Ok(Self {
+            indices: Indices::new(indices, vertices.len()),
+            vertices,
+            sub_meshes,
+        })
+    }
+
+    pub fn get_vertices(&self) -> &[V] {
+        &self.vertices
+    }
+
+    pub fn get_indices(&self) -> &Indices {
+        &self.indices
     }
 }
 
@@ -103,21 +338,157 @@ impl<V: SimpleVertex> MeshData<V> {
         )
         .expect("fail to create cube")
     }
+
+    // A unit sphere of the rings of latitude from +y to -y, each of the sectors around y, whose
+    // poles are single vertices. The triangles wind counter-clockwise from the outside like the
+    // cube. A Validation error if there are less than 2 rings or 3 sectors, or more vertices than
+    // the u16 indices can index.
+    pub fn uv_sphere(rings: u32, sectors: u32) -> Result<Self> {
+        if rings < 2 || sectors < 3 {
+            bail!(ErrorKind::Validation(format!(
+                "a sphere of {} rings and {} sectors needs at least 2 rings and 3 sectors",
+                rings, sectors
+            )));
+        }
+        let vertex_count = 2 + u64::from(rings - 1) * u64::from(sectors);
+        if vertex_count > MAX_U16_INDEXED_VERTICES as u64 {
+            bail!(ErrorKind::Validation(format!(
+                "a sphere of {} rings and {} sectors has {} vertices, more than {} can't be \
+                 indexed by u16",
+                rings, sectors, vertex_count, MAX_U16_INDEXED_VERTICES
+            )));
+        }
+        let v = V::create_from_position;
+        let mut vertices = Vec::with_capacity(vertex_count as usize);
+        vertices.push(v(0.0, 1.0, 0.0));
+        for ring in 1..rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for sector in 0..sectors {
+                let phi = 2.0 * std::f32::consts::PI * sector as f32 / sectors as f32;
+                vertices.push(v(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    -theta.sin() * phi.sin(),
+                ));
+            }
+        }
+        vertices.push(v(0.0, -1.0, 0.0));
+        let north_pole = 0;
+        let south_pole = vertices.len() as u32 - 1;
+        // the vertex of the sector on the ring, both the poles excluded
+        let ring_vertex = |ring: u32, sector: u32| 1 + (ring - 1) * sectors + sector % sectors;
+        let mut indices = Vec::with_capacity(6 * ((rings - 1) * sectors) as usize);
+        for sector in 0..sectors {
+            indices.extend_from_slice(&[
+                north_pole,
+                ring_vertex(1, sector),
+                ring_vertex(1, sector + 1),
+            ]);
+        }
+        for ring in 1..rings - 1 {
+            for sector in 0..sectors {
+                let top_left = ring_vertex(ring, sector);
+                let top_right = ring_vertex(ring, sector + 1);
+                let bottom_left = ring_vertex(ring + 1, sector);
+                let bottom_right = ring_vertex(ring + 1, sector + 1);
+                indices.extend_from_slice(&[
+                    top_left,
+                    bottom_left,
+                    bottom_right,
+                    top_left,
+                    bottom_right,
+                    top_right,
+                ]);
+            }
+        }
+        for sector in 0..sectors {
+            indices.extend_from_slice(&[
+                ring_vertex(rings - 1, sector),
+                south_pole,
+                ring_vertex(rings - 1, sector + 1),
+            ]);
+        }
+        Self::create(vertices, indices).chain_err(|| "fail to create the sphere")
+    }
+
+    // A grid of the width segments along x and the depth segments along z, spanning -1 to 1 on the
+    // x-z plane like the cube. It faces +y: the triangles wind counter-clockwise seen from above, so
+    // the bottom is culled. A Validation error if a count is 0, or there are more vertices than the
+    // u16 indices can index.
+    pub fn plane(width_segments: u32, depth_segments: u32) -> Result<Self> {
+        if width_segments == 0 || depth_segments == 0 {
+            bail!(ErrorKind::Validation(format!(
+                "a plane of {}x{} segments needs at least 1 segment in each direction",
+                width_segments, depth_segments
+            )));
+        }
+        let vertex_count = (u64::from(width_segments) + 1) * (u64::from(depth_segments) + 1);
+        if vertex_count > MAX_U16_INDEXED_VERTICES as u64 {
+            bail!(ErrorKind::Validation(format!(
+                "a plane of {}x{} segments has {} vertices, more than {} can't be indexed by u16",
+                width_segments, depth_segments, vertex_count, MAX_U16_INDEXED_VERTICES
+            )));
+        }
+        let mut vertices = Vec::with_capacity(vertex_count as usize);
+        for row in 0..=depth_segments {
+            let z = 2.0 * row as f32 / depth_segments as f32 - 1.0;
+            for column in 0..=width_segments {
+                let x = 2.0 * column as f32 / width_segments as f32 - 1.0;
+                vertices.push(V::create_from_position(x, 0.0, z));
+            }
+        }
+        let vertex = |row: u32, column: u32| row * (width_segments + 1) + column;
+        let mut indices = Vec::with_capacity(6 * (width_segments * depth_segments) as usize);
+        for row in 0..depth_segments {
+            for column in 0..width_segments {
+                let back_left = vertex(row, column);
+                let back_right = vertex(row, column + 1);
+                let front_left = vertex(row + 1, column);
+                let front_right = vertex(row + 1, column + 1);
+                indices.extend_from_slice(&[
+                    back_left,
+                    front_left,
+                    front_right,
+                    back_left,
+                    front_right,
+                    back_right,
+                ]);
+            }
+        }
+        Self::create(vertices, indices).chain_err(|| "fail to create the plane")
+    }
 }
 
 pub trait MeshT<S> {
     fn draw_commands(
         &self,
         cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        raster_state: RasterState,
+    ) -> Result<()>;
+    // Draw the vertices with the pipeline of another pass, e.g. the depth only shadow pass, which
+    // takes the transform to its clip space as the push constants instead of the uniforms.
+    fn draw_with_pipeline(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        pipeline: Pipeline,
+        transform: [[f32; 4]; 4],
     ) -> Result<()>;
+    fn get_sub_meshes(&self) -> &[SubMesh];
+    fn get_indices(&self) -> &Indices;
+    // Reverse the winding of the triangles and upload the new index buffer, which can't be drawn
+    // until the upload finishes.
+    fn flip_winding(&mut self) -> Result<Upload>;
 }
 
 // S stands for model space
 pub struct Mesh<V: VertexT, M: Material, S> {
     renderer: Arc<Renderer<V, M>>,
     vertex_buffer: Arc<dyn BufferAccess + Send + Sync>,
-    index_buffer: Arc<ImmutableBuffer<[u16]>>,
+    index_buffer: IndexBuffer,
+    // the CPU copy of the index buffer, to flip the winding
+    indices: Indices,
     descriptor_sets: Vec<Arc<dyn DescriptorSet + Send + Sync>>,
+    sub_meshes: Vec<SubMesh>,
     phantom: PhantomData<S>,
 }
 
@@ -128,111 +499,344 @@ where
     fn draw_commands(
         &self,
         cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        raster_state: RasterState,
     ) -> Result<()> {
-        cmd_buf_builder
-            .draw_indexed(
-                self.renderer.pipeline.clone(),
+        let pipeline = self
+            .renderer
+            .get_pipeline(raster_state)
+            .chain_err(|| format!("fail to get the pipeline for {:?}", raster_state))?;
+        let vertex_buffers = vec![self.vertex_buffer.clone()];
+        let descriptor_sets = self.descriptor_sets.to_vec();
+        match &self.index_buffer {
+            IndexBuffer::U16(index_buffer) => cmd_buf_builder.draw_indexed(
+                pipeline,
                 &DynamicState::none(),
-                vec![self.vertex_buffer.clone()],
-                self.index_buffer.clone(),
-                self.descriptor_sets.to_vec(),
+                vertex_buffers,
+                index_buffer.clone(),
+                descriptor_sets,
                 (),
-            )
-            .chain_err(|| "fail to add the draw command to the command builder")?;
+            ),
+            IndexBuffer::U32(index_buffer) => cmd_buf_builder.draw_indexed(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                index_buffer.clone(),
+                descriptor_sets,
+                (),
+            ),
+        }
+        .chain_err(|| "fail to add the draw command to the command builder")?;
         Ok(())
     }
+
+    fn draw_with_pipeline(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        pipeline: Pipeline,
+        transform: [[f32; 4]; 4],
+    ) -> Result<()> {
+        let vertex_buffers = vec![self.vertex_buffer.clone()];
+        match &self.index_buffer {
+            IndexBuffer::U16(index_buffer) => cmd_buf_builder.draw_indexed(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                index_buffer.clone(),
+                (),
+                transform,
+            ),
+            IndexBuffer::U32(index_buffer) => cmd_buf_builder.draw_indexed(
+                pipeline,
+                &DynamicState::none(),
+                vertex_buffers,
+                index_buffer.clone(),
+                (),
+                transform,
+            ),
+        }
+        .chain_err(|| "fail to add the draw command to the command builder")?;
+        Ok(())
+    }
+
+    fn get_sub_meshes(&self) -> &[SubMesh] {
+        &self.sub_meshes
+    }
+
+    fn get_indices(&self) -> &Indices {
+        &self.indices
+    }
+
+    fn flip_winding(&mut self) -> Result<Upload> {
+        self.indices.flip_winding();
+        let (index_buffer, index_buffer_init) =
+            create_index_buffer(&self.indices, self.renderer.upload_queue.clone())?;
+        let upload =
+            Upload::submit(index_buffer_init).chain_err(|| "fail to upload the index buffer")?;
+        self.index_buffer = index_buffer;
+        Ok(upload)
+    }
 }
 
+// Check the members of the vertex type against the inputs of the vertex shader, so that a
+// mismatch is reported with the location and the name of the attribute instead of failing to
+// create the pipeline.
+fn validate_vertex_input<V: VertexT>(input: &impl ShaderInterfaceDef) -> Result<()> {
+    for element in input.elements() {
+        let name = element.name.as_deref().ok_or_else(|| -> Error {
+            format!(
+                "the vertex shader input at location {:?} has no name",
+                element.location
+            )
+            .into()
+        })?;
+        let member = V::member(name).ok_or_else(|| -> Error {
+            format!(
+                "the vertex type has no member {} for the vertex shader input at location {:?}",
+                name, element.location
+            )
+            .into()
+        })?;
+        let location_count = element.location.end - element.location.start;
+        if !member
+            .ty
+            .matches(member.array_size, element.format, location_count)
+        {
+            return Err(format!(
+                "the vertex member {} of {:?} x {} doesn't match the vertex shader input at \
+                location {:?} of {:?}",
+                name, member.ty, member.array_size, element.location, element.format
+            )
+            .into());
+        }
+        let is_float_member = matches!(member.ty, VertexMemberTy::F32 | VertexMemberTy::F64);
+        let is_integer_input = matches!(element.format.ty(), FormatTy::Uint | FormatTy::Sint);
+        if is_float_member && is_integer_input {
+            return Err(format!(
+                "the vertex member {} of {:?} can't be read as the integer vertex shader input \
+                at location {:?} of {:?}",
+                name, member.ty, element.location, element.format
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+pub type Pipeline = Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+
 pub struct Renderer<V: VertexT, M: Material> {
     device: Arc<Device>,
     queue: Arc<Queue>,
-    pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-    pipeline_layout: Box<dyn PipelineLayoutAbstract>,
+    // the vertices and the indices of the meshes are uploaded through it, see set_upload_queue
+    upload_queue: Arc<Queue>,
+    shaders: M::Shaders,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+    // the width and the height of the viewport, locked before the pipelines
+    dimensions: Mutex<[u32; 2]>,
+    depth_range: Range<f32>,
+    blend_mode: BlendMode,
+    // the pipelines of the raster states used so far, all of them share the same layout
+    pipelines: Mutex<HashMap<RasterState, Pipeline>>,
+    pipeline_layout: Box<dyn PipelineLayoutAbstract + Send + Sync>,
     phantom: PhantomData<(V, M)>,
 }
 
 type Uniforms<M> = <<M as Material>::Shaders as ShadersT>::Uniforms;
+// the mesh, its uniforms and the upload of its vertex buffer and index buffer
+type UploadedMesh<V, M, S> = (Mesh<V, M, S>, Uniforms<M>, Upload);
 
 impl<V: VertexT, M: Material> Renderer<V, M> {
     pub fn init(
         device: Arc<Device>,
         queue: Arc<Queue>,
-        subpass: Subpass<impl RenderPassAbstract + Send + Sync + 'static>,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
         width: u32,
         height: u32,
+        depth_range: Range<f32>,
+        blend_mode: BlendMode,
     ) -> Result<Self> {
+        check_depth_range(&depth_range).chain_err(|| "invalid viewport depth range")?;
         let shaders = M::Shaders::load(device.clone()).chain_err(|| "fail to load shaders")?;
-        let pipeline = Arc::new(
-            GraphicsPipeline::start()
-                .vertex_input_single_buffer::<V>()
-                .vertex_shader(shaders.vertex_shader_main_entry_point(), ())
-                .viewports_scissors(
-                    vec![(
-                        Viewport {
-                            origin: [0.0, 0.0],
-                            dimensions: [width as f32, height as f32],
-                            depth_range: 0.0..1.0,
-                        },
-                        Scissor {
-                            origin: [0, 0],
-                            dimensions: [width, height],
-                        },
-                    )]
-                    .into_iter(),
-                )
-                .fragment_shader(shaders.fragment_shader_main_entry_point(), ())
-                .depth_stencil(DepthStencil::simple_depth_test())
-                .depth_write(true)
-                .render_pass(subpass)
-                .build(device.clone())
-                .chain_err(|| "fail to create graphics pipeline")?,
-        );
+        validate_vertex_input::<V>(shaders.vertex_shader_main_entry_point().input()).chain_err(
+            || ErrorKind::Validation("the vertex type doesn't match the vertex shader".to_owned()),
+        )?;
+        let raster_state = RasterState::default();
+        let pipeline = Self::create_pipeline(
+            device.clone(),
+            &shaders,
+            subpass.clone(),
+            [width, height],
+            depth_range.clone(),
+            blend_mode,
+            raster_state,
+        )?;
         let pipeline_layout = Box::new(
             PipelineLayout::new(device.clone(), pipeline.clone())
                 .chain_err(|| "fail to create pipeline layout from the graphics pipeline")?,
         );
+        let mut pipelines = HashMap::new();
+        pipelines.insert(raster_state, pipeline);
         Ok(Self {
             device,
+            upload_queue: queue.clone(),
             queue,
-            pipeline,
+            shaders,
+            subpass,
+            dimensions: Mutex::new([width, height]),
+            depth_range,
+            blend_mode,
+            pipelines: Mutex::new(pipelines),
             pipeline_layout,
             phantom: PhantomData,
         })
     }
 
-    // M is the model space
+    fn create_pipeline(
+        device: Arc<Device>,
+        shaders: &M::Shaders,
+        subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+        [width, height]: [u32; 2],
+        depth_range: Range<f32>,
+        blend_mode: BlendMode,
+        raster_state: RasterState,
+    ) -> Result<Pipeline> {
+        let builder = GraphicsPipeline::start()
+            .vertex_input_single_buffer::<V>()
+            .vertex_shader(shaders.vertex_shader_main_entry_point(), ())
+            .viewports_scissors(
+                vec![(
+                    Viewport {
+                        origin: [0.0, 0.0],
+                        dimensions: [width as f32, height as f32],
+                        depth_range,
+                    },
+                    Scissor {
+                        origin: [0, 0],
+                        dimensions: [width, height],
+                    },
+                )]
+                .into_iter(),
+            )
+            .fragment_shader(shaders.fragment_shader_main_entry_point(), ());
+        let builder = match blend_mode {
+            BlendMode::Opaque => builder
+                .depth_stencil(DepthStencil::simple_depth_test())
+                .depth_write(true),
+            // keep the alpha of the attachment
+            BlendMode::Additive => builder.blend_collective(AttachmentBlend {
+                enabled: true,
+                color_op: BlendOp::Add,
+                color_source: BlendFactor::One,
+                color_destination: BlendFactor::One,
+                alpha_op: BlendOp::Add,
+                alpha_source: BlendFactor::Zero,
+                alpha_destination: BlendFactor::One,
+                ..AttachmentBlend::pass_through()
+            }),
+            BlendMode::Background => builder.depth_stencil(DepthStencil {
+                depth_compare: Compare::LessOrEqual,
+                depth_write: false,
+                ..DepthStencil::simple_depth_test()
+            }),
+        };
+        let builder = match raster_state.cull_mode {
+            CullMode::None => builder.cull_mode_disabled(),
+            CullMode::Back => builder.cull_mode_back(),
+            CullMode::Front => builder.cull_mode_front(),
+        };
+        let builder = match raster_state.front_face {
+            FrontFace::CounterClockwise => builder.front_face_counter_clockwise(),
+            FrontFace::Clockwise => builder.front_face_clockwise(),
+        };
+        let builder =
+            builder.depth_clamp(raster_state.depth_clamp && device.enabled_features().depth_clamp);
+        let builder = if raster_state.wireframe && device.enabled_features().fill_mode_non_solid {
+            builder.polygon_mode_line()
+        } else {
+            builder.polygon_mode_fill()
+        };
+        Ok(Arc::new(
+            builder.render_pass(subpass).build(device).chain_err(|| {
+                ErrorKind::GpuResource("fail to create graphics pipeline".to_owned())
+            })?,
+        ))
+    }
+
+    // The pipelines are created again with the new viewport when they are used. They keep the
+    // same layout, so the descriptor sets of the meshes stay valid.
+    pub fn resize(&self, width: u32, height: u32) {
+        let mut dimensions = self.dimensions.lock().unwrap();
+        if *dimensions != [width, height] {
+            *dimensions = [width, height];
+            self.pipelines.lock().unwrap().clear();
+        }
+    }
+
+    // The queue of another family, e.g. a dedicated transfer queue, so that the uploads don't wait
+    // behind the draws. The buffers are shared by all the queue families of the device, so they
+    // need no ownership transfer, and the uploads are waited for before the meshes are drawn. The
+    // uniforms are still created for the queue drawing them.
+    pub fn set_upload_queue(&mut self, upload_queue: Arc<Queue>) {
+        self.upload_queue = upload_queue;
+    }
+
+    // creates the pipeline of the raster state ahead of the first draw using it
+    pub fn warm_up(&self, raster_state: RasterState) -> Result<()> {
+        self.get_pipeline(raster_state).map(|_| ())
+    }
+
+    fn get_pipeline(&self, raster_state: RasterState) -> Result<Pipeline> {
+        let dimensions = self.dimensions.lock().unwrap();
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&raster_state) {
+            return Ok(pipeline.clone());
+        }
+        let pipeline = Self::create_pipeline(
+            self.device.clone(),
+            &self.shaders,
+            self.subpass.clone(),
+            *dimensions,
+            self.depth_range.clone(),
+            self.blend_mode,
+            raster_state,
+        )?;
+        pipelines.insert(raster_state, pipeline.clone());
+        Ok(pipeline)
+    }
+
+    // M is the model space. Waits for the upload, for the small meshes of the renderer itself.
     pub fn create_mesh<S>(
         self: &Arc<Self>,
         data: MeshData<V>,
         material: &M,
     ) -> Result<(Mesh<V, M, S>, Uniforms<M>)> {
+        let (mesh, uniforms, upload) = self.upload_mesh(data, material)?;
+        upload.wait()?;
+        Ok((mesh, uniforms))
+    }
+
+    // Like create_mesh without waiting for the vertex buffer and the index buffer to be
+    // uploaded. The mesh can't be drawn until the upload finishes.
+    pub fn upload_mesh<S>(
+        self: &Arc<Self>,
+        data: MeshData<V>,
+        material: &M,
+    ) -> Result<UploadedMesh<V, M, S>> {
         let MeshData {
             vertices: vertex_data,
             indices: index_data,
+            sub_meshes,
         } = data;
         let (vertex_buffer, vertex_buffer_init) = ImmutableBuffer::from_iter(
             vertex_data.into_iter(),
             BufferUsage::vertex_buffer(),
-            self.queue.clone(),
+            self.upload_queue.clone(),
         )
         .chain_err(|| "fail to create vertex buffer")?;
-        let (index_buffer, index_buffer_init) = ImmutableBuffer::from_iter(
-            index_data.into_iter(),
-            BufferUsage::index_buffer(),
-            self.queue.clone(),
-        )
-        .chain_err(|| "fail to create index buffer")?;
-        vertex_buffer_init
-            .join(index_buffer_init)
-            .then_signal_fence_and_flush()
-            .chain_err(|| {
-                "fail to signal the fence and flush when initializing the vertex buffer and the \
-                index buffer"
-            })?
-            .wait(None)
-            .chain_err(|| {
-                "fail to wait for the vertex buffer and the index buffer being initialized"
-            })?;
+        let (index_buffer, index_buffer_init) =
+            create_index_buffer(&index_data, self.upload_queue.clone())?;
+        let upload = Upload::submit(Box::new(vertex_buffer_init.join(index_buffer_init)))
+            .chain_err(|| "fail to upload the vertex buffer and the index buffer")?;
 
         let uniforms = material
             .create_uniforms(self.device.clone(), self.queue.clone())
@@ -245,18 +849,927 @@ impl<V: VertexT, M: Material> Renderer<V, M> {
                 renderer: self.clone(),
                 vertex_buffer,
                 index_buffer,
+                indices: index_data,
                 descriptor_sets,
+                sub_meshes,
                 phantom: PhantomData,
             },
             uniforms,
+            upload,
         ))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem::size_of;
+
+    use euclid::{point3, vec3, Angle};
+    use vulkano::{
+        buffer::{cpu_access::CpuAccessibleBuffer, device_local::DeviceLocalBuffer},
+        command_buffer::CommandBuffer,
+        format::Format,
+        image::{attachment::AttachmentImage, immutable::ImmutableImage, Dimensions, MipmapsCount},
+        sampler::Sampler,
+    };
+
+    use super::super::super::{
+        flare::FlareRenderer,
+        light::{HemisphereAmbient, LightInfo, PointLightRenderer},
+        material::{RenderMode, SpecularModel},
+        noise::{NoiseResources, DEFAULT_NOISE_SEED},
+        object::{
+            NoTextureObjectMaterial, NormalMapObjectMaterial, ObjectUniforms,
+            ObjectWithNoTextureVertex, ObjectWithTextureVertex, TextureObjectMaterial,
+        },
+        shaders::{
+            flare, light, light::vertex_shader::MainInput as LightVertexShaderInput, phong, skybox,
+            Texture,
+        },
+        shadow::{ShadowConfig, ShadowFilter},
+        skybox::{IrradianceMap, SkyboxRenderer},
+        Camera,
+    };
+    use crate::{errors::find_error_kind, gpu_test::create_queue};
+
+    #[derive(Default, Copy, Clone, Debug, PartialEq)]
+    struct TestVertex {
+        position: [f32; 3],
+    }
+
+    vulkano::impl_vertex!(TestVertex, position);
+
+    impl SimpleVertex for TestVertex {
+        fn create_from_position(x: f32, y: f32, z: f32) -> Self {
+            Self {
+                position: [x, y, z],
+            }
+        }
+    }
+
+    fn triangle(x: f32) -> MeshData<TestVertex> {
+        MeshData::create(
+            vec![
+                TestVertex {
+                    position: [x, 0.0, 0.0],
+                },
+                TestVertex {
+                    position: [x + 1.0, 0.0, 0.0],
+                },
+                TestVertex {
+                    position: [x, 1.0, 0.0],
+                },
+            ],
+            vec![0, 1, 2],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_merge_re_indexes_the_vertices() {
+        let merged = MeshData::merge(vec![
+            ("a".to_owned(), triangle(0.0)),
+            ("b".to_owned(), triangle(2.0)),
+        ])
+        .unwrap();
+        assert_eq!(merged.vertices.len(), 6);
+        assert_eq!(merged.indices, Indices::U16(vec![0, 1, 2, 3, 4, 5]));
+        assert_eq!(
+            merged.vertices[merged.indices.iter().nth(3).unwrap() as usize].position[0],
+            2.0
+        );
+        assert_eq!(
+            merged.sub_meshes,
+            vec![
+                SubMesh {
+                    name: "a".to_owned(),
+                    indices: 0..3,
+                },
+                SubMesh {
+                    name: "b".to_owned(),
+                    indices: 3..6,
+                },
+            ]
+        );
+    }
 
-    pub fn get_device(&self) -> Arc<Device> {
-        self.device.clone()
+    #[test]
+    fn test_merge_switches_to_u32_when_u16_overflows() {
+        let vertex_count = u16::MAX as usize / 2 + 1;
+        let large = || {
+            MeshData::create(
+                vec![TestVertex::default(); vertex_count],
+                vec![0, 1, (vertex_count - 1) as u32],
+            )
+            .unwrap()
+        };
+        let merged =
+            MeshData::merge(vec![("a".to_owned(), large()), ("b".to_owned(), large())]).unwrap();
+        assert!(matches!(merged.indices, Indices::U16(_)));
+        let merged = MeshData::merge(vec![
+            ("a".to_owned(), large()),
+            ("b".to_owned(), large()),
+            ("c".to_owned(), triangle(0.0)),
+        ])
+        .unwrap();
+        let last = (vertex_count * 2) as u32;
+        assert_eq!(
+            merged.indices.iter().skip(6).collect::<Vec<_>>(),
+            vec![last, last + 1, last + 2]
+        );
+        assert!(matches!(merged.indices, Indices::U32(_)));
+    }
+
+    #[test]
+    fn test_create_picks_the_index_type() {
+        let indices = vec![0, 1, u16::MAX as u32];
+        let small = MeshData::create(vec![TestVertex::default(); 1 << 16], indices.clone());
+        assert_eq!(small.unwrap().indices, Indices::U16(vec![0, 1, u16::MAX]));
+        let large = MeshData::create(vec![TestVertex::default(); (1 << 16) + 1], indices.clone());
+        assert_eq!(large.unwrap().indices, Indices::U32(indices));
+    }
+
+    #[test]
+    fn test_uv_sphere() {
+        for (rings, sectors) in [(2, 3), (3, 4), (16, 32)].iter().cloned() {
+            let sphere = MeshData::<TestVertex>::uv_sphere(rings, sectors).unwrap();
+            assert_eq!(sphere.vertices.len(), (2 + (rings - 1) * sectors) as usize);
+            assert_eq!(
+                sphere.indices.iter().count(),
+                (6 * (rings - 1) * sectors) as usize
+            );
+            assert!(matches!(sphere.indices, Indices::U16(_)));
+            for vertex in sphere.vertices.iter() {
+                let length = vec3::<f32, euclid::UnknownUnit>(
+                    vertex.position[0],
+                    vertex.position[1],
+                    vertex.position[2],
+                )
+                .length();
+                assert!((length - 1.0).abs() < 1e-5, "{:?}", vertex);
+            }
+            // every triangle faces away from the center
+            let indices: Vec<_> = sphere.indices.iter().collect();
+            for triangle in indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| {
+                    let [x, y, z] = sphere.vertices[triangle[i] as usize].position;
+                    point3::<f32, euclid::UnknownUnit>(x, y, z)
+                });
+                let normal = (b - a).cross(c - a);
+                assert!(normal.dot(a.to_vector()) > 0.0, "{:?}", triangle);
+            }
+        }
+    }
+
+    #[test]
+    fn test_uv_sphere_validation() {
+        for (rings, sectors) in [(1, 8), (8, 2), (0, 0)].iter().cloned() {
+            let e = MeshData::<TestVertex>::uv_sphere(rings, sectors)
+                .err()
+                .unwrap();
+            assert!(
+                matches!(find_error_kind(&e), Some(ErrorKind::Validation(_))),
+                "{}",
+                e
+            );
+        }
+        // 2 + 255 * 256 vertices still fit in u16, 2 + 257 * 256 don't
+        assert!(MeshData::<TestVertex>::uv_sphere(256, 256).is_ok());
+        assert!(MeshData::<TestVertex>::uv_sphere(258, 256).is_err());
+    }
+
+    #[test]
+    fn test_plane() {
+        for (width_segments, depth_segments) in [(1, 1), (2, 3), (16, 8)].iter().cloned() {
+            let plane = MeshData::<TestVertex>::plane(width_segments, depth_segments).unwrap();
+            assert_eq!(
+                plane.vertices.len(),
+                ((width_segments + 1) * (depth_segments + 1)) as usize
+            );
+            assert_eq!(
+                plane.indices.iter().count(),
+                (6 * width_segments * depth_segments) as usize
+            );
+            for vertex in plane.vertices.iter() {
+                let [x, y, z] = vertex.position;
+                assert!(x.abs() <= 1.0 && z.abs() <= 1.0 && y == 0.0, "{:?}", vertex);
+            }
+            // every triangle faces up with the same area
+            let indices: Vec<_> = plane.indices.iter().collect();
+            let area = 4.0 / (2 * width_segments * depth_segments) as f32;
+            for triangle in indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| {
+                    let [x, y, z] = plane.vertices[triangle[i] as usize].position;
+                    point3::<f32, euclid::UnknownUnit>(x, y, z)
+                });
+                let normal = (b - a).cross(c - a);
+                assert!(normal.x == 0.0 && normal.z == 0.0, "{:?}", triangle);
+                assert!((normal.y / 2.0 - area).abs() < 1e-5, "{:?}", triangle);
+            }
+        }
+    }
+
+    #[test]
+    fn test_plane_validation() {
+        for (width_segments, depth_segments) in [(0, 1), (1, 0)].iter().cloned() {
+            let e = MeshData::<TestVertex>::plane(width_segments, depth_segments)
+                .err()
+                .unwrap();
+            assert!(
+                matches!(find_error_kind(&e), Some(ErrorKind::Validation(_))),
+                "{}",
+                e
+            );
+        }
+        // 256 * 256 vertices still fit in u16, 257 * 256 don't
+        assert!(MeshData::<TestVertex>::plane(255, 255).is_ok());
+        assert!(MeshData::<TestVertex>::plane(256, 255).is_err());
+    }
+
+    #[test]
+    fn test_flip_winding() {
+        let mut indices = vec![0, 1, 2, 2, 3, 0];
+        flip_winding(&mut indices);
+        assert_eq!(indices, vec![0, 2, 1, 2, 0, 3]);
+        flip_winding(&mut indices);
+        assert_eq!(indices, vec![0, 1, 2, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_check_depth_range() {
+        assert!(check_depth_range(&DEFAULT_DEPTH_RANGE).is_ok());
+        assert!(check_depth_range(&(0.0..0.1)).is_ok());
+        // reversed depth
+        assert!(check_depth_range(&(1.0..0.0)).is_ok());
+        assert!(check_depth_range(&(-1.0..1.0)).is_err());
+        assert!(check_depth_range(&(0.0..2.0)).is_err());
+        assert!(check_depth_range(&(0.0..f32::NAN)).is_err());
+    }
+
+    #[test]
+    fn test_validate_vertex_input() {
+        #[derive(Default, Copy, Clone)]
+        struct Matching {
+            position: [f32; 4],
+        }
+        vulkano::impl_vertex!(Matching, position);
+        assert!(validate_vertex_input::<Matching>(&LightVertexShaderInput).is_ok());
+
+        #[derive(Default, Copy, Clone)]
+        struct Missing {
+            in_position: [f32; 4],
+        }
+        vulkano::impl_vertex!(Missing, in_position);
+        let error = validate_vertex_input::<Missing>(&LightVertexShaderInput).unwrap_err();
+        assert!(error.to_string().contains("position"), "{}", error);
+
+        #[derive(Default, Copy, Clone)]
+        struct WrongSize {
+            position: [f32; 3],
+        }
+        vulkano::impl_vertex!(WrongSize, position);
+        assert!(validate_vertex_input::<WrongSize>(&LightVertexShaderInput).is_err());
+    }
+
+    fn create_subpass(device: Arc<Device>) -> Subpass<Arc<dyn RenderPassAbstract + Send + Sync>> {
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device,
+                attachments: {
+                    color: {
+                        load: DontCare,
+                        store: Store,
+                        format: Format::R8G8B8A8Unorm,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            )
+            .unwrap(),
+        );
+        Subpass::from(render_pass, 0).unwrap()
+    }
+
+    fn submit(
+        queue: &Arc<Queue>,
+        record: impl FnOnce(&mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>),
+    ) {
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            queue.device().clone(),
+            queue.family(),
+        )
+        .unwrap();
+        record(&mut builder);
+        builder
+            .build()
+            .unwrap()
+            .execute(queue.clone())
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+    }
+
+    // the bytes of the uniform buffer on the device
+    fn read_back<T: Send + Sync + 'static>(
+        queue: &Arc<Queue>,
+        buffer: Arc<DeviceLocalBuffer<T>>,
+    ) -> Vec<u8> {
+        let destination = unsafe {
+            CpuAccessibleBuffer::<T>::uninitialized(
+                queue.device().clone(),
+                BufferUsage::transfer_destination(),
+                false,
+            )
+        }
+        .unwrap();
+        submit(queue, |builder| {
+            builder.copy_buffer(buffer, destination.clone()).unwrap();
+        });
+        let content = destination.read().unwrap();
+        // the uniforms are plain floats and uints
+        unsafe { std::slice::from_raw_parts(&*content as *const T as *const u8, size_of::<T>()) }
+            .to_vec()
+    }
+
+    // the bytes as the block the shader reads, whose std140 layout is reflected from the SPIR-V
+    fn as_block<B: Copy>(bytes: &[u8]) -> B {
+        assert_eq!(
+            bytes.len(),
+            size_of::<B>(),
+            "the uniform buffer and the block of the shader differ in size"
+        );
+        unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const B) }
     }
 
-    pub fn get_queue(&self) -> Arc<Queue> {
-        self.queue.clone()
+    trait Floats {
+        fn to_bits(&self) -> Vec<u32>;
+    }
+
+    impl Floats for f32 {
+        fn to_bits(&self) -> Vec<u32> {
+            vec![f32::to_bits(*self)]
+        }
+    }
+
+    impl<const N: usize> Floats for [f32; N] {
+        fn to_bits(&self) -> Vec<u32> {
+            self.iter().map(|value| f32::to_bits(*value)).collect()
+        }
+    }
+
+    impl<const N: usize, const M: usize> Floats for [[f32; N]; M] {
+        fn to_bits(&self) -> Vec<u32> {
+            self.iter().flat_map(Floats::to_bits).collect()
+        }
+    }
+
+    // the fields of the block the shader reads are bit-for-bit the fields of the uniform, a mat4
+    // of the block being the [f32; 16] of the uniform
+    macro_rules! assert_fields_eq {
+        ($block:expr, $uniform:expr, floats: [$($float:ident),*], uints: [$($uint:ident),*]) => {
+            $(assert_eq!(
+                Floats::to_bits(&$block.$float),
+                Floats::to_bits(&$uniform.$float),
+                stringify!($float)
+            );)*
+            $(assert_eq!($block.$uint, $uniform.$uint, stringify!($uint));)*
+        };
+    }
+
+    // every float differs from the others, so that a field read at the offset of another fails
+    fn fill(values: &mut [f32], next: &mut f32) {
+        for value in values {
+            *next += 1.0;
+            *value = *next;
+        }
+    }
+
+    fn matrix(next: &mut f32) -> [f32; 16] {
+        let mut matrix = [0.0; 16];
+        fill(&mut matrix, next);
+        matrix
+    }
+
+    fn create_texture(device: Arc<Device>) -> Texture {
+        Texture {
+            image: AttachmentImage::sampled(device.clone(), [1, 1], Format::R8G8B8A8Unorm).unwrap(),
+            sampler: Sampler::simple_repeat_linear(device),
+        }
+    }
+
+    fn create_camera() -> Camera {
+        Camera::new(
+            Angle::degrees(60.0),
+            1.5,
+            0.5,
+            50.0,
+            &point3(1.0, 2.0, 3.0),
+            &point3(0.0, 0.5, 0.0),
+            &vec3(0.0, 1.0, 0.0),
+        )
+        .unwrap()
+    }
+
+    // the setters of the phong uniforms, with inputs that don't repeat the prefilled floats
+    fn set_object_uniforms(uniforms: &mut dyn ObjectUniforms, next: &mut f32) {
+        uniforms.set_model_matrix(matrix(next));
+        uniforms.set_normal_matrix(matrix(next));
+        uniforms.set_view_proj_matrix_from_camera(&create_camera());
+        uniforms
+            .set_lights(&[
+                LightInfo {
+                    position: point3(-1.5, 2.5, 3.5),
+                    color: [0.25, 0.5, 0.75],
+                    intensity: 3.0,
+                },
+                LightInfo {
+                    position: point3(4.5, -5.5, 6.5),
+                    color: [0.125, 0.375, 0.625],
+                    intensity: 5.0,
+                },
+            ])
+            .unwrap();
+        uniforms.set_camera_pos(&create_camera());
+        uniforms.set_hemisphere_ambient(&HemisphereAmbient {
+            enabled: true,
+            sky_color: [0.3, 0.4, 0.5],
+            ground_color: [0.6, 0.7, 0.8],
+            intensity: 1.5,
+            up: vec3(0.0, 0.0, 1.0),
+        });
+        uniforms.set_directional_light(&vec3(1.0, -2.0, 0.5), [0.9, 0.8, 0.7], 2.5);
+        uniforms.set_uv_transform([[-1.0, -2.0, -3.0, -4.0], [-5.0, -6.0, -7.0, -8.0]]);
+        uniforms.set_light_space_matrix(Some(matrix(next)));
+        uniforms.set_shadow_config(&ShadowConfig {
+            bias: 0.0125,
+            filter: ShadowFilter::PoissonPcf { kernel_radius: 3 },
+        });
+        uniforms.set_shadow_noise(&NoiseResources::new(DEFAULT_NOISE_SEED));
+        uniforms.set_specular_aa_strength(2.25);
+        uniforms.set_diffuse_ibl(true);
+        uniforms.set_ambient_strength(0.75);
+        uniforms.set_render_mode(RenderMode::Depth);
+        uniforms.set_shininess(12.5);
+        uniforms.set_specular_model(SpecularModel::BlinnPhong);
+        uniforms.set_gamma_correction(true);
+    }
+
+    // Writes the uniforms of every shader set through the command buffer like the frames do, and
+    // reads them back as the blocks of the shaders, which catches a field of define_uniforms!
+    // moved without moving it in the GLSL.
+    #[test]
+    fn test_uniforms_round_trip_through_the_shader_blocks() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        let subpass = create_subpass(device.clone());
+        let texture = create_texture(device.clone());
+        let mut next = 0.0;
+
+        let renderer = PointLightRenderer::init(
+            device.clone(),
+            queue.clone(),
+            subpass.clone(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Opaque,
+        )
+        .unwrap();
+        let mut uniform = light::Uniform::default();
+        fill(&mut uniform.light_color, &mut next);
+        fill(
+            std::slice::from_mut(&mut uniform.light_intensity),
+            &mut next,
+        );
+        uniform.unlit = 1;
+        // distinct from unlit, to catch the two swapped
+        uniform.gamma_correction = 2;
+        let mut uniforms =
+            light::ShadersUniforms::new(device.clone(), queue.clone(), uniform).unwrap();
+        uniforms.set_model_matrix(matrix(&mut next));
+        uniforms.set_view_proj_matrix_from_camera(&create_camera());
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let bytes = read_back(&queue, uniforms.get_uniform_buffer());
+        let vertex_block: light::vertex_shader::ty::UniformBufferObject = as_block(&bytes);
+        let fragment_block: light::fragment_shader::ty::UniformBufferObject = as_block(&bytes);
+        for block in [
+            (vertex_block.model, vertex_block.view, vertex_block.proj),
+            (
+                fragment_block.model,
+                fragment_block.view,
+                fragment_block.proj,
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(block.0.to_bits(), uniforms.uniform.model.to_bits());
+            assert_eq!(block.1.to_bits(), uniforms.uniform.view.to_bits());
+            assert_eq!(block.2.to_bits(), uniforms.uniform.proj.to_bits());
+        }
+        assert_fields_eq!(
+            vertex_block,
+            uniforms.uniform,
+            floats: [light_color, light_intensity],
+            uints: [unlit, gamma_correction]
+        );
+        assert_fields_eq!(
+            fragment_block,
+            uniforms.uniform,
+            floats: [light_color, light_intensity],
+            uints: [unlit, gamma_correction]
+        );
+
+        let irradiance_map = IrradianceMap::new(device.clone(), &queue)
+            .unwrap()
+            .get_texture();
+        let renderer = Renderer::<ObjectWithTextureVertex, TextureObjectMaterial>::init(
+            device.clone(),
+            queue.clone(),
+            subpass.clone(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Opaque,
+        )
+        .unwrap();
+        let mut fs_uniform = phong::FsUniform::default();
+        fill(&mut fs_uniform.kd, &mut next);
+        fill(&mut fs_uniform.ks, &mut next);
+        fill(&mut fs_uniform.ambient_sky_color, &mut next);
+        fill(&mut fs_uniform.uv_transform_u, &mut next);
+        fill(&mut fs_uniform.uv_transform_v, &mut next);
+        fill(
+            std::slice::from_mut(&mut fs_uniform.shadow_texel_size),
+            &mut next,
+        );
+        fs_uniform.hemisphere_ambient = 2;
+        let mut uniforms = phong::with_texture::ShadersUniforms::new(
+            device.clone(),
+            queue.clone(),
+            Default::default(),
+            fs_uniform,
+            texture.clone(),
+            texture.clone(),
+            irradiance_map.clone(),
+            texture.clone(),
+        )
+        .unwrap();
+        set_object_uniforms(&mut uniforms, &mut next);
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let vs_block: phong::texture_vertex_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_vs_uniform_buffer()));
+        assert_fields_eq!(
+            vs_block,
+            uniforms.vs_uniform,
+            floats: [model, view, proj, normal_matrix],
+            uints: []
+        );
+        let fs_block: phong::texture_fragment_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_fs_uniform_buffer()));
+        assert_fields_eq!(
+            fs_block,
+            uniforms.fs_uniform,
+            floats: [
+                kd,
+                ks,
+                light_positions,
+                light_colors,
+                camera_pos,
+                ambient_sky_color,
+                ambient_ground_color,
+                ambient_up,
+                sun_direction,
+                sun_color,
+                light_space,
+                uv_transform_u,
+                uv_transform_v,
+                poisson_disk,
+                rotation_noise,
+                shadow_bias,
+                shadow_texel_size,
+                specular_aa_strength,
+                ambient_strength,
+                camera_near,
+                camera_far,
+                shininess
+            ],
+            uints: [
+                light_count,
+                hemisphere_ambient,
+                shadow_enabled,
+                shadow_pcf_radius,
+                shadow_filter,
+                poisson_disk_size,
+                diffuse_ibl,
+                render_mode,
+                specular_model,
+                gamma_correction
+            ]
+        );
+
+        let renderer = Renderer::<ObjectWithTextureVertex, NormalMapObjectMaterial>::init(
+            device.clone(),
+            queue.clone(),
+            subpass.clone(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Opaque,
+        )
+        .unwrap();
+        let mut fs_uniform = phong::FsUniform::default();
+        fill(&mut fs_uniform.kd, &mut next);
+        fill(&mut fs_uniform.ks, &mut next);
+        fill(&mut fs_uniform.ambient_sky_color, &mut next);
+        fill(&mut fs_uniform.uv_transform_u, &mut next);
+        fill(&mut fs_uniform.uv_transform_v, &mut next);
+        fill(
+            std::slice::from_mut(&mut fs_uniform.shadow_texel_size),
+            &mut next,
+        );
+        fs_uniform.hemisphere_ambient = 2;
+        let mut uniforms = phong::with_normal_map::ShadersUniforms::new(
+            device.clone(),
+            queue.clone(),
+            Default::default(),
+            fs_uniform,
+            texture.clone(),
+            texture.clone(),
+            irradiance_map.clone(),
+            texture.clone(),
+            texture.clone(),
+        )
+        .unwrap();
+        set_object_uniforms(&mut uniforms, &mut next);
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let vs_block: phong::normal_map_vertex_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_vs_uniform_buffer()));
+        assert_fields_eq!(
+            vs_block,
+            uniforms.vs_uniform,
+            floats: [model, view, proj, normal_matrix],
+            uints: []
+        );
+        let fs_block: phong::normal_map_fragment_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_fs_uniform_buffer()));
+        assert_fields_eq!(
+            fs_block,
+            uniforms.fs_uniform,
+            floats: [
+                kd,
+                ks,
+                light_positions,
+                light_colors,
+                camera_pos,
+                ambient_sky_color,
+                ambient_ground_color,
+                ambient_up,
+                sun_direction,
+                sun_color,
+                light_space,
+                uv_transform_u,
+                uv_transform_v,
+                poisson_disk,
+                rotation_noise,
+                shadow_bias,
+                shadow_texel_size,
+                specular_aa_strength,
+                ambient_strength,
+                camera_near,
+                camera_far,
+                shininess
+            ],
+            uints: [
+                light_count,
+                hemisphere_ambient,
+                shadow_enabled,
+                shadow_pcf_radius,
+                shadow_filter,
+                poisson_disk_size,
+                diffuse_ibl,
+                render_mode,
+                specular_model,
+                gamma_correction
+            ]
+        );
+
+        let renderer = Renderer::<ObjectWithNoTextureVertex, NoTextureObjectMaterial>::init(
+            device.clone(),
+            queue.clone(),
+            subpass.clone(),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Opaque,
+        )
+        .unwrap();
+        let mut fs_uniform = phong::FsUniform::default();
+        fill(&mut fs_uniform.kd, &mut next);
+        fill(&mut fs_uniform.ks, &mut next);
+        fill(&mut fs_uniform.ambient_sky_color, &mut next);
+        fill(&mut fs_uniform.uv_transform_u, &mut next);
+        fill(&mut fs_uniform.uv_transform_v, &mut next);
+        fill(
+            std::slice::from_mut(&mut fs_uniform.shadow_texel_size),
+            &mut next,
+        );
+        let mut uniforms = phong::no_texture::ShadersUniforms::new(
+            device.clone(),
+            queue.clone(),
+            Default::default(),
+            fs_uniform,
+            texture.clone(),
+            irradiance_map,
+        )
+        .unwrap();
+        set_object_uniforms(&mut uniforms, &mut next);
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let vs_block: phong::no_texture_vertex_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_vs_uniform_buffer()));
+        assert_fields_eq!(
+            vs_block,
+            uniforms.vs_uniform,
+            floats: [model, view, proj, normal_matrix],
+            uints: []
+        );
+        let fs_block: phong::no_texture_fragment_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_fs_uniform_buffer()));
+        assert_fields_eq!(
+            fs_block,
+            uniforms.fs_uniform,
+            floats: [
+                kd,
+                ks,
+                light_positions,
+                light_colors,
+                camera_pos,
+                ambient_sky_color,
+                ambient_ground_color,
+                ambient_up,
+                sun_direction,
+                sun_color,
+                light_space,
+                uv_transform_u,
+                uv_transform_v,
+                poisson_disk,
+                rotation_noise,
+                shadow_bias,
+                shadow_texel_size,
+                specular_aa_strength,
+                ambient_strength,
+                camera_near,
+                camera_far,
+                shininess
+            ],
+            uints: [
+                light_count,
+                hemisphere_ambient,
+                shadow_enabled,
+                shadow_pcf_radius,
+                shadow_filter,
+                poisson_disk_size,
+                diffuse_ibl,
+                render_mode,
+                specular_model,
+                gamma_correction
+            ]
+        );
+
+        let renderer = FlareRenderer::init(
+            device.clone(),
+            queue.clone(),
+            subpass,
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Additive,
+        )
+        .unwrap();
+        let mut uniform = flare::Uniform::default();
+        fill(&mut uniform.color, &mut next);
+        fill(&mut uniform.viewport, &mut next);
+        fill(std::slice::from_mut(&mut uniform.size), &mut next);
+        let mut uniforms = flare::ShadersUniforms::new(
+            device.clone(),
+            queue.clone(),
+            uniform,
+            texture.clone(),
+            texture,
+        )
+        .unwrap();
+        uniforms.set_model_matrix(matrix(&mut next));
+        uniforms.set_view_proj_matrix_from_camera(&create_camera());
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let bytes = read_back(&queue, uniforms.get_uniform_buffer());
+        let vertex_block: flare::vertex_shader::ty::UniformBufferObject = as_block(&bytes);
+        let fragment_block: flare::fragment_shader::ty::UniformBufferObject = as_block(&bytes);
+        assert_fields_eq!(
+            vertex_block,
+            uniforms.uniform,
+            floats: [model, view, proj, inv_proj, color, viewport, size],
+            uints: []
+        );
+        assert_fields_eq!(
+            fragment_block,
+            uniforms.uniform,
+            floats: [model, view, proj, inv_proj, color, viewport, size],
+            uints: []
+        );
+
+        let renderer = SkyboxRenderer::init(
+            device.clone(),
+            queue.clone(),
+            create_subpass(device.clone()),
+            1,
+            1,
+            DEFAULT_DEPTH_RANGE,
+            BlendMode::Background,
+        )
+        .unwrap();
+        let (cubemap, cubemap_init) = ImmutableImage::from_iter(
+            vec![[0u8; 4]; 6].into_iter(),
+            Dimensions::Cubemap { size: 1 },
+            MipmapsCount::One,
+            Format::R8G8B8A8Unorm,
+            queue.clone(),
+        )
+        .unwrap();
+        cubemap_init
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+        let mut uniforms = skybox::ShadersUniforms::new(
+            device.clone(),
+            queue.clone(),
+            Default::default(),
+            Texture {
+                image: cubemap,
+                sampler: Sampler::simple_repeat_linear(device),
+            },
+        )
+        .unwrap();
+        uniforms.set_view_proj_matrix_from_camera(&create_camera());
+        uniforms.uniform.gamma_correction = 1;
+        let descriptor_sets = uniforms
+            .create_descriptor_sets(renderer.pipeline_layout.as_ref())
+            .unwrap();
+        assert_eq!(descriptor_sets.len(), 1);
+        submit(&queue, |builder| uniforms.update_buffers(builder).unwrap());
+        let vertex_block: skybox::vertex_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_uniform_buffer()));
+        assert_fields_eq!(
+            vertex_block,
+            uniforms.uniform,
+            floats: [view, proj],
+            uints: [gamma_correction]
+        );
+        let fragment_block: skybox::fragment_shader::ty::UniformBufferObject =
+            as_block(&read_back(&queue, uniforms.get_uniform_buffer()));
+        assert_fields_eq!(
+            fragment_block,
+            uniforms.uniform,
+            floats: [view, proj],
+            uints: [gamma_correction]
+        );
     }
 }
@@ -3,110 +3,439 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
+mod import_options;
+mod material_desc;
 mod mesh_renderer;
+mod offscreen_target;
+mod resource_failure;
 
-use std::{cell::RefCell, collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    io,
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use euclid::Transform3D;
+use euclid::{vec3, Box3D, Point2D, Point3D, Transform3D};
 use image::{io::Reader as ImageReader, RgbaImage};
-use obj::{Obj, ObjData, ObjMaterial};
+use obj::{Group, MtlError, Obj, ObjData, ObjError, ObjMaterial};
 use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
     command_buffer::{
-        pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder, SubpassContents,
+        pool::standard::StandardCommandPoolBuilder, AutoCommandBuffer, AutoCommandBufferBuilder,
+        CommandBuffer, SubpassContents,
     },
     device::{Device, Queue},
     format::{ClearValue, D16Unorm, Format},
-    framebuffer::{Framebuffer, RenderPassAbstract, Subpass},
-    image::{attachment::AttachmentImage, traits::ImageViewAccess},
+    framebuffer::{Framebuffer, RenderPassAbstract, RenderPassDesc, Subpass},
+    image::{attachment::AttachmentImage, traits::ImageViewAccess, ImageUsage},
+    sync::GpuFuture,
 };
 
 use super::{
-    light::{PointLight, PointLightRenderer},
-    material::{Material, SetCamera},
-    object::{NoTextureObjectMaterial, Object, ObjectRenderer, TextureObjectMaterial},
-    Camera, TriangleSpace, WorldSpace,
+    camera::may_be_in_frustum,
+    denoise::{denoise, DenoiseParams},
+    flare::{Flare, FlareSettings},
+    light::{
+        DirectionalLight, HemisphereAmbient, PointLight, PointLightRenderer, MAX_POINT_LIGHTS,
+    },
+    material::{
+        validate_shininess, validate_specular_aa_strength, Material, RenderMode, SetCamera,
+        SpecularModel, UvAnimation, DEFAULT_SHININESS, DEFAULT_SPECULAR_AA_STRENGTH,
+    },
+    noise::{NoiseResources, DEFAULT_NOISE_SEED},
+    object::{
+        generate_missing_normals, group_position_triangles, is_closed_manifold,
+        NoTextureObjectMaterial, Object, ObjectLights, ObjectRenderer, ShaderFeatures,
+        TextureObjectMaterial, WarmUp,
+    },
+    path_tracer::{PathTracer, PointLightSource, SurfacePoint, TracedSurface},
+    shadow::{get_light_camera, get_light_space_transform, ShadowConfig, ShadowMap},
+    skybox::{validate_ambient_strength, Skybox, SkyboxRenderer, DEFAULT_AMBIENT_STRENGTH},
+    upload::{Upload, UploadQueue},
+    Camera, NDCSpace, TriangleSpace, WorldSpace,
 };
-use crate::errors::*;
-pub use mesh_renderer::{Mesh, MeshData, MeshT, Renderer as MeshRenderer, SimpleVertex};
+use crate::{errors::*, readback::to_rgba_image};
+use import_options::{bake_positions_and_normals, flip_winding, is_winding_flipped};
+pub use import_options::{ImportOptions, LengthUnit, UpAxis};
+use material_desc::{MaterialDesc, ObjectMaterial};
+pub use mesh_renderer::{
+    BlendMode, CullMode, FrontFace, Indices, Mesh, MeshData, MeshT, Pipeline, RasterState,
+    Renderer as MeshRenderer, SimpleVertex, SubMesh, DEFAULT_DEPTH_RANGE,
+};
+pub use offscreen_target::{LayerView, OffscreenTarget};
+use resource_failure::{create_checkerboard, is_out_of_memory, BatchObject, ModelLoad};
+pub use resource_failure::{FailedResource, ResourceFailure};
 
 #[derive(Clone)]
 pub struct ModelAndTexture {
     obj: Arc<ObjData>,
     textures: HashMap<String, Arc<RgbaImage>>,
+    // the files the textures are loaded from, keyed by the map_kd of the materials
+    texture_paths: HashMap<String, PathBuf>,
+}
+
+// the metadata of a material of the loaded model
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialInfo {
+    pub name: String,
+    // None for the textured materials
+    pub kd: Option<[f32; 3]>,
+    pub ks: [f32; 3],
+    // the file the diffuse texture is loaded from, None if the material is not textured
+    pub texture_path: Option<PathBuf>,
+}
+
+impl MaterialInfo {
+    pub fn is_textured(&self) -> bool {
+        self.texture_path.is_some()
+    }
 }
 
 impl ModelAndTexture {
     pub fn load(obj_path: &PathBuf) -> Result<Self> {
-        let mut obj = Obj::load(obj_path.as_path()).chain_err(|| "fail to load obj file")?;
-        obj.load_mtls()
-            .chain_err(|| "fail to load associated mtl file")?;
+        let mut obj = match Obj::load(obj_path.as_path()) {
+            Ok(obj) => obj,
+            Err(e) => {
+                let path = obj_path.display().to_string();
+                let kind = match e {
+                    ObjError::Io(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        ErrorKind::AssetNotFound(path)
+                    }
+                    _ => ErrorKind::AssetParse(path),
+                };
+                return Err(Error::with_chain(e, kind)).chain_err(|| "fail to load obj file");
+            }
+        };
+        if let Err(e) = obj.load_mtls() {
+            let missing = e.0.iter().find_map(|(name, e)| match e {
+                MtlError::Io(ref e) if e.kind() == io::ErrorKind::NotFound => Some(name.clone()),
+                _ => None,
+            });
+            let kind = match missing {
+                Some(name) => ErrorKind::AssetNotFound(name),
+                None => ErrorKind::AssetParse(format!("the mtl files of {}", obj_path.display())),
+            };
+            return Err(Error::with_chain(e, kind))
+                .chain_err(|| "fail to load associated mtl file");
+        }
+        generate_missing_normals(&mut obj.data)
+            .chain_err(|| ErrorKind::AssetParse(obj_path.display().to_string()))?;
         let mut textures: HashMap<_, _> = Default::default();
+        let mut texture_paths: HashMap<_, _> = Default::default();
+        let load_texture = |name: &String| -> Result<(RgbaImage, PathBuf)> {
+            let texture_path = obj_path
+                .parent()
+                .expect("the path to obj file can't be root")
+                .join(&name);
+            let texture = ImageReader::open(texture_path.as_path())
+                .chain_err(|| ErrorKind::AssetNotFound(texture_path.display().to_string()))?
+                .decode()
+                .chain_err(|| ErrorKind::AssetParse(texture_path.display().to_string()))?
+                .to_rgba8();
+            Ok((texture, texture_path))
+        };
         for mtl in obj.data.material_libs.iter() {
             for material in mtl.materials.iter() {
                 if let Some(ref name) = material.map_kd {
-                    let texture_path = obj_path
-                        .parent()
-                        .expect("the path to obj file can't be root")
-                        .join(&name);
-                    let texture = ImageReader::open(texture_path.as_path())
-                        .chain_err(|| {
-                            format!("fail to open image file: {}", texture_path.display())
-                        })?
-                        .decode()
-                        .chain_err(|| "fail to decode the image")?
-                        .to_rgba8();
+                    let (texture, texture_path) = load_texture(name)?;
                     textures.insert(name.clone(), Arc::new(texture));
+                    texture_paths.insert(name.clone(), texture_path);
+                    // the normal and the specular maps are only drawn with the texture coords of
+                    // map_kd
+                    for map_name in material.map_bump.iter().chain(material.map_ks.iter()) {
+                        let (map, _) = load_texture(map_name)?;
+                        textures.insert(map_name.clone(), Arc::new(map));
+                    }
                 }
             }
         }
         Ok(Self {
             obj: Arc::new(obj.data),
             textures,
+            texture_paths,
         })
     }
+
+    pub fn materials(&self) -> Vec<MaterialInfo> {
+        self.obj
+            .material_libs
+            .iter()
+            .flat_map(|mtl| mtl.materials.iter())
+            .map(|material| {
+                let texture_path = material
+                    .map_kd
+                    .as_ref()
+                    .and_then(|name| self.texture_paths.get(name))
+                    .cloned();
+                MaterialInfo {
+                    name: material.name.clone(),
+                    kd: if texture_path.is_some() {
+                        None
+                    } else {
+                        material.kd
+                    },
+                    ks: material.ks.unwrap_or([0.0, 0.0, 0.0]),
+                    texture_path,
+                }
+            })
+            .collect()
+    }
+
+    // the shader permutations the groups are drawn with, the unused materials don't count
+    pub fn get_shader_features(&self) -> Vec<ShaderFeatures> {
+        let mut all_features = vec![];
+        for group in self
+            .obj
+            .objects
+            .iter()
+            .flat_map(|object| object.groups.iter())
+        {
+            if let Some(ObjMaterial::Mtl(material)) = &group.material {
+                let features = match (&material.map_kd, &material.map_bump) {
+                    (Some(_), Some(_)) => ShaderFeatures::TEXTURE | ShaderFeatures::NORMAL_MAP,
+                    (Some(_), None) => ShaderFeatures::TEXTURE,
+                    (None, _) => ShaderFeatures::empty(),
+                };
+                if !all_features.contains(&features) {
+                    all_features.push(features);
+                }
+            }
+        }
+        all_features
+    }
+}
+
+// a model waiting for the warm-up of its shader permutations, see Renderer::start_loading_model
+struct PendingLoad {
+    model_and_texture: ModelAndTexture,
+    batch_threshold: Option<usize>,
+    flip_normals: bool,
+    import_options: ImportOptions,
+    warm_up: WarmUp,
+}
+
+pub const DEFAULT_LIGHT_INTENSITY: f32 = 1.0;
+// the batches stay indexable with u16 to save memory
+const MAX_BATCH_VERTICES: usize = u16::MAX as usize + 1;
+const GROUND_PLANE_SEGMENTS: u32 = 16;
+// linear
+const GROUND_PLANE_COLOR: [f32; 3] = [0.5, 0.5, 0.5];
+
+// the plane under the loaded model, see Renderer::set_ground_plane
+struct GroundPlane {
+    object: RefCell<Object<TriangleSpace>>,
+    // not drawn until they finish, see poll_uploads
+    uploads: Vec<Upload>,
+}
+
+struct BatchCandidate<K> {
+    key: K,
+    triangle_count: usize,
+    // before the duplicate vertices are removed
+    vertex_count: usize,
 }
 
-const LIGHT_INTENSITY: f32 = 1.0;
+// Split the candidates into batches of indices into candidates. The candidates with
+// triangle_count below the threshold are batched with the other small candidates with the same
+// key, in the order they appear, the others are batched alone.
+fn plan_batches<K: Eq>(
+    candidates: Vec<BatchCandidate<K>>,
+    threshold: Option<usize>,
+) -> Vec<Vec<usize>> {
+    let mut batches = vec![];
+    // the key, the candidates and the vertex count of the batches still open
+    let mut open_batches: Vec<(K, Vec<usize>, usize)> = vec![];
+    for (i, candidate) in candidates.into_iter().enumerate() {
+        let batchable = match threshold {
+            Some(threshold) => candidate.triangle_count < threshold,
+            None => false,
+        };
+        if !batchable {
+            batches.push(vec![i]);
+            continue;
+        }
+        match open_batches
+            .iter_mut()
+            .find(|(key, _, _)| *key == candidate.key)
+        {
+            Some((_, batch, vertex_count))
+                if *vertex_count + candidate.vertex_count <= MAX_BATCH_VERTICES =>
+            {
+                batch.push(i);
+                *vertex_count += candidate.vertex_count;
+            }
+            Some((_, batch, vertex_count)) => {
+                batches.push(std::mem::replace(batch, vec![i]));
+                *vertex_count = candidate.vertex_count;
+            }
+            None => open_batches.push((candidate.key, vec![i], candidate.vertex_count)),
+        }
+    }
+    batches.extend(open_batches.into_iter().map(|(_, batch, _)| batch));
+    batches
+}
+
+// negate the normals, for the models whose normals point into the surfaces
+fn negate_normals(normals: &[[f32; 3]]) -> Vec<[f32; 3]> {
+    normals.iter().map(|[x, y, z]| [-x, -y, -z]).collect()
+}
+
+// sampled by the flare to fade out near the geometry
+fn create_depth_buffer(
+    queue: Arc<Queue>,
+    width: u32,
+    height: u32,
+) -> Result<OffscreenTarget<D16Unorm>> {
+    OffscreenTarget::array(
+        queue,
+        D16Unorm,
+        [width, height],
+        1,
+        ImageUsage {
+            depth_stencil_attachment: true,
+            sampled: true,
+            ..ImageUsage::none()
+        },
+    )
+    .chain_err(|| {
+        ErrorKind::GpuResource("fail to create the image for the depth attachment".to_owned())
+    })
+}
+
+// the only layer of the depth buffer, attached to the scene pass and sampled by the flare
+fn get_depth_view(depth_buffer: &OffscreenTarget<D16Unorm>) -> Arc<LayerView<D16Unorm>> {
+    depth_buffer
+        .get_layer_view(0)
+        .expect("the depth buffer has a single layer")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointLightState {
+    // the light is at the origin of the model space of its marker
+    pub transform: Transform3D<f32, TriangleSpace, WorldSpace>,
+    // linear
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl PointLightState {
+    fn get_position(&self) -> Result<Point3D<f32, WorldSpace>> {
+        self.transform
+            .transform_point3d(Point3D::origin())
+            .ok_or_else(|| "invalid point light model transform".into())
+    }
+}
 
 pub struct State {
+    // linear, see srgb_to_linear for the colors picked in the UI
     pub color: [f32; 3],
     pub camera: Camera,
-    pub point_light_transform: Transform3D<f32, TriangleSpace, WorldSpace>,
+    // at most MAX_POINT_LIGHTS
+    pub point_lights: Vec<PointLightState>,
     pub model_transform: Transform3D<f32, TriangleSpace, WorldSpace>,
+    pub ambient: HemisphereAmbient,
+    // set every frame, e.g. to swing the sun around
+    pub sun: DirectionalLight,
+    // the scene time, which doesn't advance while the scene is frozen
+    pub time: Duration,
+    // linear, the color attachment is cleared to it where nothing is drawn
+    pub background_color: [f32; 4],
+    pub render_mode: RenderMode,
 }
 
 pub struct Renderer {
-    point_light: RefCell<PointLight<TriangleSpace>>,
+    // the markers of the point lights of the last state
+    point_lights: RefCell<Vec<PointLight<TriangleSpace>>>,
+    light_markers_unlit: bool,
+    point_light_renderer: Arc<PointLightRenderer>,
     object_renderer: ObjectRenderer,
     objects: Vec<RefCell<Object<TriangleSpace>>>,
-    depth_buffer: Arc<AttachmentImage<D16Unorm>>,
+    // the material name of every object
+    object_materials: Vec<String>,
+    // the uploads of every object not found finished yet, the object isn't drawn until they are,
+    // see poll_uploads
+    object_uploads: Vec<Vec<Upload>>,
+    // bounds the staging buffers alive while a model is loaded
+    upload_queue: UploadQueue,
+    // keyed by the material name, the materials without one are not animated
+    uv_animations: HashMap<String, UvAnimation>,
+    // the materials whose triangles don't enclose a volume, drawn double-sided unless forced
+    // single-sided
+    open_materials: HashSet<String>,
+    single_sided_materials: HashSet<String>,
+    depth_buffer: OffscreenTarget<D16Unorm>,
     render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+    noise: NoiseResources,
+    materials: Vec<MaterialInfo>,
+    // the raster state of the objects without their own
+    raster_state: RasterState,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    // when set, the draws are recorded once into a secondary command buffer, and replayed in the
+    // following frames until the scene changes
+    reuse_command_buffers: bool,
+    cached_draws: RefCell<Option<Arc<AutoCommandBuffer>>>,
+    // the CPU time spent recording the draws of the last frame
+    draw_recording_time: Cell<Duration>,
+    flare: RefCell<Flare<TriangleSpace>>,
+    skybox_renderer: Arc<SkyboxRenderer>,
+    // drawn behind the scene instead of the background color
+    skybox: RefCell<Option<Skybox>>,
+    // the objects are lit by the irradiance of the skybox instead of the ambient while it's loaded
+    diffuse_ibl: bool,
+    ambient_strength: f32,
+    // of the first point light
+    shadow_map: ShadowMap,
+    shadow_config: ShadowConfig,
+    specular_aa_strength: f32,
+    specular_model: SpecularModel,
+    // replaces the Ns of every material if set
+    shininess_override: Option<f32>,
+    // whether the scene shaders encode their output with the gamma, see set_gamma_correction
+    gamma_correction: bool,
+    ground_plane: Option<GroundPlane>,
+    pending_load: Option<PendingLoad>,
+    // the models with resources the GPU had no memory for, see retry_failed_resources
+    failed_loads: Vec<ModelLoad>,
+    // the mtl entries of the loaded models drawn differently from what they say
+    material_warnings: Vec<String>,
 }
 
 impl Renderer {
+    // the models are uploaded through the upload queue, which may be the queue drawing them
     pub fn init(
         device: Arc<Device>,
         queue: Arc<Queue>,
+        upload_queue: Arc<Queue>,
         format: Format,
         width: u32,
         height: u32,
+        depth_range: Range<f32>,
     ) -> Result<Self> {
         let depth_format = Format::D16Unorm;
-        let render_pass = Arc::new(
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
             vulkano::single_pass_renderpass!(
                 device.clone(),
                 attachments: {
                     color: {
-                        load: DontCare,
+                        load: Clear,
                         store: Store,
                         format: format,
                         samples: 1,
                     },
+                    // the layout of a LayerView of the depth buffer, see OffscreenTarget
                     depth: {
                         load: Clear,
                         store: Store,
                         format: depth_format,
                         samples: 1,
+                        initial_layout: ImageLayout::General,
+                        final_layout: ImageLayout::General,
                     }
                 },
                 pass: {
@@ -114,7 +443,11 @@ impl Renderer {
                     depth_stencil: {depth}
                 }
             )
-            .chain_err(|| "fail to create render pass when initializing renderer")?,
+            .chain_err(|| {
+                ErrorKind::GpuResource(
+                    "fail to create render pass when initializing renderer".to_owned(),
+                )
+            })?,
         );
         let subpass = Subpass::from(render_pass.clone(), 0)
             .expect("fail to retrieve the first subpass from the renderpass");
@@ -125,194 +458,1664 @@ impl Renderer {
                 subpass.clone(),
                 width,
                 height,
+                depth_range.clone(),
+                BlendMode::Opaque,
             )
             .chain_err(|| "fail to create point light renderer")?,
         );
-        let point_light = PointLight::new(point_light_renderer, LIGHT_INTENSITY, [1.0, 0.0, 0.0])
-            .chain_err(|| "fail to create point light")?;
-        let object_renderer = ObjectRenderer::init(device.clone(), queue, subpass, width, height)
-            .chain_err(|| "fail to create object renderer")?;
-        let depth_buffer = AttachmentImage::new(device, [width, height], D16Unorm)
-            .chain_err(|| "fail to create the image for the depth attachment")?;
+        let skybox_renderer = Arc::new(
+            SkyboxRenderer::init(
+                device.clone(),
+                queue.clone(),
+                subpass.clone(),
+                width,
+                height,
+                depth_range.clone(),
+                BlendMode::Background,
+            )
+            .chain_err(|| "fail to create the skybox renderer")?,
+        );
+        let shadow_map =
+            ShadowMap::new(queue.clone()).chain_err(|| "fail to create the shadow map")?;
+        let mut object_renderer = ObjectRenderer::init(
+            device.clone(),
+            queue.clone(),
+            subpass,
+            width,
+            height,
+            depth_range.clone(),
+            shadow_map.get_texture(),
+        )
+        .chain_err(|| "fail to create object renderer")?;
+        object_renderer.set_upload_queue(upload_queue);
+        let depth_buffer = create_depth_buffer(queue.clone(), width, height)?;
+        let flare = Flare::new(
+            device.clone(),
+            queue.clone(),
+            format,
+            width,
+            height,
+            depth_range,
+            get_depth_view(&depth_buffer),
+        )
+        .chain_err(|| "fail to create the flare")?;
         Ok(Self {
-            point_light: RefCell::new(point_light),
+            point_lights: RefCell::new(vec![]),
+            light_markers_unlit: false,
+            point_light_renderer,
             object_renderer,
             objects: vec![],
+            object_materials: vec![],
+            object_uploads: vec![],
+            upload_queue: Default::default(),
+            uv_animations: Default::default(),
+            open_materials: Default::default(),
+            single_sided_materials: Default::default(),
             depth_buffer,
             render_pass,
+            noise: NoiseResources::new(DEFAULT_NOISE_SEED),
+            materials: vec![],
+            raster_state: Default::default(),
+            device,
+            queue,
+            reuse_command_buffers: false,
+            cached_draws: RefCell::new(None),
+            draw_recording_time: Cell::new(Duration::default()),
+            flare: RefCell::new(flare),
+            skybox_renderer,
+            skybox: RefCell::new(None),
+            diffuse_ibl: true,
+            ambient_strength: DEFAULT_AMBIENT_STRENGTH,
+            shadow_map,
+            shadow_config: Default::default(),
+            specular_aa_strength: DEFAULT_SPECULAR_AA_STRENGTH,
+            specular_model: Default::default(),
+            shininess_override: None,
+            gamma_correction: true,
+            ground_plane: None,
+            pending_load: None,
+            failed_loads: vec![],
+            material_warnings: vec![],
+        })
+    }
+
+    // the size of the images draw_commands draws on
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        self.depth_buffer
+            .resize([width, height])
+            .chain_err(|| "fail to resize the depth buffer")?;
+        self.point_light_renderer.resize(width, height);
+        self.skybox_renderer.resize(width, height);
+        self.object_renderer.resize(width, height);
+        self.flare
+            .borrow_mut()
+            .resize(width, height, get_depth_view(&self.depth_buffer))
+            .chain_err(|| "fail to resize the flare")?;
+        self.invalidate_cached_draws();
+        Ok(())
+    }
+
+    // Reuse the recorded draws across the frames. The uniforms are then updated by
+    // prepare_draw_commands instead of draw_commands, and the caller has to submit them before
+    // the draws, separated by a semaphore, because vulkano doesn't track the resources of the
+    // reused command buffer.
+    pub fn set_command_buffer_reuse(&mut self, enabled: bool) {
+        self.reuse_command_buffers = enabled;
+        self.invalidate_cached_draws();
+    }
+
+    pub fn is_command_buffer_reuse_enabled(&self) -> bool {
+        self.reuse_command_buffers
+    }
+
+    pub fn get_draw_recording_time(&self) -> Duration {
+        self.draw_recording_time.get()
+    }
+
+    pub fn get_flare_settings(&self) -> FlareSettings {
+        self.flare.borrow().get_settings()
+    }
+
+    pub fn set_flare_settings(&mut self, settings: FlareSettings) -> Result<()> {
+        self.flare.borrow_mut().set_settings(settings)
+    }
+
+    // record the draws again in the next frame, after the meshes or the pipelines change
+    fn invalidate_cached_draws(&self) {
+        self.cached_draws.replace(None);
+    }
+
+    // draw the point lights as solid markers of their colors, regardless of their intensities
+    pub fn set_light_marker_unlit(&mut self, unlit: bool) {
+        self.light_markers_unlit = unlit;
+        for point_light in self.point_lights.borrow_mut().iter_mut() {
+            point_light.set_unlit(unlit);
+        }
+    }
+
+    pub fn is_light_marker_unlit(&self) -> bool {
+        self.light_markers_unlit
+    }
+
+    // the faces in the order of find_skybox_faces, the last skybox is kept if they fail to load
+    pub fn load_skybox(&mut self, paths: [PathBuf; 6]) -> Result<()> {
+        let skybox = Skybox::load(
+            &self.skybox_renderer,
+            self.device.clone(),
+            self.queue.clone(),
+            paths,
+        )
+        .chain_err(|| "fail to load the skybox")?;
+        self.object_renderer
+            .get_irradiance_map()
+            .set_irradiance(skybox.get_irradiance())
+            .chain_err(|| "fail to update the irradiance map of the skybox")?;
+        self.skybox.replace(Some(skybox));
+        self.invalidate_cached_draws();
+        Ok(())
+    }
+
+    // the background color is shown again
+    pub fn clear_skybox(&mut self) {
+        self.skybox.replace(None);
+        self.invalidate_cached_draws();
+    }
+
+    pub fn get_skybox_paths(&self) -> Option<[PathBuf; 6]> {
+        self.skybox
+            .borrow()
+            .as_ref()
+            .map(|skybox| skybox.get_paths().clone())
+    }
+
+    // Creates or drops the markers to match the lights, and updates their colors. The draws are
+    // recorded again when the number of the lights changes.
+    fn update_point_lights(&self, lights: &[PointLightState]) -> Result<()> {
+        if lights.len() > MAX_POINT_LIGHTS {
+            bail!(ErrorKind::Validation(format!(
+                "{} point lights, at most {} are supported",
+                lights.len(),
+                MAX_POINT_LIGHTS
+            )));
+        }
+        let mut point_lights = self.point_lights.borrow_mut();
+        if point_lights.len() != lights.len() {
+            self.invalidate_cached_draws();
+        }
+        point_lights.truncate(lights.len());
+        while point_lights.len() < lights.len() {
+            let light = &lights[point_lights.len()];
+            let mut point_light = PointLight::new(
+                self.point_light_renderer.clone(),
+                light.intensity,
+                light.color,
+            )
+            .chain_err(|| "fail to create point light")?;
+            point_light.set_unlit(self.light_markers_unlit);
+            point_light.set_gamma_correction(self.gamma_correction);
+            point_lights.push(point_light);
+        }
+        for (point_light, light) in point_lights.iter_mut().zip(lights.iter()) {
+            point_light.set_color_and_intensity(light.color, light.intensity);
+        }
+        Ok(())
+    }
+
+    // Only updates the uniforms, the pipelines are the same for all the filters. A Validation error
+    // if the bias is invalid, see ShadowConfig::validate.
+    pub fn set_shadow_config(&mut self, config: ShadowConfig) -> Result<()> {
+        config.validate()?;
+        self.shadow_config = config;
+        Ok(())
+    }
+
+    pub fn get_shadow_config(&self) -> ShadowConfig {
+        self.shadow_config
+    }
+
+    // a Validation error if the strength is invalid, see validate_specular_aa_strength
+    pub fn set_specular_aa_strength(&mut self, strength: f32) -> Result<()> {
+        validate_specular_aa_strength(strength)?;
+        self.specular_aa_strength = strength;
+        Ok(())
+    }
+
+    pub fn get_specular_aa_strength(&self) -> f32 {
+        self.specular_aa_strength
+    }
+
+    // only updates the uniforms, the pipelines are shared by both models
+    pub fn set_specular_model(&mut self, model: SpecularModel) {
+        self.specular_model = model;
+    }
+
+    pub fn get_specular_model(&self) -> SpecularModel {
+        self.specular_model
+    }
+
+    // None draws every material with its own Ns, a Validation error if the shininess is invalid,
+    // see validate_shininess
+    pub fn set_shininess_override(&mut self, shininess: Option<f32>) -> Result<()> {
+        if let Some(shininess) = shininess {
+            validate_shininess(shininess)?;
+        }
+        self.shininess_override = shininess;
+        Ok(())
+    }
+
+    pub fn get_shininess_override(&self) -> Option<f32> {
+        self.shininess_override
+    }
+
+    // Without the gamma correction, the objects and the light markers write their linear colors
+    // to the UNORM target as they are, which looks darker, to compare against the corrected image.
+    // The skybox decodes its gamma encoded faces to match, and the readbacks like the histogram
+    // should be told whether the scene image is encoded.
+    pub fn set_gamma_correction(&mut self, enabled: bool) {
+        self.gamma_correction = enabled;
+        for point_light in self.point_lights.borrow_mut().iter_mut() {
+            point_light.set_gamma_correction(enabled);
+        }
+    }
+
+    pub fn is_gamma_correction(&self) -> bool {
+        self.gamma_correction
+    }
+
+    // A gray plane under the loaded model, which the first point light casts the shadows onto.
+    // Only a receiver, it's left out of the shadow map, the path tracer and the pixel probe.
+    pub fn set_ground_plane(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.ground_plane.is_some() {
+            return Ok(());
+        }
+        self.invalidate_cached_draws();
+        if !enabled {
+            self.ground_plane = None;
+            return Ok(());
+        }
+        let material = NoTextureObjectMaterial::new(
+            &self.object_renderer,
+            GROUND_PLANE_COLOR,
+            [0.0; 3],
+            DEFAULT_SHININESS,
+        )?;
+        self.upload_queue.reserve()?;
+        let (object, upload) = Object::plane(
+            self.object_renderer.clone(),
+            GROUND_PLANE_SEGMENTS,
+            GROUND_PLANE_SEGMENTS,
+            Arc::new(material),
+        )
+        .chain_err(|| "fail to create the ground plane")?;
+        self.upload_queue.push(upload.clone());
+        self.ground_plane = Some(GroundPlane {
+            object: RefCell::new(object),
+            uploads: vec![upload],
+        });
+        Ok(())
+    }
+
+    pub fn is_ground_plane(&self) -> bool {
+        self.ground_plane.is_some()
+    }
+
+    // The plane twice as wide as the footprint of the loaded objects, slightly below their bottom
+    // against the z-fighting. It's placed before the model transform, so it turns with the model.
+    // The plane of -1 to 1 at the origin without any object.
+    fn get_ground_plane_transform(&self) -> Transform3D<f32, TriangleSpace, TriangleSpace> {
+        let bounds = match self.get_model_bounds() {
+            Some(bounds) => bounds,
+            None => return Transform3D::identity(),
+        };
+        let size = bounds.size();
+        let scale = size.width.max(size.depth).max(f32::EPSILON);
+        let center = bounds.center();
+        Transform3D::identity()
+            .then_scale(scale, 1.0, scale)
+            .then_translate(vec3(center.x, bounds.min.y - scale * 1e-3, center.z))
+    }
+
+    // the ground plane once its upload finishes
+    fn uploaded_ground_plane(&self) -> Option<&RefCell<Object<TriangleSpace>>> {
+        self.ground_plane
+            .as_ref()
+            .filter(|ground_plane| ground_plane.uploads.is_empty())
+            .map(|ground_plane| &ground_plane.object)
+    }
+
+    // only updates the uniforms, the skybox stays in the background either way
+    pub fn set_diffuse_ibl(&mut self, enabled: bool) {
+        self.diffuse_ibl = enabled;
+    }
+
+    pub fn is_diffuse_ibl(&self) -> bool {
+        self.diffuse_ibl
+    }
+
+    // a Validation error if the strength is invalid, see validate_ambient_strength
+    pub fn set_ambient_strength(&mut self, strength: f32) -> Result<()> {
+        validate_ambient_strength(strength)?;
+        self.ambient_strength = strength;
+        Ok(())
+    }
+
+    pub fn get_ambient_strength(&self) -> f32 {
+        self.ambient_strength
+    }
+
+    // The light space of the shadow map of the first point light, looking at the objects. None if
+    // there is nothing to shadow, or the draws are reused, since vulkano doesn't transition the
+    // shadow map to be sampled by the reused draws.
+    fn get_light_space(
+        &self,
+        state: &State,
+    ) -> Result<Option<Transform3D<f32, WorldSpace, NDCSpace>>> {
+        if self.reuse_command_buffers {
+            return Ok(None);
+        }
+        let light = match state.point_lights.first() {
+            Some(light) => light,
+            None => return Ok(None),
+        };
+        let bounds = self.get_scene_bounds(&state.model_transform, &[]);
+        Ok(get_light_camera(&light.get_position()?, &bounds)
+            .as_ref()
+            .map(get_light_space_transform))
+    }
+
+    pub fn set_raster_state(&mut self, raster_state: RasterState) {
+        self.raster_state = raster_state;
+        self.invalidate_cached_draws();
+    }
+
+    pub fn get_raster_state(&self) -> RasterState {
+        self.raster_state
+    }
+
+    // the depth clamp of the raster states is ignored otherwise
+    pub fn is_depth_clamp_supported(&self) -> bool {
+        self.device.enabled_features().depth_clamp
+    }
+
+    // the wireframe of the raster states is ignored otherwise
+    pub fn is_wireframe_supported(&self) -> bool {
+        self.device.enabled_features().fill_mode_non_solid
+    }
+
+    // the objects are in the same order as get_draw_call_groups
+    pub fn get_object_raster_state(&self, object_index: usize) -> Option<RasterState> {
+        self.objects
+            .get(object_index)
+            .and_then(|object| object.borrow().get_raster_state())
+    }
+
+    pub fn set_object_raster_state(
+        &mut self,
+        object_index: usize,
+        raster_state: Option<RasterState>,
+    ) -> Result<()> {
+        self.get_object(object_index)?
+            .borrow_mut()
+            .set_raster_state(raster_state);
+        self.invalidate_cached_draws();
+        Ok(())
+    }
+
+    pub fn get_uv_animation(&self, material_name: &str) -> UvAnimation {
+        self.uv_animations
+            .get(material_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    // only the textured materials can be animated
+    pub fn set_uv_animation(&mut self, material_name: &str, animation: UvAnimation) -> Result<()> {
+        match self
+            .materials
+            .iter()
+            .find(|material| material.name == material_name)
+        {
+            Some(material) if material.is_textured() => (),
+            Some(_) => bail!(ErrorKind::Validation(format!(
+                "the material {} has no texture to animate",
+                material_name
+            ))),
+            None => bail!(ErrorKind::Validation(format!(
+                "the material {} doesn't exist",
+                material_name
+            ))),
+        }
+        if animation.is_static() {
+            self.uv_animations.remove(material_name);
+        } else {
+            self.uv_animations
+                .insert(material_name.to_owned(), animation);
+        }
+        Ok(())
+    }
+
+    // the triangles of the material don't enclose a volume, see is_closed_manifold
+    pub fn is_material_open(&self, material_name: &str) -> bool {
+        self.open_materials.contains(material_name)
+    }
+
+    // drawn regardless of the cull mode of the scene, unless the object overrides it
+    pub fn is_material_double_sided(&self, material_name: &str) -> bool {
+        self.is_material_open(material_name) && !self.single_sided_materials.contains(material_name)
+    }
+
+    // culls the open material like the closed ones, e.g. when the detection is wrong
+    pub fn set_material_single_sided(
+        &mut self,
+        material_name: &str,
+        single_sided: bool,
+    ) -> Result<()> {
+        if !self
+            .materials
+            .iter()
+            .any(|material| material.name == material_name)
+        {
+            bail!(ErrorKind::Validation(format!(
+                "the material {} doesn't exist",
+                material_name
+            )));
+        }
+        if single_sided {
+            self.single_sided_materials.insert(material_name.to_owned());
+        } else {
+            self.single_sided_materials.remove(material_name);
+        }
+        let double_sided = self.is_material_double_sided(material_name);
+        for (object, _) in self
+            .objects
+            .iter()
+            .zip(self.object_materials.iter())
+            .filter(|(_, name)| *name == material_name)
+        {
+            object.borrow_mut().set_double_sided(double_sided);
+        }
+        self.invalidate_cached_draws();
+        Ok(())
+    }
+
+    // the object isn't drawn until the new index buffer is uploaded
+    pub fn flip_object_winding(&mut self, object_index: usize) -> Result<()> {
+        self.invalidate_cached_draws();
+        self.upload_queue.reserve()?;
+        let upload = self
+            .get_object(object_index)?
+            .borrow_mut()
+            .flip_winding()
+            .chain_err(|| format!("fail to flip the winding of the object {}", object_index))?;
+        self.upload_queue.push(upload.clone());
+        self.object_uploads[object_index].push(upload);
+        Ok(())
+    }
+
+    fn get_object(&self, object_index: usize) -> Result<&RefCell<Object<TriangleSpace>>> {
+        self.objects.get(object_index).ok_or_else(|| {
+            ErrorKind::Validation(format!(
+                "the object {} doesn't exist, only {} objects are loaded",
+                object_index,
+                self.objects.len()
+            ))
+            .into()
         })
     }
 
-    pub fn load_model_and_texture(&mut self, model_and_texture: ModelAndTexture) -> Result<()> {
-        let position = &model_and_texture.obj.position;
-        let normal = &model_and_texture.obj.normal;
+    pub fn set_noise_seed(&mut self, seed: u64) {
+        self.noise = NoiseResources::new(seed);
+    }
+
+    pub fn get_noise(&self) -> &NoiseResources {
+        &self.noise
+    }
+
+    // When batch_threshold is given, the groups with fewer triangles than it are merged with the
+    // other small groups sharing the same material, and drawn with a single draw call. When
+    // flip_normals is set, all the normals of the model are negated. The import options are baked
+    // into the positions and the normals. The materials whose triangles don't enclose a volume are
+    // drawn double-sided, see is_material_double_sided. The textures and the meshes that the GPU
+    // has no memory for are left out, see get_resource_failures.
+    pub fn load_model_and_texture(
+        &mut self,
+        model_and_texture: ModelAndTexture,
+        batch_threshold: Option<usize>,
+        flip_normals: bool,
+        import_options: ImportOptions,
+    ) -> Result<()> {
+        profile_scope!("load model");
+        let materials = model_and_texture.materials();
+        let mut load = ModelLoad::new(
+            model_and_texture,
+            batch_threshold,
+            flip_normals,
+            import_options,
+        );
+        self.create_model_objects(&mut load)?;
+        self.materials.extend(materials);
+        self.material_warnings
+            .extend(load.material_warnings.iter().cloned());
+        if load.has_failures() {
+            self.failed_loads.push(load);
+        }
+        self.invalidate_cached_draws();
+        Ok(())
+    }
+
+    // the resources the GPU had no memory for, in the order the models are loaded
+    pub fn get_resource_failures(&self) -> Vec<ResourceFailure> {
+        self.failed_loads
+            .iter()
+            .flat_map(ModelLoad::get_failures)
+            .cloned()
+            .collect()
+    }
+
+    // the warnings of the materials of the loaded models, in the order they are loaded
+    pub fn get_material_warnings(&self) -> &[String] {
+        &self.material_warnings
+    }
+
+    // Creates the resources that failed to be allocated again, e.g. once other applications free
+    // some GPU memory. The objects drawn with a checkerboard are replaced once their texture is
+    // created. Returns the number of resources still failing.
+    pub fn retry_failed_resources(&mut self) -> Result<usize> {
+        let mut loads = std::mem::take(&mut self.failed_loads);
+        let mut res = Ok(());
+        for load in loads.iter_mut() {
+            res = self.create_model_objects(load);
+            if res.is_err() {
+                break;
+            }
+        }
+        loads.retain(ModelLoad::has_failures);
+        self.failed_loads = loads;
+        self.invalidate_cached_draws();
+        res.chain_err(|| "fail to retry the failed resources")?;
+        Ok(self.get_resource_failures().len())
+    }
+
+    // Creates the materials and the objects of the model not created yet. Running out of memory
+    // is recorded in the load, the other errors fail the whole model.
+    fn create_model_objects(&mut self, load: &mut ModelLoad) -> Result<()> {
+        let model_and_texture = &load.model_and_texture;
+        let normal = if load.flip_normals {
+            Cow::Owned(negate_normals(&model_and_texture.obj.normal))
+        } else {
+            Cow::Borrowed(&model_and_texture.obj.normal[..])
+        };
+        let bake_transform = load.import_options.get_bake_transform();
+        let (position, normal) = if bake_transform == Transform3D::identity() {
+            (Cow::Borrowed(&model_and_texture.obj.position[..]), normal)
+        } else {
+            profile_scope!("bake import options");
+            let (position, normal) = bake_positions_and_normals(
+                &bake_transform,
+                &model_and_texture.obj.position,
+                &normal,
+            );
+            (Cow::Owned(position), Cow::Owned(normal))
+        };
+        // keep the front faces in front when the bake transform mirrors the model
+        let flipped_groups: Vec<Vec<_>> = if is_winding_flipped(&bake_transform) {
+            model_and_texture
+                .obj
+                .objects
+                .iter()
+                .map(|object| object.groups.iter().map(flip_winding).collect())
+                .collect()
+        } else {
+            vec![]
+        };
         let texture_coord: Vec<_> = model_and_texture
             .obj
             .texture
             .iter()
             .map(|[u, v]| [*u, 1.0 - *v])
             .collect();
-        let mut name_to_texture_material: HashMap<_, _> = Default::default();
-        let mut name_to_no_texture_material: HashMap<_, _> = Default::default();
-        for mtl in model_and_texture.obj.material_libs.iter() {
-            for material in mtl.materials.iter() {
-                let name = &material.name;
-                let ks = material.ks.unwrap_or([0.0, 0.0, 0.0]);
-                if let Some(ref texture_name) = material.map_kd {
-                    let texture = model_and_texture
-                        .textures
-                        .get(texture_name)
-                        .ok_or_else(|| -> Error {
-                            format!("fail to find map_kd with name {}", texture_name).into()
-                        })?
-                        .clone();
-                    let entry = name_to_texture_material.insert(
-                        name,
-                        Arc::new(
-                            TextureObjectMaterial::new(&self.object_renderer, texture.as_ref(), ks)
-                                .chain_err(|| {
-                                    format!("fail to create the object material {}", name)
-                                })?,
-                        ),
-                    );
-                    if entry.is_some() {
-                        return Err(format!(
-                            "materials with duplicate name {} not supproted",
-                            name
-                        )
-                        .into());
-                    };
-                } else {
-                    let kd = match material.kd {
-                        Some(kd) => kd,
-                        None => {
-                            return Err(format!(
-                                "the material {} with neither map_kd nor kd is not supported",
-                                name
-                            )
-                            .into())
+        let descs = {
+            profile_scope!("resolve materials");
+            MaterialDesc::resolve_all(
+                model_and_texture
+                    .obj
+                    .material_libs
+                    .iter()
+                    .flat_map(|mtl| mtl.materials.iter().map(AsRef::as_ref)),
+                &model_and_texture.textures,
+            )
+            .chain_err(|| "fail to resolve the materials")?
+        };
+        load.material_warnings = descs
+            .iter()
+            .flat_map(|desc| desc.warnings.iter().cloned())
+            .collect();
+        {
+            profile_scope!("upload materials");
+            for desc in descs.iter() {
+                if load.materials.contains_key(&desc.name) {
+                    continue;
+                }
+                self.upload_queue.reserve()?;
+                match ObjectMaterial::upload(
+                    desc,
+                    &model_and_texture.textures,
+                    &self.object_renderer,
+                ) {
+                    Ok(material_and_upload) => {
+                        if let (_, Some(upload)) = &material_and_upload {
+                            self.upload_queue.push(upload.clone());
                         }
-                    };
-                    let entry = name_to_no_texture_material.insert(
-                        name,
-                        Arc::new(NoTextureObjectMaterial::new(kd, ks).chain_err(|| {
-                            format!("fail to create the object material {}", name)
-                        })?),
-                    );
-                    if entry.is_some() {
-                        return Err(format!(
-                            "materials with duplicate name {} not supproted",
-                            name
-                        )
-                        .into());
-                    };
+                        load.materials
+                            .insert(desc.name.clone(), material_and_upload);
+                        load.material_failures.remove(&desc.name);
+                    }
+                    Err(e) if is_out_of_memory(&e) => {
+                        let failure = ResourceFailure::new(
+                            FailedResource::Texture {
+                                material: desc.name.clone(),
+                            },
+                            &e,
+                        );
+                        load.material_failures.insert(desc.name.clone(), failure);
+                    }
+                    Err(e) => {
+                        return Err(e).chain_err(|| {
+                            format!("fail to create the object material {}", desc.name)
+                        })
+                    }
                 }
             }
         }
 
-        for object in model_and_texture.obj.objects.iter() {
-            for group in object.groups.iter() {
+        let mut groups = vec![];
+        for (i, object) in model_and_texture.obj.objects.iter().enumerate() {
+            let object_groups = flipped_groups.get(i).unwrap_or(&object.groups);
+            for group in object_groups.iter() {
                 let material = match &group.material {
                     Some(ObjMaterial::Mtl(material)) => material,
                     Some(ObjMaterial::Ref(name)) => {
-                        return Err(format!(
-                            "object material {} in group {} not loaded",
+                        return Err(ErrorKind::AssetNotFound(format!(
+                            "the material {} of the group {}",
                             name, group.name
-                        )
+                        ))
                         .into())
                     }
                     None => {
-                        return Err(format!(
+                        return Err(ErrorKind::UnsupportedFeature(format!(
                             "object group {} without material associated is not supported",
                             group.name
-                        )
+                        ))
                         .into())
                     }
                 };
-                if material.map_kd.is_some() {
-                    let material = name_to_texture_material
-                        .get(&material.name)
-                        .expect("all material should have been loaded");
-                    let object = Object::with_texture(
-                        self.object_renderer.clone(),
-                        position,
+                groups.push((group, material));
+            }
+        }
+
+        {
+            profile_scope!("detect open surfaces");
+            let mut material_triangles: HashMap<&str, Vec<[usize; 3]>> = HashMap::new();
+            for (group, material) in groups.iter() {
+                material_triangles
+                    .entry(material.name.as_str())
+                    .or_default()
+                    .extend(group_position_triangles(group));
+            }
+            for (material_name, triangles) in material_triangles {
+                if !is_closed_manifold(&triangles) {
+                    self.open_materials.insert(material_name.to_owned());
+                }
+            }
+        }
+
+        let batches = plan_batches(
+            groups
+                .iter()
+                .map(|(group, material)| BatchCandidate {
+                    key: material.name.as_str(),
+                    triangle_count: group
+                        .polys
+                        .iter()
+                        .map(|poly| poly.0.len().saturating_sub(2))
+                        .sum(),
+                    vertex_count: group.polys.iter().map(|poly| poly.0.len()).sum(),
+                })
+                .collect(),
+            load.batch_threshold,
+        );
+        if load.batches.is_empty() {
+            load.batches = vec![BatchObject::Missing; batches.len()];
+        }
+        profile_scope!("create objects");
+        for (batch_index, batch) in batches.iter().enumerate() {
+            let material_name = groups[batch[0]].1.name.as_str();
+            let material = load.materials.get(material_name).cloned();
+            match (load.batches[batch_index], &material) {
+                (BatchObject::Created(_), _) | (BatchObject::Placeholder(_), None) => continue,
+                _ => (),
+            }
+            let batch_groups: Vec<_> = batch.iter().map(|i| groups[*i].0).collect();
+            let created = match material {
+                Some((material, material_upload)) => self
+                    .create_object(&position, &texture_coord, &normal, &batch_groups, &material)
+                    .map(|(object, upload)| (object, upload, material_upload, false)),
+                None => {
+                    let (ks, shininess) = descs
+                        .iter()
+                        .find(|desc| desc.name == material_name)
+                        .map_or(([0.0; 3], DEFAULT_SHININESS), |desc| {
+                            (desc.ks, desc.shininess)
+                        });
+                    self.create_placeholder_object(
+                        &position,
                         &texture_coord,
-                        normal,
-                        group,
-                        material.clone(),
+                        &normal,
+                        &batch_groups,
+                        ks,
+                        shininess,
                     )
-                    .chain_err(|| "fail to create object")?;
-                    self.objects.push(RefCell::new(object));
-                } else {
-                    let material = name_to_no_texture_material
-                        .get(&material.name)
-                        .expect("all material should have been loaded");
-                    let object = Object::without_texture(
-                        self.object_renderer.clone(),
-                        position,
-                        normal,
-                        group,
-                        material.clone(),
-                    )
-                    .chain_err(|| "fail to create object")?;
+                    .map(|(object, upload, material_upload)| {
+                        (object, upload, Some(material_upload), true)
+                    })
+                }
+            };
+            let (mut object, upload, material_upload, placeholder) = match created {
+                Ok(created) => created,
+                Err(e) if is_out_of_memory(&e) => {
+                    let failure = ResourceFailure::new(
+                        FailedResource::Mesh {
+                            groups: batch_groups
+                                .iter()
+                                .map(|group| group.name.clone())
+                                .collect(),
+                        },
+                        &e,
+                    );
+                    load.batch_failures.insert(batch_index, failure);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            object.set_double_sided(self.is_material_double_sided(material_name));
+            let uploads = std::iter::once(upload).chain(material_upload).collect();
+            let object_index = match load.batches[batch_index] {
+                BatchObject::Placeholder(object_index) => {
+                    self.objects[object_index] = RefCell::new(object);
+                    self.object_uploads[object_index] = uploads;
+                    object_index
+                }
+                _ => {
                     self.objects.push(RefCell::new(object));
+                    self.object_materials.push(material_name.to_owned());
+                    self.object_uploads.push(uploads);
+                    self.objects.len() - 1
+                }
+            };
+            load.batches[batch_index] = if placeholder {
+                BatchObject::Placeholder(object_index)
+            } else {
+                BatchObject::Created(object_index)
+            };
+            load.batch_failures.remove(&batch_index);
+        }
+        Ok(())
+    }
+
+    fn create_object(
+        &mut self,
+        position: &[[f32; 3]],
+        texture_coord: &[[f32; 2]],
+        normal: &[[f32; 3]],
+        groups: &[&Group],
+        material: &ObjectMaterial,
+    ) -> Result<(Object<TriangleSpace>, Upload)> {
+        self.upload_queue.reserve()?;
+        let (object, upload) = match material {
+            ObjectMaterial::Texture(material) => Object::with_texture(
+                self.object_renderer.clone(),
+                position,
+                texture_coord,
+                normal,
+                groups,
+                material.clone(),
+            ),
+            ObjectMaterial::NormalMap(material) => Object::with_normal_map(
+                self.object_renderer.clone(),
+                position,
+                texture_coord,
+                normal,
+                groups,
+                material.clone(),
+            ),
+            ObjectMaterial::NoTexture(material) => Object::without_texture(
+                self.object_renderer.clone(),
+                position,
+                normal,
+                groups,
+                material.clone(),
+            ),
+        }
+        .chain_err(|| "fail to create object")?;
+        self.upload_queue.push(upload.clone());
+        Ok((object, upload))
+    }
+
+    // textured with the checkerboard, for the material whose texture failed to be allocated
+    fn create_placeholder_object(
+        &mut self,
+        position: &[[f32; 3]],
+        texture_coord: &[[f32; 2]],
+        normal: &[[f32; 3]],
+        groups: &[&Group],
+        ks: [f32; 3],
+        shininess: f32,
+    ) -> Result<(Object<TriangleSpace>, Upload, Upload)> {
+        self.upload_queue.reserve()?;
+        let (material, material_upload) = TextureObjectMaterial::new(
+            &self.object_renderer,
+            Arc::new(create_checkerboard()),
+            None,
+            ks,
+            shininess,
+        )
+        .chain_err(|| "fail to create the placeholder material")?;
+        self.upload_queue.push(material_upload.clone());
+        let material = ObjectMaterial::Texture(Arc::new(material));
+        let (object, upload) =
+            self.create_object(position, texture_coord, normal, groups, &material)?;
+        Ok((object, upload, material_upload))
+    }
+
+    // Like load_model_and_texture, but the shader permutations the model needs and doesn't have
+    // yet are built on a background thread first, so that creating their pipelines doesn't stall
+    // a frame. The objects are only added by poll_pending_load once the warm-up finishes.
+    pub fn start_loading_model(
+        &mut self,
+        model_and_texture: ModelAndTexture,
+        batch_threshold: Option<usize>,
+        flip_normals: bool,
+        import_options: ImportOptions,
+    ) -> Result<()> {
+        if self.pending_load.is_some() {
+            bail!(ErrorKind::Validation(
+                "another model is still being prepared".to_owned()
+            ));
+        }
+        let warm_up = self
+            .object_renderer
+            .start_warm_up(&model_and_texture.get_shader_features(), self.raster_state)
+            .chain_err(|| "fail to start the warm-up of the shader permutations")?;
+        match warm_up {
+            Some(warm_up) => {
+                self.pending_load = Some(PendingLoad {
+                    model_and_texture,
+                    batch_threshold,
+                    flip_normals,
+                    import_options,
+                    warm_up,
+                });
+                Ok(())
+            }
+            None => self.load_model_and_texture(
+                model_and_texture,
+                batch_threshold,
+                flip_normals,
+                import_options,
+            ),
+        }
+    }
+
+    // called every frame, true when the pending model is loaded in this call
+    pub fn poll_pending_load(&mut self) -> Result<bool> {
+        let pending_load = match self.pending_load.take() {
+            Some(pending_load) => pending_load,
+            None => return Ok(false),
+        };
+        if !self
+            .object_renderer
+            .finish_warm_up(&pending_load.warm_up)
+            .chain_err(|| "fail to warm up the shader permutations")?
+        {
+            self.pending_load = Some(pending_load);
+            return Ok(false);
+        }
+        self.load_model_and_texture(
+            pending_load.model_and_texture,
+            pending_load.batch_threshold,
+            pending_load.flip_normals,
+            pending_load.import_options,
+        )?;
+        Ok(true)
+    }
+
+    // Bounds the memory of the staging buffers, loading a model waits for the GPU to finish the
+    // oldest uploads beyond the limit.
+    pub fn set_max_uploads_in_flight(&mut self, max_uploads_in_flight: usize) -> Result<()> {
+        self.upload_queue.set_max_in_flight(max_uploads_in_flight)
+    }
+
+    // the uploads not found finished yet, and the limit
+    pub fn get_uploads_in_flight(&mut self) -> (usize, usize) {
+        (
+            self.upload_queue.get_in_flight_count(),
+            self.upload_queue.get_max_in_flight(),
+        )
+    }
+
+    // whether a model is waiting for the warm-up
+    pub fn is_preparing(&self) -> bool {
+        self.pending_load.is_some()
+    }
+
+    // Called every frame before drawing, never waits for the GPU. The objects whose uploads have
+    // all finished are drawn from now on. Returns the number of objects still being uploaded.
+    pub fn poll_uploads(&mut self) -> usize {
+        let mut finished = false;
+        let ground_plane_uploads = self
+            .ground_plane
+            .as_mut()
+            .map(|ground_plane| &mut ground_plane.uploads);
+        for uploads in self.object_uploads.iter_mut().chain(ground_plane_uploads) {
+            let pending = uploads.len();
+            uploads.retain(|upload| !upload.is_finished());
+            finished |= pending > 0 && uploads.is_empty();
+        }
+        if finished {
+            self.invalidate_cached_draws();
+        }
+        self.object_uploads
+            .iter()
+            .filter(|uploads| !uploads.is_empty())
+            .count()
+    }
+
+    // the objects whose uploads are found finished by poll_uploads
+    fn uploaded_objects(&self) -> impl Iterator<Item = &RefCell<Object<TriangleSpace>>> {
+        self.objects
+            .iter()
+            .zip(self.object_uploads.iter())
+            .filter(|(_, uploads)| uploads.is_empty())
+            .map(|(object, _)| object)
+    }
+
+    // the materials of the loaded models, in the order they are declared in the mtl files
+    pub fn materials(&self) -> Vec<MaterialInfo> {
+        self.materials.clone()
+    }
+
+    // the bounds of the loaded objects and the point light in the world space
+    pub fn get_scene_bounds(
+        &self,
+        model_transform: &Transform3D<f32, TriangleSpace, WorldSpace>,
+        point_light_transforms: &[Transform3D<f32, TriangleSpace, WorldSpace>],
+    ) -> Box3D<f32, WorldSpace> {
+        let mut points: Vec<_> = point_light_transforms
+            .iter()
+            .filter_map(|transform| transform.transform_point3d(Point3D::origin()))
+            .collect();
+        for object in self.objects.iter() {
+            if let Some(bounds) = object.borrow().get_bvh().get_bounds() {
+                points.extend(
+                    bounds
+                        .corners()
+                        .iter()
+                        .filter_map(|corner| model_transform.transform_point3d(*corner)),
+                );
+            }
+        }
+        if let Some(ground_plane) = self.ground_plane.as_ref() {
+            let transform = self.get_ground_plane_transform().then(model_transform);
+            if let Some(bounds) = ground_plane.object.borrow().get_bvh().get_bounds() {
+                points.extend(
+                    bounds
+                        .corners()
+                        .iter()
+                        .filter_map(|corner| transform.transform_point3d(*corner)),
+                );
+            }
+        }
+        Box3D::from_points(points)
+    }
+
+    // The center of the bounds of the loaded objects in the world space, None without any. The
+    // center is taken before the model transform so that it stays put as the model turns.
+    pub fn get_model_center(
+        &self,
+        model_transform: &Transform3D<f32, TriangleSpace, WorldSpace>,
+    ) -> Option<Point3D<f32, WorldSpace>> {
+        model_transform.transform_point3d(self.get_model_bounds()?.center())
+    }
+
+    // the bounds of the loaded objects before the model transform, None without any
+    fn get_model_bounds(&self) -> Option<Box3D<f32, TriangleSpace>> {
+        self.objects
+            .iter()
+            .filter_map(|object| {
+                let object = object.borrow();
+                let bounds = object.get_bvh().get_bounds()?;
+                Some(Box3D::new(bounds.min, bounds.max))
+            })
+            .reduce(|bounds, other| bounds.union(&other))
+    }
+
+    // the objects whose bounding boxes may be in the view frustum of the camera
+    pub fn count_objects_in_view(
+        &self,
+        camera: &Camera,
+        model_transform: &Transform3D<f32, TriangleSpace, WorldSpace>,
+    ) -> usize {
+        let planes = camera.get_frustum_planes();
+        self.objects
+            .iter()
+            .filter(|object| match object.borrow().get_bvh().get_bounds() {
+                Some(bounds) => {
+                    let corners: Vec<_> = bounds
+                        .corners()
+                        .iter()
+                        .filter_map(|corner| model_transform.transform_point3d(*corner))
+                        .collect();
+                    may_be_in_frustum(&planes, &corners)
                 }
+                None => false,
+            })
+            .count()
+    }
+
+    // the names of the shader permutations the loaded objects use
+    pub fn get_live_shader_permutations(&self) -> Vec<String> {
+        self.object_renderer
+            .get_live_permutations()
+            .iter()
+            .map(ShaderFeatures::get_name)
+            .collect()
+    }
+
+    // the groups drawn by every draw call of the loaded model
+    pub fn get_draw_call_groups(&self) -> Vec<Vec<SubMesh>> {
+        self.objects
+            .iter()
+            .map(|object| object.borrow().get_sub_meshes().to_vec())
+            .collect()
+    }
+
+    // the index type of every draw call, u32 only for the draw calls with many vertices
+    pub fn get_draw_call_index_types(&self) -> Vec<&'static str> {
+        self.objects
+            .iter()
+            .map(|object| object.borrow().get_indices().get_type_name())
+            .collect()
+    }
+
+    // A slow reference rendering of the scene with the CPU path tracer, seeded by the noise seed,
+    // so the same state always produces the same image. When denoise_params is given, the noise
+    // is filtered with the normals and the depth of the first hits as the guides.
+    pub fn path_trace(
+        &self,
+        state: &State,
+        width: u32,
+        height: u32,
+        samples: u32,
+        denoise_params: Option<&DenoiseParams>,
+    ) -> Result<RgbaImage> {
+        let lights = state
+            .point_lights
+            .iter()
+            .map(|light| {
+                Ok(PointLightSource {
+                    position: light.get_position()?,
+                    intensity: light.intensity,
+                    color: light.color,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let objects: Vec<_> = self.objects.iter().map(|object| object.borrow()).collect();
+        let surfaces: Vec<&dyn TracedSurface<TriangleSpace>> = objects
+            .iter()
+            .map(|object| -> &dyn TracedSurface<TriangleSpace> { &**object })
+            .collect();
+        let path_tracer = PathTracer::new(surfaces, state.model_transform, lights)
+            .ok_or_else(|| -> Error { "the model transform is not invertible".into() })?;
+        let image =
+            path_tracer.render(&state.camera, width, height, samples, self.noise.get_seed());
+        match denoise_params {
+            Some(denoise_params) => {
+                let (normals, depth) = path_tracer.render_guides(&state.camera, width, height);
+                denoise(&image, &normals, &depth, denoise_params)
+                    .chain_err(|| "fail to denoise the path traced image")
             }
+            None => Ok(image),
+        }
+    }
+
+    // The closest surface through the point on the screen, cast on the CPU against the same BVHs
+    // as the path tracer. The normal is the geometric one of the triangle, facing the camera, not
+    // the interpolated or normal mapped one the shaders use.
+    pub fn probe_surface(
+        &self,
+        camera: &Camera,
+        model_transform: Transform3D<f32, TriangleSpace, WorldSpace>,
+        screen_point: Point2D<f32, NDCSpace>,
+    ) -> Result<Option<SurfacePoint>> {
+        let objects: Vec<_> = self.objects.iter().map(|object| object.borrow()).collect();
+        let surfaces: Vec<&dyn TracedSurface<TriangleSpace>> = objects
+            .iter()
+            .map(|object| -> &dyn TracedSurface<TriangleSpace> { &**object })
+            .collect();
+        let path_tracer = PathTracer::new(surfaces, model_transform, vec![])
+            .ok_or_else(|| -> Error { "the model transform is not invertible".into() })?;
+        Ok(path_tracer.probe(&camera.screen_ray(screen_point)))
+    }
+
+    // Draws the state into an offscreen image of the size and reads it back, waiting for the GPU.
+    // The renderer is resized for it and back, and the draws are recorded inline. The aspect ratio
+    // of the camera of the state is kept.
+    pub fn render_to_image(&mut self, state: &State, width: u32, height: u32) -> Result<RgbaImage> {
+        if width == 0 || height == 0 {
+            bail!(ErrorKind::Validation(format!(
+                "can't render an image of {}x{}",
+                width, height
+            )));
+        }
+        let format = self
+            .render_pass
+            .attachment_desc(0)
+            .expect("the render pass has a color attachment")
+            .format;
+        let image = AttachmentImage::with_usage(
+            self.device.clone(),
+            [width, height],
+            format,
+            ImageUsage {
+                transfer_source: true,
+                ..ImageUsage::color_attachment()
+            },
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource(format!("fail to create an image of {}x{}", width, height))
+        })?;
+        let len = width as usize * height as usize * 4;
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_destination(),
+            true,
+            (0..len).map(|_| 0u8),
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource(format!("fail to create a readback buffer of {} bytes", len))
+        })?;
+
+        let [previous_width, previous_height] = self.depth_buffer.get_size();
+        let reuse_command_buffers = self.reuse_command_buffers;
+        self.reuse_command_buffers = false;
+        self.resize(width, height)
+            .chain_err(|| "fail to resize the renderer to the image")?;
+        let res = (|| -> Result<()> {
+            let mut cmd_buf_builder = AutoCommandBufferBuilder::primary_one_time_submit(
+                self.device.clone(),
+                self.queue.family(),
+            )
+            .chain_err(|| "fail to create the command buffer builder")?;
+            self.draw_commands(&mut cmd_buf_builder, image.clone(), state)?;
+            cmd_buf_builder
+                .copy_image_to_buffer(image, buffer.clone())
+                .chain_err(|| {
+                    "fail to add the copy image to buffer command to the command builder"
+                })?;
+            let cmd_buf = cmd_buf_builder
+                .build()
+                .chain_err(|| "fail to build the command buffer")?;
+            cmd_buf
+                .execute(self.queue.clone())
+                .chain_err(|| "fail to submit the command buffer")?
+                .then_signal_fence_and_flush()
+                .chain_err(|| "fail to signal the fence and flush")?
+                .wait(None)
+                .chain_err(|| "fail to wait for the rendering")
+        })();
+        self.reuse_command_buffers = reuse_command_buffers;
+        self.resize(previous_width, previous_height)
+            .chain_err(|| "fail to resize the renderer back")?;
+        res.chain_err(|| "fail to render the image")?;
+
+        let pixels = buffer
+            .read()
+            .chain_err(|| "fail to read the rendered image")?
+            .to_vec();
+        to_rgba_image(format, [width, height], pixels)
+    }
+
+    // update the uniforms of the point light and the objects from the state
+    pub fn prepare_draw_commands(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        state: &State,
+    ) -> Result<()> {
+        profile_scope!("prepare draw commands");
+        self.object_renderer
+            .get_irradiance_map()
+            .prepare_draw_commands(cmd_buf_builder)
+            .chain_err(|| "fail to issue commands to update the irradiance map")?;
+        if let Some(skybox) = self.skybox.borrow_mut().as_mut() {
+            skybox
+                .prepare_draw_commands(cmd_buf_builder, &state.camera, self.gamma_correction)
+                .chain_err(|| "fail to issue commands to prepare drawing for the skybox")?;
+        }
+        let mut light_infos = vec![];
+        {
+            profile_scope!("point light uniforms");
+            self.update_point_lights(&state.point_lights)
+                .chain_err(|| "fail to update the point lights")?;
+            for (point_light, light) in self
+                .point_lights
+                .borrow_mut()
+                .iter_mut()
+                .zip(state.point_lights.iter())
+            {
+                point_light
+                    .prepare_draw_commands(cmd_buf_builder, &light.transform, &state.camera)
+                    .chain_err(|| {
+                        "fail to issue commands to prepare drawing for the point light mesh"
+                    })?;
+                light_infos.push(point_light.get_light_info()?);
+            }
+        }
+        let light_space = self.get_light_space(state)?;
+        let diffuse_ibl = self.diffuse_ibl && self.skybox.borrow().is_some();
+        profile_scope!("object uniforms");
+        let lights = ObjectLights {
+            points: &light_infos,
+            ambient: &state.ambient,
+            sun: &state.sun,
+            light_space,
+            shadow: self.shadow_config,
+            noise: &self.noise,
+            specular_aa_strength: self.specular_aa_strength,
+            diffuse_ibl,
+            ambient_strength: self.ambient_strength,
+            specular_model: self.specular_model,
+            shininess_override: self.shininess_override,
+            gamma_correction: self.gamma_correction,
+        };
+        for (object, material_name) in self.objects.iter().zip(self.object_materials.iter()) {
+            let uv_transform = self
+                .get_uv_animation(material_name)
+                .transform_at(state.time);
+            object
+                .borrow_mut()
+                .prepare_draw_commands(
+                    cmd_buf_builder,
+                    &state.model_transform,
+                    &state.camera,
+                    &lights,
+                    uv_transform,
+                    state.render_mode,
+                )
+                .chain_err(|| "fail to issue commands to prepare drawing for the object mesh")?;
+        }
+        if let Some(ground_plane) = self.ground_plane.as_ref() {
+            ground_plane
+                .object
+                .borrow_mut()
+                .prepare_draw_commands(
+                    cmd_buf_builder,
+                    &self
+                        .get_ground_plane_transform()
+                        .then(&state.model_transform),
+                    &state.camera,
+                    &lights,
+                    UvAnimation::default().transform_at(state.time),
+                    state.render_mode,
+                )
+                .chain_err(|| "fail to issue commands to prepare drawing for the ground plane")?;
+        }
+        Ok(())
+    }
+
+    // the draws of the point light and the objects, within the subpass
+    fn record_draws(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+    ) -> Result<()> {
+        // first, while the depth is still cleared
+        if let Some(skybox) = self.skybox.borrow().as_ref() {
+            skybox.draw_commands(cmd_buf_builder)?;
+        }
+        for point_light in self.point_lights.borrow().iter() {
+            point_light
+                .mesh
+                .draw_commands(cmd_buf_builder, RasterState::default())
+                .chain_err(|| "fail to issue draw commands for the point light mesh")?;
+        }
+        for object in self.uploaded_objects() {
+            object
+                .borrow()
+                .draw_commands(cmd_buf_builder, self.raster_state)
+                .chain_err(|| "fail to issue draw commands for the object mesh")?;
+        }
+        if let Some(ground_plane) = self.uploaded_ground_plane() {
+            ground_plane
+                .borrow()
+                .draw_commands(cmd_buf_builder, self.raster_state)
+                .chain_err(|| "fail to issue draw commands for the ground plane")?;
         }
         Ok(())
     }
 
+    // the secondary command buffer with the draws, recorded again if the scene has changed
+    fn get_cached_draws(&self) -> Result<Arc<AutoCommandBuffer>> {
+        if let Some(ref cached_draws) = *self.cached_draws.borrow() {
+            return Ok(cached_draws.clone());
+        }
+        profile_scope!("record cached draws");
+        let subpass = Subpass::from(self.render_pass.clone(), 0)
+            .expect("fail to retrieve the first subpass from the renderpass");
+        // the same command buffer may still be executing for the previous frame
+        let mut cmd_buf_builder = AutoCommandBufferBuilder::secondary_graphics_simultaneous_use(
+            self.device.clone(),
+            self.queue.family(),
+            subpass,
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource("fail to create the command buffer for the draws".to_owned())
+        })?;
+        self.record_draws(&mut cmd_buf_builder)?;
+        let cached_draws = Arc::new(
+            cmd_buf_builder
+                .build()
+                .chain_err(|| "fail to build the command buffer for the draws")?,
+        );
+        self.cached_draws.replace(Some(cached_draws.clone()));
+        Ok(cached_draws)
+    }
+
+    // When the command buffers are reused, the uniforms are not updated here, see
+    // set_command_buffer_reuse.
     pub fn draw_commands(
         &self,
         cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
         image: Arc<impl ImageViewAccess + Send + Sync + 'static>,
         state: &State,
     ) -> Result<()> {
+        profile_scope!("draw commands");
         let framebuffer = Arc::new(
             Framebuffer::start(self.render_pass.clone())
-                .add(image)
+                .add(image.clone())
                 .chain_err(|| "fail to add the color attachment to the framebuffer")?
-                .add(self.depth_buffer.clone())
+                .add(get_depth_view(&self.depth_buffer))
                 .chain_err(|| "fail to add the depth attachment to the framebuffer")?
                 .build()
                 .chain_err(|| "fail to create the framebuffer to draw on")?,
         );
-        self.point_light
-            .borrow_mut()
-            .prepare_draw_commands(cmd_buf_builder, &state.point_light_transform, &state.camera)
-            .chain_err(|| "fail to issue commands to prepare drawing for the point light mesh")?;
-        for object in self.objects.iter() {
-            object
-                .borrow_mut()
-                .prepare_draw_commands(
-                    cmd_buf_builder,
-                    &state.model_transform,
-                    &state.camera,
-                    &self.point_light.borrow(),
-                )
-                .chain_err(|| "fail to issue commands to prepare drawing for the object mesh")?;
+        if !self.reuse_command_buffers {
+            self.prepare_draw_commands(cmd_buf_builder, state)?;
         }
+        let recording_start = Instant::now();
+        if let Some(light_space) = self.get_light_space(state)? {
+            profile_scope!("shadow pass");
+            let light_space_model = state.model_transform.then(&light_space);
+            self.shadow_map.begin(cmd_buf_builder)?;
+            for object in self.uploaded_objects() {
+                object
+                    .borrow()
+                    .shadow_draw_commands(cmd_buf_builder, &self.shadow_map, &light_space_model)
+                    .chain_err(|| "fail to issue shadow draw commands for the object mesh")?;
+            }
+            self.shadow_map.end(cmd_buf_builder)?;
+        }
+        let contents = if self.reuse_command_buffers {
+            SubpassContents::SecondaryCommandBuffers
+        } else {
+            SubpassContents::Inline
+        };
         cmd_buf_builder
             .begin_render_pass(
                 framebuffer.clone(),
-                SubpassContents::Inline,
-                vec![ClearValue::None, ClearValue::Depth(1.0)],
+                contents,
+                vec![
+                    ClearValue::Float(state.background_color),
+                    ClearValue::Depth(1.0),
+                ],
             )
             .chain_err(|| "fail to add the begin renderpass command to the command builder")?;
-        self.point_light
-            .borrow()
-            .mesh
-            .draw_commands(cmd_buf_builder)
-            .chain_err(|| "fail to issue draw commands for the point light mesh")?;
-        for object in self.objects.iter() {
-            object
-                .borrow()
-                .draw_commands(cmd_buf_builder)
-                .chain_err(|| "fail to issue draw commands for the object mesh")?;
+        if self.reuse_command_buffers {
+            let cached_draws = self
+                .get_cached_draws()
+                .chain_err(|| "fail to record the draws to reuse")?;
+            // the buffers and the images the draws access are only written by the uniform
+            // updates, which the caller submits before with a semaphore
+            unsafe { cmd_buf_builder.execute_commands(cached_draws) }
+                .chain_err(|| "fail to add the execute commands command to the command builder")?;
+        } else {
+            profile_scope!("record draws");
+            self.record_draws(cmd_buf_builder)?;
         }
         cmd_buf_builder
             .end_render_pass()
             .chain_err(|| "fail to add the end renderpass command to the command builder")?;
+        self.draw_recording_time.set(recording_start.elapsed());
+        // a late pass, always recorded inline, so that the depth buffer is transitioned to be
+        // sampled, only for the first point light
+        if let Some(light) = state.point_lights.first() {
+            profile_scope!("flare");
+            self.flare
+                .borrow_mut()
+                .draw_commands(
+                    cmd_buf_builder,
+                    image,
+                    &light.transform,
+                    light.intensity,
+                    light.color,
+                    &state.camera,
+                )
+                .chain_err(|| "fail to issue draw commands for the flare")?;
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::errors::find_error_kind;
+
+    // a new directory for the files of the test
+    fn create_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "games202-hw-vulkano-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_missing_model() {
+        let dir = create_test_dir("missing-model");
+        let e = ModelAndTexture::load(&dir.join("missing.obj"))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(find_error_kind(&e), Some(ErrorKind::AssetNotFound(_))),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_load_malformed_model() {
+        let dir = create_test_dir("malformed-model");
+        let obj_path = dir.join("malformed.obj");
+        fs::write(&obj_path, "v 1.0 not-a-number 0.0\n").unwrap();
+        let e = ModelAndTexture::load(&obj_path).err().unwrap();
+        assert!(
+            matches!(find_error_kind(&e), Some(ErrorKind::AssetParse(_))),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_materials() {
+        let dir = create_test_dir("materials");
+        let obj_path = dir.join("model.obj");
+        fs::write(
+            &obj_path,
+            "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl plain\nf 1 2 3\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("model.mtl"),
+            "newmtl plain\nKd 0.5 0.25 1.0\nKs 0.1 0.2 0.3\n\
+            newmtl textured\nKd 1.0 1.0 1.0\nmap_Kd texture.png\n",
+        )
+        .unwrap();
+        RgbaImage::new(1, 1).save(dir.join("texture.png")).unwrap();
+        let model_and_texture = ModelAndTexture::load(&obj_path).unwrap();
+        // the textured material isn't used by any group
+        assert_eq!(
+            model_and_texture.get_shader_features(),
+            vec![ShaderFeatures::empty()]
+        );
+        let materials = model_and_texture.materials();
+        assert_eq!(
+            materials,
+            vec![
+                MaterialInfo {
+                    name: "plain".to_owned(),
+                    kd: Some([0.5, 0.25, 1.0]),
+                    ks: [0.1, 0.2, 0.3],
+                    texture_path: None,
+                },
+                MaterialInfo {
+                    name: "textured".to_owned(),
+                    kd: None,
+                    ks: [0.0, 0.0, 0.0],
+                    texture_path: Some(dir.join("texture.png")),
+                },
+            ]
+        );
+        assert!(!materials[0].is_textured());
+        assert!(materials[1].is_textured());
+    }
+
+    #[test]
+    fn test_load_checkered_specular_map() {
+        let dir = create_test_dir("specular-map");
+        let obj_path = dir.join("model.obj");
+        fs::write(
+            &obj_path,
+            "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\n\
+            usemtl tiles\nf 1/1 2/2 3/3\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("model.mtl"),
+            "newmtl tiles\nKs 1 1 1\nmap_Kd tiles.png\nmap_Ks checker.png\n",
+        )
+        .unwrap();
+        RgbaImage::new(1, 1).save(dir.join("tiles.png")).unwrap();
+        let checker = RgbaImage::from_fn(8, 8, |x, y| {
+            if (x / 4 + y / 4) % 2 == 0 {
+                image::Rgba([255; 4])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            }
+        });
+        checker.save(dir.join("checker.png")).unwrap();
+        let model_and_texture = ModelAndTexture::load(&obj_path).unwrap();
+        assert_eq!(
+            model_and_texture
+                .textures
+                .get("checker.png")
+                .map(|map| &**map),
+            Some(&checker)
+        );
+        // the specular map doesn't need its own permutation
+        assert_eq!(
+            model_and_texture.get_shader_features(),
+            vec![ShaderFeatures::TEXTURE]
+        );
+    }
+
+    #[test]
+    fn test_load_model_with_missing_texture() {
+        let dir = create_test_dir("missing-texture");
+        let obj_path = dir.join("model.obj");
+        fs::write(
+            &obj_path,
+            "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl m\nf 1 2 3\n",
+        )
+        .unwrap();
+        fs::write(dir.join("model.mtl"), "newmtl m\nmap_Kd missing.png\n").unwrap();
+        let e = ModelAndTexture::load(&obj_path).err().unwrap();
+        assert!(
+            matches!(
+                find_error_kind(&e),
+                Some(ErrorKind::AssetNotFound(path)) if path.ends_with("missing.png")
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_flip_normals_twice() {
+        let normals = vec![[0.0, 1.0, 0.0], [0.5, -0.5, 0.25], [-1.0, 0.0, 2.0]];
+        let flipped = negate_normals(&normals);
+        assert_eq!(flipped[1], [-0.5, 0.5, -0.25]);
+        assert_eq!(negate_normals(&flipped), normals);
+    }
+
+    fn candidate(key: &str, triangle_count: usize) -> BatchCandidate<&str> {
+        BatchCandidate {
+            key,
+            triangle_count,
+            vertex_count: triangle_count * 3,
+        }
+    }
+
+    #[test]
+    fn test_plan_batches_without_threshold() {
+        let candidates = vec![candidate("a", 1), candidate("a", 1), candidate("b", 1)];
+        assert_eq!(
+            plan_batches(candidates, None),
+            vec![vec![0], vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn test_plan_batches_merges_small_groups_with_the_same_key() {
+        let candidates = vec![
+            candidate("a", 10),
+            candidate("b", 10),
+            candidate("a", 1000),
+            candidate("a", 10),
+            candidate("b", 10),
+        ];
+        assert_eq!(
+            plan_batches(candidates, Some(100)),
+            vec![vec![2], vec![0, 3], vec![1, 4]]
+        );
+    }
+
+    #[test]
+    fn test_plan_batches_splits_batches_with_too_many_vertices() {
+        let third = MAX_BATCH_VERTICES / 3;
+        let candidates = (0..4)
+            .map(|_| BatchCandidate {
+                key: "a",
+                triangle_count: 1,
+                vertex_count: third,
+            })
+            .collect();
+        assert_eq!(
+            plan_batches(candidates, Some(100)),
+            vec![vec![0, 1, 2], vec![3]]
+        );
+    }
+}
@@ -0,0 +1,360 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::sync::Arc;
+
+use vulkano::{
+    device::Queue,
+    format::FormatDesc,
+    image::{
+        sys::UnsafeImageView,
+        traits::{ImageAccess, ImageViewAccess},
+        Dimensions, ImageLayout, ImageUsage, StorageImage, ViewType,
+    },
+};
+
+use crate::errors::*;
+
+// A single layer of an OffscreenTarget, viewed as a plain 2D image so that it can be added to a
+// framebuffer. The render passes drawing into it should use ImageLayout::General as both the
+// initial and the final layout of the attachment, otherwise the layout transition, which always
+// covers the whole image, may discard the content of the other layers.
+pub struct LayerView<F> {
+    // declared before image, so that the view is destroyed before the image it refers to
+    view: UnsafeImageView,
+    image: Arc<StorageImage<F>>,
+}
+
+unsafe impl<F> ImageViewAccess for LayerView<F>
+where
+    F: 'static + Send + Sync,
+{
+    fn parent(&self) -> &dyn ImageAccess {
+        self.image.as_ref()
+    }
+
+    fn dimensions(&self) -> Dimensions {
+        let dimensions = (*self.image).dimensions();
+        Dimensions::Dim2d {
+            width: dimensions.width(),
+            height: dimensions.height(),
+        }
+    }
+
+    fn inner(&self) -> &UnsafeImageView {
+        &self.view
+    }
+
+    fn descriptor_set_storage_image_layout(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn descriptor_set_combined_image_sampler_layout(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn descriptor_set_sampled_image_layout(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn descriptor_set_input_attachment_layout(&self) -> ImageLayout {
+        ImageLayout::General
+    }
+
+    fn identity_swizzle(&self) -> bool {
+        true
+    }
+}
+
+type ImageAndLayerViews<F> = (Arc<StorageImage<F>>, Vec<Arc<LayerView<F>>>);
+
+// A cube or array image together with one LayerView per layer. The whole image can be sampled
+// through get_image, e.g. as a samplerCube, while every face or layer can be rendered into
+// through get_layer_view.
+pub struct OffscreenTarget<F> {
+    queue: Arc<Queue>,
+    format: F,
+    usage: ImageUsage,
+    image: Arc<StorageImage<F>>,
+    layer_views: Vec<Arc<LayerView<F>>>,
+}
+
+impl<F> OffscreenTarget<F>
+where
+    F: FormatDesc + Clone + Send + Sync + 'static,
+{
+    pub fn cube(queue: Arc<Queue>, format: F, size: u32, usage: ImageUsage) -> Result<Self> {
+        Self::new(queue, format, Dimensions::Cubemap { size }, usage)
+    }
+
+    pub fn array(
+        queue: Arc<Queue>,
+        format: F,
+        [width, height]: [u32; 2],
+        layers: u32,
+        usage: ImageUsage,
+    ) -> Result<Self> {
+        Self::new(
+            queue,
+            format,
+            Dimensions::Dim2dArray {
+                width,
+                height,
+                array_layers: layers,
+            },
+            usage,
+        )
+    }
+
+    fn new(
+        queue: Arc<Queue>,
+        format: F,
+        dimensions: Dimensions,
+        usage: ImageUsage,
+    ) -> Result<Self> {
+        let (image, layer_views) =
+            Self::create_image_and_layer_views(&queue, format.clone(), dimensions, usage)
+                .chain_err(|| "fail to create the image for the offscreen target")?;
+        Ok(Self {
+            queue,
+            format,
+            usage,
+            image,
+            layer_views,
+        })
+    }
+
+    fn create_image_and_layer_views(
+        queue: &Arc<Queue>,
+        format: F,
+        dimensions: Dimensions,
+        usage: ImageUsage,
+    ) -> Result<ImageAndLayerViews<F>> {
+        let image = StorageImage::with_usage(
+            queue.device().clone(),
+            dimensions,
+            format,
+            usage,
+            Some(queue.family()),
+        )
+        .chain_err(|| "fail to create the layered image")?;
+        let layer_views = (0..dimensions.array_layers())
+            .map(|layer| {
+                // safe because the view covers an existing layer and the LayerView keeps the
+                // image alive
+                let view = unsafe {
+                    UnsafeImageView::raw(
+                        ImageAccess::inner(image.as_ref()).image,
+                        ViewType::Dim2d,
+                        0..1,
+                        layer..layer + 1,
+                    )
+                }
+                .chain_err(|| format!("fail to create the image view for layer {}", layer))?;
+                Ok(Arc::new(LayerView {
+                    view,
+                    image: image.clone(),
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((image, layer_views))
+    }
+
+    // Re-create the image with the new size. The views handed out before stay valid, but refer to
+    // the old image, so the framebuffers and the descriptor sets should be re-created with the new
+    // views.
+    pub fn resize(&mut self, [width, height]: [u32; 2]) -> Result<()> {
+        let dimensions = match (*self.image).dimensions() {
+            Dimensions::Cubemap { .. } if width != height => {
+                return Err(format!(
+                    "the faces of a cube target must be square, got {}x{}",
+                    width, height
+                )
+                .into())
+            }
+            Dimensions::Cubemap { .. } => Dimensions::Cubemap { size: width },
+            Dimensions::Dim2dArray { array_layers, .. } => Dimensions::Dim2dArray {
+                width,
+                height,
+                array_layers,
+            },
+            dimensions => unreachable!("unexpected offscreen target dimensions {:?}", dimensions),
+        };
+        let (image, layer_views) = Self::create_image_and_layer_views(
+            &self.queue,
+            self.format.clone(),
+            dimensions,
+            self.usage,
+        )
+        .chain_err(|| "fail to re-create the image for the offscreen target")?;
+        self.image = image;
+        self.layer_views = layer_views;
+        Ok(())
+    }
+
+    pub fn get_image(&self) -> Arc<StorageImage<F>> {
+        self.image.clone()
+    }
+
+    pub fn get_layer_view(&self, layer: u32) -> Option<Arc<LayerView<F>>> {
+        self.layer_views.get(layer as usize).cloned()
+    }
+
+    pub fn get_size(&self) -> [u32; 2] {
+        let dimensions = (*self.image).dimensions();
+        [dimensions.width(), dimensions.height()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vulkano::{
+        buffer::{BufferUsage, CpuAccessibleBuffer},
+        command_buffer::{AutoCommandBufferBuilder, CommandBuffer, SubpassContents},
+        format::{ClearValue, Format, R8G8B8A8Unorm},
+        framebuffer::Framebuffer,
+        sync::GpuFuture,
+    };
+
+    use crate::gpu_test::create_queue;
+
+    #[test]
+    fn test_render_into_each_cube_face() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let device = queue.device().clone();
+        const SIZE: u32 = 4;
+        let target = OffscreenTarget::cube(
+            queue.clone(),
+            R8G8B8A8Unorm,
+            SIZE,
+            ImageUsage {
+                color_attachment: true,
+                transfer_source: true,
+                ..ImageUsage::none()
+            },
+        )
+        .unwrap();
+        assert!(target.get_layer_view(5).is_some());
+        assert!(target.get_layer_view(6).is_none());
+
+        let render_pass = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::R8G8B8A8Unorm,
+                        samples: 1,
+                        initial_layout: ImageLayout::General,
+                        final_layout: ImageLayout::General,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {}
+                }
+            )
+            .unwrap(),
+        );
+        let face_colors: Vec<[u8; 4]> = (0..6u8).map(|i| [i * 40, 255 - i * 40, i, 255]).collect();
+        let readback = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_destination(),
+            false,
+            (0..SIZE * SIZE * 6).map(|_| [0u8; 4]),
+        )
+        .unwrap();
+        let mut cmd_buf_builder =
+            AutoCommandBufferBuilder::primary_one_time_submit(device, queue.family()).unwrap();
+        for (layer, face_color) in face_colors.iter().enumerate() {
+            let framebuffer = Arc::new(
+                Framebuffer::start(render_pass.clone())
+                    .add(target.get_layer_view(layer as u32).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            let [r, g, b, a] = *face_color;
+            cmd_buf_builder
+                .begin_render_pass(
+                    framebuffer,
+                    SubpassContents::Inline,
+                    vec![ClearValue::Float([
+                        r as f32 / 255.0,
+                        g as f32 / 255.0,
+                        b as f32 / 255.0,
+                        a as f32 / 255.0,
+                    ])],
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+        }
+        cmd_buf_builder
+            .copy_image_to_buffer_dimensions(
+                target.get_image(),
+                readback.clone(),
+                [0, 0, 0],
+                [SIZE, SIZE, 1],
+                0,
+                6,
+                0,
+            )
+            .unwrap();
+        cmd_buf_builder
+            .build()
+            .unwrap()
+            .execute(queue)
+            .unwrap()
+            .then_signal_fence_and_flush()
+            .unwrap()
+            .wait(None)
+            .unwrap();
+
+        let pixels = readback.read().unwrap();
+        for (layer, face_color) in face_colors.iter().enumerate() {
+            let face_size = (SIZE * SIZE) as usize;
+            for pixel in pixels[layer * face_size..(layer + 1) * face_size].iter() {
+                assert_eq!(pixel, face_color);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resize_keeps_the_layer_count() {
+        let queue = match create_queue() {
+            Some(queue) => queue,
+            None => {
+                eprintln!("no Vulkan device available, skip the test");
+                return;
+            }
+        };
+        let usage = ImageUsage {
+            color_attachment: true,
+            ..ImageUsage::none()
+        };
+        let mut target =
+            OffscreenTarget::array(queue.clone(), R8G8B8A8Unorm, [8, 4], 3, usage).unwrap();
+        target.resize([16, 8]).unwrap();
+        assert_eq!(target.get_size(), [16, 8]);
+        assert!(target.get_layer_view(2).is_some());
+        assert!(target.get_layer_view(3).is_none());
+
+        let mut cube = OffscreenTarget::cube(queue, R8G8B8A8Unorm, 8, usage).unwrap();
+        assert!(cube.resize([16, 8]).is_err());
+        cube.resize([16, 16]).unwrap();
+        assert!(cube.get_layer_view(5).is_some());
+        assert!(cube.get_layer_view(6).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::collections::{BTreeMap, HashMap};
+
+use image::{Rgba, RgbaImage};
+use vulkano::{
+    image::ImageCreationError, memory::DeviceMemoryAllocError, sync::FlushError, OomError,
+};
+
+use super::{material_desc::ObjectMaterial, ImportOptions, ModelAndTexture};
+use crate::{errors::*, scene::upload::Upload};
+
+// Whether creating a resource failed for the lack of memory somewhere in the chain, which may
+// succeed once memory is freed, unlike the other errors.
+pub fn is_out_of_memory(e: &Error) -> bool {
+    let mut current = Some(e);
+    while let Some(e) = current {
+        let next = match e.1.next_error.as_ref() {
+            Some(next) => next,
+            None => return false,
+        };
+        if next.is::<DeviceMemoryAllocError>()
+            || next.is::<OomError>()
+            || matches!(
+                next.downcast_ref::<ImageCreationError>(),
+                Some(ImageCreationError::AllocError(_))
+            )
+            || matches!(
+                next.downcast_ref::<FlushError>(),
+                Some(FlushError::OomError(_))
+            )
+        {
+            return true;
+        }
+        current = next.downcast_ref::<Error>();
+    }
+    false
+}
+
+// the size of the squares of the placeholder texture in texels
+const CHECKER_SIZE: u32 = 8;
+
+// sampled instead of the textures that failed to be allocated, small enough to fit anyway
+pub fn create_checkerboard() -> RgbaImage {
+    RgbaImage::from_fn(CHECKER_SIZE * 8, CHECKER_SIZE * 8, |x, y| {
+        if (x / CHECKER_SIZE + y / CHECKER_SIZE) & 1 == 0 {
+            Rgba([255, 0, 255, 255])
+        } else {
+            Rgba([32, 32, 32, 255])
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FailedResource {
+    // the objects using the material are drawn with a checkerboard instead
+    Texture { material: String },
+    // the object of the groups isn't drawn
+    Mesh { groups: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceFailure {
+    pub resource: FailedResource,
+    // the root cause, e.g. out of device memory
+    pub error: String,
+}
+
+impl ResourceFailure {
+    pub fn new(resource: FailedResource, e: &Error) -> Self {
+        Self {
+            resource,
+            error: e
+                .iter()
+                .last()
+                .map_or_else(|| e.to_string(), |e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchObject {
+    // not created yet, or failed to be
+    Missing,
+    // the index of the object drawn with the checkerboard instead of the texture of its material
+    Placeholder(usize),
+    Created(usize),
+}
+
+// What's needed to create the resources of a model again, kept while some of them have failed to
+// be allocated. The batches are planned the same way every time.
+pub struct ModelLoad {
+    pub model_and_texture: ModelAndTexture,
+    pub batch_threshold: Option<usize>,
+    pub flip_normals: bool,
+    pub import_options: ImportOptions,
+    // keyed by name, the created ones aren't uploaded again
+    pub materials: HashMap<String, (ObjectMaterial, Option<Upload>)>,
+    pub material_failures: BTreeMap<String, ResourceFailure>,
+    // empty until the batches are planned
+    pub batches: Vec<BatchObject>,
+    pub batch_failures: BTreeMap<usize, ResourceFailure>,
+    // the warnings of the resolved materials, see MaterialDesc
+    pub material_warnings: Vec<String>,
+}
+
+impl ModelLoad {
+    pub fn new(
+        model_and_texture: ModelAndTexture,
+        batch_threshold: Option<usize>,
+        flip_normals: bool,
+        import_options: ImportOptions,
+    ) -> Self {
+        Self {
+            model_and_texture,
+            batch_threshold,
+            flip_normals,
+            import_options,
+            materials: Default::default(),
+            material_failures: Default::default(),
+            batches: vec![],
+            batch_failures: Default::default(),
+            material_warnings: vec![],
+        }
+    }
+
+    pub fn get_failures(&self) -> impl Iterator<Item = &ResourceFailure> {
+        self.material_failures
+            .values()
+            .chain(self.batch_failures.values())
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.get_failures().next().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_out_of_memory_through_the_chain() {
+        let e: Result<()> = Err(DeviceMemoryAllocError::OomError(
+            OomError::OutOfDeviceMemory,
+        ))
+        .chain_err(|| "fail to create the vertex buffer")
+        .chain_err(|| "fail to create mesh");
+        let e = e.unwrap_err();
+        assert!(is_out_of_memory(&e));
+        let failure = ResourceFailure::new(
+            FailedResource::Mesh {
+                groups: vec!["a".to_owned()],
+            },
+            &e,
+        );
+        assert_eq!(
+            failure.error,
+            DeviceMemoryAllocError::OomError(OomError::OutOfDeviceMemory).to_string()
+        );
+
+        let e: Result<()> = Err(ImageCreationError::AllocError(
+            DeviceMemoryAllocError::TooManyObjects,
+        ))
+        .chain_err(|| "fail to create texture for the texture");
+        assert!(is_out_of_memory(&e.unwrap_err()));
+
+        let e: Result<()> = Err(ImageCreationError::UnsupportedUsage)
+            .chain_err(|| "fail to create texture for the texture");
+        assert!(!is_out_of_memory(&e.unwrap_err()));
+        let e: Error = ErrorKind::GpuResource("no memory type".to_owned()).into();
+        assert!(!is_out_of_memory(&e));
+    }
+
+    #[test]
+    fn test_checkerboard() {
+        let checkerboard = create_checkerboard();
+        assert_eq!(checkerboard.get_pixel(0, 0), &Rgba([255, 0, 255, 255]));
+        assert_eq!(
+            checkerboard.get_pixel(CHECKER_SIZE, 0),
+            &Rgba([32, 32, 32, 255])
+        );
+        assert_eq!(
+            checkerboard.get_pixel(CHECKER_SIZE, CHECKER_SIZE),
+            &Rgba([255, 0, 255, 255])
+        );
+    }
+}
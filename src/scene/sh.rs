@@ -0,0 +1,236 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+// The diffuse lighting from an environment map as the order 3 spherical harmonics, the bands 0, 1
+// and 2 (Ramamoorthi and Hanrahan 2001). The radiance is projected on the CPU once per environment
+// map, and convolved with the clamped cosine. The irradiance is then baked into a small cubemap,
+// so the shaders sample it once per fragment instead of integrating the environment.
+
+use std::f32::consts::PI;
+
+use image::RgbaImage;
+
+use super::color::{linear_to_srgb, srgb_to_linear};
+
+pub const SH_COEFFICIENT_COUNT: usize = 9;
+
+// the RGB coefficients in the order of sh_basis
+pub type ShCoefficients = [[f32; 3]; SH_COEFFICIENT_COUNT];
+
+// the real SH basis in the normalized direction, Y00, Y1-1, Y10, Y11, Y2-2, Y2-1, Y20, Y21, Y22
+fn sh_basis([x, y, z]: [f32; 3]) -> [f32; SH_COEFFICIENT_COUNT] {
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+// the texel center in [-1, 1] on the face
+fn face_coords(size: u32, x: u32, y: u32) -> [f32; 2] {
+    [
+        (x as f32 + 0.5) / size as f32 * 2.0 - 1.0,
+        (y as f32 + 0.5) / size as f32 * 2.0 - 1.0,
+    ]
+}
+
+// the normalized direction through the center of the texel of the face, the layers of a cube image
+// in the order +x, -x, +y, -y, +z and -z, with the rows from the top as the Vulkan cube map lookup
+fn cubemap_direction(face: usize, size: u32, x: u32, y: u32) -> [f32; 3] {
+    let [s, t] = face_coords(size, x, y);
+    let [dx, dy, dz] = match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    };
+    let length = (dx * dx + dy * dy + dz * dz).sqrt();
+    [dx / length, dy / length, dz / length]
+}
+
+// Integrates the radiance of the texels of the six faces of the size times the basis. Every texel
+// is weighted by the solid angle it covers, and the weights are normalized to the whole sphere.
+fn project_radiance(size: u32, radiance: impl Fn(usize, u32, u32) -> [f32; 3]) -> ShCoefficients {
+    let mut coefficients = ShCoefficients::default();
+    let mut total_weight = 0.0;
+    for face in 0..6 {
+        for y in 0..size {
+            for x in 0..size {
+                let [s, t] = face_coords(size, x, y);
+                let weight = (1.0 + s * s + t * t).powf(-1.5);
+                total_weight += weight;
+                let color = radiance(face, x, y);
+                let basis = sh_basis(cubemap_direction(face, size, x, y));
+                for (coefficient, basis) in coefficients.iter_mut().zip(basis.iter()) {
+                    for (c, color) in coefficient.iter_mut().zip(color.iter()) {
+                        *c += color * basis * weight;
+                    }
+                }
+            }
+        }
+    }
+    let scale = 4.0 * PI / total_weight;
+    for c in coefficients.iter_mut().flatten() {
+        *c *= scale;
+    }
+    coefficients
+}
+
+// The radiance of the skybox faces, which should be squares of the same size. The texels are sRGB
+// like the other images of the scene, and converted to linear before the projection.
+pub fn project_cubemap(faces: &[RgbaImage]) -> ShCoefficients {
+    let size = faces.first().map_or(0, |face| face.width());
+    let to_linear: Vec<_> = (0..=255u8)
+        .map(|c| srgb_to_linear([c as f32 / 255.0; 3])[0])
+        .collect();
+    project_radiance(size, |face, x, y| {
+        let [r, g, b, _] = faces[face].get_pixel(x, y).0;
+        [
+            to_linear[r as usize],
+            to_linear[g as usize],
+            to_linear[b as usize],
+        ]
+    })
+}
+
+// the irradiance around the normal from the radiance, the bands scaled by the clamped cosine lobe
+pub fn irradiance_from_radiance(radiance: &ShCoefficients) -> ShCoefficients {
+    const BAND_SCALES: [f32; SH_COEFFICIENT_COUNT] = [
+        PI,
+        2.0 * PI / 3.0,
+        2.0 * PI / 3.0,
+        2.0 * PI / 3.0,
+        PI / 4.0,
+        PI / 4.0,
+        PI / 4.0,
+        PI / 4.0,
+        PI / 4.0,
+    ];
+    let mut irradiance = *radiance;
+    for (coefficient, scale) in irradiance.iter_mut().zip(BAND_SCALES.iter()) {
+        for c in coefficient.iter_mut() {
+            *c *= scale;
+        }
+    }
+    irradiance
+}
+
+// the value of the function in the normalized direction
+fn eval_sh(coefficients: &ShCoefficients, direction: [f32; 3]) -> [f32; 3] {
+    let mut value = [0.0; 3];
+    for (coefficient, basis) in coefficients.iter().zip(sh_basis(direction).iter()) {
+        for (v, c) in value.iter_mut().zip(coefficient.iter()) {
+            *v += c * basis;
+        }
+    }
+    value
+}
+
+// The sRGB texels of the layers of a cube image of the size with the irradiance over pi, what the
+// shaders multiply by the albedo for the Lambertian radiance. The environment is an LDR image, so
+// it fits in [0, 1] but for the ringing of the truncated series, which is clamped.
+pub fn irradiance_cubemap_texels(irradiance: &ShCoefficients, size: u32) -> Vec<[u8; 4]> {
+    let mut texels = Vec::with_capacity(6 * (size * size) as usize);
+    for face in 0..6 {
+        for y in 0..size {
+            for x in 0..size {
+                let [r, g, b] = eval_sh(irradiance, cubemap_direction(face, size, x, y));
+                let clamp = |c: f32| (c / PI).clamp(0.0, 1.0);
+                let [r, g, b] = linear_to_srgb([clamp(r), clamp(g), clamp(b)]);
+                let to_u8 = |c: f32| (c * 255.0).round() as u8;
+                texels.push([to_u8(r), to_u8(g), to_u8(b), 255]);
+            }
+        }
+    }
+    texels
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::approxeq::ApproxEq;
+
+    use super::*;
+
+    #[test]
+    fn test_cubemap_direction() {
+        let centers = [
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, -1.0],
+        ];
+        for (face, center) in centers.iter().enumerate() {
+            let direction = cubemap_direction(face, 1, 0, 0);
+            for (d, c) in direction.iter().zip(center.iter()) {
+                assert!(d.approx_eq(c), "{}: {:?}", face, direction);
+            }
+        }
+        // the top left texel of +z looks towards -x and +y
+        let [x, y, z] = cubemap_direction(4, 2, 0, 0);
+        assert!(x < 0.0 && y > 0.0 && z > 0.0);
+    }
+
+    #[test]
+    fn test_basis_is_orthonormal() {
+        for i in 0..SH_COEFFICIENT_COUNT {
+            let coefficients = project_radiance(32, |face, x, y| {
+                [sh_basis(cubemap_direction(face, 32, x, y))[i]; 3]
+            });
+            for (j, [c, _, _]) in coefficients.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!(c.approx_eq_eps(&expected, &0.01), "{} {}: {}", i, j, c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_uniform_environment() {
+        let faces = vec![RgbaImage::from_pixel(4, 4, image::Rgba([255; 4])); 6];
+        let irradiance = irradiance_from_radiance(&project_cubemap(&faces));
+        // a white surface under a white sky reflects all of it
+        for direction in &[[0.0, 1.0, 0.0], [0.6, 0.0, -0.8], [0.0, -1.0, 0.0]] {
+            for e in eval_sh(&irradiance, *direction).iter() {
+                assert!(e.approx_eq_eps(&PI, &1e-3), "{}", e);
+            }
+        }
+        // the irradiance over pi is the albedo of the surface
+        let texels = irradiance_cubemap_texels(&irradiance, 2);
+        assert_eq!(texels, vec![[255; 4]; 6 * 2 * 2]);
+    }
+
+    #[test]
+    fn test_sky_above_a_black_ground() {
+        let mut faces = vec![RgbaImage::new(8, 8); 6];
+        faces[2] = RgbaImage::from_pixel(8, 8, image::Rgba([255, 128, 0, 255]));
+        let irradiance = irradiance_from_radiance(&project_cubemap(&faces));
+        let up = eval_sh(&irradiance, [0.0, 1.0, 0.0]);
+        let side = eval_sh(&irradiance, [1.0, 0.0, 0.0]);
+        let other_side = eval_sh(&irradiance, [0.0, 0.0, -1.0]);
+        let down = eval_sh(&irradiance, [0.0, -1.0, 0.0]);
+        assert!(up[0] > side[0] && side[0] > down[0]);
+        assert!(side[0].approx_eq_eps(&other_side[0], &1e-3));
+        // the colors are linear, sRGB 0.5 is about 21.4% of the light
+        assert!((up[1] / up[0]).approx_eq_eps(&0.216, &1e-2));
+        assert_eq!(up[2], 0.0);
+        // the +y layer is the sky side
+        let texels = irradiance_cubemap_texels(&irradiance, 2);
+        assert!(texels[2 * 4][0] > texels[0][0] && texels[0][0] > texels[3 * 4][0]);
+        assert!(
+            texels[3 * 4].iter().take(3).all(|c| *c < 8),
+            "{:?}",
+            texels[3 * 4]
+        );
+    }
+}
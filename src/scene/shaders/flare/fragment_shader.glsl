@@ -0,0 +1,33 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+  mat4 model;
+  mat4 view;
+  mat4 proj;
+  mat4 inv_proj;
+  vec4 color;
+  vec4 viewport;
+  float size;
+}
+ubo;
+
+layout(binding = 1) uniform sampler2D flare_texture;
+layout(binding = 2) uniform sampler2D scene_depth;
+
+layout(location = 0) in vec2 v_texture_coord;
+layout(location = 1) in vec3 v_view_position;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  vec2 uv = gl_FragCoord.xy / ubo.viewport.xy;
+  float depth = texture(scene_depth, uv).r;
+  float ndc_depth = (depth - ubo.viewport.z) / (ubo.viewport.w - ubo.viewport.z);
+  vec4 scene_position = ubo.inv_proj * vec4(uv * 2.0 - 1.0, ndc_depth, 1.0);
+  float scene_distance = length(scene_position.xyz / scene_position.w);
+  // fade out where the geometry behind is closer than the radius, instead of clipping hard
+  float fade = clamp((scene_distance - length(v_view_position)) / ubo.size, 0.0, 1.0);
+  vec4 texel = texture(flare_texture, v_texture_coord);
+  // the alpha of the attachment is kept by the additive blending
+  f_color = vec4(ubo.color.rgb * texel.rgb * texel.a * fade, 0.0);
+}
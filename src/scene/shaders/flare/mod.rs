@@ -0,0 +1,76 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use euclid::default::Transform3D;
+
+use super::super::material::SetCamera;
+use crate::impl_shaders;
+
+pub mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/scene/shaders/flare/vertex_shader.glsl",
+    }
+}
+
+pub mod fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/scene/shaders/flare/fragment_shader.glsl",
+    }
+}
+
+fn __() {
+    let _ = include_bytes!("fragment_shader.glsl");
+    let _ = include_bytes!("vertex_shader.glsl");
+}
+
+impl_shaders!(Shaders, vertex_shader, fragment_shader, {
+    uniform: {
+        layout: 0,
+        ty: "buffer",
+        def: {
+            pub model: [f32; 16],
+            pub view: [f32; 16],
+            pub proj: [f32; 16],
+            // to reconstruct the view space positions from the scene depth
+            pub inv_proj: [f32; 16],
+            pub color: [f32; 4],
+            // the width and the height of the viewport, then its depth range
+            pub viewport: [f32; 4],
+            // the radius of the sprite in the world space
+            pub size: f32,
+        },
+    },
+    flare_texture: {
+        layout: 1,
+        ty: "texture",
+    },
+    scene_depth: {
+        layout: 2,
+        ty: "texture",
+    },
+});
+
+impl SetCamera for ShadersUniforms {
+    fn set_model_matrix(&mut self, mat: [f32; 16]) {
+        self.uniform.model.copy_from_slice(&mat);
+    }
+
+    fn set_view_matrix(&mut self, mat: [f32; 16]) {
+        self.uniform.view.copy_from_slice(&mat);
+    }
+
+    fn set_proj_matrix(&mut self, mat: [f32; 16]) {
+        self.uniform.proj.copy_from_slice(&mat);
+        let inv_proj = Transform3D::from_array(mat)
+            .inverse()
+            .expect("the projection of the camera should be invertible");
+        self.uniform.inv_proj.copy_from_slice(&inv_proj.to_array());
+    }
+
+    // the sprite always faces the camera, so it has no normals to transform
+    fn set_normal_matrix(&mut self, _mat: [f32; 16]) {}
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+  mat4 model;
+  mat4 view;
+  mat4 proj;
+  mat4 inv_proj;
+  vec4 color;
+  vec4 viewport;
+  float size;
+}
+ubo;
+
+layout(location = 0) in vec2 corner;
+
+layout(location = 0) out vec2 v_texture_coord;
+layout(location = 1) out vec3 v_view_position;
+
+void main() {
+  vec4 center = ubo.view * ubo.model * vec4(0.0, 0.0, 0.0, 1.0);
+  // the quad faces the camera, and is moved towards it by the radius, so that the light marker
+  // inside doesn't fade the sprite out
+  vec3 position = center.xyz / center.w + vec3(corner * ubo.size, ubo.size);
+  v_texture_coord = corner * 0.5 + 0.5;
+  v_view_position = position;
+  gl_Position = ubo.proj * vec4(position, 1.0);
+}
@@ -0,0 +1,65 @@
+#version 450
+
+#define HISTOGRAM_BINS 256
+
+// a bin per invocation when merging the workgroup histogram
+layout(local_size_x = 16, local_size_y = 16) in;
+
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+layout(set = 0, binding = 1) buffer Histogram {
+  uint bins[HISTOGRAM_BINS];
+  uint clipped_count;
+  // the bits of the non negative luminance, which are ordered the same as the floats
+  uint min_luminance;
+  uint max_luminance;
+}
+histogram;
+
+layout(push_constant) uniform PushConstants {
+  // whether the scene is encoded with a gamma of 2.2, see Renderer::set_gamma_correction
+  uint gamma_correction;
+}
+push_constants;
+
+shared uint local_bins[HISTOGRAM_BINS];
+shared uint local_clipped_count;
+shared uint local_min_luminance;
+shared uint local_max_luminance;
+
+void main() {
+  uint index = gl_LocalInvocationIndex;
+  local_bins[index] = 0;
+  if (index == 0) {
+    local_clipped_count = 0;
+    local_min_luminance = 0xffffffff;
+    local_max_luminance = 0;
+  }
+  barrier();
+
+  ivec2 size = textureSize(scene, 0);
+  ivec2 position = ivec2(gl_GlobalInvocationID.xy);
+  if (position.x < size.x && position.y < size.y) {
+    vec3 encoded = texelFetch(scene, position, 0).rgb;
+    vec3 color = push_constants.gamma_correction != 0 ? pow(encoded, vec3(2.2)) : encoded;
+    float luminance = clamp(dot(color, vec3(0.2126, 0.7152, 0.0722)), 0.0, 1.0);
+    uint bin = min(uint(luminance * HISTOGRAM_BINS), uint(HISTOGRAM_BINS - 1));
+    atomicAdd(local_bins[bin], 1);
+    // saturated in the 8 bit target
+    if (max(encoded.r, max(encoded.g, encoded.b)) >= 1.0 - 0.5 / 255.0) {
+      atomicAdd(local_clipped_count, 1);
+    }
+    atomicMin(local_min_luminance, floatBitsToUint(luminance));
+    atomicMax(local_max_luminance, floatBitsToUint(luminance));
+  }
+  barrier();
+
+  if (local_bins[index] > 0) {
+    atomicAdd(histogram.bins[index], local_bins[index]);
+  }
+  if (index == 0) {
+    atomicAdd(histogram.clipped_count, local_clipped_count);
+    atomicMin(histogram.min_luminance, local_min_luminance);
+    atomicMax(histogram.max_luminance, local_max_luminance);
+  }
+}
@@ -0,0 +1,15 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+pub mod compute_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/scene/shaders/histogram/compute_shader.glsl",
+    }
+}
+
+fn __() {
+    let _ = include_bytes!("compute_shader.glsl");
+}
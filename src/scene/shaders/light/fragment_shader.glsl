@@ -4,11 +4,24 @@ layout(binding = 0) uniform UniformBufferObject {
   mat4 model;
   mat4 view;
   mat4 proj;
-  float light_intensity;
   vec4 light_color;
+  float light_intensity;
+  // output the color as is, so that the light looks the same whatever the intensity is
+  uint unlit;
+  // encodes the output like the phong shaders, so that the marker matches the lit objects
+  uint gamma_correction;
 }
 ubo;
 
 layout(location = 0) out vec4 f_color;
 
-void main() { f_color = vec4(ubo.light_color.xyz, 1.0); }
+void main() {
+  vec3 color = ubo.light_color.xyz;
+  if (ubo.unlit == 0) {
+    color *= ubo.light_intensity;
+  }
+  if (ubo.gamma_correction != 0) {
+    color = pow(color, vec3(1.0 / 2.2));
+  }
+  f_color = vec4(color, 1.0);
+}
@@ -33,8 +33,11 @@ impl_shaders!(Shaders, vertex_shader, fragment_shader, {
             pub model: [f32; 16],
             pub view: [f32; 16],
             pub proj: [f32; 16],
-            pub light_intensity: f32,
+            // declared before light_intensity to match the std140 layout of the shaders
             pub light_color: [f32; 4],
+            pub light_intensity: f32,
+            pub unlit: u32,
+            pub gamma_correction: u32,
         },
     },
 });
@@ -51,4 +54,7 @@ impl SetCamera for ShadersUniforms {
     fn set_proj_matrix(&mut self, mat: [f32; 16]) {
         self.uniform.proj.copy_from_slice(&mat);
     }
+
+    // the light marker isn't shaded, so it has no normals to transform
+    fn set_normal_matrix(&mut self, _mat: [f32; 16]) {}
 }
@@ -4,8 +4,10 @@ layout(binding = 0) uniform UniformBufferObject {
   mat4 model;
   mat4 view;
   mat4 proj;
-  float light_intensity;
   vec4 light_color;
+  float light_intensity;
+  uint unlit;
+  uint gamma_correction;
 }
 ubo;
 
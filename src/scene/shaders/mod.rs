@@ -1,16 +1,21 @@
+pub mod flare;
+pub mod histogram;
 pub mod light;
 pub mod phong;
+pub mod shadow;
+pub mod skybox;
 
-use std::sync::Arc;
+use std::{fmt::Write, sync::Arc};
 
 use vulkano::{
     command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
     descriptor::pipeline_layout::PipelineLayoutDesc,
     descriptor::{descriptor_set::DescriptorSet, pipeline_layout::PipelineLayoutAbstract},
     device::Device,
-    format::R8G8B8A8Unorm,
-    image::immutable::ImmutableImage,
-    pipeline::shader::{GraphicsEntryPoint, ShaderInterfaceDef},
+    image::traits::ImageViewAccess,
+    pipeline::shader::{
+        EntryPointAbstract, GraphicsEntryPoint, GraphicsEntryPointAbstract, ShaderInterfaceDef,
+    },
     sampler::Sampler,
 };
 
@@ -55,12 +60,103 @@ pub trait ShadersT: Sized {
     >;
 }
 
+// any image that can be sampled, e.g. the depth attachment of an earlier pass
 #[derive(Clone)]
 pub struct Texture {
-    pub image: Arc<ImmutableImage<R8G8B8A8Unorm>>,
+    pub image: Arc<dyn ImageViewAccess + Send + Sync>,
     pub sampler: Arc<Sampler>,
 }
 
+fn describe_interface(description: &mut String, title: &str, interface: &impl ShaderInterfaceDef) {
+    writeln!(description, "    {}:", title).unwrap();
+    for element in interface.elements() {
+        writeln!(
+            description,
+            "      location {:?}: {:?} {}",
+            element.location,
+            element.format,
+            element.name.as_deref().unwrap_or("<unnamed>")
+        )
+        .unwrap();
+    }
+}
+
+fn describe_layout(description: &mut String, layout: &impl PipelineLayoutDesc) {
+    writeln!(description, "    descriptors:").unwrap();
+    for set in 0..layout.num_sets() {
+        for binding in 0..layout.num_bindings_in_set(set).unwrap_or(0) {
+            match layout.descriptor(set, binding) {
+                Some(descriptor) => writeln!(
+                    description,
+                    "      set {} binding {}: {:?} x {}, readonly: {}",
+                    set, binding, descriptor.ty, descriptor.array_count, descriptor.readonly
+                ),
+                None => writeln!(description, "      set {} binding {}: empty", set, binding),
+            }
+            .unwrap();
+        }
+    }
+    for i in 0..layout.num_push_constants_ranges() {
+        if let Some(range) = layout.push_constants_range(i) {
+            writeln!(
+                description,
+                "      push constants {}: offset {}, size {}",
+                i, range.offset, range.size
+            )
+            .unwrap();
+        }
+    }
+}
+
+// The inputs, the outputs and the descriptor bindings of both stages as reflected from the
+// SPIR-V, which is what the pipeline layout is checked against, unlike define_uniforms!.
+pub fn describe_shaders<S: ShadersT>(name: &str, shaders: &S) -> String {
+    let mut description = format!("{}:\n", name);
+    let vertex_shader = shaders.vertex_shader_main_entry_point();
+    writeln!(description, "  vertex shader:").unwrap();
+    describe_interface(&mut description, "inputs", vertex_shader.input());
+    describe_interface(&mut description, "outputs", vertex_shader.output());
+    describe_layout(&mut description, vertex_shader.layout());
+    let fragment_shader = shaders.fragment_shader_main_entry_point();
+    writeln!(description, "  fragment shader:").unwrap();
+    describe_interface(&mut description, "inputs", fragment_shader.input());
+    describe_interface(&mut description, "outputs", fragment_shader.output());
+    describe_layout(&mut description, fragment_shader.layout());
+    description
+}
+
+// Print the reflection of every shader used by the renderer.
+pub fn dump_shader_reflection(device: Arc<Device>) -> Result<()> {
+    let light_shaders =
+        light::Shaders::load(device.clone()).chain_err(|| "fail to load the light shaders")?;
+    print!("{}", describe_shaders("light", &light_shaders));
+    let with_texture_shaders = phong::with_texture::Shaders::load(device.clone())
+        .chain_err(|| "fail to load the phong shaders with texture")?;
+    print!(
+        "{}",
+        describe_shaders("phong with texture", &with_texture_shaders)
+    );
+    let normal_map_shaders = phong::with_normal_map::Shaders::load(device.clone())
+        .chain_err(|| "fail to load the phong shaders with normal map")?;
+    print!(
+        "{}",
+        describe_shaders("phong with normal map", &normal_map_shaders)
+    );
+    let no_texture_shaders = phong::no_texture::Shaders::load(device.clone())
+        .chain_err(|| "fail to load the phong shaders without texture")?;
+    print!(
+        "{}",
+        describe_shaders("phong without texture", &no_texture_shaders)
+    );
+    let flare_shaders =
+        flare::Shaders::load(device.clone()).chain_err(|| "fail to load the flare shaders")?;
+    print!("{}", describe_shaders("flare", &flare_shaders));
+    let skybox_shaders =
+        skybox::Shaders::load(device).chain_err(|| "fail to load the skybox shaders")?;
+    print!("{}", describe_shaders("skybox", &skybox_shaders));
+    Ok(())
+}
+
 #[macro_export]
 macro_rules! define_uniforms {
     ($uniforms_name:ident, {
@@ -85,6 +181,17 @@ macro_rules! define_uniforms {
 macro_rules! uniform_defs_to_struct_defs {
     (@ {} ()) => ();
 
+    // the struct defined elsewhere, e.g. shared by the permutations of a shader
+    (@ {
+        $field_name:ident : {layout: $layout:expr, ty: "buffer", def: ($($shared:tt)*),},
+        $($rest:tt)*
+    } ()) => (
+        ::paste::paste! {
+            pub use $($shared)* as [<$field_name:camel>];
+        }
+        $crate::uniform_defs_to_struct_defs!(@ { $($rest)* } ());
+    );
+
     (@ {
         $field_name:ident : {layout: $layout:expr, ty: "buffer", def: $def:tt,},
         $($rest:tt)*
@@ -149,6 +256,9 @@ macro_rules! impl_uniforms {
     (@ $uniforms_name:ident, $device:ident, $queue:ident, {} -> (($($new_sig:tt)*), ($($self_init:tt)*))) => (
         ::paste::paste! {
             impl $uniforms_name {
+                // one argument per binding of the shader after the device and the queue, so the
+                // permutations with more textures take more than clippy's default limit
+                #[allow(clippy::too_many_arguments)]
                 pub fn new($($new_sig)*) -> $crate::errors::Result<Self> {
                     use $crate::errors::*;
                     Ok(Self {
@@ -163,6 +273,17 @@ macro_rules! impl_uniforms {
         $field_name:ident : { layout: $layout:expr, ty: "buffer", def: $def:tt, },
         $($rest:tt)*
     } -> (($($new_sig:tt)*), ($($self_init:tt)*))) => (
+        ::paste::paste! {
+            // to read the buffer back in the tests
+            #[cfg(test)]
+            impl $uniforms_name {
+                pub fn [<get_ $field_name _buffer>](&self) -> ::std::sync::Arc<
+                    ::vulkano::buffer::device_local::DeviceLocalBuffer<[<$field_name:camel>]>
+                > {
+                    self.[<$field_name _buffer>].clone()
+                }
+            }
+        }
         $crate::impl_uniforms!(@ $uniforms_name, $device, $queue, { $($rest)* } -> ((
             $($new_sig)*
             $field_name: [<$field_name:camel>],
@@ -171,7 +292,11 @@ macro_rules! impl_uniforms {
             $field_name,
             [<$field_name _buffer>]: ::vulkano::buffer::device_local::DeviceLocalBuffer::new(
                 $device.clone(),
-                ::vulkano::buffer::BufferUsage::uniform_buffer_transfer_destination(),
+                // the transfer source to read the uniforms back
+                ::vulkano::buffer::BufferUsage {
+                    transfer_source: true,
+                    ..::vulkano::buffer::BufferUsage::uniform_buffer_transfer_destination()
+                },
                 vec![$queue.family()],
             ).chain_err(|| {
                 format!(
@@ -388,3 +513,27 @@ macro_rules! impl_shaders {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vulkano::descriptor::descriptor::ShaderStages;
+
+    #[test]
+    fn test_describe_the_reflected_light_shaders() {
+        let mut description = String::new();
+        describe_interface(&mut description, "inputs", &light::vertex_shader::MainInput);
+        describe_layout(
+            &mut description,
+            &light::vertex_shader::Layout(ShaderStages::all()),
+        );
+        assert!(description.contains("inputs:"), "{}", description);
+        assert!(
+            description.contains("R32G32B32A32Sfloat position"),
+            "{}",
+            description
+        );
+        assert!(description.contains("set 0 binding 0"), "{}", description);
+    }
+}
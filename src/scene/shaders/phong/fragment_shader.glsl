@@ -1,15 +1,83 @@
 #version 450
 
+// MAX_POINT_LIGHTS in light.rs
+#define MAX_POINT_LIGHTS 8
+// POISSON_DISK_SIZE and ROTATION_NOISE_SIZE in noise.rs
+#define POISSON_DISK_SIZE 32
+#define ROTATION_NOISE_SIZE 4
+
 layout(binding = 1) uniform UniformBufferObject {
   vec4 kd;
   vec4 ks;
-  vec4 light_pos;
+  vec4 light_positions[MAX_POINT_LIGHTS];
+  // the colors of the point lights times their intensities
+  vec4 light_colors[MAX_POINT_LIGHTS];
   vec4 camera_pos;
-  float light_intensity;
+  // the colors are premultiplied by the intensity of the hemisphere ambient
+  vec4 ambient_sky_color;
+  vec4 ambient_ground_color;
+  vec4 ambient_up;
+  // the normalized direction towards the directional light, and its color times the intensity
+  vec4 sun_direction;
+  vec4 sun_color;
+  // from the world space to the clip space of the first point light, see ShadowMap
+  mat4 light_space;
+  // the rows of the affine transform of the texture coordinates, see UvAnimation, unused without
+  // the texture but kept so that all the permutations share the layout
+  vec4 uv_transform_u;
+  vec4 uv_transform_v;
+  // the offsets of the PCF taps in the unit disk, two in each element, see NoiseResources
+  vec4 poisson_disk[POISSON_DISK_SIZE / 2];
+  // the cosines and the sines of the rotations of the taps tiled over the screen, row major
+  vec4 rotation_noise[ROTATION_NOISE_SIZE * ROTATION_NOISE_SIZE];
+  uint light_count;
+  uint hemisphere_ambient;
+  // subtracted from the depth of the fragment in the light space against the shadow acne
+  float shadow_bias;
+  // whether the shadow map is drawn for the first point light
+  uint shadow_enabled;
+  // the radius in texels of the PCF kernel, 0 for the hard shadows, see ShadowFilter
+  uint shadow_pcf_radius;
+  // how the shadow map is sampled within the radius, see ShadowFilter
+  uint shadow_filter;
+  // the size of a texel of the shadow map in its texture coordinates
+  float shadow_texel_size;
+  // the count of the samples of poisson_disk
+  uint poisson_disk_size;
+  // scales the variance of the normal widening the specular lobe, 0 to disable, see
+  // specular_exponent
+  float specular_aa_strength;
+  // whether the ambient is the irradiance of the environment instead, see irradiance_map
+  uint diffuse_ibl;
+  // scales the irradiance of the environment
+  float ambient_strength;
+  // what the fragments are colored by, see RenderMode
+  uint render_mode;
+  // of the camera, the range of the depth view
+  float camera_near;
+  float camera_far;
+  // the exponent of the highlights, from the Ns of the material unless overridden
+  float shininess;
+  // how the highlights are shaped, see SpecularModel
+  uint specular_model;
+  // whether the output is encoded with the gamma of 2.2, see encode_output
+  uint gamma_correction;
 }
 ubo;
 #ifdef WITH_TEXTURE
 layout(binding = 2) uniform sampler2D tex_sampler;
+layout(binding = 3) uniform sampler2D shadow_map;
+// the irradiance of the environment over pi in the direction of the normal, see sh.rs
+layout(binding = 4) uniform samplerCube irradiance_map;
+// scales ks, stored linearly, white for the materials without map_ks
+layout(binding = 5) uniform sampler2D specular_map;
+#ifdef WITH_NORMAL_MAP
+// the tangent space normals, stored linearly
+layout(binding = 6) uniform sampler2D normal_map;
+#endif
+#else
+layout(binding = 2) uniform sampler2D shadow_map;
+layout(binding = 3) uniform samplerCube irradiance_map;
 #endif
 
 #ifdef WITH_TEXTURE
@@ -17,30 +85,203 @@ layout(location = 0) in vec2 texture_coord;
 #endif
 layout(location = 1) in vec3 frag_pos;
 layout(location = 2) in vec3 in_normal;
+#ifdef WITH_NORMAL_MAP
+layout(location = 3) in vec4 in_tangent;
+#endif
 
 layout(location = 0) out vec4 f_color;
 
+// MAX_PCF_KERNEL_RADIUS in shadow.rs
+#define MAX_PCF_KERNEL_RADIUS 4
+// ShadowFilter in shadow.rs
+#define SHADOW_FILTER_POISSON_PCF 2
+
+// 1 if the fragment is in front of the closest surface the first point light sees at the offset
+// in texels, the samples past the border of the shadow map clamped to the texels on the border
+float shadow_tap(vec2 shadow_coord, float depth, vec2 offset) {
+  // the centers of the texels on the border
+  vec2 min_coord = vec2(0.5 * ubo.shadow_texel_size);
+  vec2 max_coord = vec2(1.0 - 0.5 * ubo.shadow_texel_size);
+  vec2 coord = clamp(shadow_coord + offset * ubo.shadow_texel_size, min_coord, max_coord);
+  float closest = texture(shadow_map, coord).r;
+  return depth > closest ? 0.0 : 1.0;
+}
+
+// 0 if the fragment is behind the closest surface the first point light sees, the fragments
+// outside of the light frustum are lit and not filtered. With PCF, the fraction of the regular
+// grid of texels around the fragment it's in front of. With the Poisson PCF, the fraction of the
+// taps of the Poisson disk of the kernel radius instead, the disk rotated per pixel so that the
+// banding of the grid turns into noise.
+float visibility_to_first_light() {
+  if (ubo.shadow_enabled == 0) {
+    return 1.0;
+  }
+  vec4 clip = ubo.light_space * vec4(frag_pos, 1.0);
+  if (clip.w <= 0.0) {
+    return 1.0;
+  }
+  vec3 ndc = clip.xyz / clip.w;
+  if (any(greaterThan(abs(ndc.xy), vec2(1.0))) || ndc.z < 0.0 || ndc.z > 1.0) {
+    return 1.0;
+  }
+  vec2 shadow_coord = ndc.xy * 0.5 + 0.5;
+  float depth = ndc.z - ubo.shadow_bias;
+  int radius = int(min(ubo.shadow_pcf_radius, uint(MAX_PCF_KERNEL_RADIUS)));
+  float lit = 0.0;
+  if (ubo.shadow_filter == SHADOW_FILTER_POISSON_PCF && radius > 0) {
+    ivec2 noise_coord = ivec2(gl_FragCoord.xy) % ROTATION_NOISE_SIZE;
+    vec2 rotation = ubo.rotation_noise[noise_coord.y * ROTATION_NOISE_SIZE + noise_coord.x].xy;
+    mat2 rotate = mat2(rotation.x, rotation.y, -rotation.y, rotation.x);
+    int tap_count = int(clamp(ubo.poisson_disk_size, 1u, uint(POISSON_DISK_SIZE)));
+    for (int i = 0; i < tap_count; i++) {
+      vec4 taps = ubo.poisson_disk[i / 2];
+      vec2 tap = i % 2 == 0 ? taps.xy : taps.zw;
+      lit += shadow_tap(shadow_coord, depth, rotate * tap * float(radius));
+    }
+    return lit / float(tap_count);
+  }
+  for (int x = -radius; x <= radius; x++) {
+    for (int y = -radius; y <= radius; y++) {
+      lit += shadow_tap(shadow_coord, depth, vec2(x, y));
+    }
+  }
+  float kernel_size = float(2 * radius + 1);
+  return lit / (kernel_size * kernel_size);
+}
+
+// the most the lobe is widened, in the squared Beckmann roughness
+#define MAX_SPECULAR_AA_KERNEL 0.18
+
+// Geometric specular anti-aliasing (Kaplanyan et al. 2016): the screen space derivatives of the
+// normal estimate its variance over the pixel, which is added to the squared roughness equivalent
+// to the exponent, alpha^2 = 2 / (n + 2), so that the highlights are widened instead of aliased.
+// This is the stand-in for the Toksvig/LEAN remapping of the roughness of a PBR material until
+// there is one. With WITH_NORMAL_MAP, the normal is the one from perturbed_normal, so the
+// variance covers the normal map too and not only the interpolated normal.
+float specular_exponent(vec3 normal) {
+  vec3 dndx = dFdx(normal);
+  vec3 dndy = dFdy(normal);
+  float variance = 0.25 * ubo.specular_aa_strength * (dot(dndx, dndx) + dot(dndy, dndy));
+  float alpha2 = 2.0 / (ubo.shininess + 2.0);
+  alpha2 = min(alpha2 + min(2.0 * variance, MAX_SPECULAR_AA_KERNEL), 1.0);
+  return 2.0 / alpha2 - 2.0;
+}
+
+// SpecularModel in material.rs
+#define SPECULAR_MODEL_BLINN_PHONG 1
+
+// the highlight of the light from the normalized direction, without its color
+float specular_lobe(vec3 light_direction, vec3 view_direction, vec3 normal, float shininess) {
+  if (ubo.specular_model == SPECULAR_MODEL_BLINN_PHONG) {
+    vec3 half_direction = normalize(light_direction + view_direction);
+    return pow(max(dot(normal, half_direction), 0.0), shininess);
+  }
+  vec3 reflect_direction = reflect(-light_direction, normal);
+  return pow(max(dot(view_direction, reflect_direction), 0.0), shininess);
+}
+
+#ifdef WITH_NORMAL_MAP
+// The normal from the normal map in the OpenGL convention, with +y towards +v of the image. The
+// interpolated tangent is orthogonalized against the normal, and the bitangent is their cross
+// product times the handedness. On the back faces, the normal is already flipped, and so are the
+// tangent and the bitangent, so that the result is the flipped normal of the front face.
+vec3 perturbed_normal(vec3 normal, vec2 uv) {
+  vec3 tangent = in_tangent.xyz - dot(in_tangent.xyz, normal) * normal;
+  if (dot(tangent, tangent) < 1e-8) {
+    return normal;
+  }
+  float facing = gl_FrontFacing ? 1.0 : -1.0;
+  tangent = normalize(tangent) * facing;
+  vec3 bitangent = cross(normal, tangent) * in_tangent.w * facing;
+  vec3 tangent_normal = texture(normal_map, uv).xyz * 2.0 - 1.0;
+  return normalize(mat3(tangent, bitangent, normal) * tangent_normal);
+}
+#endif
+
+// The image is encoded with the gamma of 2.2 by default, which is close to the sRGB curve, as the
+// render targets are UNORM. Without it, the linear colors are written as they are.
+vec3 encode_output(vec3 color) {
+  if (ubo.gamma_correction != 0) {
+    return pow(color, vec3(1.0 / 2.2));
+  }
+  return color;
+}
+
+// RenderMode in material.rs
+#define RENDER_MODE_NORMALS 1
+#define RENDER_MODE_DEPTH 2
+
 void main() {
 #ifdef WITH_TEXTURE
-  vec3 color = pow(texture(tex_sampler, texture_coord).rgb, vec3(2.2));
+  vec3 uv = vec3(texture_coord, 1.0);
+  vec2 animated_uv = vec2(dot(ubo.uv_transform_u.xyz, uv), dot(ubo.uv_transform_v.xyz, uv));
+  // the texture is sRGB, decoded to the linear space by the sampler
+  vec3 color = texture(tex_sampler, animated_uv).rgb;
+  vec3 ks = ubo.ks.xyz * texture(specular_map, animated_uv).rgb;
 #else
   vec3 color = ubo.kd.xyz;
+  vec3 ks = ubo.ks.xyz;
 #endif
 
-  vec3 ambient = 0.05 * color;
-
-  vec3 light_pos = ubo.light_pos.xyz;
-  vec3 light_direction = normalize(light_pos - frag_pos);
+  // the back faces of the double-sided materials are lit from their side
   vec3 normal = normalize(in_normal);
-  float diff = max(dot(light_direction, normal), 0.0);
-  float light_atten_coff = ubo.light_intensity / length(light_pos - frag_pos);
-  vec3 diffuse = diff * light_atten_coff * color;
+  if (!gl_FrontFacing) {
+    normal = -normal;
+  }
+#ifdef WITH_NORMAL_MAP
+  normal = perturbed_normal(normal, animated_uv);
+#endif
+
+  // the debug views are written as they are, without the gamma correction
+  if (ubo.render_mode == RENDER_MODE_NORMALS) {
+    f_color = vec4(normal * 0.5 + 0.5, 1.0);
+    return;
+  }
+  if (ubo.render_mode == RENDER_MODE_DEPTH) {
+    float distance = length(frag_pos - ubo.camera_pos.xyz);
+    float depth = (distance - ubo.camera_near) / (ubo.camera_far - ubo.camera_near);
+    f_color = vec4(vec3(clamp(depth, 0.0, 1.0)), 1.0);
+    return;
+  }
+
+  // The ambient is multiplied by the albedo like the diffuse term, and everything is summed in
+  // the linear space before the gamma correction, where a tonemapper should also be applied.
+  vec3 ambient = 0.05 * color;
+  if (ubo.hemisphere_ambient != 0) {
+    float t = dot(normal, normalize(ubo.ambient_up.xyz)) * 0.5 + 0.5;
+    ambient = mix(ubo.ambient_ground_color.rgb, ubo.ambient_sky_color.rgb, t) * color;
+  }
+  if (ubo.diffuse_ibl != 0) {
+    ambient = texture(irradiance_map, normal).rgb * ubo.ambient_strength * color;
+  }
 
+  // the derivatives are taken in the uniform control flow
+  float shininess = specular_exponent(normal);
   vec3 view_direction = normalize(ubo.camera_pos.xyz - frag_pos);
-  float spec = 0.0;
-  vec3 reflect_direction = reflect(-light_direction, normal);
-  spec = pow(max(dot(view_direction, reflect_direction), 0.0), 35.0);
-  vec3 specular = ubo.ks.xyz * light_atten_coff * spec;
+  vec3 diffuse = vec3(0.0);
+  vec3 specular = vec3(0.0);
+  for (uint i = 0; i < min(ubo.light_count, uint(MAX_POINT_LIGHTS)); i++) {
+    vec3 light_pos = ubo.light_positions[i].xyz;
+    vec3 light_direction = normalize(light_pos - frag_pos);
+    float diff = max(dot(light_direction, normal), 0.0);
+    vec3 light_atten_coff = ubo.light_colors[i].rgb / length(light_pos - frag_pos);
+    if (i == 0) {
+      light_atten_coff *= visibility_to_first_light();
+    }
+    diffuse += diff * light_atten_coff * color;
+
+    float spec = specular_lobe(light_direction, view_direction, normal, shininess);
+    specular += ks * light_atten_coff * spec;
+  }
+
+  // the directional light has no falloff, and is black when off
+  vec3 sun_direction = ubo.sun_direction.xyz;
+  float sun_diff = max(dot(sun_direction, normal), 0.0);
+  float sun_spec = 0.0;
+  if (sun_diff > 0.0) {
+    sun_spec = specular_lobe(sun_direction, view_direction, normal, shininess);
+  }
+  vec3 sun = ubo.sun_color.rgb * (sun_diff * color + sun_spec * ks);
 
-  f_color = vec4(pow((ambient + diffuse + specular), vec3(1.0 / 2.2)), 1.0);
+  f_color = vec4(encode_output(ambient + diffuse + specular + sun), 1.0);
 }
\ No newline at end of file
@@ -3,7 +3,11 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use super::super::material::SetCamera;
+use super::super::{
+    light::MAX_POINT_LIGHTS,
+    material::SetCamera,
+    noise::{PoissonDiskArray, RotationNoiseArray},
+};
 use crate::impl_shaders;
 
 pub mod texture_vertex_shader {
@@ -22,6 +26,22 @@ pub mod texture_fragment_shader {
     }
 }
 
+pub mod normal_map_vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/scene/shaders/phong/vertex_shader.glsl",
+        define: [("WITH_TEXTURE", "1"), ("WITH_NORMAL_MAP", "1")],
+    }
+}
+
+pub mod normal_map_fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/scene/shaders/phong/fragment_shader.glsl",
+        define: [("WITH_TEXTURE", "1"), ("WITH_NORMAL_MAP", "1")],
+    }
+}
+
 pub mod no_texture_vertex_shader {
     vulkano_shaders::shader! {
         ty: "vertex",
@@ -41,98 +61,160 @@ fn __() {
     let _ = include_bytes!("vertex_shader.glsl");
 }
 
-pub mod with_texture {
-    use super::*;
-
-    impl_shaders!(
-        Shaders,
-        texture_vertex_shader,
-        texture_fragment_shader,
-        {
-            vs_uniform: {
-                layout: 0,
-                ty: "buffer",
-                def: {
-                    model: [f32; 16],
-                    view: [f32; 16],
-                    proj: [f32; 16],
-                },
-            },
-            fs_uniform: {
-                layout: 1,
-                ty: "buffer",
-                def: {
-                    pub kd: [f32; 4],
-                    pub ks: [f32; 4],
-                    pub light_pos: [f32; 4],
-                    pub camera_pos: [f32; 4],
-                    pub light_intensity: f32,
-                },
-            },
-            texture: {
-                layout: 2,
-                ty: "texture",
-            },
-        }
-    );
-
-    impl SetCamera for ShadersUniforms {
-        fn set_model_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.model.copy_from_slice(&mat);
-        }
+// The uniforms of all the permutations, which only differ in the textures.
+#[derive(Clone, Default)]
+pub struct VsUniform {
+    pub model: [f32; 16],
+    pub view: [f32; 16],
+    pub proj: [f32; 16],
+    pub normal_matrix: [f32; 16],
+}
 
-        fn set_view_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.view.copy_from_slice(&mat);
-        }
+#[derive(Clone, Default)]
+pub struct FsUniform {
+    pub kd: [f32; 4],
+    pub ks: [f32; 4],
+    pub light_positions: [[f32; 4]; MAX_POINT_LIGHTS],
+    pub light_colors: [[f32; 4]; MAX_POINT_LIGHTS],
+    pub camera_pos: [f32; 4],
+    pub ambient_sky_color: [f32; 4],
+    pub ambient_ground_color: [f32; 4],
+    pub ambient_up: [f32; 4],
+    pub sun_direction: [f32; 4],
+    pub sun_color: [f32; 4],
+    pub light_space: [f32; 16],
+    pub uv_transform_u: [f32; 4],
+    pub uv_transform_v: [f32; 4],
+    pub poisson_disk: PoissonDiskArray,
+    pub rotation_noise: RotationNoiseArray,
+    pub light_count: u32,
+    pub hemisphere_ambient: u32,
+    pub shadow_bias: f32,
+    pub shadow_enabled: u32,
+    pub shadow_pcf_radius: u32,
+    pub shadow_filter: u32,
+    pub shadow_texel_size: f32,
+    pub poisson_disk_size: u32,
+    pub specular_aa_strength: f32,
+    pub diffuse_ibl: u32,
+    pub ambient_strength: f32,
+    pub render_mode: u32,
+    pub camera_near: f32,
+    pub camera_far: f32,
+    pub shininess: f32,
+    pub specular_model: u32,
+    pub gamma_correction: u32,
+}
 
-        fn set_proj_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.proj.copy_from_slice(&mat);
-        }
-    }
+// the uniforms of any permutation
+pub trait PhongUniforms {
+    fn get_fs_uniform_mut(&mut self) -> &mut FsUniform;
 }
 
-pub mod no_texture {
-    use super::*;
-
-    impl_shaders!(
-        Shaders,
-        no_texture_vertex_shader,
-        no_texture_fragment_shader,
-        {
-            vs_uniform: {
-                layout: 0,
-                ty: "buffer",
-                def: {
-                    pub model: [f32; 16],
-                    pub view: [f32; 16],
-                    pub proj: [f32; 16],
+macro_rules! impl_phong_shaders {
+    ($vs_mod:ident, $fs_mod:ident, { $($textures:tt)* }) => {
+        use super::*;
+
+        impl_shaders!(
+            Shaders,
+            $vs_mod,
+            $fs_mod,
+            {
+                vs_uniform: {
+                    layout: 0,
+                    ty: "buffer",
+                    def: (super::VsUniform),
                 },
-            },
-            fs_uniform: {
-                layout: 1,
-                ty: "buffer",
-                def: {
-                    pub kd: [f32; 4],
-                    pub ks: [f32; 4],
-                    pub light_pos: [f32; 4],
-                    pub camera_pos: [f32; 4],
-                    pub light_intensity: f32,
+                fs_uniform: {
+                    layout: 1,
+                    ty: "buffer",
+                    def: (super::FsUniform),
                 },
-            },
+                $($textures)*
+            }
+        );
+
+        impl PhongUniforms for ShadersUniforms {
+            fn get_fs_uniform_mut(&mut self) -> &mut FsUniform {
+                &mut self.fs_uniform
+            }
         }
-    );
 
-    impl SetCamera for ShadersUniforms {
-        fn set_model_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.model.copy_from_slice(&mat);
-        }
+        impl SetCamera for ShadersUniforms {
+            fn set_model_matrix(&mut self, mat: [f32; 16]) {
+                self.vs_uniform.model.copy_from_slice(&mat);
+            }
 
-        fn set_view_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.view.copy_from_slice(&mat);
-        }
+            fn set_view_matrix(&mut self, mat: [f32; 16]) {
+                self.vs_uniform.view.copy_from_slice(&mat);
+            }
+
+            fn set_proj_matrix(&mut self, mat: [f32; 16]) {
+                self.vs_uniform.proj.copy_from_slice(&mat);
+            }
 
-        fn set_proj_matrix(&mut self, mat: [f32; 16]) {
-            self.vs_uniform.proj.copy_from_slice(&mat);
+            fn set_normal_matrix(&mut self, mat: [f32; 16]) {
+                self.vs_uniform.normal_matrix.copy_from_slice(&mat);
+            }
         }
-    }
+    };
+}
+
+pub mod with_texture {
+    impl_phong_shaders!(texture_vertex_shader, texture_fragment_shader, {
+        texture: {
+            layout: 2,
+            ty: "texture",
+        },
+        shadow_map: {
+            layout: 3,
+            ty: "texture",
+        },
+        irradiance_map: {
+            layout: 4,
+            ty: "texture",
+        },
+        specular_map: {
+            layout: 5,
+            ty: "texture",
+        },
+    });
+}
+
+pub mod with_normal_map {
+    impl_phong_shaders!(normal_map_vertex_shader, normal_map_fragment_shader, {
+        texture: {
+            layout: 2,
+            ty: "texture",
+        },
+        shadow_map: {
+            layout: 3,
+            ty: "texture",
+        },
+        irradiance_map: {
+            layout: 4,
+            ty: "texture",
+        },
+        specular_map: {
+            layout: 5,
+            ty: "texture",
+        },
+        normal_map: {
+            layout: 6,
+            ty: "texture",
+        },
+    });
+}
+
+pub mod no_texture {
+    impl_phong_shaders!(no_texture_vertex_shader, no_texture_fragment_shader, {
+        shadow_map: {
+            layout: 2,
+            ty: "texture",
+        },
+        irradiance_map: {
+            layout: 3,
+            ty: "texture",
+        },
+    });
 }
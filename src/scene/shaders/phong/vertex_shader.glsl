@@ -4,6 +4,8 @@ layout(binding = 0) uniform UniformBufferObject {
   mat4 model;
   mat4 view;
   mat4 proj;
+  // the inverse transpose of the model matrix, to transform the normals
+  mat4 normal_matrix;
 }
 ubo;
 
@@ -12,20 +14,31 @@ layout(location = 1) in vec4 in_normal;
 #ifdef WITH_TEXTURE
 layout(location = 2) in vec2 in_texture_coord;
 #endif
+#ifdef WITH_NORMAL_MAP
+// the handedness of the tangent space in w, see generate_tangents
+layout(location = 3) in vec4 in_tangent;
+#endif
 
 #ifdef WITH_TEXTURE
 layout(location = 0) out vec2 texture_coord;
 #endif
 layout(location = 1) out vec3 frag_pos;
 layout(location = 2) out vec3 normal;
+#ifdef WITH_NORMAL_MAP
+layout(location = 3) out vec4 tangent;
+#endif
 
 void main() {
   frag_pos = (ubo.model * vec4(in_position.xyz, 1.0)).xyz;
-  normal = (ubo.model * vec4(in_normal.xyz, 0.0)).xyz;
+  normal = (ubo.normal_matrix * vec4(in_normal.xyz, 0.0)).xyz;
 
   gl_Position = ubo.proj * ubo.view * vec4(frag_pos, 1.0);
 
 #ifdef WITH_TEXTURE
   texture_coord = in_texture_coord;
 #endif
+#ifdef WITH_NORMAL_MAP
+  // the tangents are transformed like the edges of the triangles
+  tangent = vec4(mat3(ubo.model) * in_tangent.xyz, in_tangent.w);
+#endif
 }
@@ -0,0 +1,11 @@
+#version 450
+
+layout(push_constant) uniform PushConstants {
+  // from the model space to the clip space of the light
+  mat4 light_space_model;
+}
+push_constants;
+
+layout(location = 0) in vec4 in_position;
+
+void main() { gl_Position = push_constants.light_space_model * vec4(in_position.xyz, 1.0); }
@@ -0,0 +1,24 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+  mat4 view;
+  mat4 proj;
+  uint gamma_correction;
+}
+ubo;
+
+layout(binding = 1) uniform samplerCube cubemap;
+
+layout(location = 0) in vec3 direction;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  // the faces are gamma encoded like the output of the phong shader, so they're written as is
+  // unless the other shaders write their linear colors
+  vec3 color = texture(cubemap, direction).rgb;
+  if (ubo.gamma_correction == 0) {
+    color = pow(color, vec3(2.2));
+  }
+  f_color = vec4(color, 1.0);
+}
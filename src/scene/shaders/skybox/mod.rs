@@ -0,0 +1,60 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use super::super::material::SetCamera;
+use crate::impl_shaders;
+
+pub mod vertex_shader {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/scene/shaders/skybox/vertex_shader.glsl",
+    }
+}
+
+pub mod fragment_shader {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/scene/shaders/skybox/fragment_shader.glsl",
+    }
+}
+
+fn __() {
+    let _ = include_bytes!("fragment_shader.glsl");
+    let _ = include_bytes!("vertex_shader.glsl");
+}
+
+impl_shaders!(Shaders, vertex_shader, fragment_shader, {
+    uniform: {
+        layout: 0,
+        ty: "buffer",
+        def: {
+            pub view: [f32; 16],
+            pub proj: [f32; 16],
+            pub gamma_correction: u32,
+        },
+    },
+    cubemap: {
+        layout: 1,
+        ty: "texture",
+    },
+});
+
+impl SetCamera for ShadersUniforms {
+    // the box is centered at the camera wherever it is
+    fn set_model_matrix(&mut self, _mat: [f32; 16]) {}
+
+    // the translation is dropped, so that the box turns with the camera but never gets closer
+    fn set_view_matrix(&mut self, mat: [f32; 16]) {
+        self.uniform.view.copy_from_slice(&mat);
+        self.uniform.view[12..15].copy_from_slice(&[0.0; 3]);
+    }
+
+    fn set_proj_matrix(&mut self, mat: [f32; 16]) {
+        self.uniform.proj.copy_from_slice(&mat);
+    }
+
+    // the directions of the box aren't lit, so it has no normals to transform
+    fn set_normal_matrix(&mut self, _mat: [f32; 16]) {}
+}
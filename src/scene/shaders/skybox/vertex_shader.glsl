@@ -0,0 +1,20 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject {
+  // only the rotation of the camera, the box is infinitely far away
+  mat4 view;
+  mat4 proj;
+  // see Renderer::set_gamma_correction
+  uint gamma_correction;
+}
+ubo;
+
+layout(location = 0) in vec4 position;
+
+layout(location = 0) out vec3 direction;
+
+void main() {
+  direction = position.xyz;
+  // z = w puts the box on the far plane, behind everything drawn over it
+  gl_Position = (ubo.proj * ubo.view * vec4(position.xyz, 1.0)).xyww;
+}
@@ -0,0 +1,414 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::sync::Arc;
+
+use euclid::{vec3, Angle, Box3D, Point3D, Transform3D};
+use vulkano::{
+    command_buffer::{
+        pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder, SubpassContents,
+    },
+    device::{Device, Queue},
+    format::{ClearValue, D16Unorm, Format},
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+    image::ImageUsage,
+    pipeline::{
+        depth_stencil::DepthStencil,
+        vertex::Vertex,
+        viewport::{Scissor, Viewport},
+        GraphicsPipeline,
+    },
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+use super::{
+    camera::MIN_AUTO_NEAR,
+    object::{ObjectWithNoTextureVertex, ObjectWithTextureVertex},
+    renderer::{LayerView, OffscreenTarget, Pipeline, DEFAULT_DEPTH_RANGE},
+    shaders::{
+        shadow::{fragment_shader, vertex_shader},
+        Texture,
+    },
+    Camera, NDCSpace, WorldSpace,
+};
+use crate::errors::*;
+
+// the shadow map is never resized, so that the descriptor sets sampling it stay valid
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+// in the NDC depth of the light, subtracted before the depth comparison against the shadow acne
+pub const DEFAULT_SHADOW_BIAS: f32 = 0.005;
+// the widest the light can see when it's close to or inside the scene
+const MAX_LIGHT_FOV_DEGREES: f32 = 120.0;
+// in texels, the same as the phong fragment shader, which takes (2r + 1)^2 samples with Pcf
+pub const MAX_PCF_KERNEL_RADIUS: u32 = 4;
+
+// How the shadow map is sampled by the phong shaders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadowFilter {
+    #[default]
+    Hard,
+    // percentage closer filtering over the square of texels within the radius
+    Pcf {
+        kernel_radius: u32,
+    },
+    // percentage closer filtering over the taps of a Poisson disk of the radius rotated per pixel,
+    // see NoiseResources
+    PoissonPcf {
+        kernel_radius: u32,
+    },
+}
+
+impl ShadowFilter {
+    // the radius of the kernel in texels, clamped to MAX_PCF_KERNEL_RADIUS, 0 for the hard shadows
+    pub fn get_kernel_radius(&self) -> u32 {
+        match self {
+            Self::Hard => 0,
+            Self::Pcf { kernel_radius } | Self::PoissonPcf { kernel_radius } => {
+                (*kernel_radius).min(MAX_PCF_KERNEL_RADIUS)
+            }
+        }
+    }
+
+    // the side of the square of texels covered, e.g. 3 for a 3x3 kernel, 1 for the hard shadows
+    pub fn get_kernel_size(&self) -> u32 {
+        2 * self.get_kernel_radius() + 1
+    }
+
+    // the count of the texels sampled, with the Poisson disk of the size for PoissonPcf
+    pub fn get_tap_count(&self, poisson_disk_size: usize) -> usize {
+        match self {
+            Self::Hard | Self::Pcf { .. } => {
+                let kernel_size = self.get_kernel_size() as usize;
+                kernel_size * kernel_size
+            }
+            Self::PoissonPcf { .. } => poisson_disk_size.max(1),
+        }
+    }
+
+    // the shadow_filter of the phong fragment uniforms, the same as SHADOW_FILTER_* in the shader
+    pub fn to_uniform(self) -> u32 {
+        match self {
+            Self::Hard => 0,
+            Self::Pcf { .. } => 1,
+            Self::PoissonPcf { .. } => 2,
+        }
+    }
+}
+
+// How the phong shaders shadow the objects, written to their fragment uniforms every frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowConfig {
+    // in the NDC depth of the light, see DEFAULT_SHADOW_BIAS
+    pub bias: f32,
+    pub filter: ShadowFilter,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            bias: DEFAULT_SHADOW_BIAS,
+            filter: Default::default(),
+        }
+    }
+}
+
+impl ShadowConfig {
+    // a Validation error if the bias is negative or not finite
+    pub fn validate(&self) -> Result<()> {
+        if !self.bias.is_finite() || self.bias < 0.0 {
+            bail!(ErrorKind::Validation(format!(
+                "the shadow bias {} is not a non-negative number",
+                self.bias
+            )));
+        }
+        Ok(())
+    }
+}
+
+// The camera at the point light looking at the center of the scene bounds, with a fov just
+// enclosing the bounding sphere. None if the bounds are empty or the light is at their center.
+pub fn get_light_camera(
+    light_position: &Point3D<f32, WorldSpace>,
+    bounds: &Box3D<f32, WorldSpace>,
+) -> Option<Camera> {
+    if bounds.is_empty() {
+        return None;
+    }
+    let center = bounds.center();
+    let radius = (bounds.max - center).length();
+    let distance = (center - *light_position).length();
+    let max_fov = MAX_LIGHT_FOV_DEGREES.to_radians();
+    let fov = if distance > radius {
+        Angle::radians(((radius / distance).asin() * 2.0).min(max_fov))
+    } else {
+        Angle::radians(max_fov)
+    };
+    let direction = (center - *light_position).try_normalize()?;
+    // any up not colinear to the direction
+    let up = if direction.y.abs() < 0.99 {
+        vec3(0.0, 1.0, 0.0)
+    } else {
+        vec3(1.0, 0.0, 0.0)
+    };
+    let mut camera = Camera::new(fov, 1.0, 0.1, 1.0, light_position, &center, &up).ok()?;
+    let (near, far) = camera.fit_near_far(bounds, MIN_AUTO_NEAR)?;
+    camera.set_near_far(near, far).ok()?;
+    Some(camera)
+}
+
+// from the world space to the clip space of the light camera
+pub fn get_light_space_transform(camera: &Camera) -> Transform3D<f32, WorldSpace, NDCSpace> {
+    camera
+        .get_view_transform()
+        .then(&camera.get_projection_transform())
+}
+
+fn create_pipeline<V: Vertex>(
+    device: Arc<Device>,
+    subpass: Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>,
+) -> Result<Pipeline> {
+    let vertex_shader = vertex_shader::Shader::load(device.clone())
+        .chain_err(|| "fail to load the shadow vertex shader")?;
+    let fragment_shader = fragment_shader::Shader::load(device.clone())
+        .chain_err(|| "fail to load the shadow fragment shader")?;
+    let pipeline = GraphicsPipeline::start()
+        .vertex_input_single_buffer::<V>()
+        .vertex_shader(vertex_shader.main_entry_point(), ())
+        .viewports_scissors(vec![(
+            Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [SHADOW_MAP_SIZE as f32; 2],
+                depth_range: DEFAULT_DEPTH_RANGE,
+            },
+            Scissor {
+                origin: [0, 0],
+                dimensions: [SHADOW_MAP_SIZE; 2],
+            },
+        )])
+        .fragment_shader(fragment_shader.main_entry_point(), ())
+        .depth_stencil(DepthStencil::simple_depth_test())
+        .depth_write(true)
+        // both faces cast shadows, the models aren't always closed
+        .cull_mode_disabled()
+        .render_pass(subpass)
+        .build(device)
+        .chain_err(|| ErrorKind::GpuResource("fail to create the shadow pipeline".to_owned()))?;
+    Ok(Arc::new(pipeline))
+}
+
+// The depth of the scene from the point of view of a light, drawn by a depth only pass before the
+// scene and sampled by the phong shaders.
+pub struct ShadowMap {
+    depth: Arc<LayerView<D16Unorm>>,
+    framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+    sampler: Arc<Sampler>,
+    with_texture_pipeline: Pipeline,
+    no_texture_pipeline: Pipeline,
+}
+
+impl ShadowMap {
+    pub fn new(queue: Arc<Queue>) -> Result<Self> {
+        let device = queue.device().clone();
+        let render_pass: Arc<dyn RenderPassAbstract + Send + Sync> = Arc::new(
+            vulkano::single_pass_renderpass!(
+                device.clone(),
+                attachments: {
+                    // the layout of a LayerView, see OffscreenTarget
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: Format::D16Unorm,
+                        samples: 1,
+                        initial_layout: ImageLayout::General,
+                        final_layout: ImageLayout::General,
+                    }
+                },
+                pass: {
+                    color: [],
+                    depth_stencil: {depth}
+                }
+            )
+            .chain_err(|| {
+                ErrorKind::GpuResource(
+                    "fail to create the render pass of the shadow map".to_owned(),
+                )
+            })?,
+        );
+        let depth = OffscreenTarget::array(
+            queue,
+            D16Unorm,
+            [SHADOW_MAP_SIZE; 2],
+            1,
+            ImageUsage {
+                depth_stencil_attachment: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource("fail to create the image for the shadow map".to_owned())
+        })?
+        // sampled as a sampler2D, unlike the array image
+        .get_layer_view(0)
+        .expect("the shadow map has a single layer");
+        let framebuffer = Arc::new(
+            Framebuffer::start(render_pass.clone())
+                .add(depth.clone())
+                .chain_err(|| "fail to add the shadow map to the framebuffer")?
+                .build()
+                .chain_err(|| "fail to create the framebuffer of the shadow map")?,
+        );
+        // the depth is compared in the shader, so it's sampled without filtering
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Nearest,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            SamplerAddressMode::ClampToEdge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .chain_err(|| ErrorKind::GpuResource("fail to create the shadow map sampler".to_owned()))?;
+        let subpass = Subpass::from(render_pass.clone(), 0)
+            .expect("fail to retrieve the first subpass from the renderpass");
+        Ok(Self {
+            with_texture_pipeline: create_pipeline::<ObjectWithTextureVertex>(
+                device.clone(),
+                subpass.clone(),
+            )?,
+            no_texture_pipeline: create_pipeline::<ObjectWithNoTextureVertex>(device, subpass)?,
+            depth,
+            framebuffer,
+            sampler,
+        })
+    }
+
+    pub fn get_texture(&self) -> Texture {
+        Texture {
+            image: self.depth.clone(),
+            sampler: self.sampler.clone(),
+        }
+    }
+
+    pub fn get_with_texture_pipeline(&self) -> Pipeline {
+        self.with_texture_pipeline.clone()
+    }
+
+    pub fn get_no_texture_pipeline(&self) -> Pipeline {
+        self.no_texture_pipeline.clone()
+    }
+
+    // the draws in between are recorded inline
+    pub fn begin(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+    ) -> Result<()> {
+        cmd_buf_builder
+            .begin_render_pass(
+                self.framebuffer.clone(),
+                SubpassContents::Inline,
+                vec![ClearValue::Depth(1.0)],
+            )
+            .chain_err(|| "fail to add the begin shadow pass command to the command builder")?;
+        Ok(())
+    }
+
+    pub fn end(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+    ) -> Result<()> {
+        cmd_buf_builder
+            .end_render_pass()
+            .chain_err(|| "fail to add the end shadow pass command to the command builder")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::point3;
+
+    #[test]
+    fn test_light_camera_encloses_the_bounds() {
+        let bounds = Box3D::new(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0));
+        let light_position = point3(0.0, 5.0, 5.0);
+        let camera = get_light_camera(&light_position, &bounds).unwrap();
+        let light_space = get_light_space_transform(&camera);
+        for i in 0..8 {
+            let corner = point3(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            let ndc = light_space.transform_point3d(corner).unwrap();
+            assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0, "{:?}", ndc);
+            assert!((0.0..=1.0).contains(&ndc.z), "{:?}", ndc);
+        }
+        // the light looks straight at the center
+        let center = light_space
+            .transform_point3d(point3(0.0, 0.0, 0.0))
+            .unwrap();
+        assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_shadow_config() {
+        let config = ShadowConfig::default();
+        assert_eq!(config.bias, DEFAULT_SHADOW_BIAS);
+        assert_eq!(config.filter.get_kernel_size(), 1);
+        assert_eq!(config.filter.get_tap_count(32), 1);
+        assert!(config.validate().is_ok());
+        assert_eq!(ShadowFilter::Pcf { kernel_radius: 1 }.get_kernel_size(), 3);
+        assert_eq!(ShadowFilter::Pcf { kernel_radius: 2 }.get_kernel_size(), 5);
+        assert_eq!(ShadowFilter::Pcf { kernel_radius: 2 }.get_tap_count(32), 25);
+        let poisson = ShadowFilter::PoissonPcf { kernel_radius: 1 };
+        assert_eq!(poisson.get_tap_count(32), 32);
+        assert_eq!(poisson.get_tap_count(0), 1);
+        for bias in &[-0.001, f32::NAN, f32::INFINITY] {
+            let config = ShadowConfig {
+                bias: *bias,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_shadow_filter_kernel_radius() {
+        assert_eq!(ShadowFilter::default().get_kernel_radius(), 0);
+        assert_eq!(
+            ShadowFilter::Pcf { kernel_radius: 2 }.get_kernel_radius(),
+            2
+        );
+        assert_eq!(
+            ShadowFilter::Pcf { kernel_radius: 100 }.get_kernel_radius(),
+            MAX_PCF_KERNEL_RADIUS
+        );
+        assert_eq!(
+            ShadowFilter::PoissonPcf { kernel_radius: 100 }.get_kernel_radius(),
+            MAX_PCF_KERNEL_RADIUS
+        );
+    }
+
+    #[test]
+    fn test_light_camera_above_or_inside_the_bounds() {
+        let bounds = Box3D::new(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0));
+        // up can't be y when the light is right above
+        assert!(get_light_camera(&point3(0.0, 10.0, 0.0), &bounds).is_some());
+        let camera = get_light_camera(&point3(0.5, 0.5, 0.5), &bounds).unwrap();
+        assert_eq!(
+            camera.get_fov().unwrap().to_degrees().round(),
+            MAX_LIGHT_FOV_DEGREES
+        );
+        assert!(get_light_camera(&point3(0.0, 0.0, 0.0), &bounds).is_none());
+        assert!(get_light_camera(&point3(0.0, 5.0, 0.0), &Box3D::zero()).is_none());
+    }
+}
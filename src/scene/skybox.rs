@@ -0,0 +1,425 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::{io::Reader as ImageReader, RgbaImage};
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    command_buffer::{pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder},
+    device::{Device, Queue},
+    format::{R8G8B8A8Srgb, R8G8B8A8Unorm},
+    image::{immutable::ImmutableImage, Dimensions, ImageUsage, MipmapsCount},
+    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+    sync::GpuFuture,
+};
+
+use super::{
+    material::{Material, SetCamera},
+    renderer::{Mesh, MeshData, MeshRenderer, MeshT, OffscreenTarget, RasterState, SimpleVertex},
+    sh::{irradiance_cubemap_texels, irradiance_from_radiance, project_cubemap, ShCoefficients},
+    shaders::{
+        skybox::{Shaders as SkyboxShaders, Uniform as SkyboxUniform},
+        ShadersT, Texture, UniformsT,
+    },
+    Camera, WorldSpace,
+};
+use crate::errors::*;
+
+// the names of the face images looked up in a folder, in the order of the layers of a cube image,
+// +x, -x, +y, -y, +z and -z
+const FACE_FILE_STEMS: [[&str; 2]; 6] = [
+    ["px", "right"],
+    ["nx", "left"],
+    ["py", "top"],
+    ["ny", "bottom"],
+    ["pz", "front"],
+    ["nz", "back"],
+];
+const FACE_FILE_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "bmp"];
+
+// the size of the faces of the irradiance map, which varies slowly with the normal
+const IRRADIANCE_MAP_SIZE: u32 = 32;
+
+// Scales the irradiance of the skybox lighting the phong materials, see IrradianceMap. Without a
+// skybox, the materials keep the ambient of the scene.
+pub const DEFAULT_AMBIENT_STRENGTH: f32 = 1.0;
+pub const MAX_AMBIENT_STRENGTH: f32 = 4.0;
+
+// a Validation error if the strength is outside of [0, MAX_AMBIENT_STRENGTH]
+pub fn validate_ambient_strength(strength: f32) -> Result<()> {
+    if !(0.0..=MAX_AMBIENT_STRENGTH).contains(&strength) {
+        bail!(ErrorKind::Validation(format!(
+            "the ambient strength {} is not within [0, {}]",
+            strength, MAX_AMBIENT_STRENGTH
+        )));
+    }
+    Ok(())
+}
+
+// The six face images in the folder, named px, nx, py, ny, pz and nz, or right, left, top, bottom,
+// front and back. An AssetNotFound error names the first missing face.
+pub fn find_skybox_faces(dir: &Path) -> Result<[PathBuf; 6]> {
+    let mut faces: [PathBuf; 6] = Default::default();
+    for (face, stems) in faces.iter_mut().zip(FACE_FILE_STEMS.iter()) {
+        *face = stems
+            .iter()
+            .flat_map(|stem| {
+                FACE_FILE_EXTENSIONS
+                    .iter()
+                    .map(move |extension| dir.join(format!("{}.{}", stem, extension)))
+            })
+            .find(|path| path.is_file())
+            .ok_or_else(|| -> Error {
+                ErrorKind::AssetNotFound(dir.join(stems.join(" or ")).display().to_string()).into()
+            })?;
+    }
+    Ok(faces)
+}
+
+// the size of the faces, a Validation error unless they're squares of the same size
+fn get_face_size(faces: &[RgbaImage]) -> Result<u32> {
+    let size = faces.first().map_or(0, |face| face.width());
+    for (i, face) in faces.iter().enumerate() {
+        if size == 0 || face.width() != size || face.height() != size {
+            bail!(ErrorKind::Validation(format!(
+                "the skybox face {} is {}x{}, the faces should be squares of the same size",
+                i,
+                face.width(),
+                face.height()
+            )));
+        }
+    }
+    Ok(size)
+}
+
+type IrradianceTexels = CpuAccessibleBuffer<[[u8; 4]]>;
+
+// The irradiance of the skybox over pi, sampled in the direction of the normal by all the phong
+// materials like the shadow map. The image lives as long as the renderer, and the texels of a new
+// skybox are copied into it by the next frame, before the scene samples it.
+pub struct IrradianceMap {
+    device: Arc<Device>,
+    target: OffscreenTarget<R8G8B8A8Srgb>,
+    sampler: Arc<Sampler>,
+    // the texels to copy into the image in the next frame
+    pending: RefCell<Option<Arc<IrradianceTexels>>>,
+}
+
+impl IrradianceMap {
+    // black until the first skybox, the image is cleared by the first frame
+    pub fn new(device: Arc<Device>, queue: &Arc<Queue>) -> Result<Self> {
+        let target = OffscreenTarget::cube(
+            queue.clone(),
+            R8G8B8A8Srgb,
+            IRRADIANCE_MAP_SIZE,
+            ImageUsage {
+                transfer_destination: true,
+                sampled: true,
+                ..ImageUsage::none()
+            },
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource("fail to create the image for the irradiance map".to_owned())
+        })?;
+        let clamp_to_edge = SamplerAddressMode::ClampToEdge;
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Linear,
+            Filter::Linear,
+            MipmapMode::Nearest,
+            clamp_to_edge,
+            clamp_to_edge,
+            clamp_to_edge,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+        .chain_err(|| "fail to create the sampler for the irradiance map")?;
+        let irradiance_map = Self {
+            device,
+            target,
+            sampler,
+            pending: RefCell::new(None),
+        };
+        irradiance_map.set_irradiance(&ShCoefficients::default())?;
+        Ok(irradiance_map)
+    }
+
+    pub fn get_texture(&self) -> Texture {
+        Texture {
+            image: self.target.get_image(),
+            sampler: self.sampler.clone(),
+        }
+    }
+
+    // the irradiance of the environment, see irradiance_from_radiance
+    pub fn set_irradiance(&self, irradiance: &ShCoefficients) -> Result<()> {
+        let texels = irradiance_cubemap_texels(irradiance, IRRADIANCE_MAP_SIZE);
+        let buffer = CpuAccessibleBuffer::from_iter(
+            self.device.clone(),
+            BufferUsage::transfer_source(),
+            false,
+            texels.into_iter(),
+        )
+        .chain_err(|| {
+            ErrorKind::GpuResource("fail to create the buffer of the irradiance map".to_owned())
+        })?;
+        self.pending.replace(Some(buffer));
+        Ok(())
+    }
+
+    // outside of the render pass
+    pub fn prepare_draw_commands(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+    ) -> Result<()> {
+        if let Some(buffer) = self.pending.borrow_mut().take() {
+            cmd_buf_builder
+                .copy_buffer_to_image_dimensions(
+                    buffer,
+                    self.target.get_image(),
+                    [0; 3],
+                    [IRRADIANCE_MAP_SIZE, IRRADIANCE_MAP_SIZE, 1],
+                    0,
+                    6,
+                    0,
+                )
+                .chain_err(|| {
+                    "fail to add the copy of the irradiance map to the command builder"
+                })?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SkyboxMaterial {
+    cubemap: Texture,
+}
+
+type SkyboxUniforms = <SkyboxShaders as ShadersT>::Uniforms;
+
+impl Material for SkyboxMaterial {
+    type Shaders = SkyboxShaders;
+
+    fn create_uniforms(&self, device: Arc<Device>, queue: Arc<Queue>) -> Result<SkyboxUniforms> {
+        SkyboxUniforms::new(
+            device,
+            queue,
+            SkyboxUniform {
+                view: Default::default(),
+                proj: Default::default(),
+                gamma_correction: 1,
+            },
+            self.cubemap.clone(),
+        )
+    }
+}
+
+#[derive(Default, Copy, Clone)]
+pub struct SkyboxVertex {
+    position: [f32; 4],
+}
+
+vulkano::impl_vertex!(SkyboxVertex, position);
+
+impl SimpleVertex for SkyboxVertex {
+    fn create_from_position(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            position: [x, y, z, 1.0],
+        }
+    }
+}
+
+pub type SkyboxRenderer = MeshRenderer<SkyboxVertex, SkyboxMaterial>;
+
+// The environment behind the scene, a cube around the camera sampling a cubemap in the direction
+// of its fragments. It's drawn first on the far plane, so the scene covers it.
+pub struct Skybox {
+    mesh: Mesh<SkyboxVertex, SkyboxMaterial, WorldSpace>,
+    uniforms: SkyboxUniforms,
+    paths: [PathBuf; 6],
+    // of the faces for the diffuse lighting, see sh.rs
+    irradiance: ShCoefficients,
+}
+
+impl Skybox {
+    // the faces in the order of find_skybox_faces, waits for the cubemap to be uploaded
+    pub fn load(
+        mesh_renderer: &Arc<SkyboxRenderer>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        paths: [PathBuf; 6],
+    ) -> Result<Self> {
+        let faces = paths
+            .iter()
+            .map(|path| -> Result<RgbaImage> {
+                Ok(ImageReader::open(path)
+                    .chain_err(|| ErrorKind::AssetNotFound(path.display().to_string()))?
+                    .decode()
+                    .chain_err(|| ErrorKind::AssetParse(path.display().to_string()))?
+                    .to_rgba8())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let size = get_face_size(&faces)?;
+        let irradiance = irradiance_from_radiance(&project_cubemap(&faces));
+        // the layers of the cube one after another
+        let pixels: Vec<_> = faces
+            .iter()
+            .flat_map(|face| face.pixels().map(|p| p.0))
+            .collect();
+        let (image, image_init) = ImmutableImage::from_iter(
+            pixels.into_iter(),
+            Dimensions::Cubemap { size },
+            MipmapsCount::One,
+            R8G8B8A8Unorm,
+            queue,
+        )
+        .chain_err(|| ErrorKind::GpuResource("fail to create the skybox cubemap".to_owned()))?;
+        image_init
+            .then_signal_fence_and_flush()
+            .chain_err(|| "fail to signal the fence and flush when uploading the skybox")?
+            .wait(None)
+            .chain_err(|| "fail to wait for the skybox being uploaded")?;
+        // clamped at the edges, so that the faces don't bleed into each other at the seams
+        let clamp_to_edge = SamplerAddressMode::ClampToEdge;
+        let material = SkyboxMaterial {
+            cubemap: Texture {
+                image,
+                sampler: Sampler::new(
+                    device,
+                    Filter::Linear,
+                    Filter::Linear,
+                    MipmapMode::Nearest,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    clamp_to_edge,
+                    0.0,
+                    1.0,
+                    0.0,
+                    0.0,
+                )
+                .chain_err(|| "fail to create the sampler for the skybox")?,
+            },
+        };
+        let (mesh, uniforms) = mesh_renderer
+            .create_mesh(MeshData::cube(), &material)
+            .chain_err(|| "fail to create the skybox mesh")?;
+        Ok(Self {
+            mesh,
+            uniforms,
+            paths,
+            irradiance,
+        })
+    }
+
+    pub fn get_paths(&self) -> &[PathBuf; 6] {
+        &self.paths
+    }
+
+    pub fn get_irradiance(&self) -> &ShCoefficients {
+        &self.irradiance
+    }
+
+    // The orthographic camera has no perspective to look around the environment with, so the box
+    // collapses to a point and isn't drawn, leaving the background color.
+    pub fn prepare_draw_commands(
+        &mut self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+        camera: &Camera,
+        gamma_correction: bool,
+    ) -> Result<()> {
+        self.uniforms.set_view_proj_matrix_from_camera(camera);
+        self.uniforms.uniform.gamma_correction = gamma_correction as u32;
+        if camera.is_orthographic() {
+            self.uniforms.set_proj_matrix([0.0; 16]);
+        }
+        self.uniforms
+            .update_buffers(cmd_buf_builder)
+            .chain_err(|| {
+                "fail to add the update buffer for uniforms command to the command builder"
+            })?;
+        Ok(())
+    }
+
+    // at the start of the scene pass, before anything writes the depth
+    pub fn draw_commands(
+        &self,
+        cmd_buf_builder: &mut AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+    ) -> Result<()> {
+        self.mesh
+            .draw_commands(cmd_buf_builder, RasterState::default())
+            .chain_err(|| "fail to issue draw commands for the skybox mesh")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::errors::find_error_kind;
+
+    #[test]
+    fn test_find_skybox_faces() {
+        let dir =
+            std::env::temp_dir().join(format!("games202-hw-vulkano-skybox-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &["right.jpg", "left.jpg", "py.png", "ny.png", "front.bmp"] {
+            fs::write(dir.join(name), []).unwrap();
+        }
+        let e = find_skybox_faces(&dir).err().unwrap();
+        assert!(
+            matches!(find_error_kind(&e), Some(ErrorKind::AssetNotFound(path)) if path.contains("nz or back")),
+            "{}",
+            e
+        );
+        fs::write(dir.join("back.jpeg"), []).unwrap();
+        let faces = find_skybox_faces(&dir).unwrap();
+        let names: Vec<_> = faces
+            .iter()
+            .map(|face| face.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "right.jpg",
+                "left.jpg",
+                "py.png",
+                "ny.png",
+                "front.bmp",
+                "back.jpeg"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_ambient_strength() {
+        assert!(validate_ambient_strength(0.0).is_ok());
+        assert!(validate_ambient_strength(DEFAULT_AMBIENT_STRENGTH).is_ok());
+        assert!(validate_ambient_strength(MAX_AMBIENT_STRENGTH).is_ok());
+        assert!(validate_ambient_strength(-0.1).is_err());
+        assert!(validate_ambient_strength(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_faces_are_squares_of_the_same_size() {
+        let square = |size| RgbaImage::new(size, size);
+        assert_eq!(get_face_size(&vec![square(4); 6]).unwrap(), 4);
+        let mut faces = vec![square(4); 6];
+        faces[3] = square(8);
+        assert!(get_face_size(&faces).is_err());
+        faces[3] = RgbaImage::new(4, 2);
+        assert!(get_face_size(&faces).is_err());
+        assert!(get_face_size(&vec![square(0); 6]).is_err());
+    }
+}
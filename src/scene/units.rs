@@ -0,0 +1,91 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+
+// What a unit length of the scene stands for. Unlike the unit of the import options, the
+// geometry isn't rescaled, only the camera speed, the auto near and far and the readouts follow.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SceneUnits {
+    Millimeter,
+    Centimeter,
+    #[default]
+    Meter,
+    // in meters per unit
+    Custom(f32),
+}
+
+impl SceneUnits {
+    pub const PRESETS: [SceneUnits; 3] = [
+        SceneUnits::Millimeter,
+        SceneUnits::Centimeter,
+        SceneUnits::Meter,
+    ];
+
+    // a Validation error unless the scale is positive
+    pub fn custom(meters_per_unit: f32) -> Result<Self> {
+        if !(meters_per_unit.is_finite() && meters_per_unit > 0.0) {
+            bail!(ErrorKind::Validation(format!(
+                "the scale of the scene units should be positive, found {}",
+                meters_per_unit
+            )));
+        }
+        Ok(SceneUnits::Custom(meters_per_unit))
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SceneUnits::Millimeter => "mm",
+            SceneUnits::Centimeter => "cm",
+            SceneUnits::Meter => "m",
+            SceneUnits::Custom(_) => "custom",
+        }
+    }
+
+    // shown next to the lengths
+    pub fn get_symbol(&self) -> &'static str {
+        match self {
+            SceneUnits::Custom(_) => "u",
+            _ => self.get_name(),
+        }
+    }
+
+    pub fn get_meters_per_unit(&self) -> f32 {
+        match self {
+            SceneUnits::Millimeter => 0.001,
+            SceneUnits::Centimeter => 0.01,
+            SceneUnits::Meter => 1.0,
+            SceneUnits::Custom(meters_per_unit) => *meters_per_unit,
+        }
+    }
+
+    // a length in meters to the scene units
+    pub fn meters_to_units(&self, length: f32) -> f32 {
+        length / self.get_meters_per_unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lengths_in_the_scene_units() {
+        assert_eq!(SceneUnits::default(), SceneUnits::Meter);
+        assert!((SceneUnits::Millimeter.meters_to_units(1.5) - 1500.0).abs() < 1e-3);
+        assert!((SceneUnits::Centimeter.meters_to_units(0.01) - 1.0).abs() < 1e-6);
+        let units = SceneUnits::custom(0.5).unwrap();
+        assert_eq!(units.meters_to_units(2.0), 4.0);
+        assert_eq!(units.get_symbol(), "u");
+        assert_eq!(SceneUnits::Millimeter.get_symbol(), "mm");
+        for meters_per_unit in [0.0, -1.0, f32::NAN, f32::INFINITY].iter() {
+            let e = SceneUnits::custom(*meters_per_unit).unwrap_err();
+            assert!(matches!(e.kind(), ErrorKind::Validation(_)));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{collections::VecDeque, sync::Arc};
+
+use vulkano::sync::{FenceSignalFuture, GpuFuture};
+
+use crate::errors::*;
+
+// enough to keep the transfers of a model busy without holding the staging buffers of all its
+// textures at once
+pub const DEFAULT_MAX_UPLOADS_IN_FLIGHT: usize = 16;
+
+// An upload to the GPU submitted without waiting for it. vulkano only lets the immutable buffers
+// and images it initializes be used once it's found finished, so the draws using them have to
+// wait for is_finished. Cloned to share the upload of a texture among the objects using it.
+#[derive(Clone)]
+pub struct Upload {
+    future: Arc<FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>>,
+}
+
+impl Upload {
+    pub fn submit(future: Box<dyn GpuFuture + Send + Sync>) -> Result<Self> {
+        let future = future
+            .then_signal_fence_and_flush()
+            .chain_err(|| "fail to signal the fence and flush the upload")?;
+        Ok(Self {
+            future: Arc::new(future),
+        })
+    }
+
+    // Never waits for the GPU. Once the fence is signaled, cleaning the future up marks the
+    // resources initialized and drops the commands, which leaves it without a queue.
+    pub fn is_finished(&self) -> bool {
+        let mut future = self.future.clone();
+        future.cleanup_finished();
+        future.queue().is_none()
+    }
+
+    // for the uploads too small to be worth deferring the draws
+    pub fn wait(&self) -> Result<()> {
+        self.future
+            .wait(None)
+            .chain_err(|| "fail to wait for the upload to finish")
+    }
+}
+
+// what UploadQueue needs of an upload, so that it can be tested without a GPU
+pub trait UploadFence {
+    fn is_finished(&self) -> bool;
+    fn wait(&self) -> Result<()>;
+}
+
+impl UploadFence for Upload {
+    fn is_finished(&self) -> bool {
+        Upload::is_finished(self)
+    }
+
+    fn wait(&self) -> Result<()> {
+        Upload::wait(self)
+    }
+}
+
+// Bounds the uploads in flight, as each one holds its staging buffers until it finishes. Before
+// another upload is created, reserve waits for the oldest ones until there is room.
+pub struct UploadQueue<U = Upload> {
+    in_flight: VecDeque<U>,
+    max_in_flight: usize,
+}
+
+impl<U> Default for UploadQueue<U> {
+    fn default() -> Self {
+        Self {
+            in_flight: VecDeque::new(),
+            max_in_flight: DEFAULT_MAX_UPLOADS_IN_FLIGHT,
+        }
+    }
+}
+
+impl<U: UploadFence> UploadQueue<U> {
+    // applies to the uploads created from now on
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) -> Result<()> {
+        if max_in_flight == 0 {
+            bail!(ErrorKind::Validation(
+                "at least 1 upload should be allowed in flight".to_owned()
+            ));
+        }
+        self.max_in_flight = max_in_flight;
+        Ok(())
+    }
+
+    pub fn get_max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    // the uploads not found finished yet
+    pub fn get_in_flight_count(&mut self) -> usize {
+        self.in_flight.retain(|upload| !upload.is_finished());
+        self.in_flight.len()
+    }
+
+    // called before creating an upload, blocks while the GPU catches up if it has to
+    pub fn reserve(&mut self) -> Result<()> {
+        self.in_flight.retain(|upload| !upload.is_finished());
+        while self.in_flight.len() >= self.max_in_flight {
+            if let Some(oldest) = self.in_flight.pop_front() {
+                profile_scope!("wait for the oldest upload");
+                oldest
+                    .wait()
+                    .chain_err(|| "fail to wait for the oldest upload in flight")?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, upload: U) {
+        self.in_flight.push_back(upload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    // finished once waited for, or once the simulated GPU gets to it
+    #[derive(Clone)]
+    struct FakeUpload {
+        id: usize,
+        finished_count: Rc<Cell<usize>>,
+    }
+
+    impl UploadFence for FakeUpload {
+        fn is_finished(&self) -> bool {
+            self.id < self.finished_count.get()
+        }
+
+        fn wait(&self) -> Result<()> {
+            self.finished_count
+                .set(self.finished_count.get().max(self.id + 1));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_the_uploads_in_flight_are_bounded() {
+        let finished_count = Rc::new(Cell::new(0));
+        let mut queue = UploadQueue::default();
+        queue.set_max_in_flight(3).unwrap();
+        for id in 0..20 {
+            queue.reserve().unwrap();
+            assert!(queue.get_in_flight_count() < 3);
+            queue.push(FakeUpload {
+                id,
+                finished_count: finished_count.clone(),
+            });
+            assert!(queue.get_in_flight_count() <= 3);
+            // the GPU finishes an upload every few submissions
+            if id % 4 == 0 {
+                finished_count.set(finished_count.get() + 1);
+            }
+        }
+        // only the oldest uploads are waited for
+        assert_eq!(finished_count.get(), 17);
+        assert_eq!(queue.get_in_flight_count(), 3);
+
+        let e = queue.set_max_in_flight(0).unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::Validation(_)));
+        assert_eq!(queue.get_max_in_flight(), 3);
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use euclid::{point2, size2, Box2D, Point2D, Size2D};
+
+use super::NDCSpace;
+
+// the window coordinates reported by the UI, i.e. the physical pixels divided by the scale factor
+pub struct LogicalPixelSpace;
+// the pixels of the swapchain image
+pub struct PhysicalPixelSpace;
+// the pixels of the image the scene is rendered to
+pub struct RenderTargetSpace;
+
+// Converts the positions between the window, the swapchain image, the render target of the scene
+// and the NDC. The render target is scaled to fit the swapchain image while keeping its aspect
+// ratio, and centered, i.e. letterboxed when the aspect ratios differ. In the NDC, (-1, -1) is the
+// top left corner and (1, 1) is the bottom right corner, the same as Camera::screen_ray.
+#[derive(Debug, Clone, Copy)]
+pub struct ViewportMapper {
+    scale_factor: f64,
+    // the area of the swapchain image covered by the render target
+    viewport: Box2D<f64, PhysicalPixelSpace>,
+    render_target_size: Size2D<u32, RenderTargetSpace>,
+}
+
+impl ViewportMapper {
+    pub fn new(
+        scale_factor: f64,
+        physical_size: Size2D<u32, PhysicalPixelSpace>,
+        render_target_size: Size2D<u32, RenderTargetSpace>,
+    ) -> Self {
+        let physical = physical_size.to_f64();
+        let render_target = render_target_size.to_f64();
+        let scale = (physical.width / render_target.width.max(1.0))
+            .min(physical.height / render_target.height.max(1.0));
+        let viewport_size = size2(render_target.width * scale, render_target.height * scale);
+        let origin = point2(
+            (physical.width - viewport_size.width) / 2.0,
+            (physical.height - viewport_size.height) / 2.0,
+        );
+        Self::with_viewport(
+            scale_factor,
+            Box2D::new(origin, origin + viewport_size),
+            render_target_size,
+        )
+    }
+
+    // the render target is stretched over the viewport, e.g. the rectangle of the UI image the
+    // scene is shown in
+    pub fn with_viewport(
+        scale_factor: f64,
+        viewport: Box2D<f64, PhysicalPixelSpace>,
+        render_target_size: Size2D<u32, RenderTargetSpace>,
+    ) -> Self {
+        Self {
+            scale_factor,
+            viewport,
+            render_target_size,
+        }
+    }
+
+    pub fn logical_to_physical(
+        &self,
+        point: Point2D<f64, LogicalPixelSpace>,
+    ) -> Point2D<f64, PhysicalPixelSpace> {
+        (point.to_vector() * self.scale_factor)
+            .to_point()
+            .cast_unit()
+    }
+
+    // None if the point is on the letterbox bars
+    pub fn physical_to_render_target(
+        &self,
+        point: Point2D<f64, PhysicalPixelSpace>,
+    ) -> Option<Point2D<f64, RenderTargetSpace>> {
+        if point.x < self.viewport.min.x
+            || point.y < self.viewport.min.y
+            || point.x >= self.viewport.max.x
+            || point.y >= self.viewport.max.y
+        {
+            return None;
+        }
+        let relative = point - self.viewport.min;
+        let viewport_size = self.viewport.size();
+        let render_target = self.render_target_size.to_f64();
+        Some(point2(
+            relative.x / viewport_size.width * render_target.width,
+            relative.y / viewport_size.height * render_target.height,
+        ))
+    }
+
+    pub fn render_target_to_ndc(
+        &self,
+        point: Point2D<f64, RenderTargetSpace>,
+    ) -> Point2D<f32, NDCSpace> {
+        let render_target = self.render_target_size.to_f64();
+        point2(
+            (point.x / render_target.width * 2.0 - 1.0) as f32,
+            (point.y / render_target.height * 2.0 - 1.0) as f32,
+        )
+    }
+
+    // None if the point is on the letterbox bars
+    pub fn logical_to_ndc(
+        &self,
+        point: Point2D<f64, LogicalPixelSpace>,
+    ) -> Option<Point2D<f32, NDCSpace>> {
+        self.physical_to_render_target(self.logical_to_physical(point))
+            .map(|point| self.render_target_to_ndc(point))
+    }
+}
+
+// the viewport and the inverse mappings, only used to check the round trips in the tests
+#[cfg(test)]
+impl ViewportMapper {
+    pub fn get_viewport(&self) -> Box2D<f64, PhysicalPixelSpace> {
+        self.viewport
+    }
+
+    pub fn physical_to_logical(
+        &self,
+        point: Point2D<f64, PhysicalPixelSpace>,
+    ) -> Point2D<f64, LogicalPixelSpace> {
+        (point.to_vector() / self.scale_factor)
+            .to_point()
+            .cast_unit()
+    }
+
+    pub fn render_target_to_physical(
+        &self,
+        point: Point2D<f64, RenderTargetSpace>,
+    ) -> Point2D<f64, PhysicalPixelSpace> {
+        let viewport_size = self.viewport.size();
+        let render_target = self.render_target_size.to_f64();
+        self.viewport.min
+            + size2(
+                point.x / render_target.width * viewport_size.width,
+                point.y / render_target.height * viewport_size.height,
+            )
+            .to_vector()
+    }
+
+    pub fn ndc_to_render_target(
+        &self,
+        point: Point2D<f32, NDCSpace>,
+    ) -> Point2D<f64, RenderTargetSpace> {
+        let render_target = self.render_target_size.to_f64();
+        point2(
+            (f64::from(point.x) + 1.0) / 2.0 * render_target.width,
+            (f64::from(point.y) + 1.0) / 2.0 * render_target.height,
+        )
+    }
+
+    pub fn ndc_to_logical(&self, point: Point2D<f32, NDCSpace>) -> Point2D<f64, LogicalPixelSpace> {
+        self.physical_to_logical(self.render_target_to_physical(self.ndc_to_render_target(point)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use euclid::approxeq::ApproxEq;
+
+    #[test]
+    fn test_scale_factors_without_letterbox() {
+        for scale_factor in [1.0, 1.5, 2.0].iter().cloned() {
+            let physical_size = size2(1200, 800);
+            let mapper =
+                ViewportMapper::new(scale_factor, physical_size, physical_size.cast_unit());
+            let center = point2(600.0 / scale_factor, 400.0 / scale_factor);
+            let ndc = mapper.logical_to_ndc(center).unwrap();
+            assert!(ndc.approx_eq(&point2(0.0, 0.0)), "{:?}", ndc);
+            let top_left = mapper.logical_to_ndc(point2(0.0, 0.0)).unwrap();
+            assert!(top_left.approx_eq(&point2(-1.0, -1.0)), "{:?}", top_left);
+            // the cursor at the logical point (100, 50) is on the physical pixel 100 * scale_factor
+            let physical = mapper.logical_to_physical(point2(100.0, 50.0));
+            assert!(physical.approx_eq(&point2(100.0 * scale_factor, 50.0 * scale_factor)));
+            let render_target = mapper.physical_to_render_target(physical).unwrap();
+            assert!(render_target.approx_eq(&physical.cast_unit()));
+            let logical = mapper.ndc_to_logical(point2(0.5, -0.5));
+            assert!(mapper
+                .logical_to_ndc(logical)
+                .unwrap()
+                .approx_eq(&point2(0.5, -0.5)));
+        }
+    }
+
+    #[test]
+    fn test_letterbox() {
+        // a square render target in a wide window leaves bars on the left and the right
+        let mapper = ViewportMapper::new(1.5, size2(1200, 600), size2(300, 300));
+        let viewport = mapper.get_viewport();
+        assert!(viewport.min.approx_eq(&point2(300.0, 0.0)));
+        assert!(viewport.max.approx_eq(&point2(900.0, 600.0)));
+        // on the left bar
+        assert!(mapper.logical_to_ndc(point2(100.0, 200.0)).is_none());
+        // on the right bar
+        assert!(mapper.logical_to_ndc(point2(700.0, 200.0)).is_none());
+        let top_left = mapper.logical_to_ndc(point2(200.0, 0.0)).unwrap();
+        assert!(top_left.approx_eq(&point2(-1.0, -1.0)), "{:?}", top_left);
+        let render_target = mapper
+            .physical_to_render_target(point2(600.0, 300.0))
+            .unwrap();
+        assert!(render_target.approx_eq(&point2(150.0, 150.0)));
+        let physical = mapper.render_target_to_physical(point2(0.0, 300.0));
+        assert!(physical.approx_eq(&point2(300.0, 600.0)));
+    }
+
+    #[test]
+    fn test_viewport_inside_the_window() {
+        // an image of 400x300 physical pixels at (100, 50), showing a 200x150 render target
+        let mapper = ViewportMapper::with_viewport(
+            2.0,
+            Box2D::new(point2(100.0, 50.0), point2(500.0, 350.0)),
+            size2(200, 150),
+        );
+        assert!(mapper.logical_to_ndc(point2(40.0, 100.0)).is_none());
+        let top_left = mapper.logical_to_ndc(point2(50.0, 25.0)).unwrap();
+        assert!(top_left.approx_eq(&point2(-1.0, -1.0)), "{:?}", top_left);
+        let center = mapper.logical_to_ndc(point2(150.0, 100.0)).unwrap();
+        assert!(center.approx_eq(&point2(0.0, 0.0)), "{:?}", center);
+        let render_target = mapper
+            .physical_to_render_target(point2(300.0, 200.0))
+            .unwrap();
+        assert!(render_target.approx_eq(&point2(100.0, 75.0)));
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright (c) 2021 06393993lky@gmail.com
+//
+// This software is released under the MIT License.
+// https://opensource.org/licenses/MIT
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fs,
+    io::ErrorKind as IoErrorKind,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use euclid::Angle;
+use serde::{Deserialize, Serialize};
+use winit::event::VirtualKeyCode;
+
+use crate::errors::*;
+use crate::scene::{CameraDirection, ImportOptions, SceneUnits};
+use crate::support::{UiAppearance, UI_SCALE_RANGE};
+
+// the settings are kept in the working directory, next to the screenshots
+pub const SETTINGS_PATH: &str = "settings.json";
+
+// in degrees per count of the raw mouse motion
+pub const MOUSE_SENSITIVITY_RANGE: RangeInclusive<f32> = 0.005..=0.5;
+
+// How the camera turns with the mouse. The raw mouse motion is in the counts of the mouse, which
+// the DPI scale of the window doesn't affect, so the sensitivity only has to be tuned to the mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MouseLook {
+    // in degrees per count
+    pub sensitivity: f32,
+    pub invert_y: bool,
+}
+
+impl Default for MouseLook {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.05,
+            invert_y: false,
+        }
+    }
+}
+
+impl MouseLook {
+    // the yaw around up and the pitch towards up of the raw mouse motion, moving the mouse to the
+    // right turns to the right, and moving it forward looks up unless the y is inverted
+    pub fn get_yaw_and_pitch(&self, (delta_x, delta_y): (f64, f64)) -> (Angle<f32>, Angle<f32>) {
+        let pitch_sign = if self.invert_y { 1.0 } else { -1.0 };
+        (
+            Angle::degrees(-delta_x as f32 * self.sensitivity),
+            Angle::degrees(pitch_sign * delta_y as f32 * self.sensitivity),
+        )
+    }
+}
+
+// The keys that can be bound to the camera movement, named by their winit names in the settings.
+// Escape cancels the rebinding and can only quit, and F12 takes screenshots.
+pub const BINDABLE_KEYS: &[VirtualKeyCode] = {
+    use VirtualKeyCode::*;
+    &[
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1,
+        Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Numpad0, Numpad1, Numpad2, Numpad3,
+        Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Left, Right, Up, Down, Home, End,
+        PageUp, PageDown, Insert, Delete, Space, LShift, RShift, LControl, RControl, LAlt, RAlt,
+        Comma, Period, Semicolon, Apostrophe, Slash, Backslash, LBracket, RBracket, Minus, Equals,
+        Grave,
+    ]
+};
+
+pub fn get_key_name(key: VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+fn find_bindable_key(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .cloned()
+        .find(|key| get_key_name(*key) == name)
+}
+
+// the first bindable key held, imgui indexes the keys by the winit key codes
+pub fn find_held_bindable_key(keys_down: &[bool; 512]) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .cloned()
+        .find(|key| keys_down[*key as usize])
+}
+
+// escape or the first bindable key held
+pub fn find_held_quit_key(keys_down: &[bool; 512]) -> Option<VirtualKeyCode> {
+    if keys_down[VirtualKeyCode::Escape as usize] {
+        return Some(VirtualKeyCode::Escape);
+    }
+    find_held_bindable_key(keys_down)
+}
+
+// "none" when quitting with a key is off
+fn find_quit_key(name: &str) -> Option<Option<VirtualKeyCode>> {
+    match name {
+        "none" => Some(None),
+        _ if name == get_key_name(VirtualKeyCode::Escape) => Some(Some(VirtualKeyCode::Escape)),
+        _ => find_bindable_key(name).map(Some),
+    }
+}
+
+// The keys moving the camera, one for each direction, and the key quitting the application.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(
+    try_from = "BTreeMap<String, String>",
+    into = "BTreeMap<String, String>"
+)]
+pub struct KeyBindings {
+    // in the order of DIRECTIONS
+    keys: [VirtualKeyCode; 6],
+    // None by default, the application only quits by closing the window
+    quit: Option<VirtualKeyCode>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+        Self {
+            keys: [W, S, A, D, Z, X],
+            quit: None,
+        }
+    }
+}
+
+impl KeyBindings {
+    // the directions with their names in the UI and the settings
+    pub const DIRECTIONS: [(CameraDirection, &'static str); 6] = [
+        (CameraDirection::Forward, "forward"),
+        (CameraDirection::Backward, "backward"),
+        (CameraDirection::Left, "left"),
+        (CameraDirection::Right, "right"),
+        (CameraDirection::Up, "up"),
+        (CameraDirection::Down, "down"),
+    ];
+
+    // the key of the direction at the index of DIRECTIONS
+    pub fn get_key(&self, index: usize) -> VirtualKeyCode {
+        self.keys[index]
+    }
+
+    // Binds the key to the direction at the index of DIRECTIONS. The direction already bound to
+    // the key gets the previous key of this one, so that a key never moves two directions.
+    pub fn bind(&mut self, index: usize, key: VirtualKeyCode) {
+        if let Some(other) = self.keys.iter().position(|bound| *bound == key) {
+            self.keys[other] = self.keys[index];
+        }
+        if self.quit == Some(key) {
+            self.quit = None;
+        }
+        self.keys[index] = key;
+    }
+
+    pub fn get_quit_key(&self) -> Option<VirtualKeyCode> {
+        self.quit
+    }
+
+    // a Validation error if the key moves the camera, so that moving never quits
+    pub fn bind_quit(&mut self, key: Option<VirtualKeyCode>) -> Result<()> {
+        if let Some(key) = key {
+            if let Some(index) = self.keys.iter().position(|bound| *bound == key) {
+                bail!(ErrorKind::Validation(format!(
+                    "{} already moves the camera {}",
+                    get_key_name(key),
+                    Self::DIRECTIONS[index].1
+                )));
+            }
+        }
+        self.quit = key;
+        Ok(())
+    }
+
+    // moves the camera or quits
+    pub fn is_bound(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key) || self.quit == Some(key)
+    }
+
+    // the directions of the held keys
+    pub fn get_directions(&self, keys_down: &[bool; 512]) -> Vec<CameraDirection> {
+        Self::DIRECTIONS
+            .iter()
+            .zip(self.keys.iter())
+            .filter(|(_, key)| keys_down[**key as usize])
+            .map(|((direction, _), _)| *direction)
+            .collect()
+    }
+}
+
+// The names of the keys by the names of the directions in DIRECTIONS, and by "quit" with "none"
+// when quitting with a key is off.
+impl From<KeyBindings> for BTreeMap<String, String> {
+    fn from(key_bindings: KeyBindings) -> Self {
+        let mut names: Self = KeyBindings::DIRECTIONS
+            .iter()
+            .zip(key_bindings.keys.iter())
+            .map(|((_, direction), key)| (direction.to_string(), get_key_name(*key)))
+            .collect();
+        names.insert(
+            "quit".to_owned(),
+            key_bindings
+                .quit
+                .map_or_else(|| "none".to_owned(), get_key_name),
+        );
+        names
+    }
+}
+
+// The directions left out keep the default keys, so that the older settings can still be loaded.
+impl TryFrom<BTreeMap<String, String>> for KeyBindings {
+    type Error = String;
+
+    fn try_from(names: BTreeMap<String, String>) -> std::result::Result<Self, Self::Error> {
+        let mut key_bindings = Self::default();
+        for (index, (_, direction)) in Self::DIRECTIONS.iter().enumerate() {
+            if let Some(name) = names.get(*direction) {
+                let key = find_bindable_key(name)
+                    .ok_or_else(|| format!("{} can't be bound to {}", name, direction))?;
+                key_bindings.bind(index, key);
+            }
+        }
+        if let Some(name) = names.get("quit") {
+            let quit =
+                find_quit_key(name).ok_or_else(|| format!("{} can't be bound to quit", name))?;
+            key_bindings
+                .bind_quit(quit)
+                .map_err(|e| format!("fail to bind quit: {}", e))?;
+        }
+        Ok(key_bindings)
+    }
+}
+
+// The settings kept across restarts, stored as JSON. The missing fields keep the defaults and the
+// unknown ones are ignored, so that the settings written by other versions can still be loaded.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub ui_appearance: UiAppearance,
+    pub mouse_look: MouseLook,
+    pub key_bindings: KeyBindings,
+    // the options last used to import each model file
+    pub import_options: BTreeMap<PathBuf, ImportOptions>,
+    // the units of the scene of each model file
+    pub scene_units: BTreeMap<PathBuf, SceneUnits>,
+    // the hint pointing at the help is only shown on the first launch
+    pub help_hint_shown: bool,
+}
+
+impl Settings {
+    // the default settings if the file doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json)
+                .chain_err(|| ErrorKind::AssetParse(path.display().to_string())),
+            Err(e) if e.kind() == IoErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).chain_err(|| format!("fail to read {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?).chain_err(|| format!("fail to write {}", path.display()))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).chain_err(|| "fail to serialize the settings")
+    }
+
+    // a Validation error if a value is out of its range
+    pub fn from_json(json: &str) -> Result<Self> {
+        let settings: Self = serde_json::from_str(json)
+            .chain_err(|| ErrorKind::Validation("fail to deserialize the settings".to_owned()))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !UI_SCALE_RANGE.contains(&self.ui_appearance.scale) {
+            bail!(ErrorKind::Validation(format!(
+                "the UI scale {} is out of {:?}",
+                self.ui_appearance.scale, UI_SCALE_RANGE
+            )));
+        }
+        if !MOUSE_SENSITIVITY_RANGE.contains(&self.mouse_look.sensitivity) {
+            bail!(ErrorKind::Validation(format!(
+                "the mouse sensitivity {} is out of {:?}",
+                self.mouse_look.sensitivity, MOUSE_SENSITIVITY_RANGE
+            )));
+        }
+        for (path, units) in self.scene_units.iter() {
+            if let SceneUnits::Custom(meters_per_unit) = units {
+                SceneUnits::custom(*meters_per_unit)
+                    .chain_err(|| format!("invalid scene units of {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::errors::find_error_kind;
+    use crate::scene::{LengthUnit, UpAxis};
+    use crate::support::UiTheme;
+
+    #[test]
+    fn test_round_trip() {
+        let settings = Settings {
+            ui_appearance: UiAppearance {
+                theme: UiTheme::Light,
+                scale: 1.5,
+            },
+            mouse_look: MouseLook {
+                sensitivity: 0.1,
+                invert_y: true,
+            },
+            key_bindings: {
+                let mut key_bindings = KeyBindings::default();
+                key_bindings.bind(0, VirtualKeyCode::Up);
+                key_bindings.bind(4, VirtualKeyCode::Space);
+                key_bindings
+                    .bind_quit(Some(VirtualKeyCode::Escape))
+                    .unwrap();
+                key_bindings
+            },
+            import_options: vec![
+                (
+                    PathBuf::from("models/a model.obj"),
+                    ImportOptions {
+                        up_axis: UpAxis::Z,
+                        unit: LengthUnit::Centimeter,
+                    },
+                ),
+                (PathBuf::from("b.obj"), ImportOptions::default()),
+            ]
+            .into_iter()
+            .collect(),
+            scene_units: vec![
+                (PathBuf::from("models/a model.obj"), SceneUnits::Millimeter),
+                (PathBuf::from("b.obj"), SceneUnits::custom(0.3048).unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+            help_hint_shown: true,
+        };
+        assert_eq!(
+            Settings::from_json(&settings.to_json().unwrap()).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn test_from_json_ignores_unknown_fields() {
+        let settings =
+            Settings::from_json(r#"{"unknown": 1, "ui_appearance": {"theme": "classic"}}"#)
+                .unwrap();
+        assert_eq!(settings.ui_appearance.theme, UiTheme::Classic);
+        assert_eq!(settings.ui_appearance.scale, UiAppearance::default().scale);
+    }
+
+    #[test]
+    fn test_from_json_rejects_invalid_values() {
+        for json in &[
+            "ui_theme = dark",
+            r#"{"ui_appearance": {"theme": "blue"}}"#,
+            r#"{"ui_appearance": {"scale": "big"}}"#,
+            r#"{"ui_appearance": {"scale": 3}}"#,
+            r#"{"mouse_look": {"sensitivity": 0}}"#,
+            r#"{"mouse_look": {"invert_y": "yes"}}"#,
+            r#"{"help_hint_shown": 1}"#,
+            r#"{"key_bindings": {"forward": "F12"}}"#,
+            r#"{"key_bindings": {"left": "Escape"}}"#,
+            r#"{"key_bindings": {"up": "space"}}"#,
+            r#"{"key_bindings": {"quit": "F12"}}"#,
+            r#"{"key_bindings": {"quit": "W"}}"#,
+            r#"{"import_options": {"a.obj": {"up_axis": "x"}}}"#,
+            r#"{"scene_units": {"a.obj": "km"}}"#,
+            r#"{"scene_units": {"a.obj": {"custom": 0}}}"#,
+        ] {
+            let e = Settings::from_json(json).err().unwrap();
+            assert!(
+                matches!(find_error_kind(&e), Some(ErrorKind::Validation(_))),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn test_mouse_look_angles_are_linear() {
+        let mouse_look = MouseLook {
+            sensitivity: 0.1,
+            invert_y: false,
+        };
+        let (yaw, pitch) = mouse_look.get_yaw_and_pitch((100.0, -50.0));
+        assert!((yaw.to_degrees() + 10.0).abs() < 1e-4);
+        assert!((pitch.to_degrees() - 5.0).abs() < 1e-4);
+        // fast flicks aren't capped
+        let (yaw, _) = mouse_look.get_yaw_and_pitch((10000.0, 0.0));
+        assert!((yaw.to_degrees() + 1000.0).abs() < 1e-2);
+        let inverted = MouseLook {
+            invert_y: true,
+            ..mouse_look
+        };
+        let (_, pitch) = inverted.get_yaw_and_pitch((100.0, -50.0));
+        assert!((pitch.to_degrees() + 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let mut key_bindings = KeyBindings::default();
+        let mut keys_down = [false; 512];
+        keys_down[VirtualKeyCode::W as usize] = true;
+        keys_down[VirtualKeyCode::D as usize] = true;
+        keys_down[VirtualKeyCode::Q as usize] = true;
+        let names = |directions: Vec<CameraDirection>| -> Vec<String> {
+            directions.iter().map(|d| format!("{:?}", d)).collect()
+        };
+        assert_eq!(
+            names(key_bindings.get_directions(&keys_down)),
+            vec!["Forward", "Right"]
+        );
+        // binding a key of another direction swaps them
+        key_bindings.bind(0, VirtualKeyCode::D);
+        assert_eq!(key_bindings.get_key(0), VirtualKeyCode::D);
+        assert_eq!(key_bindings.get_key(3), VirtualKeyCode::W);
+        key_bindings.bind(1, VirtualKeyCode::Q);
+        assert_eq!(
+            names(key_bindings.get_directions(&keys_down)),
+            vec!["Forward", "Backward", "Right"]
+        );
+        assert_eq!(find_held_bindable_key(&keys_down), Some(VirtualKeyCode::D));
+        assert_eq!(find_held_bindable_key(&[false; 512]), None);
+    }
+
+    #[test]
+    fn test_quit_key_never_moves_the_camera() {
+        let mut key_bindings = KeyBindings::default();
+        assert_eq!(key_bindings.get_quit_key(), None);
+        assert!(key_bindings.bind_quit(Some(VirtualKeyCode::W)).is_err());
+        key_bindings.bind_quit(Some(VirtualKeyCode::Q)).unwrap();
+        assert_eq!(key_bindings.get_quit_key(), Some(VirtualKeyCode::Q));
+        // moving with the quit key unbinds it from quitting
+        key_bindings.bind(0, VirtualKeyCode::Q);
+        assert_eq!(key_bindings.get_quit_key(), None);
+
+        let mut keys_down = [false; 512];
+        keys_down[VirtualKeyCode::Escape as usize] = true;
+        assert_eq!(find_held_bindable_key(&keys_down), None);
+        assert_eq!(find_held_quit_key(&keys_down), Some(VirtualKeyCode::Escape));
+        let settings = Settings::from_json(r#"{"key_bindings": {"quit": "Escape"}}"#).unwrap();
+        assert_eq!(
+            settings.key_bindings.get_quit_key(),
+            Some(VirtualKeyCode::Escape)
+        );
+        let settings = Settings::from_json(r#"{"key_bindings": {"quit": "none"}}"#).unwrap();
+        assert_eq!(settings.key_bindings.get_quit_key(), None);
+    }
+
+    #[test]
+    fn test_key_bindings_from_json() {
+        let settings =
+            Settings::from_json(r#"{"key_bindings": {"forward": "Up", "down": "LShift"}}"#)
+                .unwrap();
+        assert_eq!(settings.key_bindings.get_key(0), VirtualKeyCode::Up);
+        assert_eq!(settings.key_bindings.get_key(5), VirtualKeyCode::LShift);
+        assert_eq!(settings.key_bindings.get_key(1), VirtualKeyCode::S);
+        // the older settings without bindings keep the defaults
+        let settings = Settings::from_json(r#"{"ui_appearance": {"scale": 1}}"#).unwrap();
+        assert_eq!(settings.key_bindings, KeyBindings::default());
+    }
+}
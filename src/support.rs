@@ -3,35 +3,49 @@
 // This software is released under the MIT License.
 // https://opensource.org/licenses/MIT
 
-use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, Ui};
+use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, Style, TextureId, Ui};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use serde::{Deserialize, Serialize};
 
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::device::Queue;
 use vulkano::device::{Device, DeviceExtensions};
-use vulkano::image::{ImageUsage, SwapchainImage};
-use vulkano::instance::{Instance, PhysicalDevice};
+use vulkano::format::Format;
+use vulkano::image::{AttachmentImage, ImageUsage, SwapchainImage};
+use vulkano::instance::{Instance, PhysicalDevice, QueueFamily};
+use vulkano::sampler::Sampler;
 use vulkano::swapchain;
 use vulkano::swapchain::Surface;
 use vulkano::swapchain::{
-    AcquireError, ColorSpace, FullscreenExclusive, PresentMode, SurfaceTransform, Swapchain,
-    SwapchainCreationError,
+    AcquireError, Capabilities, CompositeAlpha, FullscreenExclusive, PresentMode,
+    SupportedCompositeAlpha, SurfaceTransform, Swapchain, SwapchainCreationError,
 };
 use vulkano::sync;
-use vulkano::sync::{FlushError, GpuFuture};
+use vulkano::sync::{FenceSignalFuture, FlushError, GpuFuture};
 
 use vulkano_win::VkSurfaceBuild;
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Window, WindowBuilder};
 
 use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use imgui_vulkano_renderer::Renderer as UiRenderer;
 
-use super::scene::{Renderer as SceneRenderer, State as SceneState};
+use super::composite::Compositor;
+use super::frame_ring::FrameRing;
+use super::profile::{self, ProfileReason};
+use super::readback::ReadbackQueue;
+use super::scene::{
+    LuminanceHistogram, LuminanceStats, PixelProbe, ProbedPixel, Renderer as SceneRenderer,
+    State as SceneState, DEFAULT_DEPTH_RANGE,
+};
 use crate::errors::*;
 
 mod clipboard {
@@ -54,6 +68,45 @@ mod clipboard {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UiTheme {
+    Dark,
+    Light,
+    Classic,
+}
+
+impl UiTheme {
+    pub const ALL: [UiTheme; 3] = [UiTheme::Dark, UiTheme::Light, UiTheme::Classic];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            UiTheme::Dark => "dark",
+            UiTheme::Light => "light",
+            UiTheme::Classic => "classic",
+        }
+    }
+}
+
+pub const UI_SCALE_RANGE: RangeInclusive<f32> = 0.75..=2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiAppearance {
+    pub theme: UiTheme,
+    // on top of the scale factor of the window
+    pub scale: f32,
+}
+
+impl Default for UiAppearance {
+    fn default() -> Self {
+        Self {
+            theme: UiTheme::Dark,
+            scale: 1.0,
+        }
+    }
+}
+
 pub trait ApplicationT {
     fn new(surface: Arc<Surface<Window>>, scene_renderer: Rc<RefCell<SceneRenderer>>) -> Self;
     fn get_scene_state(&mut self) -> Result<SceneState>;
@@ -66,8 +119,67 @@ pub trait ApplicationT {
     fn on_mouse_button(&mut self, _button: MouseButton, _state: ElementState) -> Result<()> {
         Ok(())
     }
+    // in lines, positive when the wheel is rolled away from the user
+    fn on_mouse_wheel(&mut self, _delta: f32) -> Result<()> {
+        Ok(())
+    }
+    // when frozen, the scene is not rendered and the last rendered scene image is presented
+    fn is_scene_frozen(&self) -> bool {
+        false
+    }
+    // the path to save the scene image of this frame to
+    fn take_screenshot_request(&mut self) -> Option<PathBuf> {
+        None
+    }
+    fn set_in_flight_readbacks(&mut self, _count: usize) {}
+    // the errors of the work done in the background, e.g. saving the screenshots
+    fn on_background_error(&mut self, _e: Error) {}
+    // whether to exit when the window is closed, the application may ask for a confirmation first
+    fn on_close_requested(&mut self) -> bool {
+        true
+    }
+    // polled after the UI is updated, e.g. when the quit key is pressed or the quit is confirmed
+    fn is_quit_requested(&self) -> bool {
+        false
+    }
+    // the UI texture the scene image is registered as, and the dimensions of the scene image
+    fn set_scene_texture(&mut self, _texture_id: TextureId, _dimensions: [u32; 2]) {}
+    // when set, the scene is only shown through its UI texture, and the UI is drawn over a blank
+    // background instead of the scene
+    fn is_scene_in_window(&self) -> bool {
+        false
+    }
+    // the size to render the scene at when it's in a window, by default the swapchain size
+    fn get_scene_view_dimensions(&self) -> Option<[u32; 2]> {
+        None
+    }
+    // applied between the frames when it changes, since the font atlas has to be rebuilt
+    fn get_ui_appearance(&self) -> UiAppearance {
+        UiAppearance::default()
+    }
+    // the luminance histogram of the scene image is only computed when it's shown
+    fn is_luminance_histogram_enabled(&self) -> bool {
+        false
+    }
+    // the latest histogram read back, a few frames behind the scene image
+    fn set_luminance_stats(&mut self, _stats: LuminanceStats) {}
+    // the pixel of the scene image to read back, e.g. the one under the cursor
+    fn get_probed_pixel(&self) -> Option<[u32; 2]> {
+        None
+    }
+    // the latest pixel read back, a frame or more behind the scene image
+    fn set_probed_pixel(&mut self, _probed: ProbedPixel) {}
 }
 
+// More frames in flight let the CPU record the next frames while the GPU is still rendering, at
+// the cost of the input showing up that many frames later.
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+pub const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+// Send and Sync, as the fences are shared by Arc
+type FrameFuture = Box<dyn GpuFuture + Send + Sync>;
+type FrameFence = Arc<FenceSignalFuture<FrameFuture>>;
+
 pub struct System {
     pub event_loop: EventLoop<()>,
     pub device: Arc<Device>,
@@ -75,11 +187,144 @@ pub struct System {
     pub surface: Arc<Surface<Window>>,
     pub swapchain: Arc<Swapchain<Window>>,
     pub images: Vec<Arc<SwapchainImage<Window>>>,
+    // the scene is rendered here and copied to the swapchain image, so that it can be presented
+    // again without being rendered
+    pub scene_image: Arc<AttachmentImage>,
     pub imgui: Context,
     pub platform: WinitPlatform,
     pub ui_renderer: UiRenderer,
+    compositor: Compositor,
     pub font_size: f32,
+    // the style before any UI appearance is applied
+    base_style: Style,
+    frames_in_flight: usize,
+    // where the profiled frames are written on exit
+    profile_dump: Option<PathBuf>,
     pub scene_renderer: Rc<RefCell<SceneRenderer>>,
+    // what the initialization fell back from, reported to the application once it's created
+    startup_warnings: Vec<Error>,
+}
+
+fn create_scene_image(
+    device: Arc<Device>,
+    dimensions: [u32; 2],
+    format: Format,
+) -> Result<Arc<AttachmentImage>> {
+    AttachmentImage::with_usage(
+        device,
+        dimensions,
+        format,
+        ImageUsage {
+            transfer_source: true,
+            transfer_destination: true,
+            sampled: true,
+            ..ImageUsage::color_attachment()
+        },
+    )
+    .chain_err(|| "fail to create the image to render the scene on")
+}
+
+// some compositors transiently report no formats right after the window is created
+const SURFACE_CAPABILITIES_ATTEMPTS: u32 = 5;
+const SURFACE_CAPABILITIES_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+// touchpads scroll in pixels, converted to the lines of the mouse wheels
+const PIXELS_PER_SCROLL_LINE: f64 = 20.0;
+
+// A family only for the transfers, e.g. the copy engine of a discrete GPU, so that the uploads of
+// the models run beside the rendering. None if the device doesn't expose one.
+fn find_transfer_queue_family(physical: PhysicalDevice) -> Option<QueueFamily> {
+    physical.queue_families().find(|family| {
+        family.explicitly_supports_transfers()
+            && !family.supports_graphics()
+            && !family.supports_compute()
+            && family.queues_count() > 0
+    })
+}
+
+fn get_surface_capabilities(
+    surface: &Surface<Window>,
+    physical: PhysicalDevice,
+    title: &str,
+    warnings: &mut Vec<Error>,
+) -> Result<Capabilities> {
+    for attempt in 1..=SURFACE_CAPABILITIES_ATTEMPTS {
+        let caps = surface.capabilities(physical).chain_err(|| {
+            format!(
+                "fail to query the capabilities of the surface of window \"{}\"",
+                title
+            )
+        })?;
+        if !caps.supported_formats.is_empty() {
+            return Ok(caps);
+        }
+        warnings.push(
+            format!(
+                "the surface of window \"{}\" reports no supported formats, attempt {} of {}",
+                title, attempt, SURFACE_CAPABILITIES_ATTEMPTS
+            )
+            .into(),
+        );
+        thread::sleep(SURFACE_CAPABILITIES_RETRY_INTERVAL);
+    }
+    Err(format!(
+        "the surface of window \"{}\" reports no supported formats after {} attempts, check the \
+        window system and the Vulkan driver",
+        title, SURFACE_CAPABILITIES_ATTEMPTS
+    )
+    .into())
+}
+
+// prefer opaque, otherwise fall back to whatever the compositor supports
+fn choose_composite_alpha(
+    supported: SupportedCompositeAlpha,
+    warnings: &mut Vec<Error>,
+) -> Result<CompositeAlpha> {
+    if supported.opaque {
+        return Ok(CompositeAlpha::Opaque);
+    }
+    let alpha = supported.iter().next().ok_or_else(|| -> Error {
+        "the surface doesn't support any composite alpha mode".into()
+    })?;
+    warnings.push(
+        format!(
+            "the surface doesn't support opaque composite alpha, fall back to {:?}",
+            alpha
+        )
+        .into(),
+    );
+    Ok(alpha)
+}
+
+fn choose_image_count(caps: &Capabilities) -> Result<u32> {
+    let image_count = caps.min_image_count.max(1);
+    match caps.max_image_count {
+        Some(max_image_count) if image_count > max_image_count => Err(format!(
+            "invalid surface image count range, min = {}, max = {}",
+            caps.min_image_count, max_image_count
+        )
+        .into()),
+        _ => Ok(image_count),
+    }
+}
+
+fn choose_dimensions(caps: &Capabilities, window_dimensions: [u32; 2]) -> Result<[u32; 2]> {
+    let [min_width, min_height] = caps.min_image_extent;
+    let [max_width, max_height] = caps.max_image_extent;
+    if max_width == 0 || max_height == 0 || min_width > max_width || min_height > max_height {
+        return Err(format!(
+            "invalid surface extent range, min = {:?}, max = {:?}, the window may be minimized",
+            caps.min_image_extent, caps.max_image_extent
+        )
+        .into());
+    }
+    if let Some(current_extent) = caps.current_extent {
+        return Ok(current_extent);
+    }
+    let [width, height] = window_dimensions;
+    Ok([
+        width.max(min_width).min(max_width),
+        height.max(min_height).min(max_height),
+    ])
 }
 
 pub fn init(title: &str) -> Result<System> {
@@ -111,24 +356,37 @@ pub fn init(title: &str) -> Result<System> {
         khr_swapchain: true,
         ..DeviceExtensions::none()
     };
+    let transfer_queue_family = find_transfer_queue_family(physical);
+    // the optional features, e.g. depth_clamp and fill_mode_non_solid for the wireframe, are used
+    // when they are enabled
     let (device, mut queues) = Device::new(
         physical,
         physical.supported_features(),
         &device_ext,
-        [(queue_family, 0.5)].iter().cloned(),
+        std::iter::once((queue_family, 0.5))
+            .chain(transfer_queue_family.map(|family| (family, 0.5))),
     )
     .unwrap();
 
     let queue = queues.next().unwrap();
+    // the models are uploaded through the graphics queue without a transfer queue
+    let upload_queue = queues.next().unwrap_or_else(|| queue.clone());
 
+    let mut startup_warnings = vec![];
     let (swapchain, images, format) = {
-        let caps = surface.capabilities(physical).unwrap();
+        let caps = get_surface_capabilities(&surface, physical, title, &mut startup_warnings)
+            .chain_err(|| "fail to get the surface capabilities")?;
+
+        let alpha = choose_composite_alpha(caps.supported_composite_alpha, &mut startup_warnings)
+            .chain_err(|| "fail to choose the composite alpha mode")?;
 
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+        let (format, color_space) = caps.supported_formats[0];
 
-        let format = caps.supported_formats[0].0;
+        let image_count =
+            choose_image_count(&caps).chain_err(|| "fail to choose the swapchain image count")?;
 
-        let dimensions: [u32; 2] = surface.window().inner_size().into();
+        let dimensions = choose_dimensions(&caps, surface.window().inner_size().into())
+            .chain_err(|| "fail to choose the swapchain dimensions")?;
 
         let image_usage = ImageUsage {
             transfer_destination: true,
@@ -138,7 +396,7 @@ pub fn init(title: &str) -> Result<System> {
         let (swapchain, image) = Swapchain::new(
             device.clone(),
             surface.clone(),
-            caps.min_image_count,
+            image_count,
             format,
             dimensions,
             1,
@@ -149,9 +407,9 @@ pub fn init(title: &str) -> Result<System> {
             PresentMode::Mailbox,
             FullscreenExclusive::Default,
             true,
-            ColorSpace::SrgbNonLinear,
+            color_space,
         )
-        .unwrap();
+        .chain_err(|| "fail to create the swapchain")?;
         (swapchain, image, format)
     };
 
@@ -164,38 +422,32 @@ pub fn init(title: &str) -> Result<System> {
     let mut platform = WinitPlatform::init(&mut imgui);
     platform.attach_window(imgui.io_mut(), &surface.window(), HiDpiMode::Rounded);
 
+    // The UI works in logical pixels of the rounded scale factor, i.e. io.mouse_pos and the window
+    // rects, and io.display_framebuffer_scale is that factor. The fonts are rasterized at the
+    // physical size and scaled back so they stay sharp.
     let hidpi_factor = platform.hidpi_factor();
-    let font_size = (13.0 * hidpi_factor) as f32;
-    imgui.fonts().add_font(&[
-        FontSource::DefaultFontData {
-            config: Some(FontConfig {
-                size_pixels: font_size,
-                ..FontConfig::default()
-            }),
-        },
-        FontSource::TtfData {
-            data: include_bytes!("../resources/mplus-1p-regular.ttf"),
-            size_pixels: font_size,
-            config: Some(FontConfig {
-                rasterizer_multiply: 1.75,
-                glyph_ranges: FontGlyphRanges::japanese(),
-                ..FontConfig::default()
-            }),
-        },
-    ]);
-
-    imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
+    let font_size = add_fonts(&mut imgui, hidpi_factor, 1.0);
+    let base_style = *imgui.style();
 
+    let compositor = Compositor::new(device.clone(), swapchain.dimensions(), format)?;
+    // the scene and the UI are drawn in UNORM, see Compositor
+    let format = compositor.get_format();
     let ui_renderer = UiRenderer::init(&mut imgui, device.clone(), queue.clone(), format)
         .expect("Failed to initialize UI renderer");
 
+    let scene_image = create_scene_image(device.clone(), swapchain.dimensions(), format)?;
+
+    // the swapchain dimensions may differ from the window size when the surface restricts them
+    let [width, height] = swapchain.dimensions();
     let scene_renderer = Rc::new(RefCell::new(
         SceneRenderer::init(
             device.clone(),
             queue.clone(),
+            upload_queue,
             format,
-            surface.window().inner_size().width,
-            surface.window().inner_size().height,
+            width,
+            height,
+            DEFAULT_DEPTH_RANGE,
         )
         .chain_err(|| "fail to create scene renderer")?,
     ));
@@ -207,15 +459,82 @@ pub fn init(title: &str) -> Result<System> {
         surface,
         swapchain,
         images,
+        scene_image,
         imgui,
         platform,
         ui_renderer,
+        compositor,
         font_size,
+        base_style,
+        frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+        profile_dump: None,
         scene_renderer,
+        startup_warnings,
     })
 }
 
+// returns the font size in physical pixels
+fn add_fonts(imgui: &mut Context, hidpi_factor: f64, scale: f32) -> f32 {
+    let font_size = (13.0 * hidpi_factor) as f32 * scale;
+    imgui.fonts().add_font(&[
+        FontSource::DefaultFontData {
+            config: Some(FontConfig {
+                size_pixels: font_size,
+                ..FontConfig::default()
+            }),
+        },
+        FontSource::TtfData {
+            data: include_bytes!("../resources/mplus-1p-regular.ttf"),
+            size_pixels: font_size,
+            config: Some(FontConfig {
+                rasterizer_multiply: 1.75,
+                glyph_ranges: FontGlyphRanges::japanese(),
+                ..FontConfig::default()
+            }),
+        },
+    ]);
+
+    imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
+    font_size
+}
+
+// the font texture has to be uploaded again after this
+fn apply_ui_appearance(
+    imgui: &mut Context,
+    base_style: &Style,
+    hidpi_factor: f64,
+    appearance: UiAppearance,
+) {
+    imgui.fonts().clear();
+    add_fonts(imgui, hidpi_factor, appearance.scale);
+    let style = imgui.style_mut();
+    *style = *base_style;
+    style.scale_all_sizes(appearance.scale);
+    match appearance.theme {
+        UiTheme::Dark => style.use_dark_colors(),
+        UiTheme::Light => style.use_light_colors(),
+        UiTheme::Classic => style.use_classic_colors(),
+    };
+}
+
 impl System {
+    pub fn set_frames_in_flight(&mut self, frames_in_flight: usize) -> Result<()> {
+        if frames_in_flight == 0 || frames_in_flight > MAX_FRAMES_IN_FLIGHT {
+            bail!(ErrorKind::Validation(format!(
+                "the frames in flight should be in 1..={}, found {}",
+                MAX_FRAMES_IN_FLIGHT, frames_in_flight
+            )));
+        }
+        self.frames_in_flight = frames_in_flight;
+        Ok(())
+    }
+
+    // profiles every frame, not only while the profiler panel is open
+    pub fn set_profile_dump(&mut self, path: PathBuf) {
+        profile::set_enabled(ProfileReason::Dump, true);
+        self.profile_dump = Some(path);
+    }
+
     pub fn main_loop<T: ApplicationT + 'static>(self) -> ! {
         let System {
             event_loop,
@@ -224,18 +543,62 @@ impl System {
             surface,
             mut swapchain,
             mut images,
+            mut scene_image,
             mut imgui,
             mut platform,
             mut ui_renderer,
+            mut compositor,
+            base_style,
+            frames_in_flight,
+            profile_dump,
             scene_renderer,
+            startup_warnings,
             ..
         } = self;
 
         let mut recreate_swapchain = false;
+        // the scene image has to be rendered at least once after it is created, even when frozen
+        let mut scene_image_outdated = true;
 
-        let mut previous_frame_end = Some(sync::now(device.clone()).boxed());
+        // the fences of the frames in flight, the GPU work of each frame is still chained after
+        // the previous frame, since they share the scene image
+        let mut frames: FrameRing<FrameFence> = FrameRing::new(frames_in_flight);
+        // imgui starts with the dark colors at the scale of 1
+        let mut ui_appearance = UiAppearance::default();
 
         let mut application = T::new(surface.clone(), scene_renderer.clone());
+        for warning in startup_warnings {
+            application.on_background_error(warning);
+        }
+
+        let scene_sampler = Sampler::simple_repeat_linear_no_mipmap(device.clone());
+        let scene_texture_id = ui_renderer
+            .textures()
+            .insert((scene_image.clone(), scene_sampler.clone()));
+        application.set_scene_texture(scene_texture_id, scene_image.dimensions());
+
+        let mut readback_queue = match ReadbackQueue::new(device.clone()) {
+            Ok(readback_queue) => readback_queue,
+            Err(e) => {
+                eprint_chained_err(&e);
+                ::std::process::exit(exit_code(&e));
+            }
+        };
+        let mut luminance_histogram = match LuminanceHistogram::new(device.clone()) {
+            Ok(luminance_histogram) => luminance_histogram,
+            Err(e) => {
+                eprint_chained_err(&e);
+                ::std::process::exit(exit_code(&e));
+            }
+        };
+
+        let mut pixel_probe = match PixelProbe::new(device.clone()) {
+            Ok(pixel_probe) => pixel_probe,
+            Err(e) => {
+                eprint_chained_err(&e);
+                ::std::process::exit(exit_code(&e));
+            }
+        };
 
         let res = Arc::new(Mutex::new(Ok(())));
         event_loop.run(move |event, _, control_flow| match event {
@@ -243,16 +606,72 @@ impl System {
                 // imgui.io_mut().update_delta_time(Instant::now());
             }
             Event::MainEventsCleared => {
+                // between the frames, so that the draw data of the current frame still refers to
+                // the old font texture
+                let new_ui_appearance = application.get_ui_appearance();
+                if new_ui_appearance != ui_appearance {
+                    apply_ui_appearance(
+                        &mut imgui,
+                        &base_style,
+                        platform.hidpi_factor(),
+                        new_ui_appearance,
+                    );
+                    if let Err(e) =
+                        ui_renderer.reload_font_texture(&mut imgui, device.clone(), queue.clone())
+                    {
+                        *control_flow = ControlFlow::Exit;
+                        *res.lock().unwrap() =
+                            Err(e).chain_err(|| "fail to upload the UI font texture");
+                        return;
+                    }
+                    ui_appearance = new_ui_appearance;
+                }
                 platform
                     .prepare_frame(imgui.io_mut(), &surface.window())
                     .expect("Failed to prepare frame");
                 surface.window().request_redraw();
             }
             Event::RedrawRequested(_) => {
-                previous_frame_end.as_mut().unwrap().cleanup_finished();
+                profile::begin_frame();
+                for frame in frames.iter_mut() {
+                    frame.cleanup_finished();
+                }
+                // block until the frame last submitted from this slot is finished, so that the
+                // CPU is at most frames_in_flight frames ahead of the GPU
+                if let Some(frame) = frames.take_current() {
+                    let waited = {
+                        profile_scope!("wait for frame in flight");
+                        frame.wait(None)
+                    };
+                    if let Err(e) = waited {
+                        *control_flow = ControlFlow::Exit;
+                        *res.lock().unwrap() =
+                            Err(e).chain_err(|| "fail to wait for a frame in flight");
+                        return;
+                    }
+                }
+                for e in readback_queue.poll() {
+                    application.on_background_error(e);
+                }
+                application.set_in_flight_readbacks(readback_queue.get_in_flight_count());
+                for e in luminance_histogram.poll() {
+                    application.on_background_error(
+                        e.chain_err(|| "fail to read back the luminance histogram"),
+                    );
+                }
+                if let Some(stats) = luminance_histogram.take_stats() {
+                    application.set_luminance_stats(stats);
+                }
+                for e in pixel_probe.poll() {
+                    application
+                        .on_background_error(e.chain_err(|| "fail to read the probed pixel"));
+                }
+                if let Some(probed) = pixel_probe.take_probed() {
+                    application.set_probed_pixel(probed);
+                }
 
                 if recreate_swapchain {
-                    // TODO: recreate scene_renderer here
+                    // physical pixels, the same as the swapchain and the scene image
                     let dimensions: [u32; 2] = surface.window().inner_size().into();
                     let (new_swapchain, new_images) =
                         match swapchain.recreate_with_dimensions(dimensions) {
@@ -264,15 +683,64 @@ impl System {
                     images = new_images;
                     swapchain = new_swapchain;
                     recreate_swapchain = false;
+                    if let Err(e) = compositor.resize(device.clone(), swapchain.dimensions()) {
+                        *control_flow = ControlFlow::Exit;
+                        *res.lock().unwrap() = Err(e);
+                        return;
+                    }
+                }
+
+                // the scene image is copied to the swapchain image unless it's in a window, so
+                // they have to be of the same size
+                let scene_dimensions = match application.get_scene_view_dimensions() {
+                    Some(dimensions) if application.is_scene_in_window() => dimensions,
+                    _ => swapchain.dimensions(),
+                };
+                if scene_dimensions != scene_image.dimensions() {
+                    let [width, height] = scene_dimensions;
+                    let resized = create_scene_image(
+                        device.clone(),
+                        scene_dimensions,
+                        compositor.get_format(),
+                    )
+                    .and_then(|new_scene_image| {
+                        scene_renderer
+                            .borrow_mut()
+                            .resize(width, height)
+                            .chain_err(|| "fail to resize the scene renderer")?;
+                        Ok(new_scene_image)
+                    });
+                    scene_image = match resized {
+                        Ok(scene_image) => scene_image,
+                        Err(e) => {
+                            *control_flow = ControlFlow::Exit;
+                            *res.lock().unwrap() = Err(e);
+                            return;
+                        }
+                    };
+                    ui_renderer.textures().replace(
+                        scene_texture_id,
+                        (scene_image.clone(), scene_sampler.clone()),
+                    );
+                    application.set_scene_texture(scene_texture_id, scene_image.dimensions());
+                    scene_image_outdated = true;
                 }
 
                 let mut ui = imgui.frame();
 
-                if let Err(e) = application.update_ui(&mut ui) {
+                let updated = {
+                    profile_scope!("update ui");
+                    application.update_ui(&mut ui)
+                };
+                if let Err(e) = updated {
                     *control_flow = ControlFlow::Exit;
                     *res.lock().unwrap() = Err(e);
                     return;
                 }
+                if application.is_quit_requested() {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
 
                 let (image_num, suboptimal, acquire_future) =
                     match swapchain::acquire_next_image(swapchain.clone(), None) {
@@ -288,6 +756,7 @@ impl System {
                     recreate_swapchain = true;
                 }
 
+                let ui_scope = profile::ProfileScopeGuard::new("record ui");
                 platform.prepare_render(&ui, surface.window());
                 let draw_data = ui.render();
 
@@ -295,92 +764,211 @@ impl System {
                     AutoCommandBufferBuilder::new(device.clone(), queue.family())
                         .expect("Failed to create UI command buffer");
 
-                ui_renderer
-                    .draw_commands(
-                        &mut ui_cmd_buf_builder,
-                        queue.clone(),
-                        images[image_num].clone(),
-                        draw_data,
-                    )
-                    .expect("Rendering failed");
+                // the scene image is under the UI unless it's in a window
+                let background = if application.is_scene_in_window() {
+                    None
+                } else {
+                    Some(scene_image.clone())
+                };
+                if let Err(e) = compositor.record(
+                    &mut ui_cmd_buf_builder,
+                    &mut ui_renderer,
+                    queue.clone(),
+                    background,
+                    images[image_num].clone(),
+                    draw_data,
+                ) {
+                    *control_flow = ControlFlow::Exit;
+                    *res.lock().unwrap() = Err(e);
+                    return;
+                }
 
                 let ui_cmd_buf = ui_cmd_buf_builder
                     .build()
                     .expect("Failed to build UI command buffer");
+                drop(ui_scope);
 
                 let mut scene_cmd_buf_builder =
                     AutoCommandBufferBuilder::new(device.clone(), queue.family())
                         .expect("Failed to create scene renderer command buffer");
+                // the uniform updates of the scene, when they are separated from the reused draws
+                let mut uniform_cmd_buf = None;
 
-                scene_cmd_buf_builder
-                    .clear_color_image(images[image_num].clone(), [0.0; 4].into())
-                    .unwrap();
-
-                let scene_state = match application
-                    .get_scene_state()
-                    .chain_err(|| "fail to get scene state when trying to render the scene")
-                {
-                    Ok(scene_state) => scene_state,
-                    Err(e) => {
+                if scene_image_outdated || !application.is_scene_frozen() {
+                    let scene_state = {
+                        profile_scope!("get scene state");
+                        application.get_scene_state()
+                    };
+                    let scene_state = match scene_state
+                        .chain_err(|| "fail to get scene state when trying to render the scene")
+                    {
+                        Ok(scene_state) => scene_state,
+                        Err(e) => {
+                            *control_flow = ControlFlow::Exit;
+                            *res.lock().unwrap() = Err(e);
+                            return;
+                        }
+                    };
+                    if scene_renderer.borrow().is_command_buffer_reuse_enabled() {
+                        let mut uniform_cmd_buf_builder =
+                            AutoCommandBufferBuilder::new(device.clone(), queue.family())
+                                .expect("Failed to create scene uniforms command buffer");
+                        if let Err(e) = scene_renderer
+                            .borrow()
+                            .prepare_draw_commands(&mut uniform_cmd_buf_builder, &scene_state)
+                            .chain_err(|| "scene renderer fail to issue uniform update commands")
+                        {
+                            *control_flow = ControlFlow::Exit;
+                            *res.lock().unwrap() = Err(e);
+                            return;
+                        }
+                        uniform_cmd_buf = Some(
+                            uniform_cmd_buf_builder
+                                .build()
+                                .expect("Failed to build scene uniforms command buffer"),
+                        );
+                    }
+                    if let Err(e) = scene_renderer
+                        .borrow()
+                        .draw_commands(
+                            &mut scene_cmd_buf_builder,
+                            scene_image.clone(),
+                            &scene_state,
+                        )
+                        .chain_err(|| "scene renderer fail to issue draw commands")
+                    {
                         *control_flow = ControlFlow::Exit;
                         *res.lock().unwrap() = Err(e);
                         return;
                     }
-                };
-                if let Err(e) = scene_renderer
-                    .borrow()
-                    .draw_commands(
+                    scene_image_outdated = false;
+                }
+                if let Some(path) = application.take_screenshot_request() {
+                    let res = readback_queue.record(
                         &mut scene_cmd_buf_builder,
-                        images[image_num].clone(),
-                        &scene_state,
-                    )
-                    .chain_err(|| "scene renderer fail to issue draw commands")
-                {
-                    *control_flow = ControlFlow::Exit;
-                    *res.lock().unwrap() = Err(e);
-                    return;
+                        scene_image.clone(),
+                        move |image| {
+                            image
+                                .save(&path)
+                                .chain_err(|| format!("fail to save the screenshot to {:?}", path))
+                        },
+                    );
+                    if let Err(e) = res {
+                        application
+                            .on_background_error(e.chain_err(|| "fail to take a screenshot"));
+                    }
+                }
+                let gamma_correction = scene_renderer.borrow().is_gamma_correction();
+                if application.is_luminance_histogram_enabled() {
+                    if let Err(e) = luminance_histogram
+                        .record(
+                            &mut scene_cmd_buf_builder,
+                            scene_image.clone(),
+                            gamma_correction,
+                        )
+                        .chain_err(|| "fail to compute the luminance histogram")
+                    {
+                        *control_flow = ControlFlow::Exit;
+                        *res.lock().unwrap() = Err(e);
+                        return;
+                    }
+                }
+                if let Some(pixel) = application.get_probed_pixel() {
+                    if let Err(e) = pixel_probe.record(
+                        &mut scene_cmd_buf_builder,
+                        scene_image.clone(),
+                        pixel,
+                        gamma_correction,
+                    ) {
+                        application.on_background_error(e.chain_err(|| "fail to probe the pixel"));
+                    }
                 }
                 let scene_cmd_buf = scene_cmd_buf_builder.build().unwrap();
 
-                let future = previous_frame_end
-                    .take()
-                    .unwrap()
-                    .join(acquire_future)
-                    .then_execute(queue.clone(), scene_cmd_buf)
-                    .unwrap()
-                    .then_execute(queue.clone(), ui_cmd_buf)
-                    .unwrap()
-                    .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
-                    .then_signal_fence_and_flush();
+                let previous_frame_end: FrameFuture = match frames.previous() {
+                    Some(frame) => Box::new(frame.clone()),
+                    None => Box::new(sync::now(device.clone())),
+                };
+                let mut future: FrameFuture = Box::new(previous_frame_end.join(acquire_future));
+                if let Some(uniform_cmd_buf) = uniform_cmd_buf {
+                    // vulkano doesn't synchronize the reused draws, so the semaphores order the
+                    // uniform updates after the draws of the previous frame and before the ones
+                    // of this frame
+                    future = Box::new(
+                        future
+                            .then_signal_semaphore()
+                            .then_execute(queue.clone(), uniform_cmd_buf)
+                            .unwrap()
+                            .then_signal_semaphore(),
+                    );
+                }
+                let future: FrameFuture = Box::new(
+                    future
+                        .then_execute(queue.clone(), scene_cmd_buf)
+                        .unwrap()
+                        .then_execute(queue.clone(), ui_cmd_buf)
+                        .unwrap()
+                        .then_swapchain_present(queue.clone(), swapchain.clone(), image_num),
+                );
+                let future = future.then_signal_fence_and_flush();
 
                 match future {
                     Ok(future) => {
-                        previous_frame_end = Some(future.boxed());
+                        readback_queue.submitted();
+                        luminance_histogram.submitted();
+                        pixel_probe.submitted();
+                        frames.submit(Some(Arc::new(future)));
                     }
                     Err(FlushError::OutOfDate) => {
+                        readback_queue.discard_recorded();
+                        luminance_histogram.discard_recorded();
+                        pixel_probe.discard_recorded();
                         recreate_swapchain = true;
-                        previous_frame_end = Some(sync::now(device.clone()).boxed());
+                        frames.submit(None);
                     }
                     Err(e) => {
-                        eprintln!("Failed to flush future: {:?}", e);
-                        previous_frame_end = Some(sync::now(device.clone()).boxed());
+                        readback_queue.discard_recorded();
+                        luminance_histogram.discard_recorded();
+                        pixel_probe.discard_recorded();
+                        application
+                            .on_background_error(Error::with_chain(e, "fail to flush the frame"));
+                        frames.submit(None);
                     }
                 }
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
-            } => *control_flow = ControlFlow::Exit,
+            } => {
+                if application.on_close_requested() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
             Event::LoopDestroyed => {
-                let exit_code = if let Err(ref e) = *res.lock().unwrap() {
+                // wait for the frames in flight, so that the screenshots taken are saved
+                for frame in frames.drain() {
+                    let _ = frame.wait(None);
+                }
+                if let Some(path) = &profile_dump {
+                    // the last frame is kept too
+                    profile::begin_frame();
+                    if let Err(e) = profile::dump(path) {
+                        eprint_chained_err(&e);
+                    }
+                }
+                for e in readback_queue.finish() {
+                    eprint_chained_err(&e);
+                }
+                // the same codes as the errors at the startup, see exit_code
+                let code = if let Err(ref e) = *res.lock().unwrap() {
                     eprint_chained_err(e);
-                    1
+                    exit_code(e)
                 } else {
                     0
                 };
 
                 platform.handle_event(imgui.io_mut(), surface.window(), &event);
-                ::std::process::exit(exit_code);
+                ::std::process::exit(code);
             }
             event => {
                 let app_event_handler_res = match event {
@@ -388,6 +976,15 @@ impl System {
                         event: WindowEvent::MouseInput { state, button, .. },
                         ..
                     } => application.on_mouse_button(button, state),
+                    Event::WindowEvent {
+                        event: WindowEvent::MouseWheel { delta, .. },
+                        ..
+                    } => application.on_mouse_wheel(match delta {
+                        MouseScrollDelta::LineDelta(_, y) => y,
+                        MouseScrollDelta::PixelDelta(position) => {
+                            (position.y / PIXELS_PER_SCROLL_LINE) as f32
+                        }
+                    }),
                     Event::DeviceEvent {
                         event: DeviceEvent::MouseMotion { delta },
                         ..